@@ -0,0 +1,454 @@
+//! Execution of the subcommands
+
+use std::fs::{ File, OpenOptions };
+use std::io::{ BufWriter, Write };
+
+use clap::ArgMatches;
+
+use rustylink::{ Link, LinkError, Transport, Recorder, Replay, Dump, DumpFormat, RdpLevel, ProtectedSector, FlashAlgorithm, SRamInfo };
+use rustylink::rusb;
+
+use super::{ CliError, Image };
+
+
+/// Execute the subcommand selected by the user
+pub fn execute(matches: &ArgMatches) -> Result<(), CliError> {
+	// Commands that do not need a connection to the target
+	if let ("probe", Some(sub)) = matches.subcommand() {
+		return match sub.subcommand_name() {
+			Some("list") => probe_list(&context()?),
+			_ => unreachable!(),
+		};
+	}
+
+	let mode = super::debug_mode(matches);
+
+	// Re-run a recorded session without the probe
+	if let Some(path) = matches.value_of("replay") {
+		let replay = Replay::open(path)?;
+		info!("Replaying {} transfers with a {}", replay.events().len(), replay.model().name);
+
+		let model = replay.model();
+		return run(&mut Link::open(replay, model, mode)?, matches);
+	}
+
+	let ctx = context()?;
+
+	let selector = super::probe_selector(matches)?;
+
+	match matches.value_of("record") {
+		Some(path) => {
+			let mut link = Link::open_usb_wrapped(&ctx, &selector, 3, mode, |transport, model| {
+				Recorder::new(transport, model, BufWriter::new(File::create(path)?))
+			})?;
+			info!("Recording the session to {}", path);
+
+			run(&mut link, matches)
+		},
+		None => run(&mut Link::open_usb_with(&ctx, &selector, 3, mode)?, matches),
+	}
+}
+
+/// Execute a subcommand on an opened link
+fn run<T: Transport>(link: &mut Link<T>, matches: &ArgMatches) -> Result<(), CliError> {
+	let mode = super::debug_mode(matches);
+
+	if let Some(khz) = matches.value_of("speed") {
+		link.set_speed(mode, super::parse_number(khz)? as usize)?;
+	}
+
+	match matches.subcommand() {
+		("info", _) => info(link),
+
+		("map", _) => {
+			print!("{}", link.memory_map());
+			Ok(())
+		},
+
+		("status", _) => {
+			println!("Current mode: {:?}", link.current_mode()?);
+			println!("Core status : 0x{:X}", link.status()?);
+			Ok(())
+		},
+
+		("read", Some(sub)) => {
+			let address = super::parse_number(sub.value_of("address").unwrap())?;
+			let length = super::parse_number(sub.value_of("length").unwrap())? as usize;
+
+			let data = link.read_memory(address, length)?;
+			hexdump(address, &data);
+			Ok(())
+		},
+
+		("dump", Some(sub)) => dump(link, sub),
+
+		("write", Some(sub)) => {
+			let address = super::parse_number(sub.value_of("address").unwrap())?;
+			let data = super::parse_hex_bytes(sub.values_of("bytes").unwrap())?;
+
+			link.write_memory(address, &data)?;
+			info!("Wrote {} bytes at 0x{:08X}", data.len(), address);
+			Ok(())
+		},
+
+		("fill", Some(sub)) => {
+			let address = super::parse_number(sub.value_of("address").unwrap())?;
+			let length = super::parse_number(sub.value_of("length").unwrap())? as usize;
+			let pattern = super::parse_hex_bytes(sub.values_of("bytes").unwrap())?;
+
+			link.fill_memory(address, length, &pattern)?;
+			info!("Filled {} bytes at 0x{:08X}", length, address);
+			Ok(())
+		},
+
+		("verify", Some(sub)) => {
+			let base = super::parse_number(sub.value_of("address").unwrap())?;
+			let image = Image::open(sub.value_of("file").unwrap(), base)?;
+
+			let mut differ = 0;
+
+			for segment in image.segments.iter() {
+				for m in link.compare_memory(segment.address, &segment.data)? {
+					println!("0x{:08X}: expected {:02X?}, read {:02X?}", m.address, m.expected, m.actual);
+					differ += m.len();
+				}
+			}
+
+			match differ {
+				0 => {
+					info!("Verified {} bytes", image.size());
+					Ok(())
+				},
+				n => Err(CliError::Verify(n)),
+			}
+		},
+
+		("find", Some(sub)) => {
+			let start = super::parse_number(sub.value_of("start").unwrap())?;
+			let end = super::parse_number(sub.value_of("end").unwrap())?;
+			let pattern = super::parse_hex_bytes(sub.values_of("bytes").unwrap())?;
+			let mask = match sub.value_of("mask") {
+				Some(m) => Some(super::parse_hex_bytes(Some(m))?),
+				None => None,
+			};
+
+			let found = link.find_pattern(start..end, &pattern, mask.as_deref())?;
+
+			for address in found.iter() {
+				println!("0x{:08X}", address);
+			}
+			info!("{} matches", found.len());
+			Ok(())
+		},
+
+		("flash", Some(sub)) => {
+			let base = super::parse_number(sub.value_of("address").unwrap())?;
+			let image = Image::open(sub.value_of("file").unwrap(), base)?;
+
+			set_algorithm(link, sub)?;
+
+			if sub.is_present("fast") {
+				// Fast programming needs the whole flash erased
+				link.mass_erase()?;
+				link.set_fast_programming(true);
+			} else {
+				// Erase every sector under the image once
+				let sectors = link.flash_sectors()?.iter()
+					.filter(|s| image.segments.iter().any(|segment| s.overlaps(segment.address, segment.data.len())))
+					.map(|s| s.index)
+					.collect::<Vec<_>>();
+
+				link.erase_sectors(&sectors)?;
+			}
+
+			link.set_flash_loader(sub.is_present("loader"));
+
+			for segment in image.segments.iter() {
+				info!("Programming {} bytes at 0x{:08X}", segment.data.len(), segment.address);
+				link.program_flash(segment.address, &segment.data, &mut progress("Programmed"))?;
+				eprintln!();
+			}
+
+			info!("Programmed and verified {} bytes", image.size());
+			Ok(())
+		},
+
+		("erase", Some(sub)) => {
+			set_algorithm(link, sub)?;
+
+			match sub.value_of("sectors") {
+				Some(s) => link.erase_sectors(&super::parse_sectors(s)?)?,
+				None => link.mass_erase()?,
+			}
+
+			Ok(())
+		},
+
+		("eeprom", Some(sub)) => match sub.subcommand() {
+			("read", Some(sub)) => {
+				let address = super::parse_number(sub.value_of("address").unwrap())?;
+				let length = super::parse_number(sub.value_of("length").unwrap())? as usize;
+
+				let data = link.read_eeprom(address, length)?;
+				hexdump(address, &data);
+				Ok(())
+			},
+
+			("write", Some(sub)) => {
+				let address = super::parse_number(sub.value_of("address").unwrap())?;
+				let data = super::parse_hex_bytes(sub.values_of("bytes").unwrap())?;
+
+				link.write_eeprom(address, &data)?;
+				info!("Wrote and verified {} bytes at 0x{:08X}", data.len(), address);
+				Ok(())
+			},
+
+			_ => unreachable!(),
+		},
+
+		("options", _) => {
+			print!("{}", link.option_bytes()?);
+			Ok(())
+		},
+
+		("rdp", Some(sub)) => {
+			let level = match sub.value_of("level") {
+				Some("0") => RdpLevel::Level0,
+				Some("1") => RdpLevel::Level1,
+				Some(_) => RdpLevel::Level2,
+				None => {
+					println!("RDP {}", link.rdp_level()?);
+					return Ok(());
+				},
+			};
+
+			if level == RdpLevel::Level0 && link.rdp_level()? == RdpLevel::Level1 && !sub.is_present("erase") {
+				return Err(CliError::Argument("going back to RDP level 0 erases the whole flash, accept it with --erase".to_string()));
+			}
+
+			link.set_rdp_level(level, sub.value_of("confirm"))?;
+			info!("Read protection at {}", link.rdp_level()?);
+			Ok(())
+		},
+
+		("protect", Some(sub)) => {
+			let (address, length) = match (sub.value_of("address"), sub.value_of("length")) {
+				(Some(a), Some(l)) => (super::parse_number(a)?, super::parse_number(l)? as usize),
+				_ => {
+					print_protection(&link.protected_sectors()?);
+					return Ok(());
+				},
+			};
+
+			let sectors = match sub.is_present("remove") {
+				true => link.unprotect_range(address, length)?,
+				false => link.protect_range(address, length)?,
+			};
+
+			info!("Write protection of {} sectors changed", sectors.len());
+			print_protection(&link.protected_sectors()?);
+			Ok(())
+		},
+
+		("reset", Some(sub)) => {
+			if sub.is_present("halt") {
+				link.reset_halt()?;
+				info!("Core halted at PC 0x{:08X}", link.read_reg(15)?);
+			} else {
+				link.usb_reset()?;
+				link.run()?;
+			}
+			Ok(())
+		},
+
+		("regs", _) => {
+			link.halt()?;
+			println!("{}", link.read_core_regs()?);
+			Ok(())
+		},
+
+		("run", _) => Ok(link.run()?),
+
+		("halt", _) => {
+			link.halt()?;
+			info!("Core halted at PC 0x{:08X}", link.read_reg(15)?);
+			Ok(())
+		},
+
+		("step", _) => {
+			link.step()?;
+			info!("Core halted at PC 0x{:08X}", link.read_reg(15)?);
+			Ok(())
+		},
+
+		_ => unreachable!(),
+	}
+}
+
+
+/// Open a USB context
+fn context() -> Result<rusb::Context, CliError> {
+	match rusb::Context::new() {
+		Ok(context) => Ok(context),
+		Err(e) => {
+			error!("Could not open a USB context");
+			Err(LinkError::from(e).into())
+		},
+	}
+}
+
+/// List the connected probes
+fn probe_list(ctx: &rusb::Context) -> Result<(), CliError> {
+	let probes = Link::probes(ctx);
+
+	if probes.is_empty() {
+		println!("No STLink probes found");
+	}
+
+	for probe in probes.iter() {
+		println!("{}", probe);
+	}
+
+	Ok(())
+}
+
+/// Dump a memory range into a file
+/// Transient USB errors are retried from the last chunk written
+fn dump<T: Transport>(link: &mut Link<T>, sub: &ArgMatches) -> Result<(), CliError> {
+	const RETRIES: usize = 3;
+
+	let address = super::parse_number(sub.value_of("address").unwrap())?;
+	let length = super::parse_number(sub.value_of("length").unwrap())? as usize;
+	let path = sub.value_of("file").unwrap();
+	let format = super::dump_format(sub);
+
+	let (file, offset) = if sub.is_present("resume") {
+		let existing = std::fs::metadata(path).map(|m| m.len() as usize).unwrap_or(0);
+
+		if format == DumpFormat::IntelHex {
+			return Err(CliError::Argument(String::from("an Intel HEX dump cannot be resumed")));
+		}
+
+		// Restart if the headers are incomplete
+		let offset = existing.saturating_sub(format.header_size());
+
+		match offset {
+			0 => (File::create(path)?, 0),
+			_ => (OpenOptions::new().append(true).open(path)?, offset),
+		}
+	} else {
+		(File::create(path)?, 0)
+	};
+
+	if offset > 0 {
+		info!("Resuming the dump at offset 0x{:X}", offset);
+	}
+
+	let mut dump = Dump::resume(address, length, format, BufWriter::new(file), offset);
+	let mut progress = progress("Dumped");
+
+	let mut failures = 0;
+
+	loop {
+		match link.dump(&mut dump, &mut progress) {
+			Ok(_) => break,
+			Err(e) if e.is_transient() && failures < RETRIES => {
+				failures += 1;
+				eprintln!();
+				warn!("Dump interrupted at offset 0x{:X}: {}. Retrying ({}/{})", dump.offset(), e, failures, RETRIES);
+			},
+			Err(e) => {
+				eprintln!();
+				// Keep what was read so the dump can be resumed
+				let _ = dump.into_inner().flush();
+				return Err(e.into());
+			},
+		}
+	}
+
+	eprintln!();
+	info!("Dumped {} bytes at 0x{:08X} into {}", length, address, path);
+
+	Ok(())
+}
+
+/// Use the flash algorithm given with `--algorithm`, in the SRAM given with `--ram`
+fn set_algorithm<T: Transport>(link: &mut Link<T>, sub: &ArgMatches) -> Result<(), CliError> {
+	let path = match sub.value_of("algorithm") {
+		Some(path) => path,
+		None => return Ok(()),
+	};
+
+	link.set_flash_algorithm(Some(FlashAlgorithm::open(path)?));
+
+	if let Some(mut values) = sub.values_of("ram") {
+		let base = super::parse_number(values.next().unwrap())?;
+		let size = super::parse_number(values.next().unwrap())?;
+		link.set_algorithm_ram(Some(SRamInfo { base, size }));
+	}
+
+	Ok(())
+}
+
+/// Progress of a long operation, printed on a single line of the standard error
+fn progress(what: &'static str) -> impl FnMut(usize, usize) {
+	move |done: usize, total: usize| {
+		eprint!("\r{} {} of {} bytes ({}%)", what, done, total, (done * 100).checked_div(total).unwrap_or(100));
+		let _ = std::io::stderr().flush();
+	}
+}
+
+/// Print the probe and target information
+fn info<T: Transport>(link: &mut Link<T>) -> Result<(), CliError> {
+	let version = *link.probe_version();
+	let (vid, pid) = link.usb_id();
+
+	println!("Probe     : {} {:04X}:{:04X}", link.model().name, vid, pid);
+	println!("Firmware  : V{} J{} S{} (API {})", version.stlink, version.jtag, version.swim, version.jtag_api);
+	match link.voltage() {
+		Ok(v) => println!("Voltage   : {:.2} V", v),
+		Err(e) => debug!("Target voltage not available: {}", e),
+	}
+	println!("Core    ID: 0x{:08X}", link.core_id()?);
+	println!("Chip    ID: 0x{:03X}", link.get_chip_info()?);
+
+	let memory = link.memory().clone();
+	println!("Flash     : {} kB at 0x{:08X}", memory.flash.size, memory.flash.base);
+	for r in memory.ram.iter() {
+		println!("SRAM      : {} kB at 0x{:08X}", r.size / 1024, r.base);
+	}
+	println!("System    : {} kB at 0x{:08X}", memory.sys.size / 1024, memory.sys.base);
+
+	Ok(())
+}
+
+/// Print `data` as a hexdump starting at `address`
+fn hexdump(address: u32, data: &[u8]) {
+	for (i, line) in data.chunks(16).enumerate() {
+		let hex: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+		let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+
+		println!("0x{:08X}: {:<47}  {}", address + (i * 16) as u32, hex.join(" "), ascii);
+	}
+}
+
+/// Print the protected sectors, with the runs of consecutive sectors on one line
+fn print_protection(protected: &[ProtectedSector]) {
+	if protected.is_empty() {
+		println!("No protected sector");
+		return;
+	}
+
+	let mut runs: Vec<(ProtectedSector, ProtectedSector)> = Vec::new();
+
+	for p in protected.iter() {
+		match runs.last_mut() {
+			Some(run) if run.1.protection == p.protection && run.1.sector.address + run.1.sector.size == p.sector.address => run.1 = *p,
+			_ => runs.push((*p, *p)),
+		}
+	}
+
+	for (first, last) in runs {
+		println!("0x{:08X}-0x{:08X} sectors {}-{} {}", first.sector.address, last.sector.address + last.sector.size - 1, first.sector.index, last.sector.index, first.protection);
+	}
+}
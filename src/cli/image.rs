@@ -0,0 +1,89 @@
+//! Firmware images loaded from ELF files or raw binaries
+
+use std::path::Path;
+use std::io::Read;
+
+use super::CliError;
+
+
+/// Contiguous block of data to be loaded at `address`
+#[derive(Debug, Clone)]
+pub struct Segment {
+	pub address: u32,
+	pub data: Vec<u8>,
+}
+
+/// Image to be programmed in the target
+#[derive(Debug, Clone)]
+pub struct Image {
+	pub segments: Vec<Segment>,
+}
+
+impl Image {
+	/// Load an image from a file
+	/// ELF files are detected by their magic number, any other file is
+	/// considered a raw binary that will be loaded at `base`
+	pub fn open<P: AsRef<Path>>(path: P, base: u32) -> Result<Self, CliError> {
+		let mut data = Vec::new();
+		std::fs::File::open(path.as_ref())?.read_to_end(&mut data)?;
+
+		match data.get(0..4) {
+			Some(b"\x7FELF") => Self::elf(path.as_ref()),
+			_ => Ok(Self::binary(data, base)),
+		}
+	}
+
+	/// Create an image from a raw binary
+	pub fn binary(data: Vec<u8>, base: u32) -> Self {
+		Self {
+			segments: vec![Segment { address: base, data }],
+		}
+	}
+
+	/// Load the allocated sections of an ELF file at their load address
+	pub fn elf(path: &Path) -> Result<Self, CliError> {
+		use elf::types::{ PT_LOAD, SHT_PROGBITS, SHF_ALLOC };
+
+		let file = match elf::File::open_path(path) {
+			Ok(f) => f,
+			Err(e) => return Err(CliError::Image(format!("could not parse ELF file {:?}: {:?}", path, e))),
+		};
+
+		let mut segments = Vec::new();
+
+		for section in file.sections.iter() {
+			let shdr = &section.shdr;
+
+			if shdr.shtype != SHT_PROGBITS || shdr.flags.0 & SHF_ALLOC.0 == 0 || shdr.size == 0 {
+				continue;
+			}
+
+			// The section is loaded at the physical address of the segment that contains it
+			let segment = file.phdrs.iter()
+				.filter(|p| p.progtype == PT_LOAD)
+				.find(|p| shdr.addr >= p.vaddr && (shdr.addr + shdr.size) <= (p.vaddr + p.memsz));
+
+			let address = match segment {
+				Some(p) => p.paddr + (shdr.addr - p.vaddr),
+				None => shdr.addr,
+			};
+
+			debug!("ELF section {} : {} bytes at 0x{:08X}", shdr.name, section.data.len(), address);
+
+			segments.push(Segment { address: address as u32, data: section.data.clone() });
+		}
+
+		if segments.is_empty() {
+			return Err(CliError::Image(format!("{:?} has no loadable sections", path)));
+		}
+
+		segments.sort_by_key(|s| s.address);
+
+		Ok(Self { segments })
+	}
+
+	/// Total number of bytes in the image
+	pub fn size(&self) -> usize {
+		self.segments.iter().map(|s| s.data.len()).sum()
+	}
+}
@@ -0,0 +1,484 @@
+//! Command line interface of `rustylink`
+//! Definition of the arguments and parsing of the values given by the user.
+
+mod commands;
+mod image;
+
+pub use self::commands::execute;
+pub use self::image::Image;
+
+use clap::{ App, AppSettings, Arg, ArgMatches, SubCommand };
+
+use rustylink::{ LinkError, DebugMode, DumpFormat, ProbeSelector };
+
+
+/// Errors reported by the command line tool
+#[derive(Debug)]
+pub enum CliError {
+	/// The probe or the target failed
+	Link(LinkError),
+	/// Could not read or write a file
+	Io(std::io::Error),
+	/// The given file is not a valid image
+	Image(String),
+	/// An argument could not be parsed
+	Argument(String),
+	/// The target memory differs from the image in the given number of bytes
+	Verify(usize),
+}
+
+impl std::fmt::Display for CliError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			CliError::Link(e) => write!(f, "{}", e),
+			CliError::Io(e) => write!(f, "I/O error: {}", e),
+			CliError::Image(s) => write!(f, "Invalid image: {}", s),
+			CliError::Argument(s) => write!(f, "Invalid argument: {}", s),
+			CliError::Verify(n) => write!(f, "Verification failed: {} bytes differ", n),
+		}
+	}
+}
+
+impl std::error::Error for CliError {}
+
+impl From<LinkError> for CliError {
+	fn from(e: LinkError) -> Self {
+		CliError::Link(e)
+	}
+}
+
+impl From<std::io::Error> for CliError {
+	fn from(e: std::io::Error) -> Self {
+		CliError::Io(e)
+	}
+}
+
+
+/// Build the argument parser
+pub fn app() -> App<'static, 'static> {
+	App::new("rustylink")
+		.version(env!("CARGO_PKG_VERSION"))
+		.about("STM32 devices interface through STLink probes")
+		.setting(AppSettings::SubcommandRequiredElseHelp)
+		.setting(AppSettings::VersionlessSubcommands)
+		.arg(Arg::with_name("mode")
+			.long("mode")
+			.short("m")
+			.takes_value(true)
+			.possible_values(&["swd", "jtag", "swim"])
+			.case_insensitive(true)
+			.default_value("swd")
+			.global(true)
+			.help("Debug mode used to connect to the target"))
+		.arg(Arg::with_name("speed")
+			.long("speed")
+			.short("s")
+			.takes_value(true)
+			.value_name("kHz")
+			.validator(validate_number)
+			.global(true)
+			.help("Clock speed of the debug connection"))
+		.arg(Arg::with_name("serial")
+			.long("serial")
+			.takes_value(true)
+			.conflicts_with("probe")
+			.global(true)
+			.help("Serial number of the probe to use"))
+		.arg(Arg::with_name("probe")
+			.long("probe")
+			.short("p")
+			.takes_value(true)
+			.value_name("INDEX|BUS:ADDRESS|BUS-PORT")
+			.validator(|s| parse_probe(&s).map(|_| ()).map_err(|e| e.to_string()))
+			.global(true)
+			.help("Index or USB location of the probe to use, as shown by 'probe list'"))
+		.arg(Arg::with_name("record")
+			.long("record")
+			.takes_value(true)
+			.value_name("FILE")
+			.conflicts_with("replay")
+			.global(true)
+			.help("Record the USB traffic of the session into a file"))
+		.arg(Arg::with_name("replay")
+			.long("replay")
+			.takes_value(true)
+			.value_name("FILE")
+			.global(true)
+			.help("Replay a recorded session instead of using a probe"))
+		.arg(Arg::with_name("log-level")
+			.long("log-level")
+			.short("l")
+			.takes_value(true)
+			.possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+			.case_insensitive(true)
+			.default_value("info")
+			.global(true)
+			.help("Maximum level of the log messages"))
+		.subcommand(SubCommand::with_name("probe")
+			.about("Probe management")
+			.setting(AppSettings::SubcommandRequiredElseHelp)
+			.subcommand(SubCommand::with_name("list")
+				.about("List the connected STLink probes")))
+		.subcommand(SubCommand::with_name("info")
+			.about("Show the probe and target information"))
+		.subcommand(SubCommand::with_name("map")
+			.about("Show the memory map of the target"))
+		.subcommand(SubCommand::with_name("status")
+			.about("Show the core status"))
+		.subcommand(SubCommand::with_name("read")
+			.about("Read the target memory and print a hexdump")
+			.arg(Arg::with_name("address")
+				.required(true)
+				.validator(validate_number)
+				.help("Start address"))
+			.arg(Arg::with_name("length")
+				.required(true)
+				.validator(validate_number)
+				.help("Number of bytes to read")))
+		.subcommand(SubCommand::with_name("dump")
+			.about("Dump the target memory into a file")
+			.arg(Arg::with_name("address")
+				.required(true)
+				.validator(validate_number)
+				.help("Start address"))
+			.arg(Arg::with_name("length")
+				.required(true)
+				.validator(validate_number)
+				.help("Number of bytes to dump"))
+			.arg(Arg::with_name("file")
+				.required(true)
+				.help("Output file"))
+			.arg(Arg::with_name("format")
+				.long("format")
+				.short("f")
+				.takes_value(true)
+				.possible_values(&["raw", "ihex", "elf"])
+				.case_insensitive(true)
+				.help("Format of the output, guessed from the file extension by default"))
+			.arg(Arg::with_name("resume")
+				.long("resume")
+				.help("Continue an interrupted raw or ELF dump at the end of the file")))
+		.subcommand(SubCommand::with_name("write")
+			.about("Write bytes into the target memory")
+			.arg(Arg::with_name("address")
+				.required(true)
+				.validator(validate_number)
+				.help("Start address"))
+			.arg(Arg::with_name("bytes")
+				.required(true)
+				.multiple(true)
+				.help("Bytes to write in hexadecimal (e.g. 'DEADBEEF' or 'DE AD BE EF')")))
+		.subcommand(SubCommand::with_name("fill")
+			.about("Fill the target memory with a repeated byte pattern")
+			.arg(Arg::with_name("address")
+				.required(true)
+				.validator(validate_number)
+				.help("Start address"))
+			.arg(Arg::with_name("length")
+				.required(true)
+				.validator(validate_number)
+				.help("Number of bytes to fill"))
+			.arg(Arg::with_name("bytes")
+				.required(true)
+				.multiple(true)
+				.help("Pattern in hexadecimal (e.g. '00' or 'DEADBEEF')")))
+		.subcommand(SubCommand::with_name("verify")
+			.about("Compare the target memory with an image")
+			.arg(Arg::with_name("file")
+				.required(true)
+				.help("ELF file or raw binary"))
+			.arg(Arg::with_name("address")
+				.long("address")
+				.short("a")
+				.takes_value(true)
+				.validator(validate_number)
+				.default_value("0x08000000")
+				.help("Load address of a raw binary")))
+		.subcommand(SubCommand::with_name("find")
+			.about("Search the target memory for a byte pattern")
+			.arg(Arg::with_name("start")
+				.required(true)
+				.validator(validate_number)
+				.help("Start address"))
+			.arg(Arg::with_name("end")
+				.required(true)
+				.validator(validate_number)
+				.help("End address, not included"))
+			.arg(Arg::with_name("bytes")
+				.required(true)
+				.multiple(true)
+				.help("Pattern in hexadecimal"))
+			.arg(Arg::with_name("mask")
+				.long("mask")
+				.takes_value(true)
+				.help("Bits of the pattern to compare, in hexadecimal")))
+		.subcommand(SubCommand::with_name("flash")
+			.about("Program an image into the target flash")
+			.arg(Arg::with_name("file")
+				.required(true)
+				.help("ELF file or raw binary"))
+			.arg(Arg::with_name("address")
+				.long("address")
+				.short("a")
+				.takes_value(true)
+				.validator(validate_number)
+				.default_value("0x08000000")
+				.help("Load address of a raw binary"))
+			.arg(Arg::with_name("fast")
+				.long("fast")
+				.help("Mass erase and program whole rows with fast programming (L4/G0/WB)"))
+			.arg(Arg::with_name("loader")
+				.long("loader")
+				.conflicts_with("fast")
+				.help("Program with a loader running in the target SRAM, halting the core"))
+			.arg(Arg::with_name("algorithm")
+				.long("algorithm")
+				.conflicts_with_all(&["fast", "loader"])
+				.takes_value(true)
+				.value_name("FLM")
+				.help("CMSIS flash algorithm used instead of the driver of the chip"))
+			.arg(Arg::with_name("ram")
+				.long("ram")
+				.number_of_values(2)
+				.value_names(&["ADDRESS", "SIZE"])
+				.validator(validate_number)
+				.requires("algorithm")
+				.help("SRAM where the flash algorithm runs, when the chip is not known")))
+		.subcommand(SubCommand::with_name("erase")
+			.about("Erase the target flash")
+			.arg(Arg::with_name("mass")
+				.long("mass")
+				.help("Erase the whole flash"))
+			.arg(Arg::with_name("sectors")
+				.long("sectors")
+				.takes_value(true)
+				.value_name("LIST")
+				.validator(|s| parse_sectors(&s).map(|_| ()).map_err(|e| e.to_string()))
+				.help("Sectors to erase (e.g. '0,2,4-7')"))
+			.arg(Arg::with_name("algorithm")
+				.long("algorithm")
+				.takes_value(true)
+				.value_name("FLM")
+				.help("CMSIS flash algorithm used instead of the driver of the chip"))
+			.arg(Arg::with_name("ram")
+				.long("ram")
+				.number_of_values(2)
+				.value_names(&["ADDRESS", "SIZE"])
+				.validator(validate_number)
+				.requires("algorithm")
+				.help("SRAM where the flash algorithm runs, when the chip is not known"))
+			.group(clap::ArgGroup::with_name("target")
+				.args(&["mass", "sectors"])
+				.required(true)))
+		.subcommand(SubCommand::with_name("eeprom")
+			.about("Data EEPROM of the L0/L1 devices")
+			.setting(AppSettings::SubcommandRequiredElseHelp)
+			.subcommand(SubCommand::with_name("read")
+				.about("Read the data EEPROM and print a hexdump")
+				.arg(Arg::with_name("address")
+					.required(true)
+					.validator(validate_number)
+					.help("Start address"))
+				.arg(Arg::with_name("length")
+					.required(true)
+					.validator(validate_number)
+					.help("Number of bytes to read")))
+			.subcommand(SubCommand::with_name("write")
+				.about("Write bytes into the data EEPROM")
+				.arg(Arg::with_name("address")
+					.required(true)
+					.validator(validate_number)
+					.help("Start address"))
+				.arg(Arg::with_name("bytes")
+					.required(true)
+					.multiple(true)
+					.help("Bytes to write in hexadecimal (e.g. 'DEADBEEF' or 'DE AD BE EF')"))))
+		.subcommand(SubCommand::with_name("options")
+			.about("Read and decode the option bytes"))
+		.subcommand(SubCommand::with_name("rdp")
+			.about("Read or change the read protection level")
+			.arg(Arg::with_name("level")
+				.possible_values(&["0", "1", "2"])
+				.help("New RDP level, the current one is printed without it"))
+			.arg(Arg::with_name("erase")
+				.long("erase")
+				.help("Accept the mass erase of the flash when going back to level 0"))
+			.arg(Arg::with_name("confirm")
+				.long("confirm")
+				.takes_value(true)
+				.value_name("TOKEN")
+				.help("Confirmation of level 2, which disables the debug port for good")))
+		.subcommand(SubCommand::with_name("protect")
+			.about("List the protected sectors or change the write protection of a range")
+			.arg(Arg::with_name("address")
+				.validator(validate_number)
+				.requires("length")
+				.help("Start of the range, the protected sectors are listed without it"))
+			.arg(Arg::with_name("length")
+				.validator(validate_number)
+				.help("Number of bytes of the range"))
+			.arg(Arg::with_name("remove")
+				.long("remove")
+				.requires("address")
+				.help("Remove the write protection instead of adding it")))
+		.subcommand(SubCommand::with_name("reset")
+			.about("Reset the target")
+			.arg(Arg::with_name("halt")
+				.long("halt")
+				.help("Halt the core at the reset vector")))
+		.subcommand(SubCommand::with_name("regs")
+			.about("Halt the core and print its registers"))
+		.subcommand(SubCommand::with_name("run")
+			.about("Resume the execution of the core"))
+		.subcommand(SubCommand::with_name("halt")
+			.about("Halt the core"))
+		.subcommand(SubCommand::with_name("step")
+			.about("Execute a single instruction"))
+}
+
+
+/// Log level selected by the user
+pub fn log_level(matches: &ArgMatches) -> log::LevelFilter {
+	match matches.value_of("log-level").map(|s| s.to_lowercase()).as_deref() {
+		Some("off")   => log::LevelFilter::Off,
+		Some("error") => log::LevelFilter::Error,
+		Some("warn")  => log::LevelFilter::Warn,
+		Some("debug") => log::LevelFilter::Debug,
+		Some("trace") => log::LevelFilter::Trace,
+		_ => log::LevelFilter::Info,
+	}
+}
+
+/// Dump format selected by the user
+pub fn dump_format(matches: &ArgMatches) -> DumpFormat {
+	match matches.value_of("format").map(|s| s.to_lowercase()).as_deref() {
+		Some("raw")  => DumpFormat::Raw,
+		Some("ihex") => DumpFormat::IntelHex,
+		Some("elf")  => DumpFormat::ElfCore,
+		_ => DumpFormat::from_path(matches.value_of("file").unwrap()),
+	}
+}
+
+/// Debug mode selected by the user
+pub fn debug_mode(matches: &ArgMatches) -> DebugMode {
+	match matches.value_of("mode").map(|s| s.to_lowercase()).as_deref() {
+		Some("jtag") => DebugMode::JTAG,
+		Some("swim") => DebugMode::SWIM,
+		_ => DebugMode::SWD,
+	}
+}
+
+/// Probe selected by the user
+pub fn probe_selector(matches: &ArgMatches) -> Result<ProbeSelector, CliError> {
+	match (matches.value_of("serial"), matches.value_of("probe")) {
+		(Some(serial), _) => Ok(ProbeSelector::Serial(String::from(serial))),
+		(None, Some(probe)) => parse_probe(probe),
+		(None, None) => Ok(ProbeSelector::First),
+	}
+}
+
+/// Parse a probe index (`2`), USB location (`1:14`) or USB port path (`1-2.3`)
+pub fn parse_probe(s: &str) -> Result<ProbeSelector, CliError> {
+	let invalid = || CliError::Argument(format!("'{}' is not a probe index, a BUS:ADDRESS location or a BUS-PORT path", s));
+
+	if let Some(dash) = s.find('-') {
+		let ports = s[dash+1..].trim().split('.')
+			.map(|p| p.parse::<u8>().map_err(|_| invalid()))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		return Ok(ProbeSelector::Port { bus: s[..dash].trim().parse::<u8>().map_err(|_| invalid())?, ports });
+	}
+
+	let mut parts = s.trim().splitn(2, ':');
+	let first = parts.next().ok_or_else(invalid)?;
+
+	match parts.next() {
+		Some(address) => Ok(ProbeSelector::Path {
+			bus: first.parse::<u8>().map_err(|_| invalid())?,
+			address: address.parse::<u8>().map_err(|_| invalid())?,
+		}),
+		None => Ok(ProbeSelector::Index(first.parse::<usize>().map_err(|_| invalid())?)),
+	}
+}
+
+/// Parse a number in decimal or hexadecimal (`0x` prefix) notation
+/// Underscores can be used as separators
+pub fn parse_number(s: &str) -> Result<u32, CliError> {
+	let clean = s.trim().replace('_', "");
+
+	let result = if clean.starts_with("0x") || clean.starts_with("0X") {
+		u32::from_str_radix(&clean[2..], 16)
+	} else {
+		clean.parse::<u32>()
+	};
+
+	result.map_err(|_| CliError::Argument(format!("'{}' is not a valid number", s)))
+}
+
+/// Parse a list of bytes written in hexadecimal
+/// Each argument may contain several bytes and an optional `0x` prefix
+pub fn parse_hex_bytes<'a, I: IntoIterator<Item=&'a str>>(args: I) -> Result<Vec<u8>, CliError> {
+	let mut out = Vec::new();
+
+	for arg in args {
+		let arg = arg.trim();
+		let digits = if arg.starts_with("0x") || arg.starts_with("0X") { &arg[2..] } else { arg };
+		let digits: String = digits.chars().filter(|c| *c != '_' && *c != ':' && !c.is_whitespace()).collect();
+
+		// Only ASCII digits, so the string can be split at any byte
+		if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+			return Err(CliError::Argument(format!("'{}' is not a valid hex byte string", arg)));
+		}
+
+		if !digits.len().is_multiple_of(2) {
+			return Err(CliError::Argument(format!("'{}' has an odd number of hex digits", arg)));
+		}
+
+		for i in (0..digits.len()).step_by(2) {
+			match u8::from_str_radix(&digits[i..i+2], 16) {
+				Ok(b) => out.push(b),
+				Err(_) => return Err(CliError::Argument(format!("'{}' is not a valid hex byte string", arg))),
+			}
+		}
+	}
+
+	match out.len() {
+		0 => Err(CliError::Argument(String::from("no bytes to write"))),
+		_ => Ok(out),
+	}
+}
+
+/// Parse a list of sectors such as `0,2,4-7`
+pub fn parse_sectors(s: &str) -> Result<Vec<usize>, CliError> {
+	let invalid = || CliError::Argument(format!("'{}' is not a valid sector list", s));
+
+	let mut out = Vec::new();
+
+	for item in s.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+		let mut bounds = item.splitn(2, '-');
+
+		let first = bounds.next().ok_or_else(invalid)?.trim().parse::<usize>().map_err(|_| invalid())?;
+		let last = match bounds.next() {
+			Some(l) => l.trim().parse::<usize>().map_err(|_| invalid())?,
+			None => first,
+		};
+
+		if last < first {
+			return Err(invalid());
+		}
+
+		out.extend(first..=last);
+	}
+
+	out.sort();
+	out.dedup();
+
+	match out.len() {
+		0 => Err(invalid()),
+		_ => Ok(out),
+	}
+}
+
+fn validate_number(s: String) -> Result<(), String> {
+	parse_number(&s).map(|_| ()).map_err(|e| e.to_string())
+}
@@ -25,7 +25,7 @@ pub fn write_u16(buffer: &mut [u8], ui: u16, swap: bool) {
 	}
 }
 */
-pub fn version_to_raw(version: libusb::Version) -> usize {
+pub fn version_to_raw(version: rusb::Version) -> usize {
 	((version.major() as usize) << 8) | ((version.minor() as usize) << 4) | version.sub_minor() as usize
 }
 
@@ -33,6 +33,6 @@ pub fn version_to_raw(version: libusb::Version) -> usize {
 #[macro_export]
 macro_rules! mask {
 	( $x:expr ) => {
-		(1u32 << $x) - 1;
+		(1u32 << $x) - 1
 	};
 }
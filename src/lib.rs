@@ -0,0 +1,47 @@
+//! `rustylink`
+//! STM32 devices interface through STLink probes.
+//!
+//! The main entry point is `Link`, which opens a probe, connects to the
+//! target and gives access to its memory, core registers and flash.
+
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate log;
+extern crate fern;
+extern crate chrono;
+extern crate elf;
+
+pub extern crate rusb;
+
+//extern crate libusb_sys as rawusb;
+extern crate crossbeam_channel as crossbeam;
+
+pub mod logging;
+
+pub mod util;
+pub mod usb;
+#[macro_use]
+pub mod common;
+pub mod link;
+
+pub mod sim;
+
+pub mod dbg;
+
+
+pub use crate::link::link::Link;
+pub use crate::link::error::{ LinkError, StatusError, FlashError };
+pub use crate::link::transport::Transport;
+pub use crate::link::record::{ Recorder, Replay };
+pub use crate::link::memmap::{ MemoryMap, MemoryRegion, RegionKind, AccessPolicy };
+pub use crate::link::dump::{ Dump, DumpFormat, Progress };
+pub use crate::link::algorithm::{ FlashAlgorithm, FlashDevice };
+pub use crate::link::options::{ OptionBytes, OptionsF4, OptionsF1, OptionsL0, OptionsL4, RdpLevel, rdp2_confirmation };
+pub use crate::link::enums::{ STLinkMode, DebugMode, FlashType, Parallelism, Protection, ProbeSelector };
+pub use crate::link::structs::{ STLinkUSBVersion, ProbeInfo, CoreRegisters, MemInfo, FlashInfo, SRamInfo, SysMemInfo, EepromInfo, FlashSector, ProtectedSector, Mismatch };
+pub use crate::link::chipid::{ STM32ChipID, ChipParams, STMCHIPS, get_chip_from_id_u32, data_eeprom_size };
+pub use crate::usb::model::ProbeModel;
+pub use crate::usb::transport::UsbTransport;
+pub use crate::dbg::internal::Debugger;
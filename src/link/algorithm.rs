@@ -0,0 +1,243 @@
+//! CMSIS-Pack flash algorithms
+//! A flash algorithm (.FLM file of the Keil and CMSIS device packs) is an ELF
+//! file with position independent functions, linked from address 0, and a
+//! `FlashDevice` descriptor of the flash they program. The code and data are
+//! copied to the target SRAM as a single image, the functions are called with
+//! their offset in it and the static base (r9) at the start of the data.
+
+use std::path::Path;
+use std::io::Read;
+
+use super::error::LinkError;
+use super::structs::FlashSector;
+use super::util::{ buf_read_u16, buf_read_u32 };
+
+
+/// Largest image of code and data
+const MAX_IMAGE: usize = 0x10_0000;
+
+/// Offsets of the fields of the `FlashDevice` descriptor
+mod descriptor {
+	pub const VERS     : usize = 0;
+	pub const DEVNAME  : usize = 2;
+	pub const DEVTYPE  : usize = 130;
+	pub const DEVADR   : usize = 132;
+	pub const SZDEV    : usize = 136;
+	pub const SZPAGE   : usize = 140;
+	pub const VALEMPTY : usize = 148;
+	pub const TOPROG   : usize = 152;
+	pub const TOERASE  : usize = 156;
+	pub const SECTORS  : usize = 160;
+
+	/// Length of the device name
+	pub const NAME_LEN : usize = 128;
+	/// Marker ending the sector list
+	pub const SECTOR_END : u32 = 0xFFFF_FFFF;
+}
+
+
+/// Flash described by a flash algorithm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashDevice {
+	/// Version of the driver interface
+	pub version: u16,
+	pub name: String,
+	/// Kind of device: 1 on chip, 2 to 4 external 8 to 32 bit, 5 external SPI
+	pub kind: u16,
+	pub address: u32,
+	pub size: u32,
+	/// Bytes programmed by a `ProgramPage` call
+	pub page_size: u32,
+	/// Value of an erased byte
+	pub erased: u8,
+	/// Timeout of a `ProgramPage` call in ms
+	pub program_timeout: u32,
+	/// Timeout of an `EraseSector` call in ms
+	pub erase_timeout: u32,
+	/// Runs of sectors of the same size: the size and the offset of the first
+	/// sector from `address`. A run ends at the next one or at the end of the flash.
+	pub sectors: Vec<(u32, u32)>,
+}
+
+impl FlashDevice {
+	/// Decode the `FlashDevice` descriptor in `data`
+	pub fn parse(data: &[u8]) -> Result<Self, LinkError> {
+		use self::descriptor::*;
+
+		if data.len() < SECTORS + 8 {
+			error!("Flash algorithm. The FlashDevice descriptor has only {} bytes.", data.len());
+			return Err(LinkError::Algorithm("truncated FlashDevice descriptor"));
+		}
+
+		let name = &data[DEVNAME..DEVNAME + NAME_LEN];
+		let name = &name[..name.iter().position(|c| *c == 0).unwrap_or(NAME_LEN)];
+
+		let mut sectors = Vec::new();
+		for entry in data[SECTORS..].chunks(8).filter(|e| e.len() == 8) {
+			let (size, offset) = (buf_read_u32(entry, 0, true), buf_read_u32(entry, 4, true));
+			if size == SECTOR_END && offset == SECTOR_END {
+				break;
+			}
+			sectors.push((size, offset));
+		}
+
+		let device = Self {
+			version: buf_read_u16(data, VERS, true),
+			name: String::from_utf8_lossy(name).into_owned(),
+			kind: buf_read_u16(data, DEVTYPE, true),
+			address: buf_read_u32(data, DEVADR, true),
+			size: buf_read_u32(data, SZDEV, true),
+			page_size: buf_read_u32(data, SZPAGE, true),
+			erased: data[VALEMPTY],
+			program_timeout: buf_read_u32(data, TOPROG, true),
+			erase_timeout: buf_read_u32(data, TOERASE, true),
+			sectors,
+		};
+
+		if device.page_size == 0 || device.sectors.is_empty() || device.sectors.iter().any(|s| s.0 == 0) {
+			error!("Flash algorithm. {} has no pages or no sectors.", device.name);
+			return Err(LinkError::Algorithm("FlashDevice without pages or sectors"));
+		}
+
+		Ok(device)
+	}
+
+	/// Erasable sectors of the flash, numbered from 0 in address order
+	pub fn flash_sectors(&self) -> Vec<FlashSector> {
+		let mut sectors = Vec::new();
+
+		for (i, &(size, offset)) in self.sectors.iter().enumerate() {
+			let end = self.sectors.get(i + 1).map(|s| s.1).unwrap_or(self.size);
+
+			let mut o = offset;
+			while o < end {
+				sectors.push(FlashSector { index: sectors.len(), address: self.address + o, size });
+				o += size;
+			}
+		}
+
+		sectors
+	}
+}
+
+impl std::fmt::Display for FlashDevice {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}: {} kB at 0x{:08X} in pages of {} bytes", self.name, self.size / 1024, self.address, self.page_size)
+	}
+}
+
+
+/// Flash algorithm loaded from a .FLM file
+#[derive(Debug, Clone)]
+pub struct FlashAlgorithm {
+	pub device: FlashDevice,
+	/// Code and data of the algorithm, zero initialised data included
+	pub image: Vec<u8>,
+	/// Offset of the data in the image, the static base of the functions
+	pub data: u32,
+
+	/// Offsets of the functions in the image
+	pub init: Option<u32>,
+	pub uninit: Option<u32>,
+	pub erase_chip: Option<u32>,
+	pub erase_sector: u32,
+	pub program_page: u32,
+}
+
+impl FlashAlgorithm {
+	/// Load the flash algorithm in the file at `path`
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LinkError> {
+		let mut data = Vec::new();
+		std::fs::File::open(path.as_ref())?.read_to_end(&mut data)?;
+
+		Self::parse(&data)
+	}
+
+	/// Load the flash algorithm in the ELF file `data`
+	pub fn parse(data: &[u8]) -> Result<Self, LinkError> {
+		use elf::types::{ SHF_ALLOC, SHF_WRITE, SHT_NOBITS, SHT_SYMTAB, STT_FUNC };
+
+		let file = match elf::File::open_stream(&mut std::io::Cursor::new(data)) {
+			Ok(f) => f,
+			Err(e) => {
+				error!("Flash algorithm. Could not parse the ELF file: {:?}", e);
+				return Err(LinkError::Algorithm("not an ELF file"));
+			},
+		};
+
+		let symbols = match file.sections.iter().find(|s| s.shdr.shtype == SHT_SYMTAB).map(|s| file.get_symbols(s)) {
+			Some(Ok(symbols)) => symbols,
+			_ => {
+				error!("Flash algorithm. The ELF file has no symbol table.");
+				return Err(LinkError::Algorithm("no symbol table"));
+			},
+		};
+
+		// The functions are Thumb code, with the low bit of their address set
+		let function = |name: &str| symbols.iter()
+			.find(|s| s.name == name && s.symtype == STT_FUNC)
+			.map(|s| s.value as u32 & !1);
+
+		// Descriptor of the flash, in a section that is not loaded in the target
+		let descriptor = match symbols.iter().find(|s| s.name == "FlashDevice") {
+			Some(s) => s,
+			None => {
+				error!("Flash algorithm. The ELF file has no FlashDevice descriptor.");
+				return Err(LinkError::Algorithm("no FlashDevice descriptor"));
+			},
+		};
+
+		let device = match file.sections.get(descriptor.shndx as usize) {
+			Some(section) if descriptor.value >= section.shdr.addr && descriptor.value < section.shdr.addr + section.data.len() as u64 => {
+				FlashDevice::parse(&section.data[(descriptor.value - section.shdr.addr) as usize..])?
+			},
+			_ => {
+				error!("Flash algorithm. The FlashDevice descriptor is not in a section of the file.");
+				return Err(LinkError::Algorithm("FlashDevice outside of the sections"));
+			},
+		};
+
+		// Code and data, without the section of the descriptor
+		let loaded = file.sections.iter().enumerate()
+			.filter(|&(i, s)| i != descriptor.shndx as usize && s.shdr.flags.0 & SHF_ALLOC.0 != 0 && s.shdr.size != 0)
+			.map(|(_, s)| s)
+			.collect::<Vec<_>>();
+
+		let size = loaded.iter().map(|s| s.shdr.addr + s.shdr.size).max().unwrap_or(0) as usize;
+		if size > MAX_IMAGE {
+			error!("Flash algorithm. The code and data end at 0x{:X}, beyond any SRAM.", size);
+			return Err(LinkError::Algorithm("code and data too large"));
+		}
+
+		let mut image = vec![0; size];
+
+		for section in loaded.iter().filter(|s| s.shdr.shtype != SHT_NOBITS) {
+			debug!("Flash algorithm section {} : {} bytes at offset 0x{:X}", section.shdr.name, section.data.len(), section.shdr.addr);
+			let offset = section.shdr.addr as usize;
+			image[offset..offset + section.data.len()].copy_from_slice(&section.data);
+		}
+
+		let data = loaded.iter()
+			.filter(|s| s.shdr.flags.0 & SHF_WRITE.0 != 0)
+			.map(|s| s.shdr.addr as u32)
+			.min()
+			.unwrap_or(size as u32);
+
+		let algorithm = Self {
+			device,
+			image,
+			data,
+
+			init: function("Init"),
+			uninit: function("UnInit"),
+			erase_chip: function("EraseChip"),
+			erase_sector: function("EraseSector").ok_or(LinkError::Algorithm("no EraseSector function"))?,
+			program_page: function("ProgramPage").ok_or(LinkError::Algorithm("no ProgramPage function"))?,
+		};
+
+		info!("Flash algorithm for {}", algorithm.device);
+
+		Ok(algorithm)
+	}
+}
+
@@ -686,4 +686,20 @@ pub fn get_chip_from_id_u32(id: u32) -> ChipParams {
 			_ => panic!("Corrupted program data. - STMChip is a `static` but the last element wasn't found")
 		},
 	}
-}
\ No newline at end of file
+}
+
+/// Size of the data EEPROM of the L0/L1 chips, 0 for the chips without one
+pub fn data_eeprom_size(id: u32) -> u32 {
+	match id {
+		id if id == STM32ChipID::L011 as u32         => 0x200,   // 512 B
+		id if id == STM32ChipID::L0Cat2 as u32       => 0x400,   // 1 kB
+		id if id == STM32ChipID::L0 as u32           => 0x800,   // 2 kB
+		id if id == STM32ChipID::L0Cat5 as u32       => 0x1800,  // 6 kB
+		id if id == STM32ChipID::L1Medium as u32     => 0x1000,  // 4 kB
+		id if id == STM32ChipID::L1Cat2 as u32       => 0x1000,  // 4 kB
+		id if id == STM32ChipID::L1MediumPlus as u32 => 0x2000,  // 8 kB
+		id if id == STM32ChipID::L1High as u32       => 0x3000,  // 12 kB
+		id if id == STM32ChipID::L152RE as u32       => 0x4000,  // 16 kB
+		_ => 0,
+	}
+}
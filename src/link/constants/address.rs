@@ -2,4 +2,9 @@
 
 pub mod STM32 {
 	pub const CPUID: u32 = 0xE000ED00;
+
+	/// DBGMCU IDCODE register of the Cortex-M3/M4/M7 devices
+	pub const DBGMCU_IDCODE: u32 = 0xE0042000;
+	/// DBGMCU IDCODE register of the Cortex-M0/M0+ devices (F0, L0, G0)
+	pub const DBGMCU_IDCODE_M0: u32 = 0x40015800;
 }
\ No newline at end of file
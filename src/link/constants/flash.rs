@@ -25,6 +25,360 @@ pub mod cr {
 	pub const PSIZE_32 : u32 = (2 <<  8);
 	pub const PSIZE_64 : u32 = (3 <<  8);
 	pub const LOCK     : u32 = (1 << 31);
+
+	/// Mask of the PSIZE field
+	pub const PSIZE    : u32 = 3 <<  8;
+	/// Position of the sector number (SNB) field
+	pub const SNB_SHIFT: u32 = 3;
+}
+
+pub mod sr {
+	pub const EOP      : u32 = 1 <<  0;
+	pub const OPERR    : u32 = 1 <<  1;
+	pub const WRPERR   : u32 = 1 <<  4;
+	pub const PGAERR   : u32 = 1 <<  5;
+	pub const PGPERR   : u32 = 1 <<  6;
+	pub const PGSERR   : u32 = 1 <<  7;
+	/// RDERR for f42x/43x and f7
+	pub const RDERR    : u32 = 1 <<  8;
+	pub const BSY      : u32 = 1 << 16;
+
+	/// All the error flags
+	pub const ERRORS   : u32 = OPERR | WRPERR | PGAERR | PGPERR | PGSERR | RDERR;
+}
+
+pub mod optcr {
+	pub const OPTLOCK  : u32 = 1 <<  0;
+	pub const OPTSTRT  : u32 = 1 <<  1;
+	/// Mask of the BOR_LEV field
+	pub const BOR_LEV  : u32 = 3 <<  2;
+	/// BFB2 for f42x/43x, WWDG_SW for f7
+	pub const BFB2     : u32 = 1 <<  4;
+	pub const WDG_SW   : u32 = 1 <<  5;
+	pub const NRST_STOP: u32 = 1 <<  6;
+	pub const NRST_STDBY: u32 = 1 <<  7;
+	/// Dual bank mode of 1 MB f42x/43x devices
+	pub const DB1M     : u32 = 1 << 30;
+	/// Single bank mode of f76x/77x devices, dual bank when cleared
+	pub const NDBANK   : u32 = 1 << 29;
+	/// nWRP selects the PCROP sectors on f401/f411, f42x/43x and f446
+	pub const SPRMOD   : u32 = 1 << 31;
+
+	/// Position of the BOR_LEV field
+	pub const BOR_LEV_SHIFT: u32 = 2;
+	/// Position of the RDP byte
+	pub const RDP_SHIFT: u32 = 8;
+	/// Position of the nWRP field
+	pub const NWRP_SHIFT: u32 = 16;
+	/// Mask of the nWRP field, after the shift
+	pub const NWRP     : u32 = 0xFFF;
+}
+
+pub mod optcr1 {
+	/// Position of the nWRP field of the second bank of f42x/43x
+	pub const NWRP_SHIFT: u32 = 16;
+	/// Position of BOOT_ADD1 on f7, BOOT_ADD0 is in the low half-word
+	pub const BOOT_ADD1_SHIFT: u32 = 16;
+}
+
+/// Flash controller of the F0/F1/F3 devices
+/// XL-density F1 devices have a second set of registers for the second bank
+pub mod f1 {
+	pub mod register {
+		pub const BASE    : u32 = 0x40022000;
+		pub const ACR     : u32 = 0x40022000;
+		pub const KEYR    : u32 = 0x40022004;
+		pub const OPTKEYR : u32 = 0x40022008;
+		pub const SR      : u32 = 0x4002200C;
+		pub const CR      : u32 = 0x40022010;
+		pub const AR      : u32 = 0x40022014;
+		pub const OBR     : u32 = 0x4002201C;
+		pub const WRPR    : u32 = 0x40022020;
+
+		pub const KEYR2   : u32 = 0x40022044;
+		pub const SR2     : u32 = 0x4002204C;
+		pub const CR2     : u32 = 0x40022050;
+		pub const AR2     : u32 = 0x40022054;
+	}
+
+	pub mod cr {
+		pub const PG       : u32 = 1 <<  0;
+		pub const PER      : u32 = 1 <<  1;
+		pub const MER      : u32 = 1 <<  2;
+		pub const OPTPG    : u32 = 1 <<  4;
+		pub const OPTER    : u32 = 1 <<  5;
+		pub const STRT     : u32 = 1 <<  6;
+		pub const LOCK     : u32 = 1 <<  7;
+		pub const OPTWRE   : u32 = 1 <<  9;
+		/// OBL_LAUNCH for F0/F3
+		pub const OBL_LAUNCH: u32 = 1 << 13;
+	}
+
+	pub mod sr {
+		pub const BSY      : u32 = 1 <<  0;
+		pub const PGERR    : u32 = 1 <<  2;
+		pub const WRPRTERR : u32 = 1 <<  4;
+		pub const EOP      : u32 = 1 <<  5;
+
+		/// All the error flags
+		pub const ERRORS   : u32 = PGERR | WRPRTERR;
+	}
+
+	pub mod obr {
+		pub const OPTERR   : u32 = 1 <<  0;
+		/// RDPRT of F1, RDPRT[1:0] from this bit on F0/F3
+		pub const RDPRT    : u32 = 1 <<  1;
+
+		/// Position of the user byte on F1, the data bytes follow it
+		pub const USER_SHIFT: u32 = 2;
+		/// Position of the user byte on F0/F3, the data bytes follow it
+		pub const F0_USER_SHIFT: u32 = 8;
+	}
+
+	/// Bits of the user option byte
+	pub mod user {
+		pub const WDG_SW   : u8 = 1 << 0;
+		pub const NRST_STOP: u8 = 1 << 1;
+		pub const NRST_STDBY: u8 = 1 << 2;
+	}
+
+	/// Option bytes, each as a half-word with its complement in the high byte
+	pub mod option {
+		pub const RDP      : u32 = 0x1FFFF800;
+		pub const USER     : u32 = 0x1FFFF802;
+		pub const DATA0    : u32 = 0x1FFFF804;
+		pub const DATA1    : u32 = 0x1FFFF806;
+		pub const WRP0     : u32 = 0x1FFFF808;
+	}
+
+	/// RDP byte of level 0 on F1, 0xAA on F0/F3
+	pub const RDP_KEY    : u8 = 0xA5;
+
+	/// Size of the first bank of XL-density devices
+	pub const BANK1_SIZE : u32 = 512 * 1024;
+}
+
+/// Non-volatile memory interface of the L0/L1 devices
+/// The registers are at the same offsets on both, from a different base
+pub mod l0 {
+	/// Base of the registers on L0 devices
+	pub const L0_BASE  : u32 = 0x40022000;
+	/// Base of the registers on L1 devices
+	pub const L1_BASE  : u32 = 0x40023C00;
+
+	/// Offsets of the registers from the base
+	pub mod register {
+		pub const ACR     : u32 = 0x00;
+		pub const PECR    : u32 = 0x04;
+		pub const PDKEYR  : u32 = 0x08;
+		pub const PEKEYR  : u32 = 0x0C;
+		pub const PRGKEYR : u32 = 0x10;
+		pub const OPTKEYR : u32 = 0x14;
+		pub const SR      : u32 = 0x18;
+		pub const OPTR    : u32 = 0x1C;
+		pub const WRPROT1 : u32 = 0x20;
+		/// WRPROT3 and WRPROT4 of L1 devices follow it
+		pub const WRPROT2 : u32 = 0x80;
+	}
+
+	pub mod pecr {
+		pub const PELOCK   : u32 = 1 <<  0;
+		pub const PRGLOCK  : u32 = 1 <<  1;
+		pub const OPTLOCK  : u32 = 1 <<  2;
+		pub const PROG     : u32 = 1 <<  3;
+		pub const DATA     : u32 = 1 <<  4;
+		/// FIX on L0, FTDW on L1
+		pub const FIX      : u32 = 1 <<  8;
+		pub const ERASE    : u32 = 1 <<  9;
+		pub const FPRG     : u32 = 1 << 10;
+		pub const OBL_LAUNCH: u32 = 1 << 18;
+	}
+
+	pub mod sr {
+		pub const BSY      : u32 = 1 <<  0;
+		pub const EOP      : u32 = 1 <<  1;
+		pub const WRPERR   : u32 = 1 <<  8;
+		pub const PGAERR   : u32 = 1 <<  9;
+		pub const SIZERR   : u32 = 1 << 10;
+		pub const OPTVERR  : u32 = 1 << 11;
+		/// RDERR, NOTZEROERR and FWWERR for L0
+		pub const RDERR    : u32 = 1 << 13;
+		pub const NOTZEROERR: u32 = 1 << 16;
+		pub const FWWERR   : u32 = 1 << 17;
+
+		/// All the error flags
+		pub const ERRORS   : u32 = WRPERR | PGAERR | SIZERR | OPTVERR | RDERR | NOTZEROERR | FWWERR;
+	}
+
+	pub mod optr {
+		/// Mask of the RDP byte
+		pub const RDP      : u32 = 0xFF;
+		/// WPRMOD on L0, SPRMOD on L1: the WRPROT bits select the PCROP sectors
+		pub const WPRMOD   : u32 = 1 <<  8;
+		/// IWDG_SW on L1
+		pub const WDG_SW   : u32 = 1 << 20;
+		pub const NRST_STOP: u32 = 1 << 21;
+		pub const NRST_STDBY: u32 = 1 << 22;
+
+		/// Position of the BOR_LEV field
+		pub const BOR_LEV_SHIFT: u32 = 16;
+		/// Mask of the BOR_LEV field, after the shift
+		pub const BOR_LEV  : u32 = 0xF;
+		/// BOR_LEV of level 1, up to level 5. Lower values turn the BOR off.
+		pub const BOR_LEVEL1: u32 = 0x8;
+	}
+
+	/// Option bytes, each word holding a half-word and its complement in the high half-word
+	pub mod option {
+		/// RDP and WPRMOD, loaded into OPTR[15:0]
+		pub const RDP      : u32 = 0x1FF80000;
+		/// User bits, loaded into OPTR[31:16]
+		pub const USER     : u32 = 0x1FF80004;
+		/// Low half-word of WRPROT1, the high half-word follows it
+		pub const WRPROT1  : u32 = 0x1FF80008;
+		/// WRPROT2 of L0 devices
+		pub const L0_WRPROT2: u32 = 0x1FF80010;
+		/// WRPROT2 of L1 devices, WRPROT3 and WRPROT4 follow it
+		pub const L1_WRPROT2: u32 = 0x1FF80080;
+	}
+
+	pub mod misc {
+		/// PECR unlock key 1
+		pub const PEKEY1   : u32 = 0x89ABCDEF;
+		/// PECR unlock key 2
+		pub const PEKEY2   : u32 = 0x02030405;
+		/// Program memory unlock key 1
+		pub const PRGKEY1  : u32 = 0x8C9DAEBF;
+		/// Program memory unlock key 2
+		pub const PRGKEY2  : u32 = 0x13141516;
+		/// Option bytes unlock key 1
+		pub const OPTKEY1  : u32 = 0xFBEAD9C8;
+		/// Option bytes unlock key 2
+		pub const OPTKEY2  : u32 = 0x24252627;
+	}
+
+	/// Start of the data EEPROM
+	pub const EEPROM_BASE: u32 = 0x0808_0000;
+}
+
+/// Flash controller of the L4/G0/WB devices
+/// The registers are at the same offsets on all of them, from a different base on WB
+pub mod l4 {
+	/// Base of the registers on L4 and G0 devices
+	pub const L4_BASE  : u32 = 0x40022000;
+	/// Base of the registers on WB devices
+	pub const WB_BASE  : u32 = 0x58004000;
+
+	/// Offsets of the registers from the base
+	pub mod register {
+		pub const ACR      : u32 = 0x00;
+		pub const KEYR     : u32 = 0x08;
+		pub const OPTKEYR  : u32 = 0x0C;
+		pub const SR       : u32 = 0x10;
+		pub const CR       : u32 = 0x14;
+		pub const ECCR     : u32 = 0x18;
+		pub const OPTR     : u32 = 0x20;
+		pub const PCROP1SR : u32 = 0x24;
+		pub const PCROP1ER : u32 = 0x28;
+		pub const WRP1AR   : u32 = 0x2C;
+		pub const WRP1BR   : u32 = 0x30;
+		pub const PCROP2SR : u32 = 0x44;
+		pub const PCROP2ER : u32 = 0x48;
+		pub const WRP2AR   : u32 = 0x4C;
+		pub const WRP2BR   : u32 = 0x50;
+		/// Secure flash start address of WB devices
+		pub const SFR      : u32 = 0x80;
+	}
+
+	pub mod cr {
+		pub const PG       : u32 = 1 <<  0;
+		pub const PER      : u32 = 1 <<  1;
+		pub const MER1     : u32 = 1 <<  2;
+		/// Second bank of dual bank L4 devices
+		pub const BKER     : u32 = 1 << 11;
+		pub const MER2     : u32 = 1 << 15;
+		pub const STRT     : u32 = 1 << 16;
+		pub const OPTSTRT  : u32 = 1 << 17;
+		pub const FSTPG    : u32 = 1 << 18;
+		pub const OBL_LAUNCH: u32 = 1 << 27;
+		pub const OPTLOCK  : u32 = 1 << 30;
+		pub const LOCK     : u32 = 1 << 31;
+
+		/// Position of the page number (PNB) field
+		pub const PNB_SHIFT: u32 = 3;
+	}
+
+	pub mod sr {
+		pub const EOP      : u32 = 1 <<  0;
+		pub const OPERR    : u32 = 1 <<  1;
+		pub const PROGERR  : u32 = 1 <<  3;
+		pub const WRPERR   : u32 = 1 <<  4;
+		pub const PGAERR   : u32 = 1 <<  5;
+		pub const SIZERR   : u32 = 1 <<  6;
+		pub const PGSERR   : u32 = 1 <<  7;
+		pub const MISERR   : u32 = 1 <<  8;
+		pub const FASTERR  : u32 = 1 <<  9;
+		pub const RDERR    : u32 = 1 << 14;
+		pub const OPTVERR  : u32 = 1 << 15;
+		pub const BSY      : u32 = 1 << 16;
+		/// CFGBSY for G0 and WB
+		pub const CFGBSY   : u32 = 1 << 18;
+		/// Operations suspended by CPU2 on WB
+		pub const PESD     : u32 = 1 << 19;
+
+		/// All the error flags
+		pub const ERRORS   : u32 = OPERR | PROGERR | WRPERR | PGAERR | SIZERR | PGSERR | MISERR | FASTERR | RDERR | OPTVERR;
+	}
+
+	pub mod optr {
+		/// Mask of the RDP byte
+		pub const RDP      : u32 = 0xFF;
+		/// nRST_STOP, nRST_STDBY and nRST_SHDW are one bit higher on G0
+		pub const NRST_STOP: u32 = 1 << 12;
+		pub const NRST_STDBY: u32 = 1 << 13;
+		pub const NRST_SHDW: u32 = 1 << 14;
+		pub const IWDG_SW  : u32 = 1 << 16;
+		pub const IWDG_STOP: u32 = 1 << 17;
+		pub const IWDG_STDBY: u32 = 1 << 18;
+		pub const WWDG_SW  : u32 = 1 << 19;
+		/// Boot from bank 2 of dual bank L4 devices
+		pub const BFB2     : u32 = 1 << 20;
+		/// Dual bank mode of L47x/L48x and L49x/L4Ax devices
+		pub const DUALBANK : u32 = 1 << 21;
+		/// Dual bank mode of L4Rx/L4Sx devices
+		pub const DBANK    : u32 = 1 << 22;
+
+		/// Position of the BOR_LEV field on L4, 9 on WB
+		pub const BOR_LEV_SHIFT: u32 = 8;
+		/// Mask of the BOR_LEV field, after the shift
+		pub const BOR_LEV  : u32 = 0x7;
+	}
+
+	/// Write protection area registers (WRP1AR, WRP1BR, WRP2AR and WRP2BR)
+	pub mod wrp {
+		/// Mask of the start and end page fields, after the shift
+		pub const PAGE     : u32 = 0xFF;
+		/// Position of the end page field
+		pub const END_SHIFT: u32 = 16;
+	}
+
+	/// PCROP start and end address registers of L4 devices
+	pub mod pcrop {
+		/// Mask of the offset from the bank, in double words
+		pub const OFFSET   : u32 = 0xFFFF;
+	}
+
+	pub mod sfr {
+		/// Page number of the start of the CPU2 secure area
+		pub const SFSA     : u32 = 0xFF;
+		/// Secure area disabled
+		pub const FSD      : u32 = 1 <<  8;
+	}
+
+	/// Bytes of a fast programming row on L4 and G0
+	pub const ROW      : u32 = 256;
+	/// Bytes of a fast programming row on WB
+	pub const WB_ROW   : u32 = 512;
 }
 
 pub mod misc {
@@ -8,6 +8,14 @@ pub mod TIMEOUT {
 }
 
 
+pub mod RETRY {
+	/// Max number of times a command is repeated after a WAIT answer
+	pub const MAX_WAIT : usize = 8;
+	/// Base delay between retries, doubled on each retry
+	pub const BACKOFF  : std::time::Duration = std::time::Duration::from_millis(1);
+}
+
+
 pub mod SIZE {
 	pub const SG        : usize = (31);
 	pub const DATA      : usize = (4096);
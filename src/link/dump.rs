@@ -0,0 +1,253 @@
+//! Streaming memory dumps
+//! A `Dump` reads a memory range in chunks and writes each chunk to any
+//! `std::io::Write` as soon as it is read, so dumps of any size do not have to
+//! fit in memory. The dump keeps track of the bytes already written: after a
+//! transient error the same `Dump` is passed again to `Link::dump` and it
+//! resumes at the first chunk not written.
+
+use std::io::Write;
+
+use super::error::LinkError;
+
+
+/// Default number of bytes read and written at once
+pub const CHUNK: usize = 0x4000;
+
+/// Size of the ELF header and the single program header of an ELF core dump
+const ELF_HEADERS: usize = 52 + 32;
+
+/// Bytes in an Intel HEX data record
+const HEX_RECORD: usize = 16;
+
+
+/// File format of a dump
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+	/// The bytes of the range as they are
+	Raw,
+	/// Intel HEX records with extended linear addresses
+	IntelHex,
+	/// 32 bit ARM ELF core file with the range in a single loadable segment
+	ElfCore,
+}
+
+impl DumpFormat {
+	/// Format usually associated with the file extension of `path`
+	/// Unknown extensions are dumped raw
+	pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Self {
+		match path.as_ref().extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()) {
+			Some(ref e) if e == "hex" || e == "ihex" => DumpFormat::IntelHex,
+			Some(ref e) if e == "elf" || e == "core" => DumpFormat::ElfCore,
+			_ => DumpFormat::Raw,
+		}
+	}
+
+	/// Bytes written before the data of the range
+	pub fn header_size(&self) -> usize {
+		match *self {
+			DumpFormat::ElfCore => ELF_HEADERS,
+			_ => 0,
+		}
+	}
+}
+
+
+/// Receives the progress of a dump
+pub trait Progress {
+	/// Called after each chunk with the number of bytes done out of `total`
+	fn update(&mut self, done: usize, total: usize);
+}
+
+impl<F: FnMut(usize, usize)> Progress for F {
+	fn update(&mut self, done: usize, total: usize) {
+		self(done, total)
+	}
+}
+
+
+/// Dump of a memory range in progress
+pub struct Dump<W: Write> {
+	address: u32,
+	size: usize,
+	format: DumpFormat,
+	output: W,
+
+	/// Bytes of the range already written
+	offset: usize,
+	/// Bytes read at once
+	chunk: usize,
+	/// The headers of the format are written
+	started: bool,
+
+	/// Upper 16 bits of the last Intel HEX extended linear address record
+	upper: Option<u16>,
+}
+
+impl<W: Write> Dump<W> {
+	/// Dump `size` bytes at `address` into `output`
+	pub fn new(address: u32, size: usize, format: DumpFormat, output: W) -> Self {
+		Self::resume(address, size, format, output, 0)
+	}
+
+	/// Continue a dump of which the first `offset` bytes are already in `output`
+	/// The output must hold exactly what was written for those bytes, as is the
+	/// case for a file truncated by an interrupted dump.
+	pub fn resume(address: u32, size: usize, format: DumpFormat, output: W, offset: usize) -> Self {
+		Self { address, size, format, output, offset: std::cmp::min(offset, size), chunk: CHUNK, started: offset > 0, upper: None }
+	}
+
+	/// Read `chunk` bytes at once instead of the default `CHUNK`
+	pub fn with_chunk(mut self, chunk: usize) -> Self {
+		self.chunk = std::cmp::max(chunk, 4);
+		self
+	}
+
+	/// Start address of the range
+	pub fn address(&self) -> u32 {
+		self.address
+	}
+
+	/// Size of the range
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	/// Format of the output
+	pub fn format(&self) -> DumpFormat {
+		self.format
+	}
+
+	/// Bytes of the range already written
+	pub fn offset(&self) -> usize {
+		self.offset
+	}
+
+	/// Check if the whole range was written
+	pub fn done(&self) -> bool {
+		self.offset == self.size
+	}
+
+	/// Output written so far
+	pub fn output(&self) -> &W {
+		&self.output
+	}
+
+	/// Output of the dump
+	pub fn into_inner(self) -> W {
+		self.output
+	}
+
+	/// Next range to read, the chunks after the first are aligned to the chunk size
+	pub(crate) fn next_chunk(&self) -> (u32, usize) {
+		let address = self.address as usize + self.offset;
+		let size = std::cmp::min(self.chunk - (address % self.chunk), self.size - self.offset);
+
+		(address as u32, size)
+	}
+
+	/// Write the headers that come before the data
+	pub(crate) fn begin(&mut self) -> Result<(), LinkError> {
+		if self.started {
+			return Ok(());
+		}
+
+		if self.format == DumpFormat::ElfCore {
+			self.elf_headers()?;
+		}
+
+		self.started = true;
+
+		Ok(())
+	}
+
+	/// Write the next chunk of the range
+	pub(crate) fn write(&mut self, data: &[u8]) -> Result<(), LinkError> {
+		let address = self.address.wrapping_add(self.offset as u32);
+
+		match self.format {
+			DumpFormat::Raw | DumpFormat::ElfCore => self.output.write_all(data)?,
+			DumpFormat::IntelHex => self.hex_data(address, data)?,
+		}
+
+		self.offset += data.len();
+
+		Ok(())
+	}
+
+	/// Write the trailer of the format and flush the output
+	pub(crate) fn finish(&mut self) -> Result<(), LinkError> {
+		if self.format == DumpFormat::IntelHex {
+			self.hex_record(0x01, 0, &[])?;
+		}
+
+		Ok(self.output.flush()?)
+	}
+
+	/// Data records of a chunk, with an extended linear address record each
+	/// time the upper 16 bits of the address change
+	fn hex_data(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		let mut i = 0;
+
+		while i < data.len() {
+			let a = address.wrapping_add(i as u32);
+			let upper = (a >> 16) as u16;
+
+			if self.upper != Some(upper) {
+				self.hex_record(0x04, 0, &upper.to_be_bytes())?;
+				self.upper = Some(upper);
+			}
+
+			// Records do not cross a 64 kB boundary
+			let n = std::cmp::min(std::cmp::min(HEX_RECORD, data.len() - i), 0x10000 - (a as usize & 0xFFFF));
+
+			self.hex_record(0x00, a as u16, &data[i..i + n])?;
+			i += n;
+		}
+
+		Ok(())
+	}
+
+	/// Write an Intel HEX record
+	fn hex_record(&mut self, kind: u8, address: u16, data: &[u8]) -> Result<(), LinkError> {
+		let mut line = format!(":{:02X}{:04X}{:02X}", data.len(), address, kind);
+		let mut sum = (data.len() as u8).wrapping_add((address >> 8) as u8).wrapping_add(address as u8).wrapping_add(kind);
+
+		for b in data {
+			line += &format!("{:02X}", b);
+			sum = sum.wrapping_add(*b);
+		}
+
+		Ok(writeln!(self.output, "{}{:02X}", line, sum.wrapping_neg())?)
+	}
+
+	/// ELF header and program header of a core file with a single segment
+	fn elf_headers(&mut self) -> Result<(), LinkError> {
+		let mut h = Vec::with_capacity(ELF_HEADERS);
+
+		// Identification : ELF32, little endian, version 1
+		h.extend_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+		// Core file for ARM, version 1
+		h.extend_from_slice(&4u16.to_le_bytes());
+		h.extend_from_slice(&40u16.to_le_bytes());
+		h.extend_from_slice(&1u32.to_le_bytes());
+		// Entry, program header offset, section header offset, flags (EABI 5)
+		h.extend_from_slice(&0u32.to_le_bytes());
+		h.extend_from_slice(&52u32.to_le_bytes());
+		h.extend_from_slice(&0u32.to_le_bytes());
+		h.extend_from_slice(&0x0500_0000u32.to_le_bytes());
+		// Header sizes, one program header and no sections
+		h.extend_from_slice(&52u16.to_le_bytes());
+		h.extend_from_slice(&32u16.to_le_bytes());
+		h.extend_from_slice(&1u16.to_le_bytes());
+		h.extend_from_slice(&40u16.to_le_bytes());
+		h.extend_from_slice(&0u16.to_le_bytes());
+		h.extend_from_slice(&0u16.to_le_bytes());
+
+		// PT_LOAD segment with the data right after the headers, readable and writable
+		for word in [1, ELF_HEADERS as u32, self.address, self.address, self.size as u32, self.size as u32, 0x6, 4].iter() {
+			h.extend_from_slice(&word.to_le_bytes());
+		}
+
+		Ok(self.output.write_all(&h)?)
+	}
+}
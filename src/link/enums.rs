@@ -73,6 +73,76 @@ pub enum FlashType {
 	Unknown,
 }
 
+/// Width of the flash program and erase operations (PSIZE of F2/F4/F7 devices)
+/// The allowed width depends on the supply voltage, 64 bit needs an external VPP
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parallelism {
+	/// 1.8 V to 2.1 V
+	X8,
+	/// 2.1 V to 2.7 V
+	X16,
+	/// 2.7 V to 3.6 V
+	X32,
+	/// External VPP, for the erase. The flash is still programmed by words.
+	X64,
+}
+
+impl Parallelism {
+	/// Widest parallelism allowed at the supply voltage `v`
+	pub fn from_voltage(v: f32) -> Self {
+		if v < 2.1 {
+			Parallelism::X8
+		} else if v < 2.7 {
+			Parallelism::X16
+		} else {
+			Parallelism::X32
+		}
+	}
+
+	/// Width in bytes
+	pub fn bytes(&self) -> usize {
+		match *self {
+			Parallelism::X8  => 1,
+			Parallelism::X16 => 2,
+			Parallelism::X32 => 4,
+			Parallelism::X64 => 8,
+		}
+	}
+}
+
+/// Protection of a flash sector
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protection {
+	/// Cannot be erased or programmed (nWRP, WRP areas)
+	Write,
+	/// Cannot be read, erased or programmed, only executed (PCROP)
+	Pcrop,
+}
+
+impl std::fmt::Display for Protection {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			Protection::Write => write!(f, "write protected"),
+			Protection::Pcrop => write!(f, "PCROP protected"),
+		}
+	}
+}
+
+/// Selection of a probe among all the connected ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeSelector {
+	/// First probe that can be opened
+	First,
+	/// Probe with the given iSerial string
+	Serial(String),
+	/// Probe at the given USB bus number and device address
+	Path { bus: u8, address: u8 },
+	/// Probe at the given USB bus and port numbers, which do not change when the probe is reconnected
+	Port { bus: u8, ports: Vec<u8> },
+	/// Probe at the given position of the enumeration list
+	Index(usize),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Cmd {
 	Int8(u8),
@@ -0,0 +1,291 @@
+//! Errors returned by the STLink interface
+
+use super::enums::{ STLinkMode, DebugMode };
+
+/// Errors reported by the STLink in the status byte of a debug command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatusError {
+	/// Generic debug fault (`ERR_FAULT`)
+	Fault,
+
+	/// The AP answered WAIT
+	ApWait,
+	/// The AP answered FAULT
+	ApFault,
+	/// The AP transaction failed
+	ApError,
+	/// Parity error in the AP transaction
+	ApParityError,
+
+	/// The DP answered WAIT
+	DpWait,
+	/// The DP answered FAULT
+	DpFault,
+	/// The DP transaction failed
+	DpError,
+	/// Parity error in the DP transaction
+	DpParityError,
+
+	/// Error while writing data to the AP
+	ApWDataError,
+	/// The AP sticky error flag is set
+	ApStickyError,
+	/// The AP sticky overrun flag is set
+	ApStickyOverrunError,
+	/// The selected AP does not exist
+	BadAp,
+
+	/// Status code not known by this library
+	Unknown(u8),
+}
+
+impl StatusError {
+	/// Decode a status byte returned by the STLink.
+	/// Returns `None` if the status is `ERR_OK`.
+	pub fn from_code(code: u8) -> Option<Self> {
+		use super::constants::commands::{ debug::{ ERR_OK, ERR_FAULT }, swd::*, STLINK_BAD_AP_ERROR };
+
+		match code {
+			ERR_OK              => None,
+			ERR_FAULT           => Some(StatusError::Fault),
+			AP_WAIT             => Some(StatusError::ApWait),
+			AP_FAULT            => Some(StatusError::ApFault),
+			AP_ERROR            => Some(StatusError::ApError),
+			AP_PARITY_ERROR     => Some(StatusError::ApParityError),
+			DP_WAIT             => Some(StatusError::DpWait),
+			DP_FAULT            => Some(StatusError::DpFault),
+			DP_ERROR            => Some(StatusError::DpError),
+			DP_PARITY_ERROR     => Some(StatusError::DpParityError),
+			AP_WDATA_ERROR      => Some(StatusError::ApWDataError),
+			AP_STICKY_ERROR     => Some(StatusError::ApStickyError),
+			AP_STICKYORUN_ERROR => Some(StatusError::ApStickyOverrunError),
+			c if c as u32 == STLINK_BAD_AP_ERROR => Some(StatusError::BadAp),
+			c                   => Some(StatusError::Unknown(c)),
+		}
+	}
+
+	/// Raw status byte of this error
+	pub fn code(&self) -> u8 {
+		use super::constants::commands::{ debug::ERR_FAULT, swd::*, STLINK_BAD_AP_ERROR };
+
+		match *self {
+			StatusError::Fault                => ERR_FAULT,
+			StatusError::ApWait               => AP_WAIT,
+			StatusError::ApFault              => AP_FAULT,
+			StatusError::ApError              => AP_ERROR,
+			StatusError::ApParityError        => AP_PARITY_ERROR,
+			StatusError::DpWait               => DP_WAIT,
+			StatusError::DpFault              => DP_FAULT,
+			StatusError::DpError              => DP_ERROR,
+			StatusError::DpParityError        => DP_PARITY_ERROR,
+			StatusError::ApWDataError         => AP_WDATA_ERROR,
+			StatusError::ApStickyError        => AP_STICKY_ERROR,
+			StatusError::ApStickyOverrunError => AP_STICKYORUN_ERROR,
+			StatusError::BadAp                => STLINK_BAD_AP_ERROR as u8,
+			StatusError::Unknown(c)           => c,
+		}
+	}
+}
+
+impl std::fmt::Display for StatusError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			StatusError::Fault                => write!(f, "debug fault"),
+			StatusError::ApWait               => write!(f, "AP wait"),
+			StatusError::ApFault              => write!(f, "AP fault"),
+			StatusError::ApError              => write!(f, "AP error"),
+			StatusError::ApParityError        => write!(f, "AP parity error"),
+			StatusError::DpWait               => write!(f, "DP wait"),
+			StatusError::DpFault              => write!(f, "DP fault"),
+			StatusError::DpError              => write!(f, "DP error"),
+			StatusError::DpParityError        => write!(f, "DP parity error"),
+			StatusError::ApWDataError         => write!(f, "AP write data error"),
+			StatusError::ApStickyError        => write!(f, "AP sticky error"),
+			StatusError::ApStickyOverrunError => write!(f, "AP sticky overrun error"),
+			StatusError::BadAp                => write!(f, "bad AP"),
+			StatusError::Unknown(c)           => write!(f, "unknown status 0x{:02X}", c),
+		}
+	}
+}
+
+
+/// Errors of the flash operations
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlashError {
+	/// The controller refused the sequence of operations (PGSERR)
+	Sequence,
+	/// The access width does not match the programming parallelism (PGPERR)
+	Parallelism,
+	/// The programmed address is not aligned (PGAERR)
+	Alignment,
+	/// The access width is not allowed for the operation (SIZERR of L0/L1)
+	Size,
+	/// The memory is write protected (WRPERR)
+	WriteProtected,
+	/// The operation failed on a sector protected by the option bytes
+	SectorProtected(usize),
+	/// The operation failed (OPERR)
+	Operation,
+	/// Fast programming data arrived too late or in the wrong order (MISERR, FASTERR)
+	FastProgramming,
+	/// The programmed location was not erased (PGERR of F0/F1/F3, NOTZEROERR of L0, PROGERR of L4)
+	NotErased,
+	/// Read of a protected area (RDERR)
+	ReadProtected,
+	/// The controller was still busy after the timeout
+	Busy,
+	/// The sector does not exist
+	Sector(usize),
+	/// The address is not in the flash
+	Address(u32),
+	/// The flash read back differs from the data programmed at the address
+	Verify(u32),
+	/// The flash loader running in the target stopped before programming the address
+	Loader(u32),
+	/// A function of the flash algorithm failed or did not return
+	Algorithm(&'static str),
+}
+
+impl std::fmt::Display for FlashError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			FlashError::Sequence       => write!(f, "programming sequence error"),
+			FlashError::Parallelism    => write!(f, "programming parallelism error"),
+			FlashError::Alignment      => write!(f, "programming alignment error"),
+			FlashError::Size           => write!(f, "programming size error"),
+			FlashError::WriteProtected => write!(f, "write protected"),
+			FlashError::SectorProtected(n) => write!(f, "sector {} is write-protected", n),
+			FlashError::Operation      => write!(f, "operation error"),
+			FlashError::FastProgramming => write!(f, "fast programming error"),
+			FlashError::NotErased      => write!(f, "location not erased before programming"),
+			FlashError::ReadProtected  => write!(f, "read protected"),
+			FlashError::Busy           => write!(f, "controller busy"),
+			FlashError::Sector(n)      => write!(f, "no sector {}", n),
+			FlashError::Address(a)     => write!(f, "address 0x{:08X} is not in the flash", a),
+			FlashError::Verify(a)      => write!(f, "verification failed at 0x{:08X}", a),
+			FlashError::Loader(a)      => write!(f, "flash loader stopped at 0x{:08X}", a),
+			FlashError::Algorithm(n)   => write!(f, "flash algorithm function {} failed", n),
+		}
+	}
+}
+
+
+/// Errors returned by the `Link` methods
+#[derive(Debug)]
+pub enum LinkError {
+	/// Error reported by libusb
+	Usb(rusb::Error),
+	/// No STLink device was found
+	NoProbe,
+	/// The probe has no USB interface with the command (`tx`) and data (`rx`) endpoints
+	Descriptor { tx: u8, rx: u8 },
+	/// A transfer moved a different amount of bytes than requested
+	Transfer { expected: usize, actual: usize },
+
+	/// The STLink reported an error in the status byte
+	Status(StatusError),
+
+	/// The address or size are not aligned to the access width
+	Alignment { address: u32, size: usize, width: usize },
+	/// The transfer is bigger than the probe allows
+	Size { requested: usize, max: usize },
+	/// The address is not in the memory map of the target
+	Unmapped { address: u32 },
+	/// An argument of the operation is not valid
+	Argument(&'static str),
+
+	/// The probe firmware or API version does not support the operation
+	Unsupported(&'static str),
+	/// The STLink is in a mode that does not allow the operation
+	Mode(STLinkMode),
+	/// The debug mode is not supported
+	DebugMode(DebugMode),
+	/// The target voltage is too low to debug
+	Voltage(f32),
+
+	/// The chip ID is not in the chip database
+	UnknownChip(u32),
+	/// The flash could not be unlocked
+	FlashLocked,
+	/// A flash operation failed
+	Flash(FlashError),
+	/// The flash algorithm file is not valid
+	Algorithm(&'static str),
+
+	/// Could not read or write a file
+	Io(std::io::Error),
+	/// The session does not follow the recording being replayed
+	Replay { event: usize },
+}
+
+impl std::fmt::Display for LinkError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			LinkError::Usb(e) => write!(f, "USB error: {}", e),
+			LinkError::NoProbe => write!(f, "no STLink probe found"),
+			LinkError::Descriptor { tx, rx } => write!(f, "probe has no interface with the endpoints 0x{:02X} and 0x{:02X}", tx, rx),
+			LinkError::Transfer { expected, actual } => write!(f, "transferred {} bytes, expected {}", actual, expected),
+
+			LinkError::Status(s) => write!(f, "STLink status: {}", s),
+
+			LinkError::Alignment { address, size, width } => write!(f, "access of {} bytes at 0x{:08X} is not {} byte aligned", size, address, width),
+			LinkError::Size { requested, max } => write!(f, "transfer of {} bytes exceeds the maximum of {} bytes", requested, max),
+			LinkError::Unmapped { address } => write!(f, "address 0x{:08X} is not in the memory map", address),
+			LinkError::Argument(what) => write!(f, "invalid argument: {}", what),
+
+			LinkError::Unsupported(what) => write!(f, "not supported by the probe: {}", what),
+			LinkError::Mode(m) => write!(f, "operation not allowed in {:?} mode", m),
+			LinkError::DebugMode(m) => write!(f, "debug mode {:?} is not supported", m),
+			LinkError::Voltage(v) => write!(f, "target voltage too low: {} V", v),
+
+			LinkError::UnknownChip(id) => write!(f, "unknown chip ID 0x{:03X}", id),
+			LinkError::FlashLocked => write!(f, "flash could not be unlocked"),
+			LinkError::Flash(e) => write!(f, "flash: {}", e),
+			LinkError::Algorithm(what) => write!(f, "invalid flash algorithm: {}", what),
+
+			LinkError::Io(e) => write!(f, "I/O error: {}", e),
+			LinkError::Replay { event } => write!(f, "session diverges from the recording at event {}", event),
+		}
+	}
+}
+
+impl LinkError {
+	/// Check if the error may not happen again when the operation is retried
+	/// Timeouts and lost USB transfers, or the debug port asking to wait
+	pub fn is_transient(&self) -> bool {
+		match self {
+			LinkError::Usb(e) => matches!(e, rusb::Error::Timeout | rusb::Error::Pipe | rusb::Error::Io | rusb::Error::Interrupted | rusb::Error::Overflow),
+			LinkError::Transfer { .. } => true,
+			LinkError::Status(StatusError::ApWait) | LinkError::Status(StatusError::DpWait) => true,
+			_ => false,
+		}
+	}
+}
+
+impl std::error::Error for LinkError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			LinkError::Usb(e) => Some(e),
+			LinkError::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<rusb::Error> for LinkError {
+	fn from(e: rusb::Error) -> Self {
+		LinkError::Usb(e)
+	}
+}
+
+impl From<FlashError> for LinkError {
+	fn from(e: FlashError) -> Self {
+		LinkError::Flash(e)
+	}
+}
+
+impl From<std::io::Error> for LinkError {
+	fn from(e: std::io::Error) -> Self {
+		LinkError::Io(e)
+	}
+}
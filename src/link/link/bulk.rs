@@ -0,0 +1,128 @@
+//! Bulk operations on the target memory
+//! Fill, compare and search ranges of any size, read and written in chunks of
+//! `dump::CHUNK` bytes through `read_memory` and `write_memory`.
+
+use std::ops::Range;
+
+use super::super::dump::CHUNK;
+use super::super::error::LinkError;
+use super::super::structs::Mismatch;
+
+use super::Link;
+use super::super::transport::Transport;
+
+impl<T: Transport> Link<T> {
+	/// Fill `size` bytes at `address` by repeating `pattern`
+	/// The pattern starts at `address`, a single byte pattern behaves as `memset`.
+	pub fn fill_memory(&mut self, address: u32, size: usize, pattern: &[u8]) -> Result<(), LinkError> {
+		if pattern.is_empty() {
+			error!("Fill memory. The pattern is empty.");
+			return Err(LinkError::Argument("empty fill pattern"));
+		}
+
+		self.check_access(address, size)?;
+
+		let mut offset = 0;
+
+		while offset < size {
+			let n = std::cmp::min(CHUNK, size - offset);
+			let data: Vec<u8> = (offset..offset + n).map(|i| pattern[i % pattern.len()]).collect();
+
+			self.write_memory(address + offset as u32, &data)?;
+			offset += n;
+		}
+
+		Ok(())
+	}
+
+	/// Compare the memory at `address` with `expected`
+	/// Returns the runs of differing bytes in address order, empty if the memory matches
+	pub fn compare_memory(&mut self, address: u32, expected: &[u8]) -> Result<Vec<Mismatch>, LinkError> {
+		self.check_access(address, expected.len())?;
+
+		let mut mismatches: Vec<Mismatch> = Vec::new();
+
+		for (c, chunk) in expected.chunks(CHUNK).enumerate() {
+			let base = address + (c * CHUNK) as u32;
+			let actual = self.read_memory(base, chunk.len())?;
+
+			for (i, (e, a)) in chunk.iter().zip(actual.iter()).enumerate() {
+				if e == a {
+					continue;
+				}
+
+				let at = base + i as u32;
+
+				// Extend the last run if it ends right before this byte
+				match mismatches.last_mut() {
+					Some(m) if m.address + m.len() as u32 == at => {
+						m.expected.push(*e);
+						m.actual.push(*a);
+					},
+					_ => mismatches.push(Mismatch { address: at, expected: vec![*e], actual: vec![*a] }),
+				}
+			}
+		}
+
+		Ok(mismatches)
+	}
+
+	/// Search `range` for `pattern`
+	/// Only the bits set in `mask` are compared, the mask must have the length of
+	/// the pattern. Returns the addresses of all matches, which may overlap.
+	pub fn find_pattern(&mut self, range: Range<u32>, pattern: &[u8], mask: Option<&[u8]>) -> Result<Vec<u32>, LinkError> {
+		if pattern.is_empty() {
+			error!("Find pattern. The pattern is empty.");
+			return Err(LinkError::Argument("empty search pattern"));
+		}
+
+		if let Some(m) = mask {
+			if m.len() != pattern.len() {
+				error!("Find pattern. The mask has {} bytes, the pattern {}.", m.len(), pattern.len());
+				return Err(LinkError::Argument("search mask and pattern lengths differ"));
+			}
+		}
+
+		if range.end < range.start {
+			error!("Find pattern. The range 0x{:08X}..0x{:08X} ends before it starts.", range.start, range.end);
+			return Err(LinkError::Argument("search range ends before it starts"));
+		}
+
+		self.check_access(range.start, (range.end - range.start) as usize)?;
+
+		let matches = |window: &[u8]| match mask {
+			Some(m) => window.iter().zip(pattern.iter()).zip(m.iter()).all(|((w, p), m)| w & m == p & m),
+			None => window == pattern,
+		};
+
+		let mut found = Vec::new();
+
+		// Bytes kept from the previous chunk, so matches across chunks are found
+		let mut window: Vec<u8> = Vec::new();
+		let mut start = range.start;
+		let mut address = range.start;
+
+		while address < range.end {
+			let n = std::cmp::min(CHUNK as u32, range.end - address);
+			window.extend_from_slice(&self.read_memory(address, n as usize)?);
+			address += n;
+
+			if window.len() < pattern.len() {
+				continue;
+			}
+
+			for i in 0..=(window.len() - pattern.len()) {
+				if matches(&window[i..i + pattern.len()]) {
+					found.push(start + i as u32);
+				}
+			}
+
+			let keep = pattern.len() - 1;
+			let drop = window.len() - keep;
+			window.drain(..drop);
+			start += drop as u32;
+		}
+
+		Ok(found)
+	}
+}
@@ -1,24 +1,25 @@
 //! Debug methods
 
 use super::Link;
+use super::super::transport::Transport;
 
 use super::super::enums::Cmd;
+use super::super::error::LinkError;
 
-use libusb::Direction;
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Run
-	pub fn run(&mut self) -> Result<(), ()> {
+	pub fn run(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::registers::dcb::{ DHCSREG, dhcsr::{ DBGKEY, C_DEBUGEN } };
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, RUNCORE };
 
 		match self.version.jtag_api {
 			1 => {
-				match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(RUNCORE)]) {
+				match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(RUNCORE)]) {
 					Ok(_) => Ok(()),
-					_ => {
+					Err(e) => {
 						error!("Failed to send run command to the device.");
-						Err(())
+						Err(e)
 					},
 				}
 			},
@@ -28,7 +29,7 @@ impl<'a> Link<'a> {
 
 
 	// Halt
-	pub fn halt(&mut self) -> Result<(), ()> {
+	pub fn halt(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::registers::dcb::{ DHCSREG, dhcsr::{ DBGKEY, C_HALT, C_DEBUGEN } };
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, FORCEDEBUG };
 
@@ -37,11 +38,11 @@ impl<'a> Link<'a> {
 			// Write the Command Block register to halt and force debug
 			1 => {
 				// Send command -> Receive response
-				match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(FORCEDEBUG)]) {
+				match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(FORCEDEBUG)]) {
 					Ok(_) => Ok(()),
-					_ => {
+					Err(e) => {
 						error!("Could not halt device");
-						Err(())
+						Err(e)
 					},
 				}
 			},
@@ -51,17 +52,17 @@ impl<'a> Link<'a> {
 	}
 
 	/// Step
-	pub fn step(&mut self) -> Result<(), ()> {
+	pub fn step(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::registers::dcb::{ DHCSREG, dhcsr::{ DBGKEY, C_HALT, C_DEBUGEN, C_MASKINTS, C_STEP } };
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, STEPCORE };
 
 		match self.version.jtag_api {
 			1 => {
-				match self.command(2,Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(STEPCORE)]) {
+				match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(STEPCORE)]) {
 					Ok(_) => Ok(()),
-					_ => {
+					Err(e) => {
 						error!("Could not send 'STEP' command");
-						Err(())
+						Err(e)
 					},
 				}
 			},
@@ -0,0 +1,42 @@
+//! Streaming memory dumps
+
+use std::io::Write;
+
+use super::super::dump::{ Dump, Progress };
+use super::super::error::LinkError;
+
+use super::Link;
+use super::super::transport::Transport;
+
+impl<T: Transport> Link<T> {
+	/// Read the range of `dump` chunk by chunk and write each chunk to its output
+	/// `progress` is updated after every chunk. On error the chunks already
+	/// written are kept: calling `dump` again with the same `Dump` resumes at
+	/// `dump.offset()`. A refused range is reported before anything is written.
+	pub fn dump<W: Write, P: Progress>(&mut self, dump: &mut Dump<W>, progress: &mut P) -> Result<(), LinkError> {
+		self.check_access(dump.address(), dump.size())?;
+
+		dump.begin()?;
+
+		progress.update(dump.offset(), dump.size());
+
+		while !dump.done() {
+			let (address, size) = dump.next_chunk();
+			debug!("Dump. Reading {} bytes at 0x{:08X}", size, address);
+
+			let data = match self.read_memory(address, size) {
+				Ok(data) => data,
+				Err(e) => {
+					error!("Dump. Stopped at offset 0x{:X} of 0x{:X}.", dump.offset(), dump.size());
+					return Err(e);
+				},
+			};
+
+			dump.write(&data)?;
+
+			progress.update(dump.offset(), dump.size());
+		}
+
+		dump.finish()
+	}
+}
@@ -0,0 +1,244 @@
+//! CMSIS flash algorithms
+//! The algorithm is copied to the SRAM before each operation and its functions
+//! are called through the core registers: the arguments in r0 to r2, the static
+//! base in r9 and a return address on a BKPT instruction, the result in r0.
+//! While `ProgramPage` runs, the next page is written into a second buffer.
+
+use std::time::Duration;
+
+use super::super::super::algorithm::FlashAlgorithm;
+use super::super::super::dump::Progress;
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::structs::{ FlashSector, SRamInfo };
+
+use super::{ MASS_ERASE_TIMEOUT, PROGRAM_TIMEOUT };
+use super::loader::XPSR_THUMB;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Operations given to `Init` and `UnInit`
+mod operation {
+	pub const ERASE   : u32 = 1;
+	pub const PROGRAM : u32 = 2;
+}
+
+/// Offset of the code from the start of the SRAM, after the BKPT instructions
+const CODE: u32 = 0x20;
+/// Bytes of stack for the functions
+const STACK: u32 = 0x400;
+/// BKPT instructions the functions return to
+const BKPT: [u8; 4] = [0x00, 0xBE, 0x00, 0xBE];
+
+/// Addresses of the algorithm copied to the SRAM
+#[derive(Debug, Copy, Clone)]
+struct Placement {
+	/// Return address of the functions
+	breakpoint: u32,
+	/// Start of the image, the functions are offsets from it
+	code: u32,
+	/// Static base of the functions
+	data: u32,
+	/// Initial stack pointer
+	stack: u32,
+	/// Page buffers
+	buffers: [u32; 2],
+}
+
+impl<T: Transport> Link<T> {
+	/// Erase the `sectors` with `EraseSector`
+	pub(super) fn algorithm_erase(&mut self, algorithm: &FlashAlgorithm, sectors: &[FlashSector]) -> Result<(), LinkError> {
+		let timeout = algorithm_timeout(algorithm.device.erase_timeout);
+
+		self.with_algorithm(algorithm, operation::ERASE, |link, placement| {
+			for sector in sectors.iter() {
+				info!("Erasing sector {} ({} kB at 0x{:08X})", sector.index, sector.size / 1024, sector.address);
+
+				if let Err(e) = link.algorithm_call(placement, "EraseSector", algorithm.erase_sector, &[sector.address], timeout) {
+					error!("Flash erase. The algorithm failed to erase the sector at 0x{:08X}.", sector.address);
+					return Err(e);
+				}
+			}
+
+			Ok(())
+		})
+	}
+
+	/// Erase the whole flash with `EraseChip`, or sector by sector without it
+	pub(super) fn algorithm_mass_erase(&mut self, algorithm: &FlashAlgorithm) -> Result<(), LinkError> {
+		match algorithm.erase_chip {
+			Some(offset) => self.with_algorithm(algorithm, operation::ERASE, |link, placement| {
+				link.algorithm_call(placement, "EraseChip", offset, &[], MASS_ERASE_TIMEOUT)
+			}),
+			None => self.algorithm_erase(algorithm, &algorithm.device.flash_sectors()),
+		}
+	}
+
+	/// Program `data` at `address` with `ProgramPage`, without crossing a page
+	/// The data is padded with erased bytes to double words.
+	pub(super) fn algorithm_program<P: Progress>(&mut self, algorithm: &FlashAlgorithm, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		let device = &algorithm.device;
+		let page = device.page_size;
+		let timeout = algorithm_timeout(device.program_timeout);
+
+		let start = address & !7;
+		let head = (address - start) as usize;
+
+		let mut buf = vec![device.erased; head];
+		buf.extend_from_slice(data);
+		buf.resize(buf.len().div_ceil(8) * 8, device.erased);
+
+		// Blocks end at the pages, counted from the start of the flash
+		let mut blocks = Vec::new();
+		let mut offset = 0;
+		while offset < buf.len() {
+			let a = start + offset as u32;
+			let n = std::cmp::min((page - (a - device.address) % page) as usize, buf.len() - offset);
+			blocks.push(offset..offset + n);
+			offset += n;
+		}
+
+		progress.update(0, data.len());
+
+		self.with_algorithm(algorithm, operation::PROGRAM, |link, placement| {
+			if let Some(first) = blocks.first() {
+				link.loader_fill(placement.buffers[0], &buf[first.clone()])?;
+			}
+
+			for (i, block) in blocks.iter().enumerate() {
+				let a = start + block.start as u32;
+
+				link.algorithm_start(placement, algorithm.program_page, &[a, block.len() as u32, placement.buffers[i % 2]])?;
+
+				// Fill the other buffer while the page is programmed
+				if let Some(next) = blocks.get(i + 1) {
+					link.loader_fill(placement.buffers[(i + 1) % 2], &buf[next.clone()])?;
+				}
+
+				if let Err(e) = link.algorithm_finish(placement, "ProgramPage", timeout) {
+					error!("Flash program. The algorithm failed to program {} bytes at 0x{:08X}.", block.len(), a);
+					return Err(e);
+				}
+
+				progress.update(std::cmp::min(block.end.saturating_sub(head), data.len()), data.len());
+			}
+
+			Ok(())
+		})
+	}
+
+	/// Copy the algorithm to the SRAM and run `f` between `Init` and `UnInit`
+	/// for the `operation`. `UnInit` is called even if `f` fails.
+	fn with_algorithm<F>(&mut self, algorithm: &FlashAlgorithm, operation: u32, f: F) -> Result<(), LinkError>
+		where F: FnOnce(&mut Self, &Placement) -> Result<(), LinkError>
+	{
+		let placement = self.algorithm_load(algorithm)?;
+
+		if let Some(offset) = algorithm.init {
+			self.algorithm_call(&placement, "Init", offset, &[algorithm.device.address, 0, operation], PROGRAM_TIMEOUT)?;
+		}
+
+		let result = f(self, &placement);
+
+		match algorithm.uninit {
+			Some(offset) => match self.algorithm_call(&placement, "UnInit", offset, &[operation], PROGRAM_TIMEOUT) {
+				Ok(_) => result,
+				Err(e) => {
+					warn!("Could not uninitialize the flash algorithm: {}", e);
+					result.and(Err(e))
+				},
+			},
+			None => result,
+		}
+	}
+
+	/// Halt the core and copy the algorithm to the SRAM
+	fn algorithm_load(&mut self, algorithm: &FlashAlgorithm) -> Result<Placement, LinkError> {
+		let ram = self.algorithm_ram()?;
+
+		let code = ram.base + CODE;
+		let stack = code + (algorithm.image.len() as u32).div_ceil(8) * 8 + STACK;
+		let page = algorithm.device.page_size.div_ceil(8) * 8;
+
+		let placement = Placement {
+			breakpoint: ram.base,
+			code,
+			data: code + algorithm.data,
+			stack,
+			buffers: [stack, stack + page],
+		};
+
+		if stack as u64 + 2 * page as u64 > ram.base as u64 + ram.size as u64 {
+			error!("Flash algorithm. {} bytes of code and two pages of {} bytes do not fit in {} kB of SRAM.", algorithm.image.len(), page, ram.size / 1024);
+			return Err(LinkError::Unsupported("flash algorithm larger than the SRAM"));
+		}
+
+		debug!("Flash algorithm at 0x{:08X}, static base 0x{:08X}, stack 0x{:08X}", placement.code, placement.data, placement.stack);
+
+		self.halt()?;
+		self.loader_fill(placement.breakpoint, &BKPT)?;
+		self.loader_fill(placement.code, &algorithm.image)?;
+
+		Ok(placement)
+	}
+
+	/// SRAM region where the algorithm runs
+	fn algorithm_ram(&self) -> Result<SRamInfo, LinkError> {
+		if let Some(ram) = self.algorithm_ram {
+			return Ok(ram);
+		}
+
+		match self.memory.ram.iter().max_by_key(|r| r.size) {
+			Some(r) => Ok(*r),
+			None => {
+				error!("Flash algorithm. The SRAM of the chip is not known, set it with `set_algorithm_ram`.");
+				Err(LinkError::Unsupported("flash algorithm without SRAM"))
+			},
+		}
+	}
+
+	/// Call the function at `offset` with `args` and wait for its result
+	fn algorithm_call(&mut self, placement: &Placement, name: &'static str, offset: u32, args: &[u32], timeout: Duration) -> Result<(), LinkError> {
+		self.algorithm_start(placement, offset, args)?;
+		self.algorithm_finish(placement, name, timeout)
+	}
+
+	/// Start the function at `offset` with `args`
+	fn algorithm_start(&mut self, placement: &Placement, offset: u32, args: &[u32]) -> Result<(), LinkError> {
+		for (i, arg) in args.iter().enumerate() {
+			self.write_reg(i as u8, *arg)?;
+		}
+
+		self.write_reg(9, placement.data)?;
+		self.write_reg(13, placement.stack)?;
+		self.write_reg(14, placement.breakpoint | 1)?;
+		self.write_reg(15, placement.code + offset)?;
+		self.write_reg(16, XPSR_THUMB)?;
+
+		self.run()
+	}
+
+	/// Wait for the function `name` to return, and check its result
+	fn algorithm_finish(&mut self, placement: &Placement, name: &'static str, timeout: Duration) -> Result<(), LinkError> {
+		let pc = self.wait_halt(timeout)?;
+		if pc != placement.breakpoint {
+			error!("Flash algorithm. {} stopped at 0x{:08X} instead of returning.", name, pc);
+			return Err(FlashError::Algorithm(name).into());
+		}
+
+		match self.read_reg(0)? {
+			0 => Ok(()),
+			result => {
+				error!("Flash algorithm. {} returned {}.", name, result);
+				Err(FlashError::Algorithm(name).into())
+			},
+		}
+	}
+}
+
+
+/// Timeout of a function from the `ms` of the descriptor, at least `PROGRAM_TIMEOUT`
+fn algorithm_timeout(ms: u32) -> Duration {
+	std::cmp::max(Duration::from_millis(ms as u64), PROGRAM_TIMEOUT)
+}
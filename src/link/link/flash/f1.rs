@@ -0,0 +1,375 @@
+//! Flash driver of the STM32F0/F1/F3 devices (`FlashType::TypeF0` and `TypeF1XL`)
+//! The flash is split in pages of `ChipParams::pagesize` bytes and programmed
+//! by half-words. XL-density F1 devices have a second bank above 512 kB, with
+//! its own set of registers.
+
+use super::super::super::dump::Progress;
+use super::super::super::enums::{ FlashType, Protection };
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::options::OptionsF1;
+use super::super::super::structs::{ FlashSector, ProtectedSector };
+
+use super::{ ERASE_TIMEOUT, MASS_ERASE_TIMEOUT, PROGRAM_TIMEOUT };
+use super::loader::Stub;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Registers of a flash bank
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Bank {
+	keyr: u32,
+	sr: u32,
+	cr: u32,
+	ar: u32,
+}
+
+const BANK1: Bank = {
+	use super::super::super::constants::flash::f1::register::{ KEYR, SR, CR, AR };
+	Bank { keyr: KEYR, sr: SR, cr: CR, ar: AR }
+};
+
+const BANK2: Bank = {
+	use super::super::super::constants::flash::f1::register::{ KEYR2, SR2, CR2, AR2 };
+	Bank { keyr: KEYR2, sr: SR2, cr: CR2, ar: AR2 }
+};
+
+impl<T: Transport> Link<T> {
+	/// Unlock the controller of every bank
+	pub(super) fn f1_unlock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::cr::LOCK;
+
+		for bank in self.f1_banks() {
+			self.write_flash_keys(bank.keyr, bank.cr, LOCK)?;
+		}
+
+		Ok(())
+	}
+
+	/// Lock the controller of every bank
+	pub(super) fn f1_lock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::cr::LOCK;
+
+		for bank in self.f1_banks() {
+			self.write_debug_reg(bank.cr, LOCK)?;
+		}
+
+		Ok(())
+	}
+
+	/// Pages of the flash, numbered across both banks
+	pub(super) fn f1_sectors(&mut self) -> Result<Vec<FlashSector>, LinkError> {
+		let flash = self.memory.flash;
+		let page = self.f1_page_size();
+
+		Ok((0..flash.size * 1024 / page).map(|n| FlashSector { index: n as usize, address: flash.base + n * page, size: page }).collect())
+	}
+
+	/// Erase a single page
+	pub(super) fn f1_erase_page(&mut self, page: &FlashSector) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::{ cr::{ PER, STRT }, sr::BSY };
+
+		let bank = self.f1_bank(page.address);
+
+		self.f1_prepare(bank)?;
+
+		self.write_debug_reg(bank.cr, PER)?;
+		self.write_debug_reg(bank.ar, page.address)?;
+		self.write_debug_reg(bank.cr, PER | STRT)?;
+
+		let status = self.wait_flash(bank.sr, BSY, ERASE_TIMEOUT)?;
+		self.f1_check(bank, status)?;
+
+		self.write_debug_reg(bank.cr, 0)
+	}
+
+	/// Erase every bank
+	pub(super) fn f1_mass_erase(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::{ cr::{ MER, STRT }, sr::BSY };
+
+		for bank in self.f1_banks() {
+			self.f1_prepare(bank)?;
+
+			self.write_debug_reg(bank.cr, MER)?;
+			self.write_debug_reg(bank.cr, MER | STRT)?;
+
+			let status = self.wait_flash(bank.sr, BSY, MASS_ERASE_TIMEOUT)?;
+			self.f1_check(bank, status)?;
+
+			self.write_debug_reg(bank.cr, 0)?;
+		}
+
+		Ok(())
+	}
+
+	/// Program `data` at `address` by half-words, from the flash loader if enabled
+	/// The data is padded with erased bytes to whole half-words. Probes without
+	/// 16 bit memory accesses always program through the loader.
+	pub(super) fn f1_program<P: Progress>(&mut self, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::{ cr::PG, sr::{ BSY, ERRORS }, BANK1_SIZE };
+
+		let start = address & !1;
+		let head = (address - start) as usize;
+
+		let mut buf = vec![0xFF; head];
+		buf.extend_from_slice(data);
+		if !buf.len().is_multiple_of(2) {
+			buf.push(0xFF);
+		}
+
+		let bank2 = self.memory.flash.base + BANK1_SIZE;
+
+		progress.update(0, data.len());
+
+		if self.loader || !self.f1_mem16() {
+			// The loader runs once for each bank
+			let split = match start < bank2 {
+				true => std::cmp::min((bank2 - start) as usize, buf.len()),
+				false => 0,
+			};
+			let total = data.len();
+
+			for part in [0..split, split..buf.len()].iter().filter(|p| !p.is_empty()) {
+				let bank = self.f1_bank(start + part.start as u32);
+				let stub = Stub { sr: bank.sr, busy: BSY, errors: ERRORS, width: 2 };
+
+				self.f1_prepare(bank)?;
+				self.write_debug_reg(bank.cr, PG)?;
+
+				let result = self.loader_program(stub, start + part.start as u32, &buf[part.clone()],
+					|done| progress.update(std::cmp::min((part.start + done).saturating_sub(head), total), total),
+					|link, status| link.f1_check(bank, status));
+
+				self.write_debug_reg(bank.cr, 0)?;
+				result?;
+			}
+
+			return Ok(());
+		}
+
+		let mut offset = 0;
+		let mut current = None;
+
+		while offset < buf.len() {
+			let a = start + offset as u32;
+			let bank = self.f1_bank(a);
+
+			// Blocks do not cross a packet or a bank boundary
+			let mut n = std::cmp::min(self.max_packet - (a as usize % self.max_packet), buf.len() - offset);
+			if a < bank2 && a as u64 + n as u64 > bank2 as u64 {
+				n = (bank2 - a) as usize;
+			}
+
+			if current != Some(bank) {
+				if let Some(previous) = current {
+					self.write_debug_reg(previous.cr, 0)?;
+				}
+
+				self.f1_prepare(bank)?;
+				self.write_debug_reg(bank.cr, PG)?;
+				current = Some(bank);
+			}
+
+			self.write_mem16(a, &buf[offset..offset + n])?;
+
+			let status = self.wait_flash(bank.sr, BSY, PROGRAM_TIMEOUT)?;
+			if let Err(e) = self.f1_check(bank, status) {
+				error!("Flash program. Failed to program {} bytes at 0x{:08X}.", n, a);
+				return Err(e);
+			}
+
+			offset += n;
+			progress.update(std::cmp::min(offset.saturating_sub(head), data.len()), data.len());
+		}
+
+		match current {
+			Some(bank) => self.write_debug_reg(bank.cr, 0),
+			None => Ok(()),
+		}
+	}
+
+	/// Read OBR and WRPR
+	pub(super) fn f1_read_options(&mut self) -> Result<OptionsF1, LinkError> {
+		use super::super::super::constants::flash::f1::register::{ OBR, WRPR };
+
+		let id = self.chip.as_ref().map(|c| c.id).unwrap_or(0);
+		let obr = self.read_debug_reg(OBR)?;
+		let wrpr = self.read_debug_reg(WRPR)?;
+
+		Ok(OptionsF1::decode(id, obr, wrpr))
+	}
+
+	/// Erase and program the option bytes, then load them
+	/// F0/F3 devices load them with OBL_LAUNCH, F1 devices are reset and halted.
+	pub(super) fn f1_write_options(&mut self, options: &OptionsF1) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ f1::{ register::{ OPTKEYR, CR, OBR }, cr::{ OPTWRE, OBL_LAUNCH } }, misc::{ KEY1, KEY2 } };
+
+		let bytes = options.bytes();
+
+		self.f1_prepare(BANK1)?;
+
+		self.write_debug_reg(OPTKEYR, KEY1)?;
+		self.write_debug_reg(OPTKEYR, KEY2)?;
+
+		if self.read_debug_reg(CR)? & OPTWRE == 0 {
+			error!("Option bytes could not be unlocked. Reason unknown.");
+			return Err(LinkError::FlashLocked);
+		}
+
+		let result = self.f1_program_options(&bytes);
+
+		// OPTWRE is cleared by writing 0
+		self.write_debug_reg(CR, 0)?;
+		result?;
+
+		match options.f0() {
+			true => if let Err(e) = self.write_debug_reg(CR, OBL_LAUNCH) {
+				debug!("Loading the option bytes reset the target: {}", e);
+			},
+			false => self.reset_halt()?,
+		}
+
+		if self.f1_read_options()?.bytes() != bytes {
+			error!("Option bytes verification. The option bytes loaded differ from those programmed.");
+			return Err(FlashError::Verify(OBR).into());
+		}
+
+		Ok(())
+	}
+
+	/// Pages protected by the cleared bits of WRPR
+	pub(super) fn f1_protection(&mut self) -> Result<Vec<ProtectedSector>, LinkError> {
+		use super::super::super::constants::flash::f1::register::WRPR;
+
+		let wrpr = self.read_debug_reg(WRPR)?;
+
+		Ok(self.f1_sectors()?.into_iter()
+			.filter(|page| wrpr & (1 << self.f1_wrp_bit(page)) == 0)
+			.map(|sector| ProtectedSector { sector, protection: Protection::Write })
+			.collect())
+	}
+
+	/// Clear or set the nWRP bits of the `pages`
+	/// Each bit protects 4 kB, so the pages next to them can change too.
+	pub(super) fn f1_protect(&mut self, options: &mut OptionsF1, pages: &[FlashSector], protect: bool) -> Result<(), LinkError> {
+		for page in pages.iter() {
+			match protect {
+				true => options.nwrp &= !(1 << self.f1_wrp_bit(page)),
+				false => options.nwrp |= 1 << self.f1_wrp_bit(page),
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Erase the option bytes and program each byte followed by its complement
+	/// OPTWRE is kept set in every write of CR. Erasing the option bytes of a
+	/// read protected chip mass erases the flash.
+	fn f1_program_options(&mut self, bytes: &[u8]) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::{ register::{ CR, SR }, cr::{ OPTER, OPTPG, OPTWRE, STRT }, sr::{ BSY, ERRORS }, option::RDP };
+
+		self.write_debug_reg(CR, OPTER | OPTWRE)?;
+		self.write_debug_reg(CR, OPTER | STRT | OPTWRE)?;
+
+		let status = self.wait_flash(SR, BSY, MASS_ERASE_TIMEOUT)?;
+		self.f1_check(BANK1, status)?;
+
+		let data = bytes.iter().flat_map(|b| vec![*b, !*b]).collect::<Vec<_>>();
+
+		self.write_debug_reg(CR, OPTPG | OPTWRE)?;
+
+		if !self.f1_mem16() {
+			let stub = Stub { sr: SR, busy: BSY, errors: ERRORS, width: 2 };
+			return self.loader_program(stub, RDP, &data, |_| (), |link, status| link.f1_check(BANK1, status));
+		}
+
+		self.write_mem16(RDP, &data)?;
+
+		let status = self.wait_flash(SR, BSY, PROGRAM_TIMEOUT)?;
+		self.f1_check(BANK1, status)
+	}
+
+	/// Check if the probe can write half-words in the flash
+	/// STLink V1 and V2 before J26 only have 8 and 32 bit memory accesses, the
+	/// half-words are then written by the flash loader.
+	fn f1_mem16(&self) -> bool {
+		use super::super::super::constants::flags::HAS_MEM_16BIT;
+
+		self.version.flags & HAS_MEM_16BIT != 0
+	}
+
+	/// Wait for the end of any ongoing operation of the bank and clear its flags
+	fn f1_prepare(&mut self, bank: Bank) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::sr::{ BSY, EOP, ERRORS };
+
+		let status = self.wait_flash(bank.sr, BSY, ERASE_TIMEOUT)?;
+
+		if status & (EOP | ERRORS) != 0 {
+			debug!("Flash SR 0x{:08X}. Clearing the flags of the last operation.", status);
+			self.write_debug_reg(bank.sr, EOP | ERRORS)?;
+		}
+
+		Ok(())
+	}
+
+	/// Decode the error flags of the status register, clearing them on error
+	fn f1_check(&mut self, bank: Bank, status: u32) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::f1::sr::{ PGERR, WRPRTERR, ERRORS };
+
+		let error = if status & WRPRTERR != 0 {
+			FlashError::WriteProtected
+		} else if status & PGERR != 0 {
+			FlashError::NotErased
+		} else {
+			return Ok(());
+		};
+
+		error!("Flash operation failed with SR 0x{:08X}: {}", status, error);
+		self.write_debug_reg(bank.sr, ERRORS)?;
+
+		Err(error.into())
+	}
+
+	/// Registers of the bank holding `address`
+	fn f1_bank(&self, address: u32) -> Bank {
+		use super::super::super::constants::flash::f1::BANK1_SIZE;
+
+		match self.f1_dual_bank() && address >= self.memory.flash.base + BANK1_SIZE {
+			true => BANK2,
+			false => BANK1,
+		}
+	}
+
+	/// Registers of all the banks
+	fn f1_banks(&self) -> Vec<Bank> {
+		match self.f1_dual_bank() {
+			true => vec![BANK1, BANK2],
+			false => vec![BANK1],
+		}
+	}
+
+	/// Check if the flash has a second bank
+	/// Only XL-density F1 devices with more than 512 kB have one
+	fn f1_dual_bank(&self) -> bool {
+		use super::super::super::constants::flash::f1::BANK1_SIZE;
+
+		let xl = self.chip.as_ref().map(|c| c.flasht == FlashType::TypeF1XL).unwrap_or(false);
+
+		xl && self.memory.flash.size * 1024 > BANK1_SIZE
+	}
+
+	/// nWRP bit of a page
+	/// Each bit protects 4 kB, the last one all the pages above on larger devices.
+	fn f1_wrp_bit(&self, page: &FlashSector) -> u32 {
+		std::cmp::min((page.address - self.memory.flash.base) / 4096, 31)
+	}
+
+	/// Page size of the chip
+	fn f1_page_size(&self) -> u32 {
+		match self.chip {
+			Some(ref chip) if chip.pagesize != 0 => chip.pagesize,
+			_ => 0x400,
+		}
+	}
+}
@@ -0,0 +1,363 @@
+//! Flash driver of the STM32F2/F4/F7 devices (`FlashType::TypeF4`)
+//! The flash is split in sectors of 16, 64 and 128 kB (twice that on F74x/75x
+//! and single bank F76x/77x). Dual bank devices number the sectors of the
+//! second bank from 12.
+
+use super::super::super::chipid::STM32ChipID;
+use super::super::super::dump::Progress;
+use super::super::super::enums::{ Parallelism, Protection };
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::options::OptionsF4;
+use super::super::super::structs::{ FlashSector, ProtectedSector };
+
+use super::{ ERASE_TIMEOUT, MASS_ERASE_TIMEOUT, PROGRAM_TIMEOUT };
+use super::loader::Stub;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Number of the first sector of the second bank
+const BANK2_FIRST: usize = 12;
+
+impl<T: Transport> Link<T> {
+	/// Write the key sequence to KEYR if the controller is locked
+	pub(super) fn f4_unlock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ CR, KEYR }, cr::{ LOCK } };
+
+		self.write_flash_keys(KEYR, CR, LOCK)
+	}
+
+	/// Set the LOCK bit, which also clears the operation bits
+	pub(super) fn f4_lock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::CR, cr::LOCK };
+
+		self.write_debug_reg(CR, LOCK)
+	}
+
+	/// Sector layout from the chip ID, the flash size and the bank mode
+	pub(super) fn f4_sectors(&mut self) -> Result<Vec<FlashSector>, LinkError> {
+		let flash = self.memory.flash;
+		let size = flash.size * 1024;
+		let dual = self.f4_dual_bank()?;
+
+		let small = match self.f4_chip() {
+			// F74x/75x and single bank F76x/77x have sectors twice as big
+			id if id == STM32ChipID::F7 as u32 => 32 * 1024,
+			id if id == STM32ChipID::F7XXXX as u32 && !dual => 32 * 1024,
+			_ => 16 * 1024,
+		};
+
+		if dual {
+			let mut sectors = f4_bank(0, flash.base, small, size / 2);
+			sectors.extend(f4_bank(BANK2_FIRST, flash.base + size / 2, small, size / 2));
+			Ok(sectors)
+		} else {
+			Ok(f4_bank(0, flash.base, small, size))
+		}
+	}
+
+	/// Erase a single sector
+	pub(super) fn f4_erase_sector(&mut self, sector: &FlashSector) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ CR, SR }, cr::{ SER, STRT, SNB_SHIFT }, sr::BSY };
+
+		// The sectors of the second bank start at SNB 0b10000
+		let snb = match sector.index {
+			n if n < BANK2_FIRST => n as u32,
+			n => 0x10 | (n - BANK2_FIRST) as u32,
+		};
+
+		let psize = self.f4_prepare()?;
+		let cr = psize | SER | (snb << SNB_SHIFT);
+
+		self.write_debug_reg(CR, cr)?;
+		self.write_debug_reg(CR, cr | STRT)?;
+
+		let status = self.wait_flash(SR, BSY, ERASE_TIMEOUT)?;
+		self.f4_check(status)?;
+
+		self.write_debug_reg(CR, psize)
+	}
+
+	/// Erase all the sectors of both banks
+	pub(super) fn f4_mass_erase(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ CR, SR }, cr::{ MER, MER1, STRT }, sr::BSY };
+
+		let mer = match self.f4_dual_bank()? {
+			true => MER | MER1,
+			false => MER,
+		};
+
+		let psize = self.f4_prepare()?;
+
+		self.write_debug_reg(CR, psize | mer)?;
+		self.write_debug_reg(CR, psize | mer | STRT)?;
+
+		let status = self.wait_flash(SR, BSY, MASS_ERASE_TIMEOUT)?;
+		self.f4_check(status)?;
+
+		self.write_debug_reg(CR, psize)
+	}
+
+	/// Program `data` at `address` with accesses of the flash parallelism, from
+	/// the flash loader if enabled. The data is padded with erased bytes to the parallelism
+	/// Neither the debug port nor the loader make 64 bit accesses, the x64
+	/// parallelism programs words with PSIZE x32.
+	pub(super) fn f4_program<P: Progress>(&mut self, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ CR, SR }, cr::PG, sr::{ BSY, ERRORS } };
+
+		let parallelism = match self.flash_parallelism() {
+			Parallelism::X64 => Parallelism::X32,
+			p => p,
+		};
+		let width = parallelism.bytes();
+
+		let start = address & !(width as u32 - 1);
+		let head = (address - start) as usize;
+
+		let mut buf = vec![0xFF; head];
+		buf.extend_from_slice(data);
+		buf.resize(buf.len().div_ceil(width) * width, 0xFF);
+
+		// Byte transfers are limited by the probe, the other widths by the packet size
+		let block = match width {
+			1 => self.model.max_rw8,
+			_ => self.max_packet,
+		};
+
+		self.f4_prepare()?;
+		let psize = f4_psize(parallelism);
+		self.write_debug_reg(CR, psize | PG)?;
+
+		progress.update(0, data.len());
+
+		if self.loader {
+			let stub = Stub { sr: SR, busy: BSY, errors: ERRORS, width };
+			let total = data.len();
+
+			self.loader_program(stub, start, &buf,
+				|done| progress.update(std::cmp::min(done.saturating_sub(head), total), total),
+				|link, status| link.f4_check(status))?;
+
+			return self.write_debug_reg(CR, psize);
+		}
+
+		let mut offset = 0;
+
+		while offset < buf.len() {
+			let a = start + offset as u32;
+			let n = std::cmp::min(block - (a as usize % block), buf.len() - offset);
+			let chunk = &buf[offset..offset + n];
+
+			match width {
+				1 => self.write_mem8(a, chunk)?,
+				2 => self.write_mem16(a, chunk)?,
+				_ => self.write_mem32(a, chunk)?,
+			}
+
+			let status = self.wait_flash(SR, BSY, PROGRAM_TIMEOUT)?;
+			if let Err(e) = self.f4_check(status) {
+				error!("Flash program. Failed to program {} bytes at 0x{:08X}.", n, a);
+				return Err(e);
+			}
+
+			offset += n;
+			progress.update(std::cmp::min(offset.saturating_sub(head), data.len()), data.len());
+		}
+
+		self.write_debug_reg(CR, psize)
+	}
+
+	/// Read OPTCR, and OPTCR1 on the devices that have it
+	pub(super) fn f4_read_options(&mut self) -> Result<OptionsF4, LinkError> {
+		use super::super::super::constants::flash::register::{ OPTCR, OPTCR1 };
+
+		let id = self.f4_chip();
+		let optcr = self.read_debug_reg(OPTCR)?;
+		let optcr1 = match OptionsF4::has_optcr1(id) {
+			true => Some(self.read_debug_reg(OPTCR1)?),
+			false => None,
+		};
+
+		Ok(OptionsF4::decode(id, optcr, optcr1))
+	}
+
+	/// Program the option bytes with OPTSTRT and lock OPTCR again, even on error
+	/// The new values are in effect once the operation completes. Going back
+	/// from RDP level 1 to level 0 mass erases the flash first.
+	pub(super) fn f4_write_options(&mut self, options: &OptionsF4) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ OPTKEYR, OPTCR, OPTCR1 }, optcr::OPTLOCK, misc::{ OPTKEY1, OPTKEY2 } };
+
+		let (optcr, optcr1) = options.encode();
+
+		self.f4_prepare()?;
+		self.write_keys(OPTKEYR, OPTCR, OPTLOCK, [OPTKEY1, OPTKEY2])?;
+
+		let result = self.f4_program_options(optcr, optcr1);
+		let lock = self.write_debug_reg(OPTCR, optcr | OPTLOCK);
+
+		if let Err(ref e) = lock {
+			warn!("Could not lock OPTCR again: {}", e);
+		}
+		result.and(lock)?;
+
+		let read = self.f4_read_options()?.encode();
+
+		if read.0 != optcr {
+			error!("Option bytes verification. OPTCR is 0x{:08X} instead of 0x{:08X}.", read.0, optcr);
+			return Err(FlashError::Verify(OPTCR).into());
+		}
+
+		if read.1 != optcr1 {
+			error!("Option bytes verification. OPTCR1 is 0x{:08X} instead of 0x{:08X}.", read.1.unwrap_or(0), optcr1.unwrap_or(0));
+			return Err(FlashError::Verify(OPTCR1).into());
+		}
+
+		Ok(())
+	}
+
+	/// Sectors protected by nWRP, or selected for PCROP with SPRMOD set
+	pub(super) fn f4_protection(&mut self) -> Result<Vec<ProtectedSector>, LinkError> {
+		let options = self.f4_read_options()?;
+
+		Ok(self.f4_sectors()?.into_iter()
+			.filter(|s| s.index < 32)
+			.filter_map(|sector| match (options.pcrop, options.nwrp & (1 << sector.index) != 0) {
+				(false, false) => Some(ProtectedSector { sector, protection: Protection::Write }),
+				(true, true) => Some(ProtectedSector { sector, protection: Protection::Pcrop }),
+				_ => None,
+			})
+			.collect())
+	}
+
+	/// Clear or set the nWRP bits of the `sectors`
+	/// nWRP selects the PCROP sectors with SPRMOD set, which is not changed here.
+	pub(super) fn f4_protect(&mut self, options: &mut OptionsF4, sectors: &[FlashSector], protect: bool) -> Result<(), LinkError> {
+		if options.pcrop {
+			error!("Write protection. nWRP selects the PCROP sectors, the write protection is not available.");
+			return Err(LinkError::Unsupported("write protection in PCROP mode"));
+		}
+
+		for sector in sectors.iter().filter(|s| s.index < 32) {
+			match protect {
+				true => options.nwrp &= !(1 << sector.index),
+				false => options.nwrp |= 1 << sector.index,
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Write the unlocked option registers and start the programming
+	fn f4_program_options(&mut self, optcr: u32, optcr1: Option<u32>) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::{ OPTCR, OPTCR1, SR }, optcr::OPTSTRT, sr::BSY };
+
+		if let Some(value) = optcr1 {
+			self.write_debug_reg(OPTCR1, value)?;
+		}
+
+		self.write_debug_reg(OPTCR, optcr)?;
+		self.write_debug_reg(OPTCR, optcr | OPTSTRT)?;
+
+		let status = self.wait_flash(SR, BSY, MASS_ERASE_TIMEOUT)?;
+		self.f4_check(status)
+	}
+
+	/// Wait for the end of any ongoing operation and clear the error flags
+	/// Returns the PSIZE bits of the flash parallelism
+	fn f4_prepare(&mut self) -> Result<u32, LinkError> {
+		use super::super::super::constants::flash::{ register::SR, sr::{ BSY, EOP, ERRORS } };
+
+		let status = self.wait_flash(SR, BSY, ERASE_TIMEOUT)?;
+
+		if status & (EOP | ERRORS) != 0 {
+			debug!("Flash SR 0x{:08X}. Clearing the flags of the last operation.", status);
+			self.write_debug_reg(SR, EOP | ERRORS)?;
+		}
+
+		Ok(f4_psize(self.flash_parallelism()))
+	}
+
+	/// Decode the error flags of the status register, clearing them on error
+	fn f4_check(&mut self, status: u32) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ register::SR, sr::* };
+
+		let error = if status & WRPERR != 0 {
+			FlashError::WriteProtected
+		} else if status & PGAERR != 0 {
+			FlashError::Alignment
+		} else if status & PGPERR != 0 {
+			FlashError::Parallelism
+		} else if status & PGSERR != 0 {
+			FlashError::Sequence
+		} else if status & RDERR != 0 {
+			FlashError::ReadProtected
+		} else if status & OPERR != 0 {
+			FlashError::Operation
+		} else {
+			return Ok(());
+		};
+
+		error!("Flash operation failed with SR 0x{:08X}: {}", status, error);
+		self.write_debug_reg(SR, ERRORS)?;
+
+		Err(error.into())
+	}
+
+	/// Check if the flash is split in two banks
+	/// F42x/43x are dual bank with 2 MB or with DB1M set, F76x/77x unless nDBANK is set
+	fn f4_dual_bank(&mut self) -> Result<bool, LinkError> {
+		use super::super::super::constants::flash::{ register::OPTCR, optcr::{ DB1M, NDBANK } };
+
+		let size = self.memory.flash.size;
+
+		match self.f4_chip() {
+			id if id == STM32ChipID::F4HD as u32 || id == STM32ChipID::F4DSI as u32 => match size {
+				2048 => Ok(true),
+				1024 => Ok(self.read_debug_reg(OPTCR)? & DB1M != 0),
+				_ => Ok(false),
+			},
+			id if id == STM32ChipID::F7XXXX as u32 => Ok(self.read_debug_reg(OPTCR)? & NDBANK == 0),
+			_ => Ok(false),
+		}
+	}
+
+	/// ID of the identified chip
+	fn f4_chip(&self) -> u32 {
+		self.chip.as_ref().map(|c| c.id).unwrap_or(0)
+	}
+}
+
+/// Sectors of a bank of `size` bytes at `base`
+/// Four small sectors, one of four times their size and the rest of eight times their size
+fn f4_bank(first: usize, base: u32, small: u32, size: u32) -> Vec<FlashSector> {
+	let mut sectors = Vec::new();
+	let mut offset = 0;
+
+	while offset < size {
+		let n = sectors.len();
+		let sector = match n {
+			0..=3 => small,
+			4 => small * 4,
+			_ => small * 8,
+		};
+
+		sectors.push(FlashSector { index: first + n, address: base + offset, size: std::cmp::min(sector, size - offset) });
+		offset += sector;
+	}
+
+	sectors
+}
+
+
+/// PSIZE bits of the `parallelism`
+fn f4_psize(parallelism: Parallelism) -> u32 {
+	use super::super::super::constants::flash::cr::{ PSIZE_8, PSIZE_16, PSIZE_32, PSIZE_64 };
+
+	match parallelism {
+		Parallelism::X8  => PSIZE_8,
+		Parallelism::X16 => PSIZE_16,
+		Parallelism::X32 => PSIZE_32,
+		Parallelism::X64 => PSIZE_64,
+	}
+}
@@ -0,0 +1,422 @@
+//! Flash driver of the STM32L0/L1 devices (`FlashType::TypeL0`)
+//! The program memory is split in pages of `ChipParams::pagesize` bytes, erased
+//! to 0 and programmed by words or half-pages. The PECR register is unlocked
+//! first, then the program memory. The data EEPROM only needs PECR unlocked.
+
+use super::super::super::chipid::STM32ChipID;
+use super::super::super::dump::Progress;
+use super::super::super::enums::Protection;
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::options::OptionsL0;
+use super::super::super::structs::{ FlashSector, ProtectedSector };
+
+use super::{ ERASE_TIMEOUT, MASS_ERASE_TIMEOUT, PROGRAM_TIMEOUT };
+use super::loader::Stub;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Bytes of data EEPROM written before waiting for the controller
+const EEPROM_BLOCK: usize = 64;
+
+impl<T: Transport> Link<T> {
+	/// Unlock PECR and then the program memory
+	pub(super) fn l0_unlock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::{ register::PRGKEYR, pecr::PRGLOCK, misc::{ PRGKEY1, PRGKEY2 } };
+
+		self.l0_unlock_pecr()?;
+
+		let base = self.l0_base();
+		self.write_keys(base + PRGKEYR, self.l0_pecr(), PRGLOCK, [PRGKEY1, PRGKEY2])
+	}
+
+	/// Set PELOCK, which locks the program memory and the option bytes too
+	pub(super) fn l0_lock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::pecr::PELOCK;
+
+		self.write_debug_reg(self.l0_pecr(), PELOCK)
+	}
+
+	/// Pages of the program memory
+	pub(super) fn l0_sectors(&mut self) -> Result<Vec<FlashSector>, LinkError> {
+		let flash = self.memory.flash;
+		let page = self.l0_page_size();
+
+		Ok((0..flash.size * 1024 / page).map(|n| FlashSector { index: n as usize, address: flash.base + n * page, size: page }).collect())
+	}
+
+	/// Pages of the 4 kB sectors protected by the set bits of the WRPROT
+	/// registers, or by PCROP for the cleared bits with WPRMOD set
+	pub(super) fn l0_protection(&mut self) -> Result<Vec<ProtectedSector>, LinkError> {
+		let options = self.l0_read_options()?;
+		let flash = self.memory.flash.base;
+
+		Ok(self.l0_sectors()?.into_iter()
+			.filter_map(|page| {
+				let sector = (page.address - flash) / 4096;
+				let bit = options.wrprot[(sector / 32) as usize] & (1 << (sector % 32)) != 0;
+
+				match (options.pcrop, bit) {
+					(false, true) => Some(ProtectedSector { sector: page, protection: Protection::Write }),
+					(true, false) => Some(ProtectedSector { sector: page, protection: Protection::Pcrop }),
+					_ => None,
+				}
+			})
+			.collect())
+	}
+
+	/// Set or clear the WRPROT bits of the 4 kB sectors of the `pages`
+	/// The WRPROT bits select the PCROP sectors with WPRMOD set, which is not changed here.
+	pub(super) fn l0_protect(&mut self, options: &mut OptionsL0, pages: &[FlashSector], protect: bool) -> Result<(), LinkError> {
+		if options.pcrop {
+			error!("Write protection. WRPROT selects the PCROP sectors, the write protection is not available.");
+			return Err(LinkError::Unsupported("write protection in PCROP mode"));
+		}
+
+		let flash = self.memory.flash.base;
+
+		for page in pages.iter() {
+			let sector = (page.address - flash) / 4096;
+			let (reg, bit) = ((sector / 32) as usize, 1 << (sector % 32));
+
+			match protect {
+				true => options.wrprot[reg] |= bit,
+				false => options.wrprot[reg] &= !bit,
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Read OPTR and the WRPROT registers of the flash size
+	pub(super) fn l0_read_options(&mut self) -> Result<OptionsL0, LinkError> {
+		use super::super::super::constants::flash::l0::register::OPTR;
+
+		let base = self.l0_base();
+		let optr = self.read_debug_reg(base + OPTR)?;
+
+		let mut wrprot = Vec::new();
+		for (reg, _) in self.l0_wrprot_regs() {
+			wrprot.push(self.read_debug_reg(base + reg)?);
+		}
+
+		Ok(OptionsL0::decode(optr, &wrprot))
+	}
+
+	/// Program the option bytes that differ from the loaded ones and load them
+	/// with OBL_LAUNCH, which resets the target. The RDP byte is written last,
+	/// going back from level 1 to level 0 mass erases the flash and the data EEPROM.
+	pub(super) fn l0_write_options(&mut self, options: &OptionsL0) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::{ register::{ OPTKEYR, OPTR }, pecr::{ OPTLOCK, OBL_LAUNCH }, misc::{ OPTKEY1, OPTKEY2 } };
+
+		let loaded = self.l0_read_options()?;
+		let current = self.l0_option_words(&loaded);
+		let words = self.l0_option_words(options);
+		let base = self.l0_base();
+		let pecr = self.l0_pecr();
+
+		self.l0_unlock_pecr()?;
+		self.write_keys(base + OPTKEYR, pecr, OPTLOCK, [OPTKEY1, OPTKEY2])?;
+		self.l0_prepare()?;
+
+		for (&(address, value), &(_, old)) in words.iter().zip(current.iter()).rev() {
+			if value != old {
+				self.l0_program_option(address, value)?;
+			}
+		}
+
+		if let Err(e) = self.write_debug_reg(pecr, OBL_LAUNCH) {
+			debug!("Loading the option bytes reset the target: {}", e);
+		}
+
+		let (optr, wrprot) = options.encode();
+		let read = self.l0_read_options()?.encode();
+
+		if read.0 != optr {
+			error!("Option bytes verification. OPTR is 0x{:08X} instead of 0x{:08X}.", read.0, optr);
+			return Err(FlashError::Verify(base + OPTR).into());
+		}
+
+		for ((reg, _), (r, w)) in self.l0_wrprot_regs().into_iter().zip(read.1.iter().zip(wrprot.iter())) {
+			if r != w {
+				error!("Option bytes verification. WRPROT at 0x{:08X} is 0x{:08X} instead of 0x{:08X}.", base + reg, r, w);
+				return Err(FlashError::Verify(base + reg).into());
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Erase a single page by writing a word into it with ERASE and PROG set
+	pub(super) fn l0_erase_page(&mut self, page: &FlashSector) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::{ pecr::{ ERASE, PROG }, sr::BSY };
+
+		let pecr = self.l0_pecr();
+
+		self.l0_prepare()?;
+		self.write_debug_reg(pecr, ERASE | PROG)?;
+		self.write_mem32(page.address, &[0; 4])?;
+
+		let status = self.wait_flash(self.l0_sr(), BSY, ERASE_TIMEOUT)?;
+		self.l0_check(status)?;
+
+		self.write_debug_reg(pecr, 0)
+	}
+
+	/// Erase every page of the program memory
+	/// The controller has no mass erase of the program memory alone.
+	pub(super) fn l0_mass_erase(&mut self) -> Result<(), LinkError> {
+		for page in self.l0_sectors()?.iter() {
+			self.l0_erase_page(page)?;
+		}
+
+		Ok(())
+	}
+
+	/// Program `data` at `address` by half-pages, and by words where a whole
+	/// half-page is not available. The flash loader programs by words only.
+	/// The data is padded with zeros to whole words.
+	pub(super) fn l0_program<P: Progress>(&mut self, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::{ pecr::{ FPRG, PROG }, sr::{ BSY, ERRORS } };
+
+		let start = address & !3;
+		let head = (address - start) as usize;
+
+		// The erased value of the L0/L1 flash is 0
+		let mut buf = vec![0x00; head];
+		buf.extend_from_slice(data);
+		while !buf.len().is_multiple_of(4) {
+			buf.push(0x00);
+		}
+
+		let half = self.l0_page_size() as usize / 2;
+		let pecr = self.l0_pecr();
+		let sr = self.l0_sr();
+
+		self.l0_prepare()?;
+
+		progress.update(0, data.len());
+
+		if self.loader {
+			let stub = Stub { sr, busy: BSY, errors: ERRORS, width: 4 };
+			let total = data.len();
+
+			return self.loader_program(stub, start, &buf,
+				|done| progress.update(std::cmp::min(done.saturating_sub(head), total), total),
+				|link, status| link.l0_check(status));
+		}
+
+		let mut offset = 0;
+
+		while offset < buf.len() {
+			let a = start + offset as u32;
+
+			let n = match (a as usize).is_multiple_of(half) && buf.len() - offset >= half {
+				true => {
+					self.write_debug_reg(pecr, FPRG | PROG)?;
+					half
+				},
+				false => 4,
+			};
+
+			self.write_mem32(a, &buf[offset..offset + n])?;
+
+			let status = self.wait_flash(sr, BSY, PROGRAM_TIMEOUT)?;
+			if n == half {
+				self.write_debug_reg(pecr, 0)?;
+			}
+			if let Err(e) = self.l0_check(status) {
+				error!("Flash program. Failed to program {} bytes at 0x{:08X}.", n, a);
+				return Err(e);
+			}
+
+			offset += n;
+			progress.update(std::cmp::min(offset.saturating_sub(head), data.len()), data.len());
+		}
+
+		Ok(())
+	}
+
+	/// Write `data` into the data EEPROM at `address`
+	/// Words are written where they are aligned, bytes at both ends. PECR is
+	/// locked again afterwards, even on error.
+	pub(super) fn l0_write_eeprom(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		self.l0_unlock_pecr()?;
+
+		let result = self.l0_write_data(address, data);
+
+		match self.l0_lock() {
+			Ok(_) => result,
+			Err(e) => {
+				warn!("Could not lock PECR again: {}", e);
+				result.and(Err(e))
+			},
+		}
+	}
+
+	/// Write the bytes to the unlocked data EEPROM
+	fn l0_write_data(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::sr::BSY;
+
+		let sr = self.l0_sr();
+
+		self.l0_prepare()?;
+
+		let mut offset = 0;
+
+		while offset < data.len() {
+			let a = address + offset as u32;
+			let left = data.len() - offset;
+
+			let n = match a % 4 {
+				0 if left >= 4 => {
+					let n = std::cmp::min(EEPROM_BLOCK, left & !3);
+					self.write_mem32(a, &data[offset..offset + n])?;
+					n
+				},
+				_ => {
+					self.write_mem8(a, &data[offset..offset + 1])?;
+					1
+				},
+			};
+
+			let status = self.wait_flash(sr, BSY, PROGRAM_TIMEOUT)?;
+			if let Err(e) = self.l0_check(status) {
+				error!("EEPROM write. Failed to write {} bytes at 0x{:08X}.", n, a);
+				return Err(e);
+			}
+
+			offset += n;
+		}
+
+		Ok(())
+	}
+
+	/// Write an option half-word with its complement into the unlocked option bytes
+	fn l0_program_option(&mut self, address: u32, value: u16) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::sr::BSY;
+
+		let word = value as u32 | (!value as u32) << 16;
+		self.write_mem32(address, &word.to_le_bytes())?;
+
+		// Leaving RDP level 1 erases the whole NVM
+		let status = self.wait_flash(self.l0_sr(), BSY, MASS_ERASE_TIMEOUT)?;
+		if let Err(e) = self.l0_check(status) {
+			error!("Option bytes. Failed to program the option bytes at 0x{:08X}.", address);
+			return Err(e);
+		}
+
+		Ok(())
+	}
+
+	/// Addresses of the option half-words and their values, RDP first
+	fn l0_option_words(&self, options: &OptionsL0) -> Vec<(u32, u16)> {
+		use super::super::super::constants::flash::l0::option::{ RDP, USER };
+
+		let (optr, wrprot) = options.encode();
+
+		let mut words = vec![(RDP, optr as u16), (USER, (optr >> 16) as u16)];
+		for ((_, address), value) in self.l0_wrprot_regs().into_iter().zip(wrprot.iter()) {
+			words.push((address, *value as u16));
+			words.push((address + 4, (*value >> 16) as u16));
+		}
+
+		words
+	}
+
+	/// Offsets of the WRPROT registers of the flash size, and the addresses of their option bytes
+	/// Each register protects 32 sectors of 4 kB.
+	fn l0_wrprot_regs(&self) -> Vec<(u32, u32)> {
+		use super::super::super::constants::flash::l0::{ L0_BASE, register::{ WRPROT1, WRPROT2 }, option };
+
+		let count = (self.memory.flash.size / 4).div_ceil(32);
+		let l0 = self.l0_base() == L0_BASE;
+
+		(0..count).map(|n| match (n, l0) {
+			(0, _) => (WRPROT1, option::WRPROT1),
+			(n, true) => (WRPROT2 + 4 * (n - 1), option::L0_WRPROT2 + 8 * (n - 1)),
+			(n, false) => (WRPROT2 + 4 * (n - 1), option::L1_WRPROT2 + 8 * (n - 1)),
+		}).collect()
+	}
+
+	/// Write the key sequence to PEKEYR if PECR is locked
+	fn l0_unlock_pecr(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::{ register::PEKEYR, pecr::PELOCK, misc::{ PEKEY1, PEKEY2 } };
+
+		let base = self.l0_base();
+		self.write_keys(base + PEKEYR, self.l0_pecr(), PELOCK, [PEKEY1, PEKEY2])
+	}
+
+	/// Wait for the end of any ongoing operation and clear the flags
+	fn l0_prepare(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::sr::{ BSY, EOP, ERRORS };
+
+		let sr = self.l0_sr();
+		let status = self.wait_flash(sr, BSY, ERASE_TIMEOUT)?;
+
+		if status & (EOP | ERRORS) != 0 {
+			debug!("Flash SR 0x{:08X}. Clearing the flags of the last operation.", status);
+			self.write_debug_reg(sr, EOP | ERRORS)?;
+		}
+
+		Ok(())
+	}
+
+	/// Decode the error flags of the status register, clearing them on error
+	fn l0_check(&mut self, status: u32) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l0::sr::*;
+
+		let error = if status & WRPERR != 0 {
+			FlashError::WriteProtected
+		} else if status & PGAERR != 0 {
+			FlashError::Alignment
+		} else if status & SIZERR != 0 {
+			FlashError::Size
+		} else if status & NOTZEROERR != 0 {
+			FlashError::NotErased
+		} else if status & RDERR != 0 {
+			FlashError::ReadProtected
+		} else if status & FWWERR != 0 {
+			FlashError::Operation
+		} else {
+			return Ok(());
+		};
+
+		error!("Flash operation failed with SR 0x{:08X}: {}", status, error);
+		self.write_debug_reg(self.l0_sr(), ERRORS)?;
+
+		Err(error.into())
+	}
+
+	/// Base of the registers, which differs between L0 and L1
+	fn l0_base(&self) -> u32 {
+		use super::super::super::constants::flash::l0::{ L0_BASE, L1_BASE };
+
+		match self.chip.as_ref().map(|c| c.id).unwrap_or(0) {
+			id if id == STM32ChipID::L0 as u32
+				|| id == STM32ChipID::L0Cat2 as u32
+				|| id == STM32ChipID::L0Cat5 as u32
+				|| id == STM32ChipID::L011 as u32 => L0_BASE,
+			_ => L1_BASE,
+		}
+	}
+
+	/// Address of PECR
+	fn l0_pecr(&self) -> u32 {
+		self.l0_base() + super::super::super::constants::flash::l0::register::PECR
+	}
+
+	/// Address of SR
+	fn l0_sr(&self) -> u32 {
+		self.l0_base() + super::super::super::constants::flash::l0::register::SR
+	}
+
+	/// Page size of the chip
+	fn l0_page_size(&self) -> u32 {
+		match self.chip {
+			Some(ref chip) if chip.pagesize != 0 => chip.pagesize,
+			_ => 0x80,
+		}
+	}
+}
@@ -0,0 +1,584 @@
+//! Flash driver of the STM32L4/G0/WB devices (`FlashType::TypeL4`, `TypeG0` and `TypeWB`)
+//! The flash is split in pages of `ChipParams::pagesize` bytes and programmed by
+//! double words, or by rows with fast programming. Dual bank L4 devices select
+//! the bank of a page with BKER. On WB the pages from SFSA hold the wireless
+//! stack of CPU2 and are never erased or programmed.
+
+use super::super::super::chipid::STM32ChipID;
+use super::super::super::dump::Progress;
+use super::super::super::enums::{ FlashType, Protection };
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::options::OptionsL4;
+use super::super::super::structs::{ FlashSector, ProtectedSector };
+
+use super::{ ERASE_TIMEOUT, MASS_ERASE_TIMEOUT, PROGRAM_TIMEOUT };
+use super::loader::Stub;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Busy flags of the status register
+const BUSY: u32 = {
+	use super::super::super::constants::flash::l4::sr::{ BSY, CFGBSY, PESD };
+	BSY | CFGBSY | PESD
+};
+
+/// Page size and start of the second bank, as an offset in the flash
+#[derive(Debug, Copy, Clone)]
+struct Geometry {
+	page: u32,
+	bank2: Option<u32>,
+}
+
+impl<T: Transport> Link<T> {
+	/// Write the key sequence to KEYR if the controller is locked
+	pub(super) fn l4_unlock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::{ register::KEYR, cr::LOCK };
+
+		let base = self.l4_base();
+		self.write_flash_keys(base + KEYR, self.l4_cr(), LOCK)
+	}
+
+	/// Set the LOCK bit, which also clears the operation bits
+	pub(super) fn l4_lock(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::cr::LOCK;
+
+		self.write_debug_reg(self.l4_cr(), LOCK)
+	}
+
+	/// Pages of the flash, numbered across both banks
+	/// The secure pages of CPU2 are left out on WB.
+	pub(super) fn l4_sectors(&mut self) -> Result<Vec<FlashSector>, LinkError> {
+		let flash = self.memory.flash;
+		let page = self.l4_geometry()?.page;
+
+		let end = match self.l4_secure_start()? {
+			Some(start) => start - flash.base,
+			None => flash.size * 1024,
+		};
+
+		Ok((0..end / page).map(|n| FlashSector { index: n as usize, address: flash.base + n * page, size: page }).collect())
+	}
+
+	/// Erase a single page
+	pub(super) fn l4_erase_page(&mut self, page: &FlashSector) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::cr::{ PER, BKER, STRT, PNB_SHIFT };
+
+		self.l4_check_secure(page.address, page.size as usize)?;
+
+		let geometry = self.l4_geometry()?;
+		let offset = page.address - self.memory.flash.base;
+
+		// The pages of the second bank are numbered from 0 again
+		let (bker, pnb) = match geometry.bank2 {
+			Some(bank2) if offset >= bank2 => (BKER, (offset - bank2) / geometry.page),
+			_ => (0, offset / geometry.page),
+		};
+
+		let cr = self.l4_cr();
+		let value = PER | bker | (pnb << PNB_SHIFT);
+
+		self.l4_prepare()?;
+		self.write_debug_reg(cr, value)?;
+		self.write_debug_reg(cr, value | STRT)?;
+
+		let status = self.wait_flash(self.l4_sr(), BUSY, ERASE_TIMEOUT)?;
+		self.l4_check(status)?;
+
+		self.write_debug_reg(cr, 0)
+	}
+
+	/// Erase every bank
+	/// On WB with a secure area the user pages are erased one by one instead.
+	pub(super) fn l4_mass_erase(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::cr::{ MER1, MER2, STRT };
+
+		if let Some(start) = self.l4_secure_start()? {
+			info!("The flash from 0x{:08X} belongs to CPU2. Erasing the pages below it.", start);
+
+			for page in self.l4_sectors()?.iter() {
+				self.l4_erase_page(page)?;
+			}
+
+			return Ok(());
+		}
+
+		let mer = match self.l4_geometry()?.bank2 {
+			Some(_) => MER1 | MER2,
+			None => MER1,
+		};
+
+		let cr = self.l4_cr();
+
+		self.l4_prepare()?;
+		self.write_debug_reg(cr, mer)?;
+		self.write_debug_reg(cr, mer | STRT)?;
+
+		let status = self.wait_flash(self.l4_sr(), BUSY, MASS_ERASE_TIMEOUT)?;
+		self.l4_check(status)?;
+
+		self.write_debug_reg(cr, 0)
+	}
+
+	/// Program `data` at `address` by double words, or by whole rows with fast
+	/// programming if enabled. The flash loader programs double words as two words.
+	/// The data is padded with erased bytes to double words.
+	pub(super) fn l4_program<P: Progress>(&mut self, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::{ cr::{ PG, FSTPG }, sr::ERRORS };
+
+		self.l4_check_secure(address, data.len())?;
+
+		let start = address & !7;
+		let head = (address - start) as usize;
+
+		let mut buf = vec![0xFF; head];
+		buf.extend_from_slice(data);
+		while !buf.len().is_multiple_of(8) {
+			buf.push(0xFF);
+		}
+
+		let row = self.l4_row() as usize;
+		let cr = self.l4_cr();
+		let sr = self.l4_sr();
+
+		self.l4_prepare()?;
+
+		progress.update(0, data.len());
+
+		if self.loader {
+			let stub = Stub { sr, busy: BUSY, errors: ERRORS, width: 4 };
+			let total = data.len();
+
+			self.write_debug_reg(cr, PG)?;
+
+			let result = self.loader_program(stub, start, &buf,
+				|done| progress.update(std::cmp::min(done.saturating_sub(head), total), total),
+				|link, status| link.l4_check(status));
+
+			self.write_debug_reg(cr, 0)?;
+			return result;
+		}
+
+		let mut offset = 0;
+
+		while offset < buf.len() {
+			let a = start + offset as u32;
+			let left = buf.len() - offset;
+
+			let (mode, n) = match self.fast && (a as usize).is_multiple_of(row) && left >= row {
+				true => (FSTPG, row),
+				false => {
+					// Blocks stop at the next row when fast programming may resume there
+					let limit = match self.fast {
+						true => row - (a as usize % row),
+						false => self.max_packet - (a as usize % self.max_packet),
+					};
+					(PG, std::cmp::min(limit, left))
+				},
+			};
+
+			self.write_debug_reg(cr, mode)?;
+			self.write_mem32(a, &buf[offset..offset + n])?;
+
+			let status = self.wait_flash(sr, BUSY, PROGRAM_TIMEOUT)?;
+			if let Err(e) = self.l4_check(status) {
+				error!("Flash program. Failed to program {} bytes at 0x{:08X}.", n, a);
+				self.write_debug_reg(cr, 0)?;
+				return Err(e);
+			}
+
+			offset += n;
+			progress.update(std::cmp::min(offset.saturating_sub(head), data.len()), data.len());
+		}
+
+		self.write_debug_reg(cr, 0)
+	}
+
+	/// Read OPTR and the WRP area registers
+	pub(super) fn l4_read_options(&mut self) -> Result<OptionsL4, LinkError> {
+		use super::super::super::constants::flash::l4::register::OPTR;
+
+		let (id, flasht) = match self.chip {
+			Some(ref chip) => (chip.id, chip.flasht),
+			None => (0, FlashType::TypeL4),
+		};
+
+		let base = self.l4_base();
+		let optr = self.read_debug_reg(base + OPTR)?;
+
+		let mut wrp = [None; 4];
+		for (i, reg) in self.l4_wrp_regs(id).iter().enumerate() {
+			if let Some(reg) = *reg {
+				wrp[i] = Some(self.read_debug_reg(base + reg)?);
+			}
+		}
+
+		Ok(OptionsL4::decode(id, flasht, optr, wrp))
+	}
+
+	/// Program OPTR and the WRP areas with OPTSTRT and load them with
+	/// OBL_LAUNCH, which resets the target. Going back from RDP level 1 to
+	/// level 0 mass erases the flash during OPTSTRT.
+	pub(super) fn l4_write_options(&mut self, options: &OptionsL4) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::{ l4::{ register::{ OPTKEYR, OPTR }, cr::{ OPTLOCK, OBL_LAUNCH } }, misc::{ OPTKEY1, OPTKEY2 } };
+
+		let (optr, wrp) = options.encode();
+		let base = self.l4_base();
+		let cr = self.l4_cr();
+
+		self.l4_prepare()?;
+		self.write_keys(base + OPTKEYR, cr, OPTLOCK, [OPTKEY1, OPTKEY2])?;
+
+		if let Err(e) = self.l4_program_options(optr, wrp) {
+			self.write_debug_reg(cr, OPTLOCK)?;
+			return Err(e);
+		}
+
+		if let Err(e) = self.write_debug_reg(cr, OBL_LAUNCH) {
+			debug!("Loading the option bytes reset the target: {}", e);
+		}
+
+		let read = self.l4_read_options()?.encode();
+
+		if read.0 != optr {
+			error!("Option bytes verification. OPTR is 0x{:08X} instead of 0x{:08X}.", read.0, optr);
+			return Err(FlashError::Verify(base + OPTR).into());
+		}
+
+		let id = self.chip.as_ref().map(|c| c.id).unwrap_or(0);
+
+		for (i, reg) in self.l4_wrp_regs(id).iter().enumerate() {
+			if let (Some(reg), true) = (*reg, read.1[i] != wrp[i]) {
+				error!("Option bytes verification. The WRP area register at 0x{:08X} is 0x{:08X} instead of 0x{:08X}.", base + reg, read.1[i].unwrap_or(0), wrp[i].unwrap_or(0));
+				return Err(FlashError::Verify(base + reg).into());
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Pages protected by the WRP areas, and by PCROP on L4 devices
+	/// A page in both is reported as PCROP protected.
+	pub(super) fn l4_protection(&mut self) -> Result<Vec<ProtectedSector>, LinkError> {
+		let options = self.l4_read_options()?;
+		let geometry = self.l4_geometry()?;
+		let base = self.memory.flash.base;
+
+		let mut wrp = Vec::new();
+		for (i, area) in options.wrp.iter().enumerate() {
+			if let (Some((start, end)), Some(bank)) = (*area, self.l4_bank_start(i / 2, geometry)) {
+				wrp.push((bank + start * geometry.page, bank + (end + 1) * geometry.page));
+			}
+		}
+
+		let pcrop = self.l4_pcrop(geometry)?;
+		let inside = |areas: &[(u32, u32)], page: &FlashSector| areas.iter().any(|&(start, end)| page.overlaps(base + start, (end - start) as usize));
+
+		Ok(self.l4_sectors()?.into_iter()
+			.filter_map(|page| match (inside(&pcrop, &page), inside(&wrp, &page)) {
+				(true, _) => Some(ProtectedSector { sector: page, protection: Protection::Pcrop }),
+				(false, true) => Some(ProtectedSector { sector: page, protection: Protection::Write }),
+				_ => None,
+			})
+			.collect())
+	}
+
+	/// Add the `pages` to the WRP areas of their bank, or take them out
+	/// A bank has two areas, an area is grown to cover pages next to it.
+	pub(super) fn l4_protect(&mut self, options: &mut OptionsL4, pages: &[FlashSector], protect: bool) -> Result<(), LinkError> {
+		let geometry = self.l4_geometry()?;
+		let base = self.memory.flash.base;
+
+		for bank in 0..2 {
+			let start = match self.l4_bank_start(bank, geometry) {
+				Some(start) => start,
+				None => continue,
+			};
+
+			// Page numbers in the bank
+			let numbers = pages.iter()
+				.map(|p| p.address - base)
+				.filter(|o| *o >= start && (bank == 1 || geometry.bank2.map(|b| *o < b).unwrap_or(true)))
+				.map(|o| (o - start) / geometry.page)
+				.collect::<Vec<_>>();
+
+			let (first, last) = match (numbers.first(), numbers.last()) {
+				(Some(first), Some(last)) => (*first, *last),
+				_ => continue,
+			};
+
+			let areas = &mut options.wrp[2 * bank..2 * bank + 2];
+
+			let done = match protect {
+				true => protect_area(areas, first, last),
+				false => unprotect_area(areas, first, last),
+			};
+
+			if !done {
+				error!("Write protection. Pages {} to {} of bank {} need a third WRP area.", first, last, bank + 1);
+				return Err(LinkError::Argument("write protection areas of the bank"));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Write the unlocked OPTR and WRP area registers and start the programming
+	fn l4_program_options(&mut self, optr: u32, wrp: [Option<u32>; 4]) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::{ register::OPTR, cr::OPTSTRT };
+
+		let base = self.l4_base();
+		let cr = self.l4_cr();
+		let id = self.chip.as_ref().map(|c| c.id).unwrap_or(0);
+
+		for (reg, value) in self.l4_wrp_regs(id).iter().zip(wrp.iter()) {
+			if let (Some(reg), Some(value)) = (*reg, *value) {
+				self.write_debug_reg(base + reg, value)?;
+			}
+		}
+
+		self.write_debug_reg(base + OPTR, optr)?;
+		self.write_debug_reg(cr, OPTSTRT)?;
+
+		let status = self.wait_flash(self.l4_sr(), BUSY, MASS_ERASE_TIMEOUT)?;
+		self.l4_check(status)?;
+
+		self.write_debug_reg(cr, 0)
+	}
+
+	/// Wait for the end of any ongoing operation and clear the flags
+	fn l4_prepare(&mut self) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::sr::{ EOP, ERRORS };
+
+		let sr = self.l4_sr();
+		let status = self.wait_flash(sr, BUSY, ERASE_TIMEOUT)?;
+
+		if status & (EOP | ERRORS) != 0 {
+			debug!("Flash SR 0x{:08X}. Clearing the flags of the last operation.", status);
+			self.write_debug_reg(sr, EOP | ERRORS)?;
+		}
+
+		Ok(())
+	}
+
+	/// Decode the error flags of the status register, clearing them on error
+	fn l4_check(&mut self, status: u32) -> Result<(), LinkError> {
+		use super::super::super::constants::flash::l4::sr::*;
+
+		let error = if status & WRPERR != 0 {
+			FlashError::WriteProtected
+		} else if status & PROGERR != 0 {
+			FlashError::NotErased
+		} else if status & PGAERR != 0 {
+			FlashError::Alignment
+		} else if status & SIZERR != 0 {
+			FlashError::Size
+		} else if status & (MISERR | FASTERR) != 0 {
+			FlashError::FastProgramming
+		} else if status & PGSERR != 0 {
+			FlashError::Sequence
+		} else if status & RDERR != 0 {
+			FlashError::ReadProtected
+		} else if status & OPERR != 0 {
+			FlashError::Operation
+		} else {
+			return Ok(());
+		};
+
+		error!("Flash operation failed with SR 0x{:08X}: {}", status, error);
+		self.write_debug_reg(self.l4_sr(), ERRORS)?;
+
+		Err(error.into())
+	}
+
+	/// Address ranges of the PCROP areas, as offsets in the flash
+	/// Only read on L4 devices, the PCROP registers of L4Rx/L4Sx, G0 and WB differ.
+	fn l4_pcrop(&mut self, geometry: Geometry) -> Result<Vec<(u32, u32)>, LinkError> {
+		use super::super::super::constants::flash::l4::{ register::{ PCROP1SR, PCROP1ER, PCROP2SR, PCROP2ER }, pcrop::OFFSET };
+
+		match self.chip {
+			Some(ref chip) if chip.flasht == FlashType::TypeL4 && chip.id != STM32ChipID::L4RX as u32 => (),
+			_ => return Ok(Vec::new()),
+		}
+
+		let base = self.l4_base();
+		let mut areas = Vec::new();
+
+		for (bank, (sr, er)) in [(PCROP1SR, PCROP1ER), (PCROP2SR, PCROP2ER)].iter().enumerate() {
+			let start = match self.l4_bank_start(bank, geometry) {
+				Some(start) => start,
+				None => continue,
+			};
+
+			let first = self.read_debug_reg(base + sr)? & OFFSET;
+			let last = self.read_debug_reg(base + er)? & OFFSET;
+
+			if first <= last {
+				areas.push((start + first * 8, start + (last + 1) * 8));
+			}
+		}
+
+		Ok(areas)
+	}
+
+	/// Offsets of WRP1AR, WRP1BR, WRP2AR and WRP2BR, those of the second bank only on dual bank devices
+	fn l4_wrp_regs(&self, id: u32) -> [Option<u32>; 4] {
+		use super::super::super::constants::flash::l4::register::{ WRP1AR, WRP1BR, WRP2AR, WRP2BR };
+
+		match OptionsL4::has_bank2(id) {
+			true => [Some(WRP1AR), Some(WRP1BR), Some(WRP2AR), Some(WRP2BR)],
+			false => [Some(WRP1AR), Some(WRP1BR), None, None],
+		}
+	}
+
+	/// Offset of the bank in the flash, `None` for the second bank in single bank mode
+	fn l4_bank_start(&self, bank: usize, geometry: Geometry) -> Option<u32> {
+		match bank {
+			0 => Some(0),
+			_ => geometry.bank2,
+		}
+	}
+
+	/// Refuse the ranges that reach into the secure area of CPU2
+	fn l4_check_secure(&mut self, address: u32, size: usize) -> Result<(), LinkError> {
+		match self.l4_secure_start()? {
+			Some(start) if address as u64 + size as u64 > start as u64 => {
+				error!("Flash protocol. The flash from 0x{:08X} belongs to CPU2 and cannot be changed.", start);
+				Err(FlashError::Address(std::cmp::max(address, start)).into())
+			},
+			_ => Ok(()),
+		}
+	}
+
+	/// Start of the secure area of CPU2 on WB devices, `None` without one
+	fn l4_secure_start(&mut self) -> Result<Option<u32>, LinkError> {
+		use super::super::super::constants::flash::l4::{ register::SFR, sfr::{ SFSA, FSD } };
+
+		if self.chip.as_ref().map(|c| c.flasht) != Some(FlashType::TypeWB) {
+			return Ok(None);
+		}
+
+		let sfr = self.read_debug_reg(self.l4_base() + SFR)?;
+		let flash = self.memory.flash;
+		let start = flash.base + (sfr & SFSA) * self.l4_geometry()?.page;
+
+		match sfr & FSD != 0 || start as u64 >= flash.base as u64 + flash.size as u64 * 1024 {
+			true => Ok(None),
+			false => Ok(Some(start)),
+		}
+	}
+
+	/// Page size and bank mode from the chip ID and the option bytes
+	/// L47x/L48x and L49x/L4Ax are dual bank with 1 MB or with DUALBANK set,
+	/// L4Rx/L4Sx with DBANK set, which also halves the page size.
+	fn l4_geometry(&mut self) -> Result<Geometry, LinkError> {
+		use super::super::super::constants::flash::l4::{ register::OPTR, optr::{ DUALBANK, DBANK } };
+
+		let size = self.memory.flash.size * 1024;
+		let (id, page) = match self.chip {
+			Some(ref chip) if chip.pagesize != 0 => (chip.id, chip.pagesize),
+			Some(ref chip) => (chip.id, 0x800),
+			None => (0, 0x800),
+		};
+
+		let optr = self.l4_base() + OPTR;
+
+		match id {
+			id if id == STM32ChipID::L4 as u32 || id == STM32ChipID::L496X as u32 => match size >= 1024 * 1024 || self.read_debug_reg(optr)? & DUALBANK != 0 {
+				true => Ok(Geometry { page, bank2: Some(size / 2) }),
+				false => Ok(Geometry { page, bank2: None }),
+			},
+			id if id == STM32ChipID::L4RX as u32 => match self.read_debug_reg(optr)? & DBANK {
+				0 => Ok(Geometry { page: 0x2000, bank2: None }),
+				_ => Ok(Geometry { page: 0x1000, bank2: Some(size / 2) }),
+			},
+			_ => Ok(Geometry { page, bank2: None }),
+		}
+	}
+
+	/// Bytes of a fast programming row
+	fn l4_row(&self) -> u32 {
+		use super::super::super::constants::flash::l4::{ ROW, WB_ROW };
+
+		match self.chip.as_ref().map(|c| c.flasht) {
+			Some(FlashType::TypeWB) => WB_ROW,
+			_ => ROW,
+		}
+	}
+
+	/// Base of the registers, which differs on WB
+	fn l4_base(&self) -> u32 {
+		use super::super::super::constants::flash::l4::{ L4_BASE, WB_BASE };
+
+		match self.chip.as_ref().map(|c| c.flasht) {
+			Some(FlashType::TypeWB) => WB_BASE,
+			_ => L4_BASE,
+		}
+	}
+
+	/// Address of CR
+	fn l4_cr(&self) -> u32 {
+		self.l4_base() + super::super::super::constants::flash::l4::register::CR
+	}
+
+	/// Address of SR
+	fn l4_sr(&self) -> u32 {
+		self.l4_base() + super::super::super::constants::flash::l4::register::SR
+	}
+}
+
+/// Protect the pages `first` to `last` with one of the two `areas` of a bank
+/// An area that overlaps or touches the pages is grown, otherwise a disabled
+/// area is used. Returns false if both areas are in use elsewhere.
+fn protect_area(areas: &mut [Option<(u32, u32)>], first: u32, last: u32) -> bool {
+	for area in areas.iter_mut() {
+		if let Some((start, end)) = *area {
+			if first <= end + 1 && start <= last + 1 {
+				*area = Some((std::cmp::min(start, first), std::cmp::max(end, last)));
+				return true;
+			}
+		}
+	}
+
+	match areas.iter_mut().find(|a| a.is_none()) {
+		Some(area) => {
+			*area = Some((first, last));
+			true
+		},
+		None => false,
+	}
+}
+
+/// Take the pages `first` to `last` out of the `areas` of a bank
+/// Splitting an area in two needs the other area. Returns false if it is in use.
+fn unprotect_area(areas: &mut [Option<(u32, u32)>], first: u32, last: u32) -> bool {
+	let mut rest = None;
+
+	for area in areas.iter_mut() {
+		if let Some((start, end)) = *area {
+			if last < start || first > end {
+				continue;
+			}
+
+			*area = match (first <= start, last >= end) {
+				(true, true) => None,
+				(true, false) => Some((last + 1, end)),
+				(false, true) => Some((start, first - 1)),
+				(false, false) => {
+					rest = Some((last + 1, end));
+					Some((start, first - 1))
+				},
+			};
+		}
+	}
+
+	match (rest, areas.iter_mut().find(|a| a.is_none())) {
+		(None, _) => true,
+		(Some(pages), Some(area)) => {
+			*area = Some(pages);
+			true
+		},
+		(Some(_), None) => false,
+	}
+}
@@ -0,0 +1,200 @@
+//! Flash loader
+//! A small Thumb routine downloaded to the start of the SRAM copies the data
+//! from a RAM buffer to the flash and polls the status register itself. The
+//! host fills the second buffer while the stub programs the first one, so the
+//! flash is programmed at the speed of the memory writes instead of waiting on
+//! the controller after every block. The drivers prepare the controller and
+//! select the program mode, `loader_program` only streams the data.
+
+use std::time::{ Duration, Instant };
+
+use super::super::super::error::{ LinkError, FlashError };
+use super::super::super::structs::SRamInfo;
+
+use super::ERASE_TIMEOUT;
+
+use super::super::Link;
+use super::super::super::transport::Transport;
+
+
+/// Status register of the controller and width of the writes of the stub
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Stub {
+	/// Address of the status register
+	pub sr: u32,
+	/// Flags of the status register to wait for after each write
+	pub busy: u32,
+	/// Flags of the status register that stop the stub
+	pub errors: u32,
+	/// Width of the flash writes: 1, 2 or 4 bytes
+	pub width: usize,
+}
+
+/// Bytes for the code and the stack at the start of the SRAM
+const STUB_SPACE: u32 = 0x100;
+/// Largest data buffer
+const MAX_BUFFER: u32 = 0x4000;
+/// Smallest data buffer
+const MIN_BUFFER: u32 = 0x100;
+/// Offset of the BKPT instruction ending the stub
+const BKPT: u32 = 22;
+/// xPSR with the Thumb bit
+pub(super) const XPSR_THUMB: u32 = 1 << 24;
+
+impl<T: Transport> Link<T> {
+	/// Program `buf` at `start` with the flash loader
+	/// `start` and the length of `buf` are multiples of the stub width. `progress`
+	/// is called with the number of bytes of `buf` programmed, and `check` with the
+	/// status register when the stub stops on an error. The core is left halted.
+	pub(super) fn loader_program<F, C>(&mut self, stub: Stub, start: u32, buf: &[u8], mut progress: F, check: C) -> Result<(), LinkError>
+		where F: FnMut(usize), C: Fn(&mut Self, u32) -> Result<(), LinkError>
+	{
+		let ram = self.loader_ram()?;
+		let size = loader_buffer(ram);
+
+		let code = ram.base;
+		let buffers = [ram.base + STUB_SPACE, ram.base + STUB_SPACE + size];
+
+		debug!("Flash loader at 0x{:08X} with two buffers of {} bytes", code, size);
+
+		self.halt()?;
+		self.write_mem32(code, &stub_code(stub.width))?;
+
+		// Blocks end at a multiple of the buffer size
+		let mut blocks = Vec::new();
+		let mut offset = 0;
+		while offset < buf.len() {
+			let a = start + offset as u32;
+			let n = std::cmp::min((size - a % size) as usize, buf.len() - offset);
+			blocks.push(offset..offset + n);
+			offset += n;
+		}
+
+		if let Some(first) = blocks.first() {
+			self.loader_fill(buffers[0], &buf[first.clone()])?;
+		}
+
+		for (i, block) in blocks.iter().enumerate() {
+			let address = start + block.start as u32;
+			let units = (block.len() / stub.width) as u32;
+
+			self.write_reg(0, buffers[i % 2])?;
+			self.write_reg(1, address)?;
+			self.write_reg(2, units)?;
+			self.write_reg(3, stub.sr)?;
+			self.write_reg(4, stub.errors)?;
+			self.write_reg(7, stub.busy)?;
+			self.write_reg(13, ram.base + STUB_SPACE)?;
+			self.write_reg(15, code)?;
+			self.write_reg(16, XPSR_THUMB)?;
+			self.run()?;
+
+			// Fill the other buffer while the stub runs
+			if let Some(next) = blocks.get(i + 1) {
+				self.loader_fill(buffers[(i + 1) % 2], &buf[next.clone()])?;
+			}
+
+			let pc = self.wait_halt(ERASE_TIMEOUT)?;
+			if pc != code + BKPT {
+				error!("Flash loader. The core halted at 0x{:08X} instead of the end of the stub.", pc);
+				return Err(FlashError::Loader(self.read_reg(1)?).into());
+			}
+
+			let left = self.read_reg(2)?;
+			if left != 0 {
+				let failed = address + (units - left) * stub.width as u32;
+				let status = self.read_debug_reg(stub.sr)?;
+				check(self, status)?;
+
+				error!("Flash loader. The stub stopped at 0x{:08X} with SR 0x{:08X}.", failed, status);
+				return Err(FlashError::Loader(failed).into());
+			}
+
+			progress(block.end);
+		}
+
+		Ok(())
+	}
+
+	/// SRAM region of the chip holding the loader
+	fn loader_ram(&self) -> Result<SRamInfo, LinkError> {
+		match self.memory.ram.iter().max_by_key(|r| r.size) {
+			Some(r) if r.size >= STUB_SPACE + 2 * MIN_BUFFER => Ok(*r),
+			_ => {
+				error!("Flash loader. The chip has no SRAM region large enough for the loader.");
+				Err(LinkError::Unsupported("flash loader without SRAM"))
+			},
+		}
+	}
+
+	/// Write `data` into the buffer at `address`, padded to whole words
+	pub(super) fn loader_fill(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		let mut words = data.to_vec();
+		words.resize(data.len().div_ceil(4) * 4, 0);
+
+		let mut offset = 0;
+		while offset < words.len() {
+			let n = std::cmp::min(self.max_packet, words.len() - offset);
+			self.write_mem32(address + offset as u32, &words[offset..offset + n])?;
+			offset += n;
+		}
+
+		Ok(())
+	}
+
+	/// Wait for the core to halt, for at most `timeout`
+	/// Returns the PC. The core is halted on timeout.
+	pub(super) fn wait_halt(&mut self, timeout: Duration) -> Result<u32, LinkError> {
+		use super::super::super::constants::registers::dcb::{ DHCSREG, dhcsr::S_HALT };
+
+		let start = Instant::now();
+
+		while self.read_debug_reg(DHCSREG)? & S_HALT == 0 {
+			if start.elapsed() > timeout {
+				error!("Flash protocol. The code running in the target did not halt after {} ms.", timeout.as_millis());
+				self.halt()?;
+				return Err(FlashError::Busy.into());
+			}
+
+			std::thread::sleep(Duration::from_millis(1));
+		}
+
+		self.read_reg(15)
+	}
+}
+
+
+/// Size of each of the two data buffers in `ram`, after the code and the stack
+fn loader_buffer(ram: SRamInfo) -> u32 {
+	let available = std::cmp::min((ram.size - STUB_SPACE) / 2, MAX_BUFFER);
+
+	1 << (31 - available.leading_zeros())
+}
+
+/// Thumb code of the stub, copying `r2` units of `width` bytes from `r0` to `r1`
+/// After each unit it waits for the `r7` flags of the status register at `r3`
+/// to clear, and stops early on the `r4` flags. `r2` holds the units left.
+fn stub_code(width: usize) -> Vec<u8> {
+	let (load, store) = match width {
+		1 => (0x7805, 0x700D), // ldrb r5, [r0] ; strb r5, [r1]
+		2 => (0x8805, 0x800D), // ldrh r5, [r0] ; strh r5, [r1]
+		_ => (0x6805, 0x600D), // ldr  r5, [r0] ; str  r5, [r1]
+	};
+
+	let code: [u16; 12] = [
+		load,
+		store,
+		0x681E,                 // wait: ldr r6, [r3]
+		0x423E,                 //       tst r6, r7
+		0xD1FC,                 //       bne wait
+		0x4226,                 //       tst r6, r4
+		0xD103,                 //       bne end
+		0x3000 | width as u16,  //       adds r0, #width
+		0x3100 | width as u16,  //       adds r1, #width
+		0x3A01,                 //       subs r2, #1
+		0xD1F4,                 //       bne start
+		0xBE00,                 // end:  bkpt #0
+	];
+
+	code.iter().flat_map(|h| h.to_le_bytes().to_vec()).collect()
+}
@@ -0,0 +1,569 @@
+//! Flash interaction
+//! The public methods select the driver of the flash controller from the
+//! `FlashType` of the identified chip. Each driver lives in its own file.
+
+mod f4;
+mod f1;
+mod l0;
+mod l4;
+mod loader;
+mod algorithm;
+
+use std::time::{ Duration, Instant };
+
+use super::super::algorithm::FlashAlgorithm;
+use super::super::dump::Progress;
+use super::super::enums::{ FlashType, Parallelism };
+use super::super::error::{ LinkError, FlashError };
+use super::super::options::{ OptionBytes, RdpLevel, rdp2_confirmation };
+use super::super::structs::{ FlashSector, ProtectedSector, SRamInfo };
+
+use super::Link;
+use super::super::transport::Transport;
+
+
+/// Maximum time of a sector erase
+const ERASE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum time of a mass erase
+const MASS_ERASE_TIMEOUT: Duration = Duration::from_secs(40);
+/// Maximum time to program a block of data
+const PROGRAM_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl<T: Transport> Link<T> {
+	/// Unlock Flash by unlocking the flash register
+	/// This is done by writing a sequence to the FLASH KEY Register
+	pub fn unlock_flash(&mut self) -> Result<(), LinkError> {
+		match self.flash_type()? {
+			FlashType::TypeF4 => self.f4_unlock(),
+			FlashType::TypeF0 | FlashType::TypeF1XL => self.f1_unlock(),
+			FlashType::TypeL0 => self.l0_unlock(),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => self.l4_unlock(),
+			t => Err(Self::no_flash_driver(t)),
+		}
+	}
+
+	/// Lock the flash registers until the next unlock or reset
+	pub fn lock_flash(&mut self) -> Result<(), LinkError> {
+		match self.flash_type()? {
+			FlashType::TypeF4 => self.f4_lock(),
+			FlashType::TypeF0 | FlashType::TypeF1XL => self.f1_lock(),
+			FlashType::TypeL0 => self.l0_lock(),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => self.l4_lock(),
+			t => Err(Self::no_flash_driver(t)),
+		}
+	}
+
+	/// Erasable sectors of the flash, in address order
+	pub fn flash_sectors(&mut self) -> Result<Vec<FlashSector>, LinkError> {
+		if let Some(ref algorithm) = self.algorithm {
+			return Ok(algorithm.device.flash_sectors());
+		}
+
+		match self.flash_type()? {
+			FlashType::TypeF4 => self.f4_sectors(),
+			FlashType::TypeF0 | FlashType::TypeF1XL => self.f1_sectors(),
+			FlashType::TypeL0 => self.l0_sectors(),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => self.l4_sectors(),
+			t => Err(Self::no_flash_driver(t)),
+		}
+	}
+
+	/// Flash parallelism used to program and erase
+	/// Chosen from the target voltage unless set with `set_flash_parallelism`
+	pub fn flash_parallelism(&mut self) -> Parallelism {
+		if let Some(p) = self.psize {
+			return p;
+		}
+
+		match self.voltage() {
+			Ok(v) => Parallelism::from_voltage(v),
+			Err(_) => {
+				warn!("Target voltage unknown. Using 32 bit flash parallelism.");
+				Parallelism::X32
+			},
+		}
+	}
+
+	/// Force the flash parallelism, `None` to choose it from the target voltage
+	pub fn set_flash_parallelism(&mut self, psize: Option<Parallelism>) {
+		self.psize = psize;
+	}
+
+	/// Program whole rows with fast programming on the L4/G0/WB devices
+	/// The bank must be mass erased first. The other controllers ignore it.
+	pub fn set_fast_programming(&mut self, fast: bool) {
+		self.fast = fast;
+	}
+
+	/// Program the flash with a loader running in the target SRAM
+	/// The core is halted and its registers and SRAM are overwritten. The loader
+	/// programs by words, half-words on F0/F1/F3, without fast programming.
+	pub fn set_flash_loader(&mut self, loader: bool) {
+		self.loader = loader;
+	}
+
+	/// Erase and program the flash with a CMSIS flash algorithm, `None` for the driver of the chip
+	/// The flash and its sectors are taken from the algorithm, so chips without a
+	/// driver can be programmed. The core is halted and its registers and SRAM are
+	/// overwritten. The option bytes still need the driver.
+	pub fn set_flash_algorithm(&mut self, algorithm: Option<FlashAlgorithm>) {
+		self.algorithm = algorithm;
+	}
+
+	/// Flash algorithm set with `set_flash_algorithm`
+	pub fn flash_algorithm(&self) -> Option<&FlashAlgorithm> {
+		self.algorithm.as_ref()
+	}
+
+	/// SRAM region where the flash algorithm runs, `None` for the largest SRAM region of the chip
+	/// Needed for the chips that are not identified.
+	pub fn set_algorithm_ram(&mut self, ram: Option<SRamInfo>) {
+		self.algorithm_ram = ram;
+	}
+
+	/// Erase the sectors with the given numbers
+	/// The flash is locked again afterwards
+	pub fn erase_sectors(&mut self, sectors: &[usize]) -> Result<(), LinkError> {
+		let layout = self.flash_sectors()?;
+
+		let mut selected = Vec::new();
+		for n in sectors {
+			match layout.iter().find(|s| s.index == *n) {
+				Some(s) => selected.push(*s),
+				None => {
+					error!("Flash erase. The chip has no sector {}.", n);
+					return Err(FlashError::Sector(*n).into());
+				},
+			}
+		}
+
+		if let Some(algorithm) = self.algorithm.clone() {
+			return self.algorithm_erase(&algorithm, &selected);
+		}
+
+		let flasht = self.flash_type()?;
+
+		self.unlocked(|link| {
+			for sector in selected.iter() {
+				info!("Erasing sector {} ({} kB at 0x{:08X})", sector.index, sector.size / 1024, sector.address);
+
+				let result = match flasht {
+					FlashType::TypeF4 => link.f4_erase_sector(sector),
+					FlashType::TypeF0 | FlashType::TypeF1XL => link.f1_erase_page(sector),
+					FlashType::TypeL0 => link.l0_erase_page(sector),
+					FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => link.l4_erase_page(sector),
+					t => return Err(Self::no_flash_driver(t)),
+				};
+
+				if let Err(e) = result {
+					return Err(link.protection_error(e, sector.address, sector.size as usize));
+				}
+			}
+
+			Ok(())
+		})
+	}
+
+	/// Erase all the sectors overlapping the `size` bytes at `address`
+	/// Returns the numbers of the erased sectors
+	pub fn erase_range(&mut self, address: u32, size: usize) -> Result<Vec<usize>, LinkError> {
+		self.check_flash_range(address, size)?;
+
+		let sectors = self.flash_sectors()?.iter()
+			.filter(|s| s.overlaps(address, size))
+			.map(|s| s.index)
+			.collect::<Vec<_>>();
+
+		self.erase_sectors(&sectors)?;
+
+		Ok(sectors)
+	}
+
+	/// Erase the whole flash, all banks included
+	pub fn mass_erase(&mut self) -> Result<(), LinkError> {
+		if let Some(algorithm) = self.algorithm.clone() {
+			info!("Erasing the whole flash");
+			return self.algorithm_mass_erase(&algorithm);
+		}
+
+		let flasht = self.flash_type()?;
+
+		info!("Erasing the whole flash");
+
+		let result = self.unlocked(|link| match flasht {
+			FlashType::TypeF4 => link.f4_mass_erase(),
+			FlashType::TypeF0 | FlashType::TypeF1XL => link.f1_mass_erase(),
+			FlashType::TypeL0 => link.l0_mass_erase(),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => link.l4_mass_erase(),
+			t => Err(Self::no_flash_driver(t)),
+		});
+
+		let flash = self.memory.flash;
+		result.map_err(|e| self.protection_error(e, flash.base, flash.size as usize * 1024))
+	}
+
+	/// Program `data` into erased flash at `address` and verify it
+	/// `progress` is updated with the number of bytes programmed.
+	pub fn program_flash<P: Progress>(&mut self, address: u32, data: &[u8], progress: &mut P) -> Result<(), LinkError> {
+		self.check_flash_range(address, data.len())?;
+
+		if let Some(algorithm) = self.algorithm.clone() {
+			self.algorithm_program(&algorithm, address, data, progress)?;
+			return self.verify_flash(address, data);
+		}
+
+		let flasht = self.flash_type()?;
+
+		let result = self.unlocked(|link| match flasht {
+			FlashType::TypeF4 => link.f4_program(address, data, progress),
+			FlashType::TypeF0 | FlashType::TypeF1XL => link.f1_program(address, data, progress),
+			FlashType::TypeL0 => link.l0_program(address, data, progress),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => link.l4_program(address, data, progress),
+			t => Err(Self::no_flash_driver(t)),
+		});
+
+		if let Err(e) = result {
+			return Err(self.protection_error(e, address, data.len()));
+		}
+
+		self.verify_flash(address, data)
+	}
+
+	/// Check that the flash at `address` holds `data`
+	pub fn verify_flash(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		match self.compare_memory(address, data)?.first() {
+			None => Ok(()),
+			Some(m) => {
+				error!("Flash verification. {} bytes differ at 0x{:08X}.", m.len(), m.address);
+				Err(FlashError::Verify(m.address).into())
+			},
+		}
+	}
+
+	/// Read `size` bytes of the data EEPROM at `address`
+	pub fn read_eeprom(&mut self, address: u32, size: usize) -> Result<Vec<u8>, LinkError> {
+		self.check_eeprom_range(address, size)?;
+
+		self.read_memory(address, size)
+	}
+
+	/// Write `data` into the data EEPROM at `address` and verify it
+	/// The controller erases the EEPROM before writing, no erase is needed.
+	pub fn write_eeprom(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		self.check_eeprom_range(address, data.len())?;
+
+		match self.flash_type()? {
+			FlashType::TypeL0 => self.l0_write_eeprom(address, data)?,
+			t => return Err(Self::no_flash_driver(t)),
+		}
+
+		self.verify_flash(address, data)
+	}
+
+	/// Option bytes of the chip, decoded for its flash controller
+	pub fn option_bytes(&mut self) -> Result<OptionBytes, LinkError> {
+		match self.flash_type()? {
+			FlashType::TypeF4 => Ok(OptionBytes::F4(self.f4_read_options()?)),
+			FlashType::TypeF0 | FlashType::TypeF1XL => Ok(OptionBytes::F1(self.f1_read_options()?)),
+			FlashType::TypeL0 => Ok(OptionBytes::L0(self.l0_read_options()?)),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => Ok(OptionBytes::L4(self.l4_read_options()?)),
+			t => {
+				error!("Option bytes. No driver for the option bytes of the {:?} flash controller.", t);
+				Err(LinkError::Unsupported("option bytes of this chip"))
+			},
+		}
+	}
+
+	/// Program the option bytes, load them and check them
+	/// The RDP level cannot be changed here. Loading the option bytes resets
+	/// the L0/L1, L4/G0/WB and F0/F3 devices, F1 devices are reset and halted.
+	pub fn write_option_bytes(&mut self, options: &OptionBytes) -> Result<(), LinkError> {
+		let current = self.option_bytes()?;
+
+		if current.rdp() != options.rdp() {
+			error!("Option bytes. The RDP level cannot be changed from {} to {} with the other option bytes.", current.rdp(), options.rdp());
+			return Err(LinkError::Argument("RDP level of the option bytes"));
+		}
+
+		self.program_option_bytes(current, options)
+	}
+
+	/// Read protection level of the chip
+	pub fn rdp_level(&mut self) -> Result<RdpLevel, LinkError> {
+		Ok(self.option_bytes()?.rdp())
+	}
+
+	/// Change the read protection level
+	/// Going back from level 1 to level 0 mass erases the flash, and the data
+	/// EEPROM of the L0/L1 devices. Level 2 cannot be left and needs the token
+	/// of `rdp2_confirmation` for the chip.
+	pub fn set_rdp_level(&mut self, level: RdpLevel, confirmation: Option<&str>) -> Result<(), LinkError> {
+		let current = self.option_bytes()?;
+
+		match (current.rdp(), level) {
+			(from, to) if from == to => {
+				info!("The read protection is already at {}.", level);
+				return Ok(());
+			},
+			(RdpLevel::Level2, _) => {
+				error!("Read protection. RDP level 2 is permanent.");
+				return Err(LinkError::Argument("RDP level 2 cannot be left"));
+			},
+			(_, RdpLevel::Level2) => {
+				if !current.has_level2() {
+					error!("Read protection. The chip has no RDP level 2.");
+					return Err(LinkError::Unsupported("RDP level 2 of this chip"));
+				}
+
+				let token = rdp2_confirmation(self.chip.as_ref().map(|c| c.id).unwrap_or(0));
+
+				if confirmation != Some(token.as_str()) {
+					error!("Read protection. RDP level 2 disables the debug port for good and needs the confirmation '{}'.", token);
+					return Err(LinkError::Argument("confirmation of RDP level 2"));
+				}
+
+				warn!("Setting RDP level 2. The debug port of the chip will be disabled for good.");
+			},
+			(RdpLevel::Level1, RdpLevel::Level0) => match current {
+				OptionBytes::L0(_) => warn!("Going back to RDP level 0. The whole flash and the data EEPROM will be erased."),
+				_ => warn!("Going back to RDP level 0. The whole flash will be erased."),
+			},
+			_ => info!("Setting RDP {}", level),
+		}
+
+		self.program_option_bytes(current, &current.with_rdp(level))
+	}
+
+	/// Sectors protected by the option bytes, in address order
+	pub fn protected_sectors(&mut self) -> Result<Vec<ProtectedSector>, LinkError> {
+		match self.flash_type()? {
+			FlashType::TypeF4 => self.f4_protection(),
+			FlashType::TypeF0 | FlashType::TypeF1XL => self.f1_protection(),
+			FlashType::TypeL0 => self.l0_protection(),
+			FlashType::TypeL4 | FlashType::TypeG0 | FlashType::TypeWB => self.l4_protection(),
+			t => Err(Self::no_flash_driver(t)),
+		}
+	}
+
+	/// Write protect the sectors overlapping the `size` bytes at `address`
+	/// Returns the numbers of the sectors. The option bytes are programmed and
+	/// loaded as with `write_option_bytes`.
+	pub fn protect_range(&mut self, address: u32, size: usize) -> Result<Vec<usize>, LinkError> {
+		self.change_protection(address, size, true)
+	}
+
+	/// Remove the write protection of the sectors overlapping the `size` bytes at `address`
+	/// Returns the numbers of the sectors. PCROP is left as it is.
+	pub fn unprotect_range(&mut self, address: u32, size: usize) -> Result<Vec<usize>, LinkError> {
+		self.change_protection(address, size, false)
+	}
+
+	/// Change the write protection of the sectors of a range in the option bytes
+	fn change_protection(&mut self, address: u32, size: usize, protect: bool) -> Result<Vec<usize>, LinkError> {
+		self.check_flash_range(address, size)?;
+
+		let sectors = self.flash_sectors()?.into_iter()
+			.filter(|s| s.overlaps(address, size))
+			.collect::<Vec<_>>();
+
+		let current = self.option_bytes()?;
+		let mut options = current;
+
+		match options {
+			OptionBytes::F4(ref mut o) => self.f4_protect(o, &sectors, protect)?,
+			OptionBytes::F1(ref mut o) => self.f1_protect(o, &sectors, protect)?,
+			OptionBytes::L0(ref mut o) => self.l0_protect(o, &sectors, protect)?,
+			OptionBytes::L4(ref mut o) => self.l4_protect(o, &sectors, protect)?,
+		}
+
+		self.program_option_bytes(current, &options)?;
+
+		Ok(sectors.iter().map(|s| s.index).collect())
+	}
+
+	/// Name the protected sector behind the write protection error of an
+	/// operation on the `size` bytes at `address`. Other errors are returned as they are.
+	fn protection_error(&mut self, error: LinkError, address: u32, size: usize) -> LinkError {
+		match error {
+			LinkError::Flash(FlashError::WriteProtected) => (),
+			e => return e,
+		}
+
+		match self.protected_sectors() {
+			Ok(protected) => match protected.iter().find(|p| p.sector.overlaps(address, size)) {
+				Some(p) => {
+					error!("Flash protocol. Sector {} at 0x{:08X} is {}.", p.sector.index, p.sector.address, p.protection);
+					FlashError::SectorProtected(p.sector.index).into()
+				},
+				None => error,
+			},
+			Err(e) => {
+				warn!("Could not read the protected sectors: {}", e);
+				error
+			},
+		}
+	}
+
+	/// Program the option bytes if they differ from `current`
+	fn program_option_bytes(&mut self, current: OptionBytes, options: &OptionBytes) -> Result<(), LinkError> {
+		if current == *options {
+			info!("The option bytes are already programmed.");
+			return Ok(());
+		}
+
+		info!("Programming the option bytes");
+
+		match (current, options) {
+			(OptionBytes::F4(_), OptionBytes::F4(o)) => self.unlocked(|link| link.f4_write_options(o)),
+			(OptionBytes::F1(_), OptionBytes::F1(o)) => self.unlocked(|link| link.f1_write_options(o)),
+			(OptionBytes::L0(_), OptionBytes::L0(o)) => self.unlocked(|link| link.l0_write_options(o)),
+			(OptionBytes::L4(_), OptionBytes::L4(o)) => self.unlocked(|link| link.l4_write_options(o)),
+			_ => {
+				error!("Option bytes. The option bytes are not those of the flash controller of the chip.");
+				Err(LinkError::Argument("option bytes of another flash controller"))
+			},
+		}
+	}
+
+	/// Flash type of the identified chip
+	fn flash_type(&self) -> Result<FlashType, LinkError> {
+		match self.chip {
+			Some(ref chip) => Ok(chip.flasht),
+			None => {
+				error!("Flash protocol. The chip has not been identified.");
+				Err(LinkError::UnknownChip(0))
+			},
+		}
+	}
+
+	/// Error for the flash types without a driver
+	fn no_flash_driver(flasht: FlashType) -> LinkError {
+		error!("Flash protocol. No driver for the {:?} flash controller.", flasht);
+		LinkError::Unsupported("flash controller of this chip")
+	}
+
+	/// Check that the range is inside the flash, or the flash of the algorithm
+	fn check_flash_range(&self, address: u32, size: usize) -> Result<(), LinkError> {
+		let (base, end) = match self.algorithm {
+			Some(ref algorithm) => (algorithm.device.address, algorithm.device.address as u64 + algorithm.device.size as u64),
+			None => (self.memory.flash.base, self.memory.flash.base as u64 + self.memory.flash.size as u64 * 1024),
+		};
+
+		if address < base || address as u64 >= end {
+			error!("Flash protocol. Address 0x{:08X} is not in the flash.", address);
+			return Err(FlashError::Address(address).into());
+		}
+
+		if address as u64 + size as u64 > end {
+			error!("Flash protocol. {} bytes at 0x{:08X} do not fit in the flash.", size, address);
+			return Err(FlashError::Address(end as u32).into());
+		}
+
+		Ok(())
+	}
+
+	/// Check that the range is inside the data EEPROM
+	fn check_eeprom_range(&self, address: u32, size: usize) -> Result<(), LinkError> {
+		let eeprom = self.memory.eeprom;
+		let end = eeprom.base as u64 + eeprom.size as u64;
+
+		if eeprom.size == 0 {
+			error!("EEPROM protocol. The chip has no data EEPROM.");
+			return Err(LinkError::Unsupported("data EEPROM of this chip"));
+		}
+
+		if address < eeprom.base || address as u64 >= end {
+			error!("EEPROM protocol. Address 0x{:08X} is not in the data EEPROM.", address);
+			return Err(FlashError::Address(address).into());
+		}
+
+		if address as u64 + size as u64 > end {
+			error!("EEPROM protocol. {} bytes at 0x{:08X} do not fit in the data EEPROM.", size, address);
+			return Err(FlashError::Address(end as u32).into());
+		}
+
+		Ok(())
+	}
+
+	/// Run `f` with the flash unlocked and lock it again, even if `f` fails
+	fn unlocked<R, F: FnOnce(&mut Self) -> Result<R, LinkError>>(&mut self, f: F) -> Result<R, LinkError> {
+		self.unlock_flash()?;
+
+		let result = f(self);
+
+		match self.lock_flash() {
+			Ok(_) => result,
+			Err(e) => {
+				warn!("Could not lock the flash again: {}", e);
+				result.and(Err(e))
+			},
+		}
+	}
+
+	/// Unlock a flash controller by writing the key sequence to `keyr`
+	/// Nothing is written if the `lock` bit of `cr` is already clear
+	fn write_flash_keys(&mut self, keyr: u32, cr: u32, lock: u32) -> Result<(), LinkError> {
+		use super::super::constants::flash::misc::{ KEY1, KEY2 };
+
+		self.write_keys(keyr, cr, lock, [KEY1, KEY2])
+	}
+
+	/// Write the two `keys` to `keyr` if the `lock` bit of `cr` is set
+	fn write_keys(&mut self, keyr: u32, cr: u32, lock: u32, keys: [u32; 2]) -> Result<(), LinkError> {
+		// First check that it's not already unlocked
+		match self.read_debug_reg(cr) {
+			Ok(value) => match value & lock {
+				0 => Ok(()),
+				_ => {
+					debug!("Received FLASH CR: {:X}", value);
+					// Unlock flash register
+					// Send KEY 1
+					self.write_debug_reg(keyr, keys[0])?;
+					// Send KEY 2
+					self.write_debug_reg(keyr, keys[1])?;
+
+					// Check that flash is unlocked
+					match self.read_debug_reg(cr) {
+						Ok(value) => match value & lock {
+							0 => Ok(()),
+							_ => {
+								debug!("Received CR 0x{:X}", value);
+								error!("Flash could not be unlocked. Reason unknown.");
+								Err(LinkError::FlashLocked)
+							},
+						},
+						Err(e) => {
+							error!("Reading Flash CR resulted in an error. Flash state is unknown.");
+							Err(e)
+						},
+					}
+				},
+			},
+			Err(e) => {
+				error!("Could not unlock flash. Error when reading the flash register.");
+				Err(e)
+			},
+		}
+	}
+
+	/// Poll the status register at `sr` until `busy` clears
+	/// Returns the last value read
+	fn wait_flash(&mut self, sr: u32, busy: u32, timeout: Duration) -> Result<u32, LinkError> {
+		let start = Instant::now();
+
+		loop {
+			let status = self.read_debug_reg(sr)?;
+
+			if status & busy == 0 {
+				return Ok(status);
+			}
+
+			if start.elapsed() > timeout {
+				error!("Flash protocol. The controller is still busy after {} ms.", timeout.as_millis());
+				return Err(FlashError::Busy.into());
+			}
+
+			std::thread::sleep(Duration::from_millis(1));
+		}
+	}
+}
@@ -1,21 +1,26 @@
 //! Link methods that give information about the 
 //! device and its state
 
-use crate::link::structs::{ FlashInfo, SysMemInfo };
+use crate::link::structs::{ FlashInfo, SysMemInfo, EepromInfo };
+use crate::link::memmap::MemoryMap;
+use crate::link::constants::flash::l0::EEPROM_BASE;
 use crate::link::enums::{ STLinkMode, DebugMode, Cmd };
 use crate::link::util::{ buf_read_u32, buf_read_u16 };
+use crate::link::error::LinkError;
 
-use libusb::Direction;
+use rusb::Direction;
 
 use super::Link;
+use super::super::transport::Transport;
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Get the target's voltage level
-	pub fn voltage(&mut self) -> Result<f32, ()> {
+	/// Returns `LinkError::Unsupported` if the probe cannot measure it
+	pub fn voltage(&mut self) -> Result<f32, LinkError> {
 		use super::super::constants::{ flags::HAS_TARGET_VOLT, commands::GET_TARGET_VOLTAGE };
 
 		match self.version.flags & HAS_TARGET_VOLT {
-			0 => return Ok(0.0),
+			0 => return Err(LinkError::Unsupported("target voltage measurement")),
 			_ => (),
 		}
 
@@ -30,19 +35,19 @@ impl<'a> Link<'a> {
 				},
 				b => {
 					error!("Voltage reading protocol. Expected 8 bytes, received {}", b);
-					Err(())
+					Err(LinkError::Transfer { expected: 8, actual: b })
 				},
 			},
-			Err(_) => {
+			Err(e) => {
 				error!("Voltage reading protocol. Data request failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 
 	/// Get the USB current mode
-	pub fn current_mode(&mut self) -> Result<STLinkMode, ()> {
+	pub fn current_mode(&mut self) -> Result<STLinkMode, LinkError> {
 		match self.command(2, Direction::In, vec![Cmd::Int8(0xF5)]) {
 			Ok(_) => {
 				//debug!("Get USB Mode protocol. Received {} bytes.", n);
@@ -57,15 +62,15 @@ impl<'a> Link<'a> {
 					}
 				)
 			},
-			Err(_) => {
+			Err(e) => {
 				error!("Get USB Mode protocol. Reading failed.");
-				Err(())
+				Err(e)
 			}
 		}
 	}
 
 	/// Get the core ID
-	pub fn core_id(&mut self) -> Result<u32, ()> {
+	pub fn core_id(&mut self) -> Result<u32, LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND };
 
 		let (offset, readcore, rxsize) = match self.version.jtag_api {
@@ -73,24 +78,30 @@ impl<'a> Link<'a> {
 			_ => (4, super::super::constants::commands::debug::apiv2::READ_IDCODES, 12),
 		};
 
-		match self.command(rxsize, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(readcore)]) {
+		// Only the API v2 answer starts with a status byte
+		let result = match self.version.jtag_api {
+			1 => self.command(rxsize, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(readcore)]),
+			_ => self.command_status(rxsize, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(readcore)]),
+		};
+
+		match result {
 			Ok(_) => Ok(buf_read_u32(&self.databuf, offset, true)),
-			Err(_) => {
+			Err(e) => {
 				error!("Get Core ID protocol. Reading failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Get the device status
-	pub fn status(&mut self) -> Result<u32, ()> {
+	pub fn status(&mut self) -> Result<u32, LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, GETSTATUS };
 
 		match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(GETSTATUS)]) {
 			Ok(_) => Ok(self.databuf[0] as u32),
-			_ => {
+			Err(e) => {
 				error!("Get status protocol. Reading failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
@@ -98,18 +109,29 @@ impl<'a> Link<'a> {
 	/// Get the Chip info
 	/// It gets all info for the link to be able to map memory correctly
 	/// Returns the chip ID if successful
-	pub fn get_chip_info(&mut self) -> Result<u32, ()> {
-		match self.read_debug_reg(0xE004_2000) {
+	pub fn get_chip_info(&mut self) -> Result<u32, LinkError> {
+		use crate::link::constants::address::STM32::{ CPUID, DBGMCU_IDCODE, DBGMCU_IDCODE_M0 };
+
+		// The DBGMCU of the Cortex-M0/M0+ devices is on the peripheral bus
+		let idcode = match self.read_debug_reg(CPUID).map(|cpuid| (cpuid >> 4) & mask!(12)) {
+			Ok(0xC20) | Ok(0xC60) => DBGMCU_IDCODE_M0,
+			_ => DBGMCU_IDCODE,
+		};
+
+		match self.read_debug_reg(idcode) {
 			Ok(chipid) => {
 				//self.chipid = super::super::chipid::get_chip_from_id_u32(chipid),
 				let chip = super::super::chipid::get_chip_from_id_u32(chipid & mask!(11));
 				match chip.id {
-					0 => Err(()),
+					0 => Err(LinkError::UnknownChip(chipid & mask!(11))),
 					_ => {
 						let size = buf_read_u16( &self.read_mem8(chip.flash_size_reg, 2)?, 0, true );
 						self.memory.flash = FlashInfo { base: 0x0800_0000, size: size as u32, pagesize: if size as u32 == chip.pagesize { None } else { Some(chip.pagesize) } };
-						self.memory.ram = chip.sram;
+						self.memory.ram = chip.sram.clone();
 						self.memory.sys = SysMemInfo { base: chip.bootrom_base, size: chip.bootrom_size };
+						self.memory.eeprom = EepromInfo { base: EEPROM_BASE, size: super::super::chipid::data_eeprom_size(chip.id) };
+						self.map = MemoryMap::from_info(&self.memory);
+						self.chip = Some(chip.clone());
 
 						info!("Recognized chip as a: {:?}", chip.description);
 						info!("It has {} kB of Flash at address 0x{:X}", size, 0x0800_0000);
@@ -118,14 +140,17 @@ impl<'a> Link<'a> {
 							info!("  Section {}: {} kB at address 0x{:X}", i + 1, r.size as f32 / 1024.0, r.base);
 						}
 						info!("Boot/system memory has a size of {} kB at address 0x{:X} ", self.memory.sys.size as f32 / 1024.0, self.memory.sys.base);
+						if self.memory.eeprom.size != 0 {
+							info!("Data EEPROM has a size of {} kB at address 0x{:X}", self.memory.eeprom.size as f32 / 1024.0, self.memory.eeprom.base);
+						}
 
 						Ok(chipid & mask!(11))
 					},
 				}
 			},
-			_ => {
+			Err(e) => {
 				error!("Failure to get chip id");
-				Err(())
+				Err(e)
 			},
 		}
 	}
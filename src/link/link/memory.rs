@@ -0,0 +1,286 @@
+//! Memory access
+//! `read_memory` and `write_memory` accept any address range and split it into
+//! transfers of the widest access allowed by the alignment and the probe.
+
+use crate::link::util::{ buf_write_u32, buf_write_u16 };
+
+use rusb::Direction;
+
+use super::super::error::LinkError;
+use super::super::memmap::AccessPolicy;
+
+use super::Link;
+use super::super::transport::Transport;
+
+
+/// A single memory transfer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Chunk {
+	address: u32,
+	size: usize,
+	/// Access width in bytes
+	width: usize,
+}
+
+/// Memory access of any size and alignment
+impl<T: Transport> Link<T> {
+	/// Read `n` bytes from `address`
+	/// The unaligned head and tail are read with 8 bit accesses (16 bit if the
+	/// probe supports them) and the word aligned body in 32 bit bursts of up to
+	/// the maximum packet size. The transfer status is checked after each burst.
+	pub fn read_memory(&mut self, address: u32, n: usize) -> Result<Vec<u8>, LinkError> {
+		self.check_access(address, n)?;
+
+		let mut out = Vec::with_capacity(n);
+
+		for chunk in self.split(address, n) {
+			match self.read_transfer(chunk.address, chunk.size, chunk.width) {
+				Ok(data) => out.extend_from_slice(&data),
+				Err(e) => {
+					error!("Read Memory protocol. Failed to read {} bytes at 0x{:08X} with {} bit accesses.", chunk.size, chunk.address, chunk.width * 8);
+					return Err(e);
+				},
+			}
+		}
+
+		Ok(out)
+	}
+
+	/// Write `data` at `address`
+	/// The range is split as in `read_memory`.
+	pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		self.check_access(address, data.len())?;
+
+		let mut offset = 0;
+
+		for chunk in self.split(address, data.len()) {
+			match self.write_transfer(chunk.address, &data[offset..offset + chunk.size], chunk.width) {
+				Ok(_) => offset += chunk.size,
+				Err(e) => {
+					error!("Write Memory protocol. Failed to write {} bytes at 0x{:08X} with {} bit accesses.", chunk.size, chunk.address, chunk.width * 8);
+					return Err(e);
+				},
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Read from unaligned memory
+	/// Limited to the maximum 8 bit transfer size of the probe model
+	pub fn read_mem8(&mut self, address: u32, n: usize) -> Result<Vec<u8>, LinkError> {
+		if n > self.model.max_rw8 {
+			error!("Cannot read more than {} B at once.", self.model.max_rw8);
+			return Err(LinkError::Size { requested: n, max: self.model.max_rw8 });
+		}
+
+		self.read_transfer(address, n, 1)
+	}
+
+	/// Read from aligned memory and a word aligned size
+	pub fn read_mem32(&mut self, address: u32, n: usize) -> Result<Vec<u8>, LinkError> {
+		self.check_transfer(address, n, 4)?;
+
+		self.read_transfer(address, n, 4)
+	}
+
+	/// Read from halfword aligned memory and a halfword aligned size
+	/// Requires a probe firmware with 16 bit memory access support
+	pub fn read_mem16(&mut self, address: u32, n: usize) -> Result<Vec<u8>, LinkError> {
+		self.check_mem16()?;
+		self.check_transfer(address, n, 2)?;
+
+		self.read_transfer(address, n, 2)
+	}
+
+	/// Write memory in 8 bit mode
+	/// Limited to the maximum 8 bit transfer size of the probe model
+	pub fn write_mem8(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		if data.len() > self.model.max_rw8 {
+			error!("Cannot write more than {} Bytes in a transfer.", self.model.max_rw8);
+			return Err(LinkError::Size { requested: data.len(), max: self.model.max_rw8 });
+		}
+
+		self.write_transfer(address, data, 1)
+	}
+
+	/// Write memory in 16 bit mode
+	/// Requires a probe firmware with 16 bit memory access support
+	pub fn write_mem16(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		self.check_mem16()?;
+		self.check_transfer(address, data.len(), 2)?;
+
+		self.write_transfer(address, data, 2)
+	}
+
+	/// Write memory in 32 bit mode
+	pub fn write_mem32(&mut self, address: u32, data: &[u8]) -> Result<(), LinkError> {
+		self.check_transfer(address, data.len(), 4)?;
+
+		self.write_transfer(address, data, 4)
+	}
+
+	/// Check the range against the memory map as set by the access policy
+	/// A range past the end of the address space is refused whatever the policy.
+	pub(super) fn check_access(&self, address: u32, size: usize) -> Result<(), LinkError> {
+		if address as u64 + size as u64 > 1 << 32 {
+			error!("Access of {} bytes at 0x{:08X} refused. The range ends past 0xFFFFFFFF.", size, address);
+			return Err(LinkError::Argument("range past the end of the address space"));
+		}
+
+		if self.access == AccessPolicy::Allow {
+			return Ok(());
+		}
+
+		match self.map.unmapped(address, size) {
+			None => Ok(()),
+			Some(a) if self.access == AccessPolicy::Warn => {
+				warn!("Access of {} bytes at 0x{:08X}. Address 0x{:08X} is not in the memory map.", size, address, a);
+				Ok(())
+			},
+			Some(a) => {
+				error!("Access of {} bytes at 0x{:08X} refused. Address 0x{:08X} is not in the memory map.", size, address, a);
+				Err(LinkError::Unmapped { address: a })
+			},
+		}
+	}
+
+	/// Check that the probe firmware supports 16 bit memory accesses
+	fn check_mem16(&self) -> Result<(), LinkError> {
+		use super::super::constants::flags::HAS_MEM_16BIT;
+
+		match self.version.flags & HAS_MEM_16BIT {
+			0 => {
+				error!("Memory 16 bit protocol. The probe firmware V{} J{} does not support 16 bit accesses.", self.version.stlink, self.version.jtag);
+				Err(LinkError::Unsupported("16 bit memory access"))
+			},
+			_ => Ok(()),
+		}
+	}
+
+	/// Check the alignment and size of a 16 or 32 bit transfer
+	fn check_transfer(&self, address: u32, size: usize, width: usize) -> Result<(), LinkError> {
+		if size > self.max_packet {
+			error!("Cannot transfer more than {} Bytes at once.", self.max_packet);
+			return Err(LinkError::Size { requested: size, max: self.max_packet });
+		}
+
+		match (address as usize % width, size % width) {
+			(0, 0) => Ok(()),
+			(0, _) => {
+				error!("Memory {} bit protocol. Number of bytes must be a multiple of {}.", width * 8, width);
+				Err(LinkError::Alignment { address, size, width })
+			},
+			(_, 0) => {
+				error!("Memory {} bit protocol. Address must be {} byte aligned.", width * 8, width);
+				Err(LinkError::Alignment { address, size, width })
+			},
+			_ => {
+				error!("Memory {} bit protocol. Address and size are misaligned.", width * 8);
+				Err(LinkError::Alignment { address, size, width })
+			},
+		}
+	}
+
+	/// Split a memory range into transfers
+	fn split(&self, address: u32, n: usize) -> Vec<Chunk> {
+		use super::super::constants::{ flags::HAS_MEM_16BIT, misc::SIZE::DATA };
+
+		let mem16 = self.version.flags & HAS_MEM_16BIT != 0;
+		// Bursts do not cross a packet boundary, the AP address auto-increment may wrap there
+		let burst = std::cmp::min(self.max_packet, DATA);
+
+		let mut chunks = Vec::new();
+		let mut address = address as u64;
+		let end = address + n as u64;
+
+		while address < end {
+			let left = (end - address) as usize;
+			let a = address as usize;
+
+			let (size, width) = if a.is_multiple_of(4) && left >= 4 {
+				(std::cmp::min(left & !3, burst - (a % burst)), 4)
+			} else if mem16 && a.is_multiple_of(2) && left >= 2 {
+				(2, 2)
+			} else {
+				// Bytes until the next 16 or 32 bit boundary
+				let next = if mem16 { 2 } else { 4 };
+				(std::cmp::min(left, next - (a % next)), 1)
+			};
+
+			chunks.push(Chunk { address: address as u32, size, width });
+			address += size as u64;
+		}
+
+		chunks
+	}
+
+	/// Read `n` bytes at `address` with accesses of `width` bytes
+	fn read_transfer(&mut self, address: u32, n: usize, width: usize) -> Result<Vec<u8>, LinkError> {
+		use super::super::constants::commands::debug::{ DEBUG_COMMAND, READMEM_8BIT, READMEM_32BIT, apiv2::READMEM_16BIT };
+
+		self.cmd_setup(n as u32, Direction::In);
+
+		self.push_command(DEBUG_COMMAND);
+		self.push_command(match width {
+			1 => READMEM_8BIT,
+			2 => READMEM_16BIT,
+			_ => READMEM_32BIT,
+		});
+
+		// Send address
+		buf_write_u32(&mut self.cmdbuf, self.cmdidx, address, true);
+		// Send transfer size
+		buf_write_u16(&mut self.cmdbuf, self.cmdidx + 4, n as u16, true);
+		self.cmdidx += 6;
+
+		// A single byte read is answered with two bytes
+		match self.recv(self.cmdidx, if n == 1 { n + 1 } else { n }, true) {
+			Ok(_) => {
+				let mut out = Vec::new();
+				out.extend_from_slice(&self.databuf[0..n]);
+
+				// Check that the target did not fault during the transfer
+				self.rw_status()?;
+
+				Ok( out )
+			},
+
+			Err(e) => {
+				error!("Could not read memory");
+				Err(e)
+			}
+		}
+	}
+
+	/// Write `data` at `address` with accesses of `width` bytes
+	fn write_transfer(&mut self, address: u32, data: &[u8], width: usize) -> Result<(), LinkError> {
+		use super::super::constants::commands::debug::{ DEBUG_COMMAND, WRITEMEM_8BIT, WRITEMEM_32BIT, apiv2::WRITEMEM_16BIT };
+
+		self.cmd_setup(data.len() as u32, Direction::Out);
+
+		self.push_command(DEBUG_COMMAND);
+		self.push_command(match width {
+			1 => WRITEMEM_8BIT,
+			2 => WRITEMEM_16BIT,
+			_ => WRITEMEM_32BIT,
+		});
+
+		// Send address
+		buf_write_u32(&mut self.cmdbuf, self.cmdidx, address, true);
+		// Send transfer size
+		buf_write_u16(&mut self.cmdbuf, self.cmdidx + 4, data.len() as u16, true);
+		self.cmdidx += 6;
+
+		self.databuf[..data.len()].copy_from_slice(data);
+
+		match self.send(self.cmdidx, data.len(), true) {
+			// Check that the target did not fault during the transfer
+			Ok(_) => self.rw_status(),
+			Err(e) => {
+				error!("Memory Write in {} bit mode. Could not send data.", width * 8);
+				Err(e)
+			},
+		}
+	}
+}
@@ -4,35 +4,47 @@
 mod sendrecv;
 mod registers;
 mod flash;
+mod memory;
+mod typed;
+mod dump;
+mod bulk;
 mod debug;
 mod info;
 mod reset;
 mod speed;
 mod modes;
+mod status;
+mod probe;
 
 
 use crate::link::link::speed::{ JTAG_DEFAULT_SPEED, SWD_DEFAULT_SPEED };
-use libusb::Direction;
+use rusb::Direction;
 
-use super::structs::{ STLinkUSBVersion, Endpoint, MemInfo };
-
-use libusb::{ DeviceHandle };
+use super::structs::{ STLinkUSBVersion, MemInfo, SRamInfo };
+use super::algorithm::FlashAlgorithm;
+use super::memmap::{ MemoryMap, AccessPolicy };
 
 
 use super::constants::{ misc::{ SIZE::{ DATA, SG } } };
 
-use super::enums::{ STLinkMode, DebugMode };
+use super::enums::{ STLinkMode, DebugMode, Parallelism, ProbeSelector };
+use super::chipid::ChipParams;
+
+use super::error::LinkError;
+
+use super::transport::Transport;
+
+use crate::usb::model::ProbeModel;
+use crate::usb::transport::UsbTransport;
 
 
 use super::util::{ buf_write_u32, buf_read_u32, buf_read_u16, buf_write_u16 };
 
 
-pub struct Link<'a> {
-	tx: u8,
-	rx: u8,
-	trace: u8,
+pub struct Link<T: Transport> {
+	transport: T,
 
-	handle: DeviceHandle<'a>,
+	model: &'static ProbeModel,
 
 	cmdbuf: [u8; SG],
 	cmdidx: usize,
@@ -49,9 +61,89 @@ pub struct Link<'a> {
 	max_packet: usize,
 
 	memory: MemInfo,
+	map: MemoryMap,
+	access: AccessPolicy,
+	/// Refuse to debug a target whose voltage is too low
+	voltage_check: bool,
+
+	/// Parameters of the identified chip
+	chip: Option<ChipParams>,
+	/// Flash parallelism chosen by the user, from the target voltage otherwise
+	psize: Option<Parallelism>,
+	/// Program whole rows of the L4/G0/WB flash with fast programming
+	fast: bool,
+	/// Program the flash with a loader running in the target SRAM
+	loader: bool,
+	/// CMSIS flash algorithm used instead of the driver of the chip
+	algorithm: Option<FlashAlgorithm>,
+	/// SRAM region where the flash algorithm runs, chosen by the user
+	algorithm_ram: Option<SRamInfo>,
 }
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
+	/// Version and capabilities of the probe
+	pub fn probe_version(&self) -> &STLinkUSBVersion {
+		&self.version
+	}
+
+	/// USB VID and PID of the probe
+	pub fn usb_id(&self) -> (u16, u16) {
+		(self.vid, self.pid)
+	}
+
+	/// Model of the probe
+	pub fn model(&self) -> &'static ProbeModel {
+		self.model
+	}
+
+	/// Transport used to talk to the probe
+	pub fn transport(&self) -> &T {
+		&self.transport
+	}
+
+	/// Mutable access to the transport used to talk to the probe
+	pub fn transport_mut(&mut self) -> &mut T {
+		&mut self.transport
+	}
+
+	/// Close the link and return its transport
+	pub fn into_transport(self) -> T {
+		self.transport
+	}
+
+	/// Memory layout of the connected chip
+	pub fn memory(&self) -> &MemInfo {
+		&self.memory
+	}
+
+	/// Parameters of the connected chip, once identified
+	pub fn chip(&self) -> Option<&ChipParams> {
+		self.chip.as_ref()
+	}
+
+	/// Memory map of the connected chip
+	/// Only the Cortex-M regions are known until the chip has been identified
+	pub fn memory_map(&self) -> &MemoryMap {
+		&self.map
+	}
+
+	/// Policy for the accesses to unmapped addresses
+	pub fn access_policy(&self) -> AccessPolicy {
+		self.access
+	}
+
+	/// Choose what to do with the accesses to unmapped addresses
+	/// Only `read_memory`, `write_memory` and the methods built on them are checked
+	pub fn set_access_policy(&mut self, policy: AccessPolicy) {
+		self.access = policy;
+	}
+
+	/// Refuse to enter a mode when the target voltage is too low, instead of only warning
+	/// Probes with an unwired VAPP pin read about 0 V, so the check is off by default
+	pub fn set_voltage_check(&mut self, check: bool) {
+		self.voltage_check = check;
+	}
+
 	pub fn dump_data(&self, offset: usize) {
 		print!("[");
 		for b in &self.databuf[0..offset] {
@@ -60,7 +152,7 @@ impl<'a> Link<'a> {
 		println!("]");
 	}
 
-	pub fn enter_debug_state(&mut self) -> Result<(), ()> {
+	pub fn enter_debug_state(&mut self) -> Result<(), LinkError> {
 		use super::constants::commands::debug::DEBUG_COMMAND;
 
 		self.cmd_setup(16, Direction::In);
@@ -77,17 +169,17 @@ impl<'a> Link<'a> {
 
 		match self.recv(self.cmdidx, 100, true) {
 			Ok(_) => Ok(()),
-			_ => Err(())
+			Err(e) => Err(e),
 		}
 	}
 }
 
 
 /// Debug methods
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 
 	/// Set SWD Clock
-	pub fn set_swdclk(&mut self, divisor: u16) -> Result<(), ()> {
+	pub fn set_swdclk(&mut self, divisor: u16) -> Result<(), LinkError> {
 		if self.version.stlink >= 2 && self.version.jtag_api == 3 {
 			self.cmd_setup(2, Direction::In);
 			self.push_command(super::constants::commands::debug::DEBUG_COMMAND);
@@ -95,219 +187,244 @@ impl<'a> Link<'a> {
 			self.push_command((divisor >> 0) as u8);
 			self.push_command((divisor >> 8) as u8);
 
-			match self.recv(self.cmdidx, 2, true) {
+			match self.recv_status(2) {
 				Ok(_) => {
 					Ok(())
 				},
-				Err(_) => {
+				Err(e) => {
 					error!("Set SWD Clock protocol. Writing failed.");
-					Err(())
+					Err(e)
 				},
 			}
 		} else {
-			Err(())
+			Err(LinkError::Unsupported("SWD clock divisor"))
 		}
 	}
 }
 
-/// Methods to connect through USB
-impl<'a> Link<'a> {
-	/// Open USB connection
-	pub fn open_usb(ctx: &'a mut libusb::Context, retries: usize, connection: DebugMode) -> Result<Self, ()> {
-		let mut tries = retries;
-		let mut new = loop {
-			// Search for a correct interface
-			let (device, desc, mut handle) = match crate::usb::search(ctx) {
-				Some(a) => a,
-				None => {
-					error!("No results found for STLink devices");
-					return Err(());
-				},
-			};
-
-
-			let cfg = match device.config_descriptor(0) {
-				Ok(c) => c,
-				_ => panic!(),
-			};
-
-			let mut eps = Vec::new();
-
-			// Loop trough the interfaces
-			// Rewrite with maps??
-			for interface in cfg.interfaces() {
-				for idesc in interface.descriptors() {
-					for endpoint in idesc.endpoint_descriptors() {
-						eps.push(Endpoint {
-							config: cfg.number(),
-							iface: idesc.interface_number(),
-							setting: idesc.setting_number(),
-							address: endpoint.address(),
-						});
-					}
-				}
-			}
-
-			match eps.len() {
-				3 => (),
-				l => {
-					error!("Device received does not have the correct number of endpoints. Expected 3 found {}", l);
-					return Err(());
-				},
-			}
-
-			/*for endpoint in eps.iter() {
-				(handle.claim_interface(endpoint.iface));
-				(handle.set_alternate_setting(endpoint.iface, endpoint.setting));
-			}*/
-
-			handle.claim_interface(eps[0].iface);
-			handle.set_alternate_setting(eps[0].iface, eps[0].setting);
-
-			info!("libusb found device VID:PID {:X}:{:X}", desc.vendor_id(), desc.product_id());
-
-			let (tx, stlink, trace) = match desc.product_id() {
-				// STLink V1
-				0x3744 => (2 | 0x00, 1, None),
-				// STLink V3
-				0x374d | 0x374e | 0x374f | 0x3753 => (1 | 0x00, 3, Some( 2 | 0x80 ) ),
-				// STLink V2.1
-				0x374b | 0x3752 => (1 | 0x00, 2, Some( 2 | 0x80 ) ),
-				// STLink V2
-				0x3748 => (2 | 0x00, 2, Some( 3 | 0x80 ) ),
-				// Default will be STLink V2
-				_ => (2 | 0x00, 2, Some( 3 | 0x80 ) ),
-			};
-
-
-			let mut new = Self {
-				tx: tx,
-				// `rx` is always the same for all versions
-				rx: 0x80 | 1,
-				trace: match trace { Some(t) => t, None => 0, },
-
-				handle: handle,
-				
-				// Command buffer, may use STLinkV2 Size or STLinkV1 size
-				// Unique size buffer, the actual command size is stored in cmdidx.
-				cmdbuf: [0; 31],
-				// Index of the next command to push. Is equal to the size of the command
-				cmdidx: 0,
-				// A 4kB buffer for data transmission.
-				databuf: [0; 4096],
-
-				version: STLinkUSBVersion { stlink:stlink, jtag: 0, swim: 0, jtag_api: 1, flags:0 },
-
-				vid: desc.vendor_id(),
-				pid: desc.product_id(),
+/// Methods to connect to a probe
+impl<T: Transport> Link<T> {
+	/// Create a link over `transport` to a probe of the given `model`
+	/// No command is sent to the probe
+	pub fn new(transport: T, model: &'static ProbeModel) -> Self {
+		Self {
+			transport,
+
+			model,
+
+			// Command buffer, may use STLinkV2 Size or STLinkV1 size
+			// Unique size buffer, the actual command size is stored in cmdidx.
+			cmdbuf: [0; 31],
+			// Index of the next command to push. Is equal to the size of the command
+			cmdidx: 0,
+			// A 4kB buffer for data transmission.
+			databuf: [0; 4096],
+
+			version: STLinkUSBVersion { stlink: model.stlink, jtag: 0, swim: 0, jtag_api: 1, flags:0 },
+
+			vid: crate::usb::model::VID,
+			pid: model.pid,
+
+			sg_transfer: 0,
+
+			// Default size
+			max_packet: 64,
+
+			memory: MemInfo::new(),
+			map: MemoryMap::cortex_m(),
+			access: AccessPolicy::Warn,
+			voltage_check: false,
+
+			chip: None,
+			psize: None,
+			fast: false,
+			loader: false,
+			algorithm: None,
+			algorithm_ram: None,
+		}
+	}
 
-				sg_transfer: 0,
+	/// Open a link over `transport` and connect to the target in the given mode
+	pub fn open(transport: T, model: &'static ProbeModel, connection: DebugMode) -> Result<Self, LinkError> {
+		let mut new = Self::new(transport, model);
 
-				// Default size
-				max_packet: 64,
+		new.version()?;
+		new.attach(connection)?;
 
-				memory: MemInfo::new(),
-			};
-
-			match new.version() {
-				Ok(_) => break new,
-				_ => match new.version.stlink {
-					1 if tries == 0 => {
-						error!("Reached max number of retries.\n");
-						return Err(());
-					},
-					_ => {
-						//new.emergency_reset();
-						if tries == 0 {
-							return Err(());
-						} else {
-							tries -= 1;
-						}
-						std::thread::sleep(std::time::Duration::from_secs(1));
-						continue;
-					},
-				},
-			}
-		};
+		Ok(new)
+	}
 
+	/// Connect to the target in the given mode
+	/// The probe version must have been read before
+	pub fn attach(&mut self, connection: DebugMode) -> Result<(), LinkError> {
 		info!("Setting up connection in {:?} mode", connection);
 
 		match connection {
-			DebugMode::SWD  => if new.version.jtag_api == 1 { return Err(()); } else { () },
-			DebugMode::JTAG => if new.version.jtag_api == 0 { return Err(()); } else { () },
-			DebugMode::SWIM => if new.version.swim     == 0 { return Err(()); } else { () },
+			DebugMode::SWD  => if self.version.jtag_api == 1 { return Err(LinkError::DebugMode(connection)); } else { () },
+			DebugMode::JTAG => if self.version.jtag_api == 0 { return Err(LinkError::DebugMode(connection)); } else { () },
+			DebugMode::SWIM => if self.version.swim     == 0 { return Err(LinkError::DebugMode(connection)); } else { () },
 			_ => {
 				error!("USB connection protocol. Debug Mode is not supported: {:?}.", connection);
-				return Err(());
+				return Err(LinkError::DebugMode(connection));
 			},
 		}
 
 		// TODO : Change this to prepare for resets
 		info!("Initializing...");
-		match new.init_mode(STLinkMode::Debug(connection), false) {
+		match self.init_mode(STLinkMode::Debug(connection), false) {
 			Ok(_) => (),
-			_ => {
+			Err(e) => {
 				error!("Could not set up STLink connection.");
-				return Err(());
+				return Err(e);
 			},
 		}
 
-		// Unlock flash
-		new.unlock_flash();
 		// Get the chip info to perform correct memory operations
-		new.get_chip_info();
+		// An unknown chip can still be debugged, and flashed with a flash algorithm
+		if connection != DebugMode::SWIM {
+			match self.get_chip_info() {
+				Ok(_) => (),
+				Err(LinkError::UnknownChip(id)) => warn!("Unknown chip ID 0x{:03X}. The memory map and the flash drivers are not available.", id),
+				Err(e) => {
+					error!("Could not identify the chip.");
+					return Err(e);
+				},
+			}
+		}
 
 		// Get the max packet size available
 		// If it's SWIM mode, the size is predetermined
 		if connection == DebugMode::SWIM {
-			new.enter_mode(DebugMode::SWIM)?;
-			new.max_packet = 4096;
+			self.enter_mode(DebugMode::SWIM)?;
+			self.max_packet = 4096;
 		} else {
-			new.max_packet = 1 << 10;
+			self.max_packet = 1 << 10;
 
-			match new.read_mem32(super::constants::address::STM32::CPUID, 12) {
-				Ok(_) => {
-					new.dump_data(12);
-					let cpuid = buf_read_u32(&new.databuf, 0, true);
+			match self.read_mem32(super::constants::address::STM32::CPUID, 12) {
+				Ok(data) => {
+					let cpuid = buf_read_u32(&data, 0, true);
 					match (cpuid >> 4) & 0xF {
-						4 | 3 => new.max_packet = 1 << 12,
+						4 | 3 => self.max_packet = 1 << 12,
 						_ => (),
 					}
 
 					debug!("CPUID: 0x{:X}", cpuid);
 					debug!("       {}", cpuid);
 				},
-				Err(_) => {
+				Err(e) => {
 					error!("Could not read max packet size.");
-					return Err(());
+					return Err(e);
 				},
 			}
 		}
 
-		info!("Max packet size: {} kB", new.max_packet as f32 / 1024.0);
+		info!("Max packet size: {} kB", self.max_packet as f32 / 1024.0);
 
-		match new.status() {
+		match self.status() {
 			Ok(s) => info!("Device status: {}", s),
 			_ => (),
 		}
 
 		// Do not halt yet, Core ID has not been requested
-		//new.halt();
+		//self.halt();
+
+		Ok(())
+	}
+}
+
+/// Methods to connect through USB
+impl Link<UsbTransport> {
+	/// Open USB connection
+	pub fn open_usb(ctx: &rusb::Context, retries: usize, connection: DebugMode) -> Result<Self, LinkError> {
+		Self::open_usb_with(ctx, &ProbeSelector::First, retries, connection)
+	}
+
+	/// Open USB connection to the probe chosen by `selector`
+	pub fn open_usb_with(ctx: &rusb::Context, selector: &ProbeSelector, retries: usize, connection: DebugMode) -> Result<Self, LinkError> {
+		Link::open_usb_wrapped(ctx, selector, retries, connection, |transport, _| Ok(transport))
+	}
+
+	/// Claim the interface of an opened probe and set up the link
+	/// The probe version is not requested yet
+	fn connect(device: &rusb::Device<rusb::Context>, desc: &rusb::DeviceDescriptor, handle: rusb::DeviceHandle<rusb::Context>) -> Result<Self, LinkError> {
+		let model = match crate::usb::model::find(desc.vendor_id(), desc.product_id()) {
+			Some(m) => m,
+			None => {
+				error!("Device {:04X}:{:04X} is not a known STLink model", desc.vendor_id(), desc.product_id());
+				return Err(LinkError::NoProbe);
+			},
+		};
+
+		info!("libusb found device VID:PID {:X}:{:X}", desc.vendor_id(), desc.product_id());
+		info!("Probe model: {}", model.name);
+
+		Ok(Self::new(UsbTransport::open(device, handle, model)?, model))
+	}
+}
+
+/// Methods to connect through USB with a custom transport
+impl<T: Transport> Link<T> {
+	/// Open USB connection to the probe chosen by `selector`
+	/// The USB transport is passed through `wrap` before any command is sent,
+	/// which allows to record the whole session
+	pub fn open_usb_wrapped<F>(ctx: &rusb::Context, selector: &ProbeSelector, retries: usize, connection: DebugMode, mut wrap: F) -> Result<Self, LinkError>
+		where F: FnMut(UsbTransport, &'static ProbeModel) -> Result<T, LinkError>
+	{
+		let mut tries = retries;
+		let mut new = loop {
+			// Search for a correct interface
+			let (device, desc, handle) = match crate::usb::search(ctx, selector) {
+				Some(a) => a,
+				None => {
+					error!("No results found for STLink devices");
+					return Err(LinkError::NoProbe);
+				},
+			};
+
+			let link = Link::connect(&device, &desc, handle)?;
+			let model = link.model;
+			let mut new = Self::new(wrap(link.transport, model)?, model);
+
+			match new.version() {
+				Ok(_) => break new,
+				Err(e) => match new.version.stlink {
+					1 if tries == 0 => {
+						error!("Reached max number of retries.\n");
+						return Err(e);
+					},
+					_ => {
+						//new.emergency_reset();
+						if tries == 0 {
+							return Err(e);
+						} else {
+							tries -= 1;
+						}
+						std::thread::sleep(std::time::Duration::from_secs(1));
+						continue;
+					},
+				},
+			}
+		};
+
+		new.attach(connection)?;
 
 		Ok(new)
 	}
+}
 
+/// Probe version and initialization
+impl<T: Transport> Link<T> {
 	/// Check version through USB and update itself if there's not an error
-	pub fn version(&mut self) -> Result<(), ()> {
+	pub fn version(&mut self) -> Result<(), LinkError> {
 		// Set up `GET_VERSION` command
 		self.cmd_setup(6, Direction::In);
 		self.push_command( super::constants::commands::GET_VERSION );
 		
 		match self.recv(self.cmdidx, 6, true) {
 			Ok(_) => (),
-			_ => {
+			Err(e) => {
 				error!("USB Version protocol. Error during version data request");
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -349,9 +466,9 @@ impl<'a> Link<'a> {
 						vid = buf_read_u16(&self.databuf,  8, true);
 						pid = buf_read_u16(&self.databuf, 10, true);
 					},
-					Err(_) => {
+					Err(e) => {
 						error!("USB Version protocol. Failed while getting STLink V3 extended info\n");
-						return Err(());
+						return Err(e);
 					},
 				}
 
@@ -407,7 +524,7 @@ impl<'a> Link<'a> {
 
 	/// Connect to device using the given `mode`
 	/// TODO : `connect_under_reset` is not working yet, maybe try to enable it later on in development
-	pub fn init_mode(&mut self, mode: STLinkMode, connect_under_reset: bool) -> Result<(), ()> {
+	pub fn init_mode(&mut self, mode: STLinkMode, connect_under_reset: bool) -> Result<(), LinkError> {
 		// Get current mode
 		// If it's unknown it may indicate a software error coming from before this method was called
 		// If it's DFU mode (USB standard), exit this mode
@@ -417,17 +534,17 @@ impl<'a> Link<'a> {
 				STLinkMode::DFU => {
 					match self.leave_mode(STLinkMode::DFU) {
 						Ok(_) => (),
-						Err(_) => {
+						Err(e) => {
 							error!("Initial Mode protocol. Could not exit DFU mode.");
-							return Err(());
+							return Err(e);
 						},
 					}
 				},
 				_ => (),
 			},
-			Err(_) => {
+			Err(e) => {
 				error!("Initial Mode protocol. Could not get current mode.");
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -439,23 +556,29 @@ impl<'a> Link<'a> {
 			Ok(m) => match m {
 				STLinkMode::DFU => {
 					error!("DFU Mode support is not supported!");
-					return Err(());
+					return Err(LinkError::Mode(m));
 				},
 				_ => match self.voltage() {
 					Ok(v) => match v {
+						x if x < 1.5 && self.voltage_check => {
+							error!("Voltage is too low to perform accurate debugging!");
+							return Err(LinkError::Voltage(v));
+						},
 						x if x < 1.5 => warn!("Voltage is too low to perform accurate debugging!"),
 						_ => info!("Target voltage level: {} V", v),
 					},
 
-					_ => {
+					Err(LinkError::Unsupported(_)) => info!("Device cannot measure the target voltage."),
+
+					Err(e) => {
 						error!("Initial Mode protocol. Could not get target voltage.");
-						return Err(());
+						return Err(e);
 					},
 				}
 			},
-			Err(_) => {
+			Err(e) => {
 				error!("Initial Mode protocol. After clearing mode, it could not get current mode.");
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -464,13 +587,13 @@ impl<'a> Link<'a> {
 			STLinkMode::Debug(m) => match m {
 				DebugMode::Unknown => {
 					error!("Initial Mode protocol. Could not set mode {:?}", mode);
-					return Err(());
+					return Err(LinkError::DebugMode(m));
 				},
 				dbg => dbg,
 			},
 			n => {
 				error!("Initial mode protocol. Error while setting mode. Mode is not permitted: {:?}", n);
-				return Err(());
+				return Err(LinkError::Mode(n));
 			},
 		};
 
@@ -479,7 +602,7 @@ impl<'a> Link<'a> {
 			DebugMode::JTAG => match self.version.jtag {
 				0 => {
 					error!("Initial Mode protocol. Device does not support JTAG.");
-					return Err(());
+					return Err(LinkError::DebugMode(dbgmode));
 				},
 				_ => match self.version.flags & super::constants::flags::HAS_JTAG_SET_FREQ {
 					0 => info!("Device cannot set speed for JTAG interface."),
@@ -498,7 +621,7 @@ impl<'a> Link<'a> {
 			}
 			d => {
 				error!("Illegal mode {:?}. This mode is not supported as an interface.", d);
-				return Err(());
+				return Err(LinkError::DebugMode(d));
 			},
 		}
 
@@ -510,9 +633,9 @@ impl<'a> Link<'a> {
 			})
 			{
 				Ok(_) => (),
-				_ => {
+				Err(e) => {
 					error!("Initial Mode protocol. Could not set STLink V3 speed.");
-					return Err(());
+					return Err(e);
 				},
 			},
 			_ => (),
@@ -527,9 +650,9 @@ impl<'a> Link<'a> {
 		// Enter the given Debug mode. Only modes accepted are JTAG and SWD
 		match self.enter_mode(dbgmode) {
 			Ok(_) => (),
-			_ => {
+			Err(e) => {
 				error!("Initial Mode protocol. Could not enter {:?} mode.", dbgmode);
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -557,15 +680,15 @@ impl<'a> Link<'a> {
 
 
 /// Helper functions
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Terminate transmission
-	pub fn terminate(&mut self) -> Result<(), ()> {
+	pub fn terminate(&mut self) -> Result<(), LinkError> {
 		use super::constants::misc::TIMEOUT::WRITE as WriteTimeout;
 		match self.version.stlink {
 			1 => {
 				// SG buffer, 13 bytes
-				let mut buf = Vec::with_capacity(13);
-				match self.handle.read_bulk(self.rx, &mut buf, WriteTimeout) {
+				let mut buf = [0u8; 13];
+				match self.transport.read_data(&mut buf, WriteTimeout) {
 					Ok(n) => {
 						if n != 13 {
 							debug!("Terminate protocol. SG buffer not filled. Expected 13 bytes, received {}", n);
@@ -575,7 +698,7 @@ impl<'a> Link<'a> {
 					},
 					Err(e) => {
 						error!("Terminate protocol. Could not complete terminate process.\nError: {}", e);
-						Err(())
+						Err(e)
 					},
 				}
 			},
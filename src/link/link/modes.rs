@@ -2,60 +2,62 @@
 
 use crate::link::enums::{ STLinkMode, DebugMode };
 use super::Link;
+use super::super::transport::Transport;
 
-use libusb::Direction;
+use rusb::Direction;
 
 use super::super::enums::Cmd;
+use super::super::error::LinkError;
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Enter SWD Mode
-	pub fn enter_swd_mode(&mut self) -> Result<(), ()> {
+	pub fn enter_swd_mode(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, ENTER_SWD_NO_RESET, apiv1::ENTER };
 
 		match self.command(0, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(ENTER), Cmd::Int8(ENTER_SWD_NO_RESET)]) {
 			Ok(_) => {
 				Ok(())
 			}
-			Err(_) => {
+			Err(e) => {
 				error!("Enter SWD protocol. Reading failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Exit DFU mode
-	pub fn exit_dfu_mode(&mut self) -> Result<(), ()> {
+	pub fn exit_dfu_mode(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::commands::dfu::{ COMMAND, EXIT };
 
 		match self.command(0, Direction::In, vec![Cmd::Int8(COMMAND), Cmd::Int8(EXIT)]) {
 			Ok(_) => {
 				Ok(())
 			}
-			Err(_) => {
+			Err(e) => {
 				error!("Exit DFU protocol. Writing failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Exit debug mode
-	pub fn exit_debug_mode(&mut self) -> Result<(), ()> {
+	pub fn exit_debug_mode(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, EXIT };
 
 		match self.command(0, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(EXIT)]) {
 			Ok(_) => {
 				Ok(())
 			}
-			Err(_) => {
+			Err(e) => {
 				error!("Exit Debug mode protocol. Writing failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Leave the mode
 	/// TODO : change and clean
-	pub fn leave_mode(&mut self, mode: STLinkMode) -> Result<(), ()> {
+	pub fn leave_mode(&mut self, mode: STLinkMode) -> Result<(), LinkError> {
 		self.cmd_setup(2, Direction::In);
 
 		match mode {
@@ -72,7 +74,7 @@ impl<'a> Link<'a> {
 				},
 				n => {
 					error!("Illegal Debug mode: {:?}", n);
-					panic!();
+					return Err(LinkError::DebugMode(n));
 				},
 			},
 
@@ -82,7 +84,7 @@ impl<'a> Link<'a> {
 			}
 			n => {
 				error!("Illegal mode: {:?}", n);
-				panic!();
+				return Err(LinkError::Mode(n));
 			},
 		}
 
@@ -90,9 +92,9 @@ impl<'a> Link<'a> {
 
 		match self.recv(self.cmdidx, 0, true) {
 			Ok(_) => Ok(()),
-			_ => {
+			Err(e) => {
 				error!("Leave mode protocol. Command error");
-				Err(())
+				Err(e)
 			},
 		}
 	}
@@ -100,7 +102,7 @@ impl<'a> Link<'a> {
 
 	/// Enter mode
 	/// TODO : change and clean
-	pub fn enter_mode(&mut self, mode: DebugMode) -> Result<(), ()> {
+	pub fn enter_mode(&mut self, mode: DebugMode) -> Result<(), LinkError> {
 		let mut rxsize = if self.version.jtag_api == 1 {
 			0
 		} else {
@@ -133,16 +135,21 @@ impl<'a> Link<'a> {
 				// No response
 				rxsize = 0;
 			},
-			_ => return Err(()),
+			m => return Err(LinkError::DebugMode(m)),
 
 		}
 
-		match self.recv(self.cmdidx, rxsize as usize, true) {
+		let result = match rxsize {
+			0 => self.recv(self.cmdidx, 0, true),
+			n => self.recv_status(n as usize),
+		};
+
+		match result {
 			Ok(_) => {
 				info!("Enter {:?} mode protocol. Correctly entered mode.", mode);
 				Ok(())
 			},
-			_ => {
+			Err(e) => {
 				error!("Enter mode {:?} protocol. Could not complete communication.", mode);
 				print!("[", );
 				for b in &self.databuf[0..16] {
@@ -154,7 +161,7 @@ impl<'a> Link<'a> {
 					print!("{:#4X}, ", b);
 				}
 				println!("]");
-				Err(())
+				Err(e)
 			},
 		}
 	}
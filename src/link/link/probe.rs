@@ -0,0 +1,56 @@
+//! Enumeration of the connected probes
+
+use crate::link::structs::ProbeInfo;
+use crate::usb::transport::UsbTransport;
+
+use super::Link;
+
+impl Link<UsbTransport> {
+	/// List all the connected STLink probes
+	/// Each probe is opened to read its serial number and firmware version.
+	/// Probes in use by another program are listed without this information.
+	pub fn probes(ctx: &rusb::Context) -> Vec<ProbeInfo> {
+		crate::usb::devices(ctx).into_iter()
+			.enumerate()
+			.map(|(index, (device, desc))| {
+				let mut info = ProbeInfo {
+					index,
+					vid: desc.vendor_id(),
+					pid: desc.product_id(),
+					name: crate::usb::model::find(desc.vendor_id(), desc.product_id()).map(|m| m.name).unwrap_or("Unknown"),
+					serial: None,
+					version: None,
+					bus: device.bus_number(),
+					address: device.address(),
+					ports: device.port_numbers().unwrap_or_default(),
+				};
+
+				let handle = match device.open() {
+					Ok(h) => h,
+					Err(e) => {
+						warn!("Could not open probe {}: {}", index, e);
+						return info;
+					},
+				};
+
+				info.serial = crate::usb::serial(&handle, &desc);
+
+				info.version = match Self::connect(&device, &desc, handle) {
+					Ok(mut link) => match link.version() {
+						Ok(_) => Some(link.version),
+						Err(e) => {
+							warn!("Could not read the firmware version of probe {}: {}", index, e);
+							None
+						},
+					},
+					Err(e) => {
+						warn!("Could not connect to probe {}: {}", index, e);
+						None
+					},
+				};
+
+				info
+			})
+			.collect()
+	}
+}
@@ -3,14 +3,16 @@
 use crate::link::util::{ buf_write_u32, buf_read_u32 };
 
 use super::super::structs::CoreRegisters;
+use super::super::error::LinkError;
 
-use libusb::Direction;
+use rusb::Direction;
 
 use super::Link;
+use super::super::transport::Transport;
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Write to debug register
-	pub fn write_debug_reg(&mut self, address: u32, value: u32) -> Result<(), ()> {
+	pub fn write_debug_reg(&mut self, address: u32, value: u32) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND };
 
 		// Match the command to the STLink version
@@ -30,23 +32,23 @@ impl<'a> Link<'a> {
 		buf_write_u32(&mut self.cmdbuf, self.cmdidx + 4, value,   true);
 		self.cmdidx += 8;
 
-		// Send command -> Receive nothing (ACK)
-		match self.recv(self.cmdidx, 2, true) {
+		// Send command -> Receive the status
+		match self.recv_status(2) {
 			Ok(_) => Ok(()),
-			_ => {
+			Err(e) => {
 				error!("Could not write to Debug Register at address: 0x{:X}", address);
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Read a debug register
-	pub fn read_debug_reg(&mut self, address: u32) -> Result<u32, ()> {
+	pub fn read_debug_reg(&mut self, address: u32) -> Result<u32, LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::READDEBUGREG };
 
 		match self.version.stlink {
 			// STLink V1 does not support this
-			1 => Err(()),
+			1 => Err(LinkError::Unsupported("reading debug registers on STLink V1")),
 			_ => {
 				// Set up command
 				self.cmd_setup(8, Direction::In);
@@ -58,12 +60,12 @@ impl<'a> Link<'a> {
 				buf_write_u32(&mut self.cmdbuf, self.cmdidx, address, true);
 				self.cmdidx += 4;
 
-				// Send command -> Receive the data
-				match self.recv(self.cmdidx, 8, false) {
+				// Send command -> Receive the status and the data
+				match self.recv_status(8) {
 					Ok(_) => Ok(buf_read_u32(&self.databuf, 4, true)),
-					_ => {
+					Err(e) => {
 						error!("Could not read debug register.");
-						Err(())
+						Err(e)
 					},
 				}
 			},
@@ -71,7 +73,7 @@ impl<'a> Link<'a> {
 	}
 
 	/// Read a register
-	pub fn read_reg(&mut self, num: u8) -> Result<u32, ()> {
+	pub fn read_reg(&mut self, num: u8) -> Result<u32, LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND };
 
 		let (readcommand, size) = match self.version.stlink {
@@ -84,25 +86,59 @@ impl<'a> Link<'a> {
 		self.push_command(readcommand);
 		self.push_command(num);
 
-		match self.recv(self.cmdidx, size, true) {
+		// The API v2 answer starts with a status word
+		let result = match size {
+			4 => self.recv(self.cmdidx, size, true),
+			_ => self.recv_status(size),
+		};
+
+		match result {
 			Ok(_) => Ok( buf_read_u32(&self.databuf, size - 4, true) ),
-			_ => {
+			Err(e) => {
 				error!("Could not get core registers (not debug).");
-				Err(())
+				Err(e)
+			},
+		}
+	}
+
+	/// Write a register
+	/// The core must be halted
+	pub fn write_reg(&mut self, num: u8, value: u32) -> Result<(), LinkError> {
+		use super::super::constants::commands::debug::{ DEBUG_COMMAND };
+
+		let writecommand = match self.version.jtag_api {
+			1 => super::super::constants::commands::debug::apiv1::WRITEREG,
+			_ => super::super::constants::commands::debug::apiv2::WRITEREG,
+		};
+
+		self.cmd_setup(2, Direction::In);
+		self.push_command(DEBUG_COMMAND);
+		self.push_command(writecommand);
+		self.push_command(num);
+
+		buf_write_u32(&mut self.cmdbuf, self.cmdidx, value, true);
+		self.cmdidx += 4;
+
+		match self.recv_status(2) {
+			Ok(_) => Ok(()),
+			Err(e) => {
+				error!("Could not write core register {}.", num);
+				Err(e)
 			},
 		}
 	}
 }
 
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Read the core registers (r0, r1, ...)
-	pub fn read_core_regs(&mut self) -> Result<CoreRegisters, ()> {
+	pub fn read_core_regs(&mut self) -> Result<CoreRegisters, LinkError> {
 		use super::super::constants::commands::debug::DEBUG_COMMAND;
 
-		let (readcommand, nregs) = match self.version.jtag_api {
-			1 => (super::super::constants::commands::debug::apiv1::READALLREGS, 84),
-			_ => (super::super::constants::commands::debug::apiv2::READALLREGS, 88),
+		// The API v2 answer starts with a status word before the registers
+		let (readcommand, nregs, base) = match self.version.jtag_api {
+			1 => (super::super::constants::commands::debug::apiv1::READALLREGS, 84, 0),
+			_ => (super::super::constants::commands::debug::apiv2::READALLREGS, 88, 4),
 		};
 
 		self.cmd_setup(nregs as u32, Direction::In);
@@ -110,22 +146,27 @@ impl<'a> Link<'a> {
 		self.push_command(DEBUG_COMMAND);
 		self.push_command(readcommand);
 
-		match self.recv(self.cmdidx, nregs, true) {
+		let result = match base {
+			0 => self.recv(self.cmdidx, nregs, true),
+			_ => self.recv_status(nregs),
+		};
+
+		match result {
 			Ok(_) => {
 				let mut regs = CoreRegisters::new();
 
-				(0..=15).for_each(|i| regs.set_r(i, buf_read_u32(&self.databuf, i*4, true)));
-				regs.set_xpsr(buf_read_u32(&self.databuf, 64, true));
-				regs.set_msp( buf_read_u32(&self.databuf, 68, true));
-				regs.set_psp( buf_read_u32(&self.databuf, 72, true));
-				regs.set_rw(  buf_read_u32(&self.databuf, 76, true));
-				regs.set_rw2( buf_read_u32(&self.databuf, 80, true));
+				(0..=15).for_each(|i| regs.set_r(i, buf_read_u32(&self.databuf, base + i*4, true)));
+				regs.set_xpsr(buf_read_u32(&self.databuf, base + 64, true));
+				regs.set_msp( buf_read_u32(&self.databuf, base + 68, true));
+				regs.set_psp( buf_read_u32(&self.databuf, base + 72, true));
+				regs.set_rw(  buf_read_u32(&self.databuf, base + 76, true));
+				regs.set_rw2( buf_read_u32(&self.databuf, base + 80, true));
 
 				Ok( regs )
 			},
-			_ => {
+			Err(e) => {
 				error!("Could not get core registers.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
@@ -1,14 +1,16 @@
 //! All reset methods
 
 use super::Link;
+use super::super::transport::Transport;
 
-use libusb::Direction;
+use rusb::Direction;
 
 use super::super::enums::{ Cmd, DebugMode, STLinkMode };
+use super::super::error::LinkError;
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// USB Reset
-	pub fn usb_reset(&mut self) -> Result<(), ()> {
+	pub fn usb_reset(&mut self) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND };
 
 		let resetsys = match self.version.jtag_api {
@@ -16,11 +18,11 @@ impl<'a> Link<'a> {
 			_ => super::super::constants::commands::debug::apiv2::RESETSYS,
 		};
 
-		match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(resetsys)]) {
+		match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(resetsys)]) {
 			Ok(_) => Ok(()),
-			Err(_) => {
+			Err(e) => {
 				error!("Exit DFU protocol. Writing failed.");
-				return Err(());
+				Err(e)
 			},
 		}
 
@@ -28,23 +30,23 @@ impl<'a> Link<'a> {
 
 
 	/// JTAG Reset
-	pub fn jtag_reset(&mut self, value: u32) -> Result<(), ()> {
+	pub fn jtag_reset(&mut self, value: u32) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::DRIVE_NRST };
 
-		match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(DRIVE_NRST), Cmd::Int8(value as u8)]) {
+		match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(DRIVE_NRST), Cmd::Int8(value as u8)]) {
 			Ok(_) => {
 				Ok(())
 			}
-			Err(_) => {
+			Err(e) => {
 				error!("Exit DFU protocol. Writing failed.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 
 	/// Assert Software Reset
-	pub fn assert_srst(&mut self, mode: STLinkMode, srst: u8) -> Result<(), ()> {
+	pub fn assert_srst(&mut self, mode: STLinkMode, srst: u8) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::DRIVE_NRST };
 
 		if mode == STLinkMode::Debug(DebugMode::SWIM) {
@@ -53,20 +55,20 @@ impl<'a> Link<'a> {
 
 		if self.version.stlink == 1 {
 			error!("STLink V1 cannot assert reset.");
-			return Err(());
+			return Err(LinkError::Unsupported("reset assertion on STLink V1"));
 		}
 
-		match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(DRIVE_NRST), Cmd::Int8(srst)]) {
+		match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(DRIVE_NRST), Cmd::Int8(srst)]) {
 			Ok(_) => Ok(()),
-			_ => {
+			Err(e) => {
 				error!("Could not assert reset.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Assert SWIM sreset line
-	pub fn swim_assert_reset(&mut self, srst: u8) -> Result<(), ()> {
+	pub fn swim_assert_reset(&mut self, srst: u8) -> Result<(), LinkError> {
 		use super::super::constants::commands::swim::{ SWIM_COMMAND, ASSERT_RESET, DEASSERT_RESET };
 
 		let rstcmd = match srst {
@@ -76,10 +78,40 @@ impl<'a> Link<'a> {
 
 		match self.command(0, Direction::In, vec![Cmd::Int8(SWIM_COMMAND), Cmd::Int8(rstcmd)]) {
 			Ok(_) => Ok(()),
-			Err(_) => {
+			Err(e) => {
 				error!("Could not assert/deassert SWIM reset.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
-}
\ No newline at end of file
+
+	/// Reset the target and halt the core at the reset vector
+	/// Uses the reset vector catch of the DEMCR register, which is restored afterwards
+	pub fn reset_halt(&mut self) -> Result<(), LinkError> {
+		use super::super::constants::registers::dcb::{ DHCSREG, DEMCREG, dhcsr::S_HALT, demcr::VC_CORERESET };
+
+		if self.version.jtag_api == 1 {
+			error!("Reset and halt is not available on STLink V1.");
+			return Err(LinkError::Unsupported("reset and halt on STLink V1"));
+		}
+
+		self.halt()?;
+
+		let demcr = self.read_debug_reg(DEMCREG)?;
+		self.write_debug_reg(DEMCREG, demcr | VC_CORERESET)?;
+
+		let reset = self.usb_reset();
+
+		// Restore the vector catch even if the reset failed
+		self.write_debug_reg(DEMCREG, demcr)?;
+		reset?;
+
+		match self.read_debug_reg(DHCSREG)? & S_HALT {
+			0 => {
+				error!("Core did not halt after reset.");
+				self.halt()
+			},
+			_ => Ok(()),
+		}
+	}
+}
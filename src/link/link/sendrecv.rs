@@ -1,29 +1,31 @@
 //! Send and receive methods of the `Link` struct
 
 use crate::link::enums::{ Cmd };
+use crate::link::error::LinkError;
 use crate::link::util::{ buf_write_u32, buf_write_u16 };
 
-use libusb::Direction;
+use rusb::Direction;
 
 use super::Link;
+use super::super::transport::Transport;
 
 /// Send and receive (IO) functions
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Send command and data
 	/// `txsize` indicates the amount of bytes to be transfered from the data buffer
 	/// `cmdsize` indicates the amount od command bytes to be sent
 	/// `terminate` indicates, if this is a STLinkV1 device, if the terminate sequence is executed
-	/// Returns `Ok(n)` where `n` is the amount of bytes sent or `Err(())` if there was an error
-	pub fn send(&mut self, cmdsize: usize, txsize: usize, terminate: bool) -> Result<usize, ()> {
+	/// Returns `Ok(n)` where `n` is the amount of bytes sent or the `LinkError` that stopped the transfer
+	pub fn send(&mut self, cmdsize: usize, txsize: usize, terminate: bool) -> Result<usize, LinkError> {
 		use super::super::constants::misc::TIMEOUT::WRITE as WriteTimeout;
 		// Send command
-		match self.handle.write_bulk(self.tx, &self.cmdbuf[0..cmdsize], WriteTimeout) {
+		match self.transport.write_command(&self.cmdbuf[0..cmdsize], WriteTimeout) {
 			// Check the amount of bytes transfered
 			Ok(n) if n == cmdsize => (),
 			Ok(n) => error!("Send protocol. Tried to send {} command bytes, sent {}", cmdsize, n),
 			Err(e) => {
 				error!("Send protocol. Failure to send command while sending internal buffer.\nError: {}", e);
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -31,7 +33,7 @@ impl<'a> Link<'a> {
 			// If txsize = 0, don't send anything
 			0 => Ok(0),
 			// If there is data to transmit, send `txsize` bytes from the internal buffer
-			_ => match self.handle.write_bulk(self.tx, &self.databuf[0..txsize], WriteTimeout) {
+			_ => match self.transport.write_data(&self.databuf[0..txsize], WriteTimeout) {
 				Ok(n) if n == txsize => Ok(n),
 				Ok(n) => {
 					error!("Send protocol. Tried to send {} bytes of data, sent {}", txsize, n);
@@ -42,7 +44,7 @@ impl<'a> Link<'a> {
 				},
 				Err(e) => {
 					error!("Send protocol. Failure to send internal buffer.\nError: {}", e);
-					Err(())
+					Err(e)
 				},
 			}
 		}
@@ -52,17 +54,17 @@ impl<'a> Link<'a> {
 	/// `rxsize` indicates the amount of bytes to be transfered from the data buffer
 	/// `cmdsize` indicates the amount od command bytes to be sent
 	/// `terminate` indicates, if this is a STLinkV1 device, if the terminate sequence is executed
-	/// Returns `Ok(n)` where `n` is the amount of bytes received or `Err(())` if there was an error
-	pub fn recv(&mut self, cmdsize: usize, rxsize: usize, terminate: bool) -> Result<usize, ()> {
+	/// Returns `Ok(n)` where `n` is the amount of bytes received or the `LinkError` that stopped the transfer
+	pub fn recv(&mut self, cmdsize: usize, rxsize: usize, terminate: bool) -> Result<usize, LinkError> {
 		use super::super::constants::misc::TIMEOUT::WRITE as WriteTimeout;
 		// Receive command
-		match self.handle.write_bulk(self.tx, &self.cmdbuf[0..cmdsize], WriteTimeout) {
+		match self.transport.write_command(&self.cmdbuf[0..cmdsize], WriteTimeout) {
 			// Check the amount of bytes transfered
 			Ok(n) if n == cmdsize => (),
 			Ok(n) => error!("Receive protocol. Tried to send {} command bytes, sent {}", cmdsize, n),
 			Err(e) => {
 				error!("Receive protocol. Failure to send command while sending internal buffer.\nError: {}", e);
-				return Err(());
+				return Err(e);
 			},
 		}
 
@@ -70,7 +72,7 @@ impl<'a> Link<'a> {
 			// If rxsize = 0, don't receive anything
 			0 => Ok(0),
 			// If there is data to transmit, send `rxsize` bytes from the internal buffer
-			_ => match self.handle.read_bulk(self.rx, &mut self.databuf, WriteTimeout) {
+			_ => match self.transport.read_data(&mut self.databuf, WriteTimeout) {
 				Ok(n) if n == rxsize => Ok(n),
 				Ok(n) => {
 					error!("Receive protocol. Tried to receive {} bytes of data, sent {}", rxsize, n);
@@ -81,7 +83,7 @@ impl<'a> Link<'a> {
 				},
 				Err(e) => {
 					error!("Receive protocol. Failure to read into internal buffer.\nError: {}", e);
-					Err(())
+					Err(e)
 				},
 			}
 		}
@@ -89,23 +91,36 @@ impl<'a> Link<'a> {
 
 
 	/// Quick way to make commands
-	pub fn command(&mut self, size: usize, dir: Direction, cmds: Vec<Cmd>) -> Result<usize, ()> {
+	pub fn command(&mut self, size: usize, dir: Direction, cmds: Vec<Cmd>) -> Result<usize, LinkError> {
+		self.load_command(size, dir, cmds);
+
+		match dir {
+			Direction::In  => self.recv(self.cmdidx, size, true),
+			Direction::Out => self.send(self.cmdidx, size, true),
+		}
+	}
+
+	/// Quick way to make commands whose answer starts with a status byte
+	/// The status is checked and the command retried as in `recv_status`
+	pub fn command_status(&mut self, size: usize, cmds: Vec<Cmd>) -> Result<usize, LinkError> {
+		self.load_command(size, Direction::In, cmds);
+
+		self.recv_status(size)
+	}
+
+	/// Set up the command buffer with the given commands
+	fn load_command(&mut self, size: usize, dir: Direction, cmds: Vec<Cmd>) {
 		self.cmd_setup(size as u32, dir);
 		cmds.iter().for_each(|&cmd| match cmd {
 			Cmd::Int8(byte) => self.push_command(byte),
 			Cmd::Int16(int) => {
-				buf_write_u16(&mut self.databuf, self.cmdidx, int, true);
+				buf_write_u16(&mut self.cmdbuf, self.cmdidx, int, true);
 				self.cmdidx += 2;
 			},
 			Cmd::Int32(int) => {
-				buf_write_u32(&mut self.databuf, self.cmdidx, int, true);
+				buf_write_u32(&mut self.cmdbuf, self.cmdidx, int, true);
 				self.cmdidx += 4;
 			},
 		});
-
-		match dir {
-			Direction::In  => self.recv(self.cmdidx, size, true),
-			Direction::Out => self.send(self.cmdidx, size, true),
-		}
 	}
 }
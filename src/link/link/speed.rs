@@ -3,9 +3,11 @@
 use crate::link::structs::SpeedMap;
 use crate::link::enums::{ DebugMode, Cmd };
 use crate::link::util::{ buf_read_u32 };
+use crate::link::error::LinkError;
 use super::Link;
+use super::super::transport::Transport;
 
-use libusb::Direction;
+use rusb::Direction;
 
 
 /// SWD clock speed
@@ -42,9 +44,9 @@ pub const JTAG_SPEED_MAP: [SpeedMap; 8] = [
 
 pub const JTAG_DEFAULT_SPEED: SpeedMap = SpeedMap::new(1125, 32);
 
-impl<'a> Link<'a> {
+impl<T: Transport> Link<T> {
 	/// Set the communication speed
-	pub fn set_speed(&mut self, mode: DebugMode, khz: usize) -> Result<(), ()> {
+	pub fn set_speed(&mut self, mode: DebugMode, khz: usize) -> Result<(), LinkError> {
 
 		match mode {
 			DebugMode::SWIM => self.speed_swim(khz),
@@ -58,12 +60,12 @@ impl<'a> Link<'a> {
 			} else {
 				self.speed_jtag(khz)
 			},
-			_ => Err(()),
+			m => Err(LinkError::DebugMode(m)),
 		}
 	}
 
 	/// Set the SWIM speed
-	pub fn speed_swim(&mut self, khz: usize) -> Result<(), ()> {
+	pub fn speed_swim(&mut self, khz: usize) -> Result<(), LinkError> {
 		use super::super::constants::commands::swim::{ SWIM_COMMAND, SPEED };
 
 		let speedcmd = match khz {
@@ -73,59 +75,59 @@ impl<'a> Link<'a> {
 
 		match self.command(0, Direction::In, vec![Cmd::Int8(SWIM_COMMAND), Cmd::Int8(SPEED), Cmd::Int8(speedcmd)]) {
 			Ok(_) => Ok(()),
-			_ => {
+			Err(e) => {
 				error!("Set SWIM speed protocol. Could not set speed, bad command.");
-				Err(())
+				Err(e)
 			},
 		}
 	}
 
 	/// Set the JTAG speed
-	pub fn speed_jtag(&mut self, khz: usize) -> Result<(), ()> {
+	pub fn speed_jtag(&mut self, khz: usize) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::JTAG_SET_FREQ };
 		use super::super::constants::flags::HAS_JTAG_SET_FREQ;
 
 
 		match self.version.flags & HAS_JTAG_SET_FREQ {
 			0 => Ok(()),
-			_ => match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(JTAG_SET_FREQ), Cmd::Int16(closest_speed( khz, &JTAG_SPEED_MAP ).divisor as u16)]) {
+			_ => match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(JTAG_SET_FREQ), Cmd::Int16(closest_speed( khz, &JTAG_SPEED_MAP ).divisor as u16)]) {
 				Ok(_) => {
 					info!("Tried to match {} kHz, set speed: {} kHz", khz, closest_speed(khz, &JTAG_SPEED_MAP).speed);
 					Ok(())
 				},
-				_ => {
+				Err(e) => {
 					error!("Set JTAG speed protocol. Could not set speed. Bad command.");
-					Err(())
+					Err(e)
 				},
 			},
 		}
 	}
 
 	/// Set thw SWD speed
-	pub fn speed_swd(&mut self, khz: usize) -> Result<(), ()> {
+	pub fn speed_swd(&mut self, khz: usize) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::SWD_SET_FREQ };
 		use super::super::constants::flags::HAS_SWD_SET_FREQ;
 
 		match self.version.flags & HAS_SWD_SET_FREQ {
 			0 => Ok(()),
-			_ => match self.command(2, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(SWD_SET_FREQ), Cmd::Int16( closest_speed(khz, &SWD_SPEED_MAP).divisor as u16 )]) {
+			_ => match self.command_status(2, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(SWD_SET_FREQ), Cmd::Int16( closest_speed(khz, &SWD_SPEED_MAP).divisor as u16 )]) {
 				Ok(_) => Ok(()),
-				_ => {
+				Err(e) => {
 					error!("Set SWD speed protocol. Could not set speed. Bad command.");
-					Err(())
+					Err(e)
 				},
 			},
 		}
 	}
 
 	/// Set speed for STLink V3 devices
-	pub fn speed_v3(&mut self,is_jtag: bool, khz: usize) -> Result<(), ()> {
+	pub fn speed_v3(&mut self,is_jtag: bool, khz: usize) -> Result<(), LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv3::SET_COM_FREQ };
 
 		let map = self.get_com_freq(is_jtag)?;
 		let jtagcmd = if is_jtag { 1 } else { 0 };
 
-		match self.command(8, Direction::In,
+		match self.command_status(8,
 			vec![Cmd::Int8(DEBUG_COMMAND),
 				Cmd::Int8(SET_COM_FREQ),
 				Cmd::Int8(jtagcmd),
@@ -134,9 +136,9 @@ impl<'a> Link<'a> {
 			])
 		{
 			Ok(_) => Ok(()),
-			_ => {
+			Err(e) => {
 				error!("STLink V3 set speed protocol. Bad command.");
-				Err(())
+				Err(e)
 			},
 		}
 
@@ -145,14 +147,14 @@ impl<'a> Link<'a> {
 
 
 	/// Method for getting com freq
-	pub fn get_com_freq(&mut self, is_jtag: bool) -> Result<Vec<SpeedMap>, ()> {
+	pub fn get_com_freq(&mut self, is_jtag: bool) -> Result<Vec<SpeedMap>, LinkError> {
 		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv3::GET_COM_FREQ };
 
 		match self.version.jtag_api {
 			3 => {
 				let jtagcmd = if is_jtag { 1 } else { 0 };
 
-				match self.command(52, Direction::In, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(GET_COM_FREQ), Cmd::Int8(jtagcmd)]) {
+				match self.command_status(52, vec![Cmd::Int8(DEBUG_COMMAND), Cmd::Int8(GET_COM_FREQ), Cmd::Int8(jtagcmd)]) {
 					Ok(_) => {
 						let size = if self.databuf[8] > super::super::constants::misc::V3::MAX_FREQ_NB {
 							super::super::constants::misc::V3::MAX_FREQ_NB
@@ -169,14 +171,14 @@ impl<'a> Link<'a> {
 
 						Ok(map)
 					},
-					_ => {
+					Err(e) => {
 						error!("STLink V3 Get Comm Frequency protocol. Failed to receive data.");
-						Err(())
+						Err(e)
 					},
 				}
 
 			},
-			_ => Err(()),
+			_ => Err(LinkError::Unsupported("communication frequency query before STLink V3")),
 		}
 	}
 }
@@ -0,0 +1,84 @@
+//! Decoding of the status byte returned by the debug commands
+
+use crate::link::error::{ LinkError, StatusError };
+
+use rusb::Direction;
+
+use super::Link;
+use super::super::transport::Transport;
+
+impl<T: Transport> Link<T> {
+	/// Decode the status byte at the start of the data buffer
+	pub fn check_status(&self) -> Result<(), LinkError> {
+		match StatusError::from_code(self.databuf[0]) {
+			None => Ok(()),
+			Some(s) => Err(LinkError::Status(s)),
+		}
+	}
+
+	/// Send the command in the command buffer and check the status byte of the answer
+	/// If the probe answers with a WAIT status the command is sent again after an
+	/// increasing delay. Any other error clears the sticky flags before being reported.
+	/// Returns `Ok(n)` where `n` is the amount of bytes received
+	pub fn recv_status(&mut self, rxsize: usize) -> Result<usize, LinkError> {
+		use super::super::constants::misc::RETRY::{ MAX_WAIT, BACKOFF };
+
+		let mut retries = 0;
+
+		loop {
+			let n = self.recv(self.cmdidx, rxsize, true)?;
+
+			match self.check_status() {
+				Ok(_) => return Ok(n),
+
+				Err(LinkError::Status(StatusError::ApWait)) |
+				Err(LinkError::Status(StatusError::DpWait)) if retries < MAX_WAIT => {
+					debug!("Status protocol. Target answered WAIT, retry {} of {}", retries + 1, MAX_WAIT);
+					std::thread::sleep(BACKOFF * (1 << retries));
+					retries += 1;
+				},
+
+				Err(e) => {
+					// On the V1 the command follows the 15 bytes USBC wrapper written by `cmd_setup`
+					let at = if self.version.stlink == 1 { 15 } else { 0 };
+					error!("Status protocol. Command 0x{:02X} 0x{:02X} failed: {}", self.cmdbuf[at], self.cmdbuf[at + 1], e);
+					self.clear_sticky();
+					return Err(e);
+				},
+			}
+		}
+	}
+
+	/// Check the status of the last memory transfer
+	/// Reading the status also clears the sticky error flags of the probe
+	pub fn rw_status(&mut self) -> Result<(), LinkError> {
+		use super::super::constants::commands::debug::{ DEBUG_COMMAND, apiv2::{ GETLASTRWSTATUS, GETLASTRWSTATUS2 } };
+		use super::super::constants::flags::HAS_GETLASTRWSTATUS2;
+
+		match self.version.jtag_api {
+			1 => Ok(()),
+			_ => {
+				let (len, cmd) = match self.version.flags & HAS_GETLASTRWSTATUS2 {
+					0 => ( 2, GETLASTRWSTATUS ),
+					_ => (12, GETLASTRWSTATUS2),
+				};
+
+				self.cmd_setup(len, Direction::In);
+				self.push_command(DEBUG_COMMAND);
+				self.push_command(cmd);
+
+				self.recv(self.cmdidx, len as usize, true)?;
+
+				self.check_status()
+			},
+		}
+	}
+
+	/// Clear the sticky errors after a failed command
+	fn clear_sticky(&mut self) {
+		match self.rw_status() {
+			Ok(_) => (),
+			Err(e) => debug!("Status protocol. Last RW status after failure: {}", e),
+		}
+	}
+}
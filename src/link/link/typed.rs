@@ -0,0 +1,73 @@
+//! Typed memory access
+//! Values are read and written in the little endian order of the target.
+
+use crate::link::util::{ buf_read_u32, buf_read_u16, buf_write_u32 };
+
+use super::super::error::LinkError;
+
+use super::Link;
+use super::super::transport::Transport;
+
+impl<T: Transport> Link<T> {
+	/// Read a byte at `address`
+	pub fn read_u8(&mut self, address: u32) -> Result<u8, LinkError> {
+		Ok(self.read_memory(address, 1)?[0])
+	}
+
+	/// Read a halfword at `address`
+	pub fn read_u16(&mut self, address: u32) -> Result<u16, LinkError> {
+		Ok(buf_read_u16(&self.read_memory(address, 2)?, 0, true))
+	}
+
+	/// Read a word at `address`
+	/// An aligned word is read with a single 32 bit access
+	pub fn read_u32(&mut self, address: u32) -> Result<u32, LinkError> {
+		Ok(buf_read_u32(&self.read_memory(address, 4)?, 0, true))
+	}
+
+	/// Read a doubleword at `address`
+	pub fn read_u64(&mut self, address: u32) -> Result<u64, LinkError> {
+		let data = self.read_memory(address, 8)?;
+
+		Ok((buf_read_u32(&data, 4, true) as u64) << 32 | buf_read_u32(&data, 0, true) as u64)
+	}
+
+	/// Read `n` consecutive words starting at `address`
+	pub fn read_u32_slice(&mut self, address: u32, n: usize) -> Result<Vec<u32>, LinkError> {
+		let data = self.read_memory(address, n * 4)?;
+
+		Ok(data.chunks(4).map(|w| buf_read_u32(w, 0, true)).collect())
+	}
+
+	/// Write a word at `address`
+	/// An aligned word is written with a single 32 bit access
+	pub fn write_u32(&mut self, address: u32, value: u32) -> Result<(), LinkError> {
+		let mut data = [0; 4];
+		buf_write_u32(&mut data, 0, value, true);
+
+		self.write_memory(address, &data)
+	}
+
+	/// Write consecutive words starting at `address`
+	pub fn write_u32_slice(&mut self, address: u32, values: &[u32]) -> Result<(), LinkError> {
+		let mut data = vec![0; values.len() * 4];
+		for (i, v) in values.iter().enumerate() {
+			buf_write_u32(&mut data, i * 4, *v, true);
+		}
+
+		self.write_memory(address, &data)
+	}
+
+	/// Read the word at `address`, replace the bits selected by `mask` with those of `value`
+	/// and write it back
+	/// The target may modify the word between the read and the write.
+	/// Returns the value written
+	pub fn modify_u32(&mut self, address: u32, mask: u32, value: u32) -> Result<u32, LinkError> {
+		let old = self.read_u32(address)?;
+		let new = (old & !mask) | (value & mask);
+
+		self.write_u32(address, new)?;
+
+		Ok(new)
+	}
+}
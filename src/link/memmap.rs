@@ -0,0 +1,165 @@
+//! Memory map of the target
+//! Combines the memories of the detected chip with the regions defined by the
+//! Cortex-M architecture, so addresses can be classified before they are accessed.
+
+use super::structs::MemInfo;
+
+
+/// Kind of memory at an address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionKind {
+	/// Main flash memory
+	Flash,
+	/// SRAM
+	Ram,
+	/// Data EEPROM of the L0/L1 devices
+	Eeprom,
+	/// Peripheral registers
+	Peripheral,
+	/// System memory, device information, option bytes and Cortex-M system registers
+	System,
+	/// External memory interfaces (FSMC / FMC)
+	External,
+	/// Nothing is known to be mapped at the address
+	Unknown,
+}
+
+/// What to do with an access to an address that is not mapped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessPolicy {
+	/// Do the access without checking
+	Allow,
+	/// Log a warning and do the access
+	Warn,
+	/// Refuse the access with `LinkError::Unmapped`
+	Refuse,
+}
+
+/// A contiguous block of the memory map
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+	pub name: &'static str,
+	pub kind: RegionKind,
+	pub base: u32,
+	pub size: u32,
+}
+
+impl MemoryRegion {
+	pub const fn new(name: &'static str, kind: RegionKind, base: u32, size: u32) -> Self {
+		Self { name, kind, base, size }
+	}
+
+	/// First address after the region
+	pub fn end(&self) -> u64 {
+		self.base as u64 + self.size as u64
+	}
+
+	/// Check if `address` is in the region
+	pub fn contains(&self, address: u32) -> bool {
+		address >= self.base && (address as u64) < self.end()
+	}
+}
+
+/// Regions defined by the Cortex-M architecture
+const CORTEX_M: [MemoryRegion; 4] = [
+	MemoryRegion::new("Peripherals",            RegionKind::Peripheral, 0x4000_0000, 0x2000_0000),
+	MemoryRegion::new("External memory",        RegionKind::External,   0x6000_0000, 0x8000_0000),
+	MemoryRegion::new("Private peripheral bus", RegionKind::System,     0xE000_0000, 0x0010_0000),
+	MemoryRegion::new("Vendor system",          RegionKind::System,     0xE010_0000, 0x1FF0_0000),
+];
+
+/// Block of the code region holding the system memory, device information and option bytes
+const INFORMATION: MemoryRegion = MemoryRegion::new("Information block", RegionKind::System, 0x1FF0_0000, 0x0010_0000);
+
+
+/// Memory map of the target
+/// Regions are searched in order, the memories of the chip come before the
+/// architectural regions that contain them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+	regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMap {
+	/// Memory map of an unknown Cortex-M chip
+	/// Only the architectural regions are known, flash and SRAM are not.
+	pub fn cortex_m() -> Self {
+		Self { regions: CORTEX_M.to_vec() }
+	}
+
+	/// Memory map of a chip with the memories described by `info`
+	pub fn from_info(info: &MemInfo) -> Self {
+		let mut regions = Vec::new();
+
+		// The flash size is given in kB
+		if info.flash.size != 0 {
+			regions.push(MemoryRegion::new("Flash", RegionKind::Flash, info.flash.base, info.flash.size * 1024));
+		}
+
+		for r in info.ram.iter() {
+			regions.push(MemoryRegion::new("SRAM", RegionKind::Ram, r.base, r.size));
+		}
+
+		if info.eeprom.size != 0 {
+			regions.push(MemoryRegion::new("Data EEPROM", RegionKind::Eeprom, info.eeprom.base, info.eeprom.size));
+		}
+
+		if info.sys.size != 0 {
+			regions.push(MemoryRegion::new("System memory", RegionKind::System, info.sys.base, info.sys.size));
+		}
+
+		// At boot the flash is aliased at address 0
+		if info.flash.size != 0 && info.flash.base != 0 {
+			regions.push(MemoryRegion::new("Boot alias", RegionKind::Flash, 0, info.flash.size * 1024));
+		}
+
+		regions.push(INFORMATION);
+		regions.extend_from_slice(&CORTEX_M);
+
+		Self { regions }
+	}
+
+	/// All the regions of the map
+	pub fn regions(&self) -> &[MemoryRegion] {
+		&self.regions
+	}
+
+	/// Region containing `address`
+	pub fn find(&self, address: u32) -> Option<&MemoryRegion> {
+		self.regions.iter().find(|r| r.contains(address))
+	}
+
+	/// Kind of memory at `address`
+	pub fn kind(&self, address: u32) -> RegionKind {
+		match self.find(address) {
+			Some(r) => r.kind,
+			None => RegionKind::Unknown,
+		}
+	}
+
+	/// First address of the range of `size` bytes at `address` that is not mapped
+	/// Returns `None` if the whole range is mapped
+	pub fn unmapped(&self, address: u32, size: usize) -> Option<u32> {
+		let end = address as u64 + size as u64;
+		let mut next = address as u64;
+
+		while next < end {
+			match self.find(next as u32) {
+				Some(r) if r.kind != RegionKind::Unknown => next = r.end(),
+				_ => return Some(next as u32),
+			}
+		}
+
+		None
+	}
+}
+
+impl std::fmt::Display for MemoryMap {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		for r in self.regions.iter() {
+			writeln!(f, "0x{:08X} - 0x{:08X} {:<11} {}", r.base, r.end() - 1, format!("{:?}", r.kind), r.name)?;
+		}
+
+		Ok(())
+	}
+}
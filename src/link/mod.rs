@@ -5,6 +5,13 @@ pub mod util;
 pub mod structs;
 pub mod enums;
 pub mod constants;
+pub mod error;
+pub mod transport;
+pub mod record;
+pub mod memmap;
+pub mod options;
+pub mod dump;
+pub mod algorithm;
 
 pub mod link;
 
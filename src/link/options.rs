@@ -0,0 +1,781 @@
+//! Option bytes of the flash controllers
+//! The option registers are decoded into a struct per family. The raw values
+//! are kept, so writing the struct back only changes the decoded fields and
+//! leaves the bits this library does not know about as they were read.
+
+use super::chipid::STM32ChipID;
+use super::enums::FlashType;
+
+
+/// Read protection level
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RdpLevel {
+	/// No protection
+	Level0,
+	/// The flash cannot be read by the debugger, going back to level 0 mass erases it
+	Level1,
+	/// The debug port is disabled for good
+	Level2,
+}
+
+impl RdpLevel {
+	/// Level of the RDP option byte, any value but 0xAA and 0xCC is level 1
+	pub fn from_byte(byte: u8) -> Self {
+		match byte {
+			0xAA => RdpLevel::Level0,
+			0xCC => RdpLevel::Level2,
+			_ => RdpLevel::Level1,
+		}
+	}
+
+	/// RDP option byte of the level
+	pub fn byte(&self) -> u8 {
+		match *self {
+			RdpLevel::Level0 => 0xAA,
+			RdpLevel::Level1 => 0x55,
+			RdpLevel::Level2 => 0xCC,
+		}
+	}
+}
+
+/// Confirmation token needed to set RDP level 2 on the chip `id`
+/// It names the chip, so a token meant for one family does not work on another.
+pub fn rdp2_confirmation(id: u32) -> String {
+	format!("PERMANENTLY-DISABLE-DEBUG-0x{:03X}", id)
+}
+
+impl std::fmt::Display for RdpLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			RdpLevel::Level0 => write!(f, "level 0"),
+			RdpLevel::Level1 => write!(f, "level 1"),
+			RdpLevel::Level2 => write!(f, "level 2"),
+		}
+	}
+}
+
+
+/// Option bytes of the F2/F4/F7 devices, from OPTCR and OPTCR1
+#[derive(Debug, Copy, Clone)]
+pub struct OptionsF4 {
+	pub rdp: RdpLevel,
+	/// Brown-out reset level, 0 when off
+	pub bor: u8,
+	/// Watchdog enabled by software instead of at reset
+	pub wdg_sw: bool,
+	/// No reset when entering stop mode
+	pub nrst_stop: bool,
+	/// No reset when entering standby mode
+	pub nrst_stdby: bool,
+	/// Cleared bits protect the sectors, those of the second bank of f42x/43x from bit 12
+	pub nwrp: u32,
+	/// The set bits of `nwrp` select the PCROP sectors instead (SPRMOD)
+	pub pcrop: bool,
+	/// Dual bank mode of f42x/43x and f76x/77x
+	pub dual_bank: Option<bool>,
+	/// Boot from the second bank of f42x/43x
+	pub bfb2: Option<bool>,
+	/// BOOT_ADD0 and BOOT_ADD1 of f7, in units of 16 kB
+	pub boot_add: Option<[u16; 2]>,
+
+	optcr: u32,
+	optcr1: Option<u32>,
+	family: Family,
+}
+
+impl OptionsF4 {
+	/// Decode the option registers of the chip `id`
+	/// `optcr1` is `None` on the devices without OPTCR1.
+	pub(crate) fn decode(id: u32, optcr: u32, optcr1: Option<u32>) -> Self {
+		use super::constants::flash::{ optcr::*, optcr1 };
+
+		let family = Family::of(id);
+		let bits = |mask: u32| optcr & mask != 0;
+
+		// The bits without a sector stay set
+		let mut nwrp = !NWRP | ((optcr >> NWRP_SHIFT) & NWRP);
+		let mut dual_bank = None;
+		let mut bfb2 = None;
+		let mut boot_add = None;
+
+		match (family, optcr1) {
+			(Family::F42x, Some(r1)) => {
+				nwrp = (nwrp & !(NWRP << 12)) | ((r1 >> optcr1::NWRP_SHIFT) & NWRP) << 12;
+				dual_bank = Some(bits(DB1M));
+				bfb2 = Some(bits(BFB2));
+			},
+			(Family::F7, Some(r1)) => {
+				if id == STM32ChipID::F7XXXX as u32 {
+					dual_bank = Some(!bits(NDBANK));
+				}
+				boot_add = Some([r1 as u16, (r1 >> optcr1::BOOT_ADD1_SHIFT) as u16]);
+			},
+			_ => (),
+		}
+
+		Self {
+			rdp: RdpLevel::from_byte((optcr >> RDP_SHIFT) as u8),
+			bor: 3 - ((optcr & BOR_LEV) >> BOR_LEV_SHIFT) as u8,
+			wdg_sw: bits(WDG_SW),
+			nrst_stop: bits(NRST_STOP),
+			nrst_stdby: bits(NRST_STDBY),
+			nwrp,
+			pcrop: bits(SPRMOD),
+			dual_bank,
+			bfb2,
+			boot_add,
+
+			optcr,
+			optcr1,
+			family,
+		}
+	}
+
+	/// Values of OPTCR and OPTCR1, without the lock and start bits
+	pub(crate) fn encode(&self) -> (u32, Option<u32>) {
+		use super::constants::flash::{ optcr::*, optcr1 };
+
+		let mut optcr = self.optcr & !(OPTLOCK | OPTSTRT | BOR_LEV | 0xFF << RDP_SHIFT | NWRP << NWRP_SHIFT);
+		optcr |= (self.rdp.byte() as u32) << RDP_SHIFT;
+		optcr |= ((3 - std::cmp::min(self.bor, 3)) as u32) << BOR_LEV_SHIFT;
+		optcr |= (self.nwrp & NWRP) << NWRP_SHIFT;
+		optcr = set(optcr, WDG_SW, self.wdg_sw);
+		optcr = set(optcr, NRST_STOP, self.nrst_stop);
+		optcr = set(optcr, NRST_STDBY, self.nrst_stdby);
+		optcr = set(optcr, SPRMOD, self.pcrop);
+
+		let mut optcr1 = self.optcr1;
+
+		match (self.family, self.optcr1) {
+			(Family::F42x, Some(r1)) => {
+				optcr1 = Some((r1 & !(NWRP << optcr1::NWRP_SHIFT)) | ((self.nwrp >> 12) & NWRP) << optcr1::NWRP_SHIFT);
+				if let Some(dual) = self.dual_bank {
+					optcr = set(optcr, DB1M, dual);
+				}
+				if let Some(bfb2) = self.bfb2 {
+					optcr = set(optcr, BFB2, bfb2);
+				}
+			},
+			(Family::F7, Some(_)) => {
+				if let Some(boot) = self.boot_add {
+					optcr1 = Some(boot[0] as u32 | (boot[1] as u32) << optcr1::BOOT_ADD1_SHIFT);
+				}
+				if let Some(dual) = self.dual_bank {
+					optcr = set(optcr, NDBANK, !dual);
+				}
+			},
+			_ => (),
+		}
+
+		(optcr, optcr1)
+	}
+
+	/// Check if the chip `id` has the OPTCR1 register
+	pub(crate) fn has_optcr1(id: u32) -> bool {
+		Family::of(id) != Family::F4
+	}
+}
+
+/// Option bytes are equal when they program the same values
+impl PartialEq for OptionsF4 {
+	fn eq(&self, other: &Self) -> bool {
+		self.encode() == other.encode()
+	}
+}
+
+impl Eq for OptionsF4 {}
+
+/// Layout of the F2/F4/F7 option registers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Family {
+	F4,
+	F42x,
+	F7,
+}
+
+impl Family {
+	fn of(id: u32) -> Self {
+		match id {
+			id if id == STM32ChipID::F4HD as u32 || id == STM32ChipID::F4DSI as u32 => Family::F42x,
+			id if id == STM32ChipID::F7 as u32 || id == STM32ChipID::F7XXXX as u32 || id == STM32ChipID::F72XXX as u32 => Family::F7,
+			_ => Family::F4,
+		}
+	}
+}
+
+impl std::fmt::Display for OptionsF4 {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		use super::constants::flash::optcr::NWRP;
+
+		writeln!(f, "OPTCR        0x{:08X}", self.optcr)?;
+		if let Some(r1) = self.optcr1 {
+			writeln!(f, "OPTCR1       0x{:08X}", r1)?;
+		}
+		writeln!(f, "RDP          {}", self.rdp)?;
+		writeln!(f, "BOR          {}", bor(self.bor))?;
+		writeln!(f, "Watchdog     {}", watchdog(self.wdg_sw))?;
+		writeln!(f, "Stop         {}", reset(self.nrst_stop))?;
+		writeln!(f, "Standby      {}", reset(self.nrst_stdby))?;
+		match (self.pcrop, self.family) {
+			// Only the bits of the sectors are listed, the others are set
+			(true, Family::F42x) => writeln!(f, "PCROP        {}", sectors(!(self.nwrp & 0xFF_FFFF)))?,
+			(true, _) => writeln!(f, "PCROP        {}", sectors(!(self.nwrp & NWRP)))?,
+			(false, _) => writeln!(f, "nWRP         {}", sectors(self.nwrp))?,
+		}
+		if let Some(dual) = self.dual_bank {
+			writeln!(f, "Banks        {}", banks(dual))?;
+		}
+		if let Some(bfb2) = self.bfb2 {
+			writeln!(f, "BFB2         {}", bfb2)?;
+		}
+		if let Some(boot) = self.boot_add {
+			writeln!(f, "BOOT_ADD0    0x{:08X}", boot[0] as u32 * 0x4000)?;
+			writeln!(f, "BOOT_ADD1    0x{:08X}", boot[1] as u32 * 0x4000)?;
+		}
+
+		Ok(())
+	}
+}
+
+
+/// Option bytes of the F0/F1/F3 devices, from OBR and WRPR
+#[derive(Debug, Copy, Clone)]
+pub struct OptionsF1 {
+	pub rdp: RdpLevel,
+	/// Watchdog enabled by software instead of at reset
+	pub wdg_sw: bool,
+	/// No reset when entering stop mode
+	pub nrst_stop: bool,
+	/// No reset when entering standby mode
+	pub nrst_stdby: bool,
+	/// User data bytes
+	pub data: [u8; 2],
+	/// Cleared bits protect groups of pages
+	pub nwrp: u32,
+
+	/// User byte as read, with the bits that are not decoded
+	user: u8,
+	/// F0/F3 layout of OBR
+	f0: bool,
+}
+
+impl OptionsF1 {
+	/// Decode OBR and WRPR of the chip `id`
+	pub(crate) fn decode(id: u32, obr: u32, wrpr: u32) -> Self {
+		use super::constants::flash::f1::{ obr::*, user::* };
+
+		let f0 = !Self::f1_layout(id);
+
+		let (rdp, shift) = match f0 {
+			true => match (obr >> 1) & 3 {
+				0 => (RdpLevel::Level0, F0_USER_SHIFT),
+				3 => (RdpLevel::Level2, F0_USER_SHIFT),
+				_ => (RdpLevel::Level1, F0_USER_SHIFT),
+			},
+			false => match obr & RDPRT {
+				0 => (RdpLevel::Level0, USER_SHIFT),
+				_ => (RdpLevel::Level1, USER_SHIFT),
+			},
+		};
+
+		let user = (obr >> shift) as u8;
+
+		Self {
+			rdp,
+			wdg_sw: user & WDG_SW != 0,
+			nrst_stop: user & NRST_STOP != 0,
+			nrst_stdby: user & NRST_STDBY != 0,
+			data: [(obr >> (shift + 8)) as u8, (obr >> (shift + 16)) as u8],
+			nwrp: wrpr,
+
+			user,
+			f0,
+		}
+	}
+
+	/// Option bytes from RDP to WRP3, without their complements
+	pub(crate) fn bytes(&self) -> [u8; 8] {
+		use super::constants::flash::f1::{ user::*, RDP_KEY };
+
+		let mut user = self.user & !(WDG_SW | NRST_STOP | NRST_STDBY);
+		if self.wdg_sw { user |= WDG_SW; }
+		if self.nrst_stop { user |= NRST_STOP; }
+		if self.nrst_stdby { user |= NRST_STDBY; }
+
+		let rdp = match (self.rdp, self.f0) {
+			(RdpLevel::Level0, false) => RDP_KEY,
+			(level, _) => level.byte(),
+		};
+
+		let w = self.nwrp.to_le_bytes();
+
+		[rdp, user, self.data[0], self.data[1], w[0], w[1], w[2], w[3]]
+	}
+
+	/// Check if the option bytes have the F0/F3 layout, with RDP level 2
+	pub(crate) fn f0(&self) -> bool {
+		self.f0
+	}
+
+	/// Check if the chip `id` has the F1 layout of OBR
+	fn f1_layout(id: u32) -> bool {
+		[
+			STM32ChipID::F1Medium, STM32ChipID::F1Low, STM32ChipID::F1High, STM32ChipID::F1Conn,
+			STM32ChipID::F1VLMediumLow, STM32ChipID::F1VLHigh, STM32ChipID::F1XL,
+		].iter().any(|c| *c as u32 == id)
+	}
+}
+
+impl PartialEq for OptionsF1 {
+	fn eq(&self, other: &Self) -> bool {
+		self.bytes() == other.bytes()
+	}
+}
+
+impl Eq for OptionsF1 {}
+
+impl std::fmt::Display for OptionsF1 {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		writeln!(f, "RDP          {}", self.rdp)?;
+		writeln!(f, "USER         0x{:02X}", self.user)?;
+		writeln!(f, "Watchdog     {}", watchdog(self.wdg_sw))?;
+		writeln!(f, "Stop         {}", reset(self.nrst_stop))?;
+		writeln!(f, "Standby      {}", reset(self.nrst_stdby))?;
+		writeln!(f, "Data         0x{:02X} 0x{:02X}", self.data[0], self.data[1])?;
+		writeln!(f, "WRP          {}", sectors(self.nwrp))
+	}
+}
+
+
+/// Option bytes of the L0/L1 devices, from OPTR and the WRPROT registers
+#[derive(Debug, Copy, Clone)]
+pub struct OptionsL0 {
+	pub rdp: RdpLevel,
+	/// Brown-out reset level from 1 to 5, 0 when off
+	pub bor: u8,
+	/// Watchdog enabled by software instead of at reset
+	pub wdg_sw: bool,
+	/// No reset when entering stop mode
+	pub nrst_stop: bool,
+	/// No reset when entering standby mode
+	pub nrst_stdby: bool,
+	/// WRPROT1 to WRPROT4, set bits protect the 4 kB sectors
+	/// Only the registers of the flash size are used, the others are 0.
+	pub wrprot: [u32; 4],
+	/// The cleared bits of `wrprot` select the PCROP sectors instead (WPRMOD)
+	pub pcrop: bool,
+
+	optr: u32,
+	/// Number of WRPROT registers of the chip
+	count: usize,
+}
+
+impl OptionsL0 {
+	/// Decode OPTR and the WRPROT registers of the chip
+	pub(crate) fn decode(optr: u32, wrprot: &[u32]) -> Self {
+		use super::constants::flash::l0::optr::*;
+
+		let bits = |mask: u32| optr & mask != 0;
+
+		let mut registers = [0; 4];
+		let count = std::cmp::min(wrprot.len(), 4);
+		registers[..count].copy_from_slice(&wrprot[..count]);
+
+		Self {
+			rdp: RdpLevel::from_byte(optr as u8),
+			bor: bor_level((optr >> BOR_LEV_SHIFT) & BOR_LEV),
+			wdg_sw: bits(WDG_SW),
+			nrst_stop: bits(NRST_STOP),
+			nrst_stdby: bits(NRST_STDBY),
+			wrprot: registers,
+			pcrop: bits(WPRMOD),
+
+			optr,
+			count,
+		}
+	}
+
+	/// Values of OPTR and of the WRPROT registers of the chip
+	pub(crate) fn encode(&self) -> (u32, Vec<u32>) {
+		use super::constants::flash::l0::optr::*;
+
+		let mut optr = (self.optr & !RDP) | self.rdp.byte() as u32;
+
+		// BOR_LEV has several values turning the BOR off, the one read is kept
+		if bor_level((optr >> BOR_LEV_SHIFT) & BOR_LEV) != self.bor {
+			let level = match self.bor {
+				0 => 0,
+				n => BOR_LEVEL1 + std::cmp::min(n, 5) as u32 - 1,
+			};
+			optr = (optr & !(BOR_LEV << BOR_LEV_SHIFT)) | level << BOR_LEV_SHIFT;
+		}
+
+		optr = set(optr, WDG_SW, self.wdg_sw);
+		optr = set(optr, NRST_STOP, self.nrst_stop);
+		optr = set(optr, NRST_STDBY, self.nrst_stdby);
+		optr = set(optr, WPRMOD, self.pcrop);
+
+		(optr, self.wrprot[..self.count].to_vec())
+	}
+}
+
+impl PartialEq for OptionsL0 {
+	fn eq(&self, other: &Self) -> bool {
+		self.encode() == other.encode()
+	}
+}
+
+impl Eq for OptionsL0 {}
+
+impl std::fmt::Display for OptionsL0 {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let wrprot = &self.wrprot[..self.count];
+
+		writeln!(f, "OPTR         0x{:08X}", self.optr)?;
+		writeln!(f, "RDP          {}", self.rdp)?;
+		writeln!(f, "BOR          {}", bor(self.bor))?;
+		writeln!(f, "Watchdog     {}", watchdog(self.wdg_sw))?;
+		writeln!(f, "Stop         {}", reset(self.nrst_stop))?;
+		writeln!(f, "Standby      {}", reset(self.nrst_stdby))?;
+		match self.pcrop {
+			true => writeln!(f, "PCROP        {}", sectors_of(wrprot, false)),
+			false => writeln!(f, "WRPROT       {}", sectors_of(wrprot, true)),
+		}
+	}
+}
+
+/// Level of the BOR_LEV field of L0/L1, 0 when off
+fn bor_level(field: u32) -> u8 {
+	use super::constants::flash::l0::optr::BOR_LEVEL1;
+
+	match field {
+		f if f >= BOR_LEVEL1 => std::cmp::min(f - BOR_LEVEL1 + 1, 5) as u8,
+		_ => 0,
+	}
+}
+
+
+/// Option bytes of the L4/G0/WB devices, from OPTR
+#[derive(Debug, Copy, Clone)]
+pub struct OptionsL4 {
+	pub rdp: RdpLevel,
+	/// Brown-out reset level from 0 to 4, `None` on G0 which has separate rising and falling thresholds
+	pub bor: Option<u8>,
+	/// No reset when entering stop mode
+	pub nrst_stop: bool,
+	/// No reset when entering standby mode
+	pub nrst_stdby: bool,
+	/// No reset when entering shutdown mode
+	pub nrst_shdw: bool,
+	/// Independent watchdog enabled by software instead of at reset
+	pub iwdg_sw: bool,
+	/// Independent watchdog running in stop mode
+	pub iwdg_stop: bool,
+	/// Independent watchdog running in standby mode
+	pub iwdg_stdby: bool,
+	/// Window watchdog enabled by software instead of at reset
+	pub wwdg_sw: bool,
+	/// Dual bank mode of L47x/L48x, L49x/L4Ax and L4Rx/L4Sx
+	pub dual_bank: Option<bool>,
+	/// Boot from the second bank
+	pub bfb2: Option<bool>,
+	/// First and last write protected page of the areas 1A, 1B, 2A and 2B,
+	/// numbered from the start of their bank. `None` when the area is disabled.
+	pub wrp: [Option<(u32, u32)>; 4],
+
+	optr: u32,
+	/// WRP1AR, WRP1BR, WRP2AR and WRP2BR, `None` for the areas of the second bank on single bank devices
+	wrp_regs: [Option<u32>; 4],
+	flasht: FlashType,
+	/// DUALBANK or DBANK, 0 on single bank devices
+	bank: u32,
+}
+
+impl OptionsL4 {
+	/// Decode OPTR and the WRP area registers of the chip `id`
+	pub(crate) fn decode(id: u32, flasht: FlashType, optr: u32, wrp_regs: [Option<u32>; 4]) -> Self {
+		use super::constants::flash::l4::optr::*;
+
+		let bank = Self::bank_bit(id);
+
+		let (bor, nrst) = match flasht {
+			FlashType::TypeG0 => (None, 1),
+			FlashType::TypeWB => (Some(((optr >> (BOR_LEV_SHIFT + 1)) & BOR_LEV) as u8), 0),
+			_ => (Some(((optr >> BOR_LEV_SHIFT) & BOR_LEV) as u8), 0),
+		};
+
+		let bits = |mask: u32| optr & mask != 0;
+		let area = |i: usize| wrp_regs[i].and_then(wrp_area);
+		let dual = |mask: u32| match bank {
+			0 => None,
+			_ => Some(bits(mask)),
+		};
+
+		Self {
+			rdp: RdpLevel::from_byte(optr as u8),
+			bor,
+			nrst_stop: bits(NRST_STOP << nrst),
+			nrst_stdby: bits(NRST_STDBY << nrst),
+			nrst_shdw: bits(NRST_SHDW << nrst),
+			iwdg_sw: bits(IWDG_SW),
+			iwdg_stop: bits(IWDG_STOP),
+			iwdg_stdby: bits(IWDG_STDBY),
+			wwdg_sw: bits(WWDG_SW),
+			dual_bank: dual(bank),
+			bfb2: dual(BFB2),
+			wrp: [area(0), area(1), area(2), area(3)],
+
+			optr,
+			wrp_regs,
+			flasht,
+			bank,
+		}
+	}
+
+	/// Check if the chip `id` can be split in two banks, with the areas 2A and 2B
+	pub(crate) fn has_bank2(id: u32) -> bool {
+		Self::bank_bit(id) != 0
+	}
+
+	/// DUALBANK or DBANK bit of the chip `id`, 0 on single bank devices
+	fn bank_bit(id: u32) -> u32 {
+		use super::constants::flash::l4::optr::{ DUALBANK, DBANK };
+
+		match id {
+			id if id == STM32ChipID::L4 as u32 || id == STM32ChipID::L496X as u32 => DUALBANK,
+			id if id == STM32ChipID::L4RX as u32 => DBANK,
+			_ => 0,
+		}
+	}
+
+	/// Values of OPTR and of the WRP area registers
+	pub(crate) fn encode(&self) -> (u32, [Option<u32>; 4]) {
+		(self.encode_optr(), [self.encode_wrp(0), self.encode_wrp(1), self.encode_wrp(2), self.encode_wrp(3)])
+	}
+
+	/// Value of the register of the WRP area `i`
+	/// Disabled areas keep the value read if it disables them too.
+	fn encode_wrp(&self, i: usize) -> Option<u32> {
+		use super::constants::flash::l4::wrp::{ PAGE, END_SHIFT };
+
+		let reg = self.wrp_regs[i]?;
+
+		if wrp_area(reg) == self.wrp[i] {
+			return Some(reg);
+		}
+
+		let (start, end) = self.wrp[i].unwrap_or((PAGE, 0));
+
+		Some((reg & !(PAGE | PAGE << END_SHIFT)) | (start & PAGE) | (end & PAGE) << END_SHIFT)
+	}
+
+	/// Value of OPTR
+	fn encode_optr(&self) -> u32 {
+		use super::constants::flash::l4::optr::*;
+
+		let (shift, nrst) = match self.flasht {
+			FlashType::TypeG0 => (None, 1),
+			FlashType::TypeWB => (Some(BOR_LEV_SHIFT + 1), 0),
+			_ => (Some(BOR_LEV_SHIFT), 0),
+		};
+
+		let mut optr = (self.optr & !RDP) | self.rdp.byte() as u32;
+
+		if let (Some(shift), Some(bor)) = (shift, self.bor) {
+			optr = (optr & !(BOR_LEV << shift)) | ((std::cmp::min(bor, 4) as u32) << shift);
+		}
+
+		optr = set(optr, NRST_STOP << nrst, self.nrst_stop);
+		optr = set(optr, NRST_STDBY << nrst, self.nrst_stdby);
+		optr = set(optr, NRST_SHDW << nrst, self.nrst_shdw);
+		optr = set(optr, IWDG_SW, self.iwdg_sw);
+		optr = set(optr, IWDG_STOP, self.iwdg_stop);
+		optr = set(optr, IWDG_STDBY, self.iwdg_stdby);
+		optr = set(optr, WWDG_SW, self.wwdg_sw);
+
+		if self.bank != 0 {
+			if let Some(dual) = self.dual_bank {
+				optr = set(optr, self.bank, dual);
+			}
+			if let Some(bfb2) = self.bfb2 {
+				optr = set(optr, BFB2, bfb2);
+			}
+		}
+
+		optr
+	}
+}
+
+impl PartialEq for OptionsL4 {
+	fn eq(&self, other: &Self) -> bool {
+		self.encode() == other.encode()
+	}
+}
+
+impl Eq for OptionsL4 {}
+
+impl std::fmt::Display for OptionsL4 {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		writeln!(f, "OPTR         0x{:08X}", self.optr)?;
+		writeln!(f, "RDP          {}", self.rdp)?;
+		if let Some(level) = self.bor {
+			writeln!(f, "BOR          level {}", level)?;
+		}
+		writeln!(f, "Stop         {}", reset(self.nrst_stop))?;
+		writeln!(f, "Standby      {}", reset(self.nrst_stdby))?;
+		writeln!(f, "Shutdown     {}", reset(self.nrst_shdw))?;
+		writeln!(f, "IWDG         {}", watchdog(self.iwdg_sw))?;
+		writeln!(f, "IWDG stop    {}", running(self.iwdg_stop))?;
+		writeln!(f, "IWDG standby {}", running(self.iwdg_stdby))?;
+		writeln!(f, "WWDG         {}", watchdog(self.wwdg_sw))?;
+		if let Some(dual) = self.dual_bank {
+			writeln!(f, "Banks        {}", banks(dual))?;
+		}
+		if let Some(bfb2) = self.bfb2 {
+			writeln!(f, "BFB2         {}", bfb2)?;
+		}
+		for (name, area) in ["1A", "1B", "2A", "2B"].iter().zip(self.wrp.iter()) {
+			if let Some((start, end)) = *area {
+				writeln!(f, "WRP{}        pages {} to {}", name, start, end)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// First and last page of a WRP area register, `None` when the start is after the end
+fn wrp_area(reg: u32) -> Option<(u32, u32)> {
+	use super::constants::flash::l4::wrp::{ PAGE, END_SHIFT };
+
+	let (start, end) = (reg & PAGE, (reg >> END_SHIFT) & PAGE);
+
+	match start <= end {
+		true => Some((start, end)),
+		false => None,
+	}
+}
+
+
+/// Option bytes of the identified chip
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OptionBytes {
+	F4(OptionsF4),
+	F1(OptionsF1),
+	L0(OptionsL0),
+	L4(OptionsL4),
+}
+
+impl OptionBytes {
+	/// Read protection level
+	pub fn rdp(&self) -> RdpLevel {
+		match *self {
+			OptionBytes::F4(ref o) => o.rdp,
+			OptionBytes::F1(ref o) => o.rdp,
+			OptionBytes::L0(ref o) => o.rdp,
+			OptionBytes::L4(ref o) => o.rdp,
+		}
+	}
+}
+
+impl OptionBytes {
+	/// Same option bytes with the read protection `level`
+	pub(crate) fn with_rdp(&self, level: RdpLevel) -> Self {
+		let mut new = *self;
+
+		match new {
+			OptionBytes::F4(ref mut o) => o.rdp = level,
+			OptionBytes::F1(ref mut o) => o.rdp = level,
+			OptionBytes::L0(ref mut o) => o.rdp = level,
+			OptionBytes::L4(ref mut o) => o.rdp = level,
+		}
+
+		new
+	}
+
+	/// Check if the chip has RDP level 2, which the F1 devices do not
+	pub(crate) fn has_level2(&self) -> bool {
+		match *self {
+			OptionBytes::F1(ref o) => o.f0(),
+			_ => true,
+		}
+	}
+}
+
+impl std::fmt::Display for OptionBytes {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			OptionBytes::F4(ref o) => o.fmt(f),
+			OptionBytes::F1(ref o) => o.fmt(f),
+			OptionBytes::L0(ref o) => o.fmt(f),
+			OptionBytes::L4(ref o) => o.fmt(f),
+		}
+	}
+}
+
+
+/// Set or clear the `mask` bits of `value`
+fn set(value: u32, mask: u32, on: bool) -> u32 {
+	match on {
+		true => value | mask,
+		false => value & !mask,
+	}
+}
+
+fn bor(level: u8) -> String {
+	match level {
+		0 => "off".to_string(),
+		n => format!("level {}", n),
+	}
+}
+
+fn watchdog(software: bool) -> &'static str {
+	match software {
+		true => "software",
+		false => "hardware",
+	}
+}
+
+fn reset(no_reset: bool) -> &'static str {
+	match no_reset {
+		true => "no reset",
+		false => "reset",
+	}
+}
+
+fn running(on: bool) -> &'static str {
+	match on {
+		true => "running",
+		false => "frozen",
+	}
+}
+
+fn banks(dual: bool) -> &'static str {
+	match dual {
+		true => "dual",
+		false => "single",
+	}
+}
+
+/// Numbers of the cleared bits of a nWRP mask
+fn sectors(nwrp: u32) -> String {
+	let protected = (0..32).filter(|n| nwrp & (1 << n) == 0).map(|n| n.to_string()).collect::<Vec<_>>();
+
+	match protected.len() {
+		0 => "none".to_string(),
+		_ => format!("{} protected", protected.join(" ")),
+	}
+}
+
+/// Numbers of the bits equal to `bit` in the registers, from bit 0 of the first one
+fn sectors_of(registers: &[u32], bit: bool) -> String {
+	let protected = (0..32 * registers.len())
+		.filter(|n| (registers[n / 32] & (1 << (n % 32)) != 0) == bit)
+		.map(|n| n.to_string())
+		.collect::<Vec<_>>();
+
+	match protected.len() {
+		0 => "none".to_string(),
+		_ => format!("{} protected", protected.join(" ")),
+	}
+}
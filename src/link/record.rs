@@ -0,0 +1,440 @@
+//! Recording and replay of the traffic between `Link` and a probe
+//! A `Recorder` wraps any `Transport` and writes every transfer to a session
+//! file. A `Replay` reads the session back and answers with the recorded data,
+//! so a session can be re-run without the probe.
+//!
+//! The session file is plain text. After the header, each line is a transfer:
+//!
+//! ```text
+//! # rustylink session
+//! probe 0483:374B
+//! 1520 CMD 1 F1
+//! 1893 IN 6 26 8D 83 04 4B 37
+//! 2210 IN Timeout
+//! 2874 IN Transfer:8:2
+//! ```
+//!
+//! The fields are the time in microseconds since the start of the session,
+//! the kind of transfer (`CMD`, `OUT`, `IN` or `TRACE`), the result and the
+//! bytes sent or received in hexadecimal. The result is the number of bytes
+//! transferred, the name of the USB error, or for the other errors of the
+//! transport `Transfer:<expected>:<actual>`, `Io:<kind>`, `Unsupported` or `NoProbe`.
+
+use std::io::{ BufRead, BufReader, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+use super::error::LinkError;
+use super::transport::Transport;
+
+use crate::usb::model::{ self, ProbeModel };
+
+
+/// First line of a session file
+const HEADER: &str = "# rustylink session";
+
+/// Kind of transfer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+	/// Command block sent to the probe
+	Command,
+	/// Data sent after a command
+	DataOut,
+	/// Answer received from the probe
+	DataIn,
+	/// Trace data received from the probe
+	Trace,
+}
+
+impl Kind {
+	fn name(&self) -> &'static str {
+		match *self {
+			Kind::Command => "CMD",
+			Kind::DataOut => "OUT",
+			Kind::DataIn  => "IN",
+			Kind::Trace   => "TRACE",
+		}
+	}
+
+	fn parse(s: &str) -> Option<Self> {
+		match s {
+			"CMD"   => Some(Kind::Command),
+			"OUT"   => Some(Kind::DataOut),
+			"IN"    => Some(Kind::DataIn),
+			"TRACE" => Some(Kind::Trace),
+			_ => None,
+		}
+	}
+}
+
+/// Error of a recorded transfer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Failure {
+	/// Error reported by libusb
+	Usb(rusb::Error),
+	/// The transfer moved a different amount of bytes than requested
+	Transfer { expected: usize, actual: usize },
+	/// I/O error of the transport, any other error of the transport is recorded as `Other`
+	Io(std::io::ErrorKind),
+	/// The probe does not support the transfer
+	Unsupported,
+	/// The probe is gone
+	NoProbe,
+}
+
+impl Failure {
+	fn new(e: &LinkError) -> Self {
+		match e {
+			LinkError::Usb(e) => Failure::Usb(*e),
+			LinkError::Transfer { expected, actual } => Failure::Transfer { expected: *expected, actual: *actual },
+			LinkError::Io(e) => Failure::Io(e.kind()),
+			LinkError::Unsupported(_) => Failure::Unsupported,
+			LinkError::NoProbe => Failure::NoProbe,
+			_ => Failure::Io(std::io::ErrorKind::Other),
+		}
+	}
+
+	fn name(&self) -> String {
+		match self {
+			Failure::Usb(e) => format!("{:?}", e),
+			Failure::Transfer { expected, actual } => format!("Transfer:{}:{}", expected, actual),
+			Failure::Io(kind) => format!("Io:{:?}", kind),
+			Failure::Unsupported => String::from("Unsupported"),
+			Failure::NoProbe => String::from("NoProbe"),
+		}
+	}
+
+	fn parse(s: &str) -> Option<Self> {
+		let mut fields = s.split(':');
+
+		match (fields.next(), fields.next(), fields.next(), fields.next()) {
+			(Some("Transfer"), Some(e), Some(a), None) => Some(Failure::Transfer { expected: e.parse().ok()?, actual: a.parse().ok()? }),
+			(Some("Io"), Some(kind), None, None) => Some(Failure::Io(io_kind(kind))),
+			(Some("Unsupported"), None, None, None) => Some(Failure::Unsupported),
+			(Some("NoProbe"), None, None, None) => Some(Failure::NoProbe),
+			(Some(name), None, None, None) => Some(Failure::Usb(usb_error(name))),
+			_ => None,
+		}
+	}
+
+	/// Error returned when the transfer is replayed
+	fn error(&self) -> LinkError {
+		match *self {
+			Failure::Usb(e) => LinkError::Usb(e),
+			Failure::Transfer { expected, actual } => LinkError::Transfer { expected, actual },
+			Failure::Io(kind) => LinkError::Io(kind.into()),
+			Failure::Unsupported => LinkError::Unsupported("recorded transfer"),
+			Failure::NoProbe => LinkError::NoProbe,
+		}
+	}
+}
+
+/// A single recorded transfer
+#[derive(Debug)]
+pub struct Event {
+	/// Time since the start of the session
+	pub time: Duration,
+	pub kind: Kind,
+	/// Number of bytes transferred or the error
+	pub result: Result<usize, Failure>,
+	/// Bytes sent or received
+	pub data: Vec<u8>,
+}
+
+
+/// Transport that writes every transfer of the wrapped transport to a session file
+pub struct Recorder<T: Transport, W: Write> {
+	transport: T,
+	output: W,
+	start: Instant,
+}
+
+impl<T: Transport, W: Write> Recorder<T, W> {
+	/// Record the traffic of `transport` to a probe of the given `model` into `output`
+	pub fn new(transport: T, model: &ProbeModel, mut output: W) -> Result<Self, LinkError> {
+		writeln!(output, "{}", HEADER)?;
+		writeln!(output, "probe {:04X}:{:04X}", model::VID, model.pid)?;
+
+		Ok(Self { transport, output, start: Instant::now() })
+	}
+
+	/// Wrapped transport
+	pub fn transport(&self) -> &T {
+		&self.transport
+	}
+
+	/// Mutable access to the wrapped transport
+	pub fn transport_mut(&mut self) -> &mut T {
+		&mut self.transport
+	}
+
+	/// Stop the recording and return the wrapped transport and the output
+	pub fn into_inner(mut self) -> (T, W) {
+		let _ = self.output.flush();
+		(self.transport, self.output)
+	}
+
+	/// Write a transfer to the session file
+	/// A failure to record does not stop the session, it is only reported
+	fn record(&mut self, kind: Kind, result: &Result<usize, LinkError>, data: &[u8]) {
+		let time = self.start.elapsed();
+		let micros = time.as_secs() * 1_000_000 + time.subsec_micros() as u64;
+
+		let mut line = format!("{} {}", micros, kind.name());
+
+		match result {
+			Ok(n) => line += &format!(" {}", n),
+			Err(e) => line += &format!(" {}", Failure::new(e).name()),
+		}
+
+		for b in data {
+			line += &format!(" {:02X}", b);
+		}
+
+		if let Err(e) = writeln!(self.output, "{}", line) {
+			warn!("Could not record the transfer: {}", e);
+		}
+	}
+}
+
+impl<T: Transport, W: Write> Transport for Recorder<T, W> {
+	fn write_command(&mut self, cmd: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		let result = self.transport.write_command(cmd, timeout);
+		self.record(Kind::Command, &result, cmd);
+		result
+	}
+
+	fn write_data(&mut self, data: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		let result = self.transport.write_data(data, timeout);
+		self.record(Kind::DataOut, &result, data);
+		result
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		let result = self.transport.read_data(buf, timeout);
+		let n = *result.as_ref().unwrap_or(&0);
+		self.record(Kind::DataIn, &result, &buf[..n]);
+		result
+	}
+
+	fn read_trace(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		let result = self.transport.read_trace(buf, timeout);
+		let n = *result.as_ref().unwrap_or(&0);
+		self.record(Kind::Trace, &result, &buf[..n]);
+		result
+	}
+}
+
+
+/// Transport that answers with the transfers of a recorded session
+/// The commands and data sent must be the same as in the recording, the
+/// first difference stops the session with `LinkError::Replay`.
+pub struct Replay {
+	model: &'static ProbeModel,
+	events: Vec<Event>,
+	next: usize,
+}
+
+impl Replay {
+	/// Load the session recorded in the file at `path`
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LinkError> {
+		Self::from_reader(std::fs::File::open(path)?)
+	}
+
+	/// Load a session recorded by a `Recorder`
+	pub fn from_reader<R: std::io::Read>(input: R) -> Result<Self, LinkError> {
+		let invalid = |line: usize, what: &str| {
+			error!("Invalid session file. Line {}: {}", line + 1, what);
+			LinkError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid session file at line {}", line + 1)))
+		};
+
+		let mut lines = BufReader::new(input).lines().enumerate();
+
+		match lines.next() {
+			Some((_, Ok(ref l))) if l.trim() == HEADER => (),
+			Some((_, Err(e))) => return Err(e.into()),
+			_ => return Err(invalid(0, "missing header")),
+		}
+
+		let model = match lines.next() {
+			Some((i, Ok(l))) => {
+				let id = l.trim().trim_start_matches("probe ").split(':')
+					.map(|s| u16::from_str_radix(s, 16).ok())
+					.collect::<Vec<_>>();
+
+				match id.as_slice() {
+					&[Some(vid), Some(pid)] => match model::find(vid, pid) {
+						Some(m) => m,
+						None => return Err(invalid(i, "unknown probe")),
+					},
+					_ => return Err(invalid(i, "missing probe ID")),
+				}
+			},
+			Some((_, Err(e))) => return Err(e.into()),
+			None => return Err(invalid(1, "missing probe ID")),
+		};
+
+		let mut events = Vec::new();
+
+		for (i, line) in lines {
+			let line = line?;
+			let mut fields = line.split_whitespace();
+
+			let micros = match fields.next() {
+				Some(f) => f.parse::<u64>().map_err(|_| invalid(i, "bad timestamp"))?,
+				None => continue,
+			};
+
+			let kind = fields.next().and_then(Kind::parse).ok_or_else(|| invalid(i, "bad transfer kind"))?;
+
+			let result = match fields.next() {
+				Some(f) => match f.parse::<usize>() {
+					Ok(n) => Ok(n),
+					Err(_) => Err(Failure::parse(f).ok_or_else(|| invalid(i, "bad result"))?),
+				},
+				None => return Err(invalid(i, "missing result")),
+			};
+
+			let data = fields.map(|f| u8::from_str_radix(f, 16))
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|_| invalid(i, "bad data byte"))?;
+
+			events.push(Event { time: Duration::from_micros(micros), kind, result, data });
+		}
+
+		Ok(Self { model, events, next: 0 })
+	}
+
+	/// Model of the recorded probe
+	pub fn model(&self) -> &'static ProbeModel {
+		self.model
+	}
+
+	/// Recorded transfers
+	pub fn events(&self) -> &[Event] {
+		&self.events
+	}
+
+	/// Number of transfers not replayed yet
+	pub fn remaining(&self) -> usize {
+		self.events.len() - self.next
+	}
+
+	/// Take the next event, which must be of the given kind
+	fn take(&mut self, kind: Kind) -> Result<&Event, LinkError> {
+		let index = self.next;
+
+		match self.events.get(index) {
+			Some(e) if e.kind == kind => {
+				self.next += 1;
+				Ok(&self.events[index])
+			},
+			Some(e) => {
+				error!("Replay. Expected {} at event {}, the session does {}", e.kind.name(), index, kind.name());
+				Err(LinkError::Replay { event: index })
+			},
+			None => {
+				error!("Replay. The recording ended, the session does {}", kind.name());
+				Err(LinkError::Replay { event: index })
+			},
+		}
+	}
+
+	/// Replay a transfer to the probe
+	fn send(&mut self, kind: Kind, data: &[u8]) -> Result<usize, LinkError> {
+		let index = self.next;
+		let event = self.take(kind)?;
+
+		if event.data != data {
+			error!("Replay. Event {} sent {:02X?}, recorded {:02X?}", index, data, event.data);
+			return Err(LinkError::Replay { event: index });
+		}
+
+		replay_result(&event.result)
+	}
+
+	/// Replay a transfer from the probe
+	fn receive(&mut self, kind: Kind, buf: &mut [u8]) -> Result<usize, LinkError> {
+		let index = self.next;
+		let event = self.take(kind)?;
+
+		if event.data.len() > buf.len() {
+			error!("Replay. Event {} received {} bytes into a buffer of {} bytes", index, event.data.len(), buf.len());
+			return Err(LinkError::Replay { event: index });
+		}
+
+		buf[..event.data.len()].copy_from_slice(&event.data);
+
+		replay_result(&event.result)
+	}
+}
+
+impl Transport for Replay {
+	fn write_command(&mut self, cmd: &[u8], _: Duration) -> Result<usize, LinkError> {
+		self.send(Kind::Command, cmd)
+	}
+
+	fn write_data(&mut self, data: &[u8], _: Duration) -> Result<usize, LinkError> {
+		self.send(Kind::DataOut, data)
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		self.receive(Kind::DataIn, buf)
+	}
+
+	fn read_trace(&mut self, buf: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		self.receive(Kind::Trace, buf)
+	}
+}
+
+/// Result of a recorded transfer
+fn replay_result(result: &Result<usize, Failure>) -> Result<usize, LinkError> {
+	match result {
+		Ok(n) => Ok(*n),
+		Err(f) => Err(f.error()),
+	}
+}
+
+/// USB error from its recorded name
+fn usb_error(name: &str) -> rusb::Error {
+	use rusb::Error::*;
+
+	match name {
+		"Io"            => Io,
+		"InvalidParam"  => InvalidParam,
+		"Access"        => Access,
+		"NoDevice"      => NoDevice,
+		"NotFound"      => NotFound,
+		"Busy"          => Busy,
+		"Timeout"       => Timeout,
+		"Overflow"      => Overflow,
+		"Pipe"          => Pipe,
+		"Interrupted"   => Interrupted,
+		"NoMem"         => NoMem,
+		"NotSupported"  => NotSupported,
+		"BadDescriptor" => BadDescriptor,
+		_               => Other,
+	}
+}
+
+/// I/O error kind from its recorded name
+fn io_kind(name: &str) -> std::io::ErrorKind {
+	use std::io::ErrorKind::*;
+
+	match name {
+		"NotFound"          => NotFound,
+		"PermissionDenied"  => PermissionDenied,
+		"ConnectionReset"   => ConnectionReset,
+		"ConnectionAborted" => ConnectionAborted,
+		"NotConnected"      => NotConnected,
+		"BrokenPipe"        => BrokenPipe,
+		"WouldBlock"        => WouldBlock,
+		"InvalidInput"      => InvalidInput,
+		"InvalidData"       => InvalidData,
+		"TimedOut"          => TimedOut,
+		"WriteZero"         => WriteZero,
+		"Interrupted"       => Interrupted,
+		"UnexpectedEof"     => UnexpectedEof,
+		_                   => Other,
+	}
+}
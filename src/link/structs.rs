@@ -1,5 +1,7 @@
 //! Structures used in STLink
 
+use super::enums::Protection;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Endpoints {
 	rx: u8,
@@ -52,6 +54,49 @@ pub struct STLinkUSBVersion {
 }
 
 
+/// Description of a connected probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeInfo {
+	/// Position in the enumeration list
+	pub index: usize,
+	pub vid: u16,
+	pub pid: u16,
+	/// Name of the probe model
+	pub name: &'static str,
+	/// iSerial string descriptor, if it could be read
+	pub serial: Option<String>,
+	/// Firmware version, if the probe could be opened
+	pub version: Option<STLinkUSBVersion>,
+	/// USB bus number
+	pub bus: u8,
+	/// USB device address in the bus
+	pub address: u8,
+	/// USB port numbers from the root hub to the probe
+	pub ports: Vec<u8>,
+}
+
+impl std::fmt::Display for ProbeInfo {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}: {} {:04X}:{:04X} at {:03}:{:03}", self.index, self.name, self.vid, self.pid, self.bus, self.address)?;
+
+		if !self.ports.is_empty() {
+			let ports: Vec<String> = self.ports.iter().map(|p| p.to_string()).collect();
+			write!(f, " (port {}-{})", self.bus, ports.join("."))?;
+		}
+
+		match self.version {
+			Some(v) => write!(f, " STLink V{} J{} S{}", v.stlink, v.jtag, v.swim)?,
+			None => write!(f, " <firmware unknown>")?,
+		}
+
+		match self.serial {
+			Some(ref s) => write!(f, " serial {}", s),
+			None => write!(f, " <serial unknown>"),
+		}
+	}
+}
+
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Endpoint {
 	pub config:  u8,
@@ -122,6 +167,7 @@ pub struct MemInfo {
 	pub flash: FlashInfo,
 	pub ram: Vec<SRamInfo>,
 	pub sys: SysMemInfo,
+	pub eeprom: EepromInfo,
 }
 
 impl MemInfo {
@@ -130,6 +176,7 @@ impl MemInfo {
 			flash: FlashInfo { base: 0, size: 0, pagesize: None },
 			ram: Vec::new(),
 			sys: SysMemInfo { base: 0, size: 0 },
+			eeprom: EepromInfo { base: 0, size: 0 },
 		}
 	}
 }
@@ -157,4 +204,57 @@ impl SRamInfo {
 pub struct SysMemInfo {
 	pub base: u32,
 	pub size: u32,
-}
\ No newline at end of file
+}
+
+/// Data EEPROM of the L0/L1 devices, with a size of 0 on the other chips
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EepromInfo {
+	pub base: u32,
+	pub size: u32,
+}
+
+/// Erasable block of the flash
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlashSector {
+	/// Sector (or page) number as given to the erase commands
+	pub index: usize,
+	pub address: u32,
+	pub size: u32,
+}
+
+impl FlashSector {
+	/// Check if the sector overlaps the `size` bytes at `address`
+	pub fn overlaps(&self, address: u32, size: usize) -> bool {
+		(address as u64) < self.address as u64 + self.size as u64 && (self.address as u64) < address as u64 + size as u64
+	}
+}
+
+/// Sector protected by the option bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProtectedSector {
+	pub sector: FlashSector,
+	pub protection: Protection,
+}
+
+/// Run of consecutive bytes that differ from the expected data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+	/// Address of the first differing byte
+	pub address: u32,
+	/// Bytes expected
+	pub expected: Vec<u8>,
+	/// Bytes read from the target
+	pub actual: Vec<u8>,
+}
+
+impl Mismatch {
+	/// Number of differing bytes
+	pub fn len(&self) -> usize {
+		self.actual.len()
+	}
+
+	/// Check if the run is empty
+	pub fn is_empty(&self) -> bool {
+		self.actual.is_empty()
+	}
+}
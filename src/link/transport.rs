@@ -0,0 +1,45 @@
+//! Transport of the STLink protocol
+//! `Link` builds the command and data buffers of the STLink protocol and hands
+//! them to a `Transport`, which moves the bytes to and from the probe.
+
+use std::time::Duration;
+
+use super::error::LinkError;
+
+
+/// Bulk transfers to and from a STLink probe
+pub trait Transport {
+	/// Send a command block
+	/// Returns the number of bytes sent
+	fn write_command(&mut self, cmd: &[u8], timeout: Duration) -> Result<usize, LinkError>;
+
+	/// Send data following a command
+	/// Returns the number of bytes sent
+	fn write_data(&mut self, data: &[u8], timeout: Duration) -> Result<usize, LinkError>;
+
+	/// Receive the answer to a command into `buf`
+	/// Returns the number of bytes received
+	fn read_data(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError>;
+
+	/// Receive trace (SWO) data into `buf`
+	/// Returns the number of bytes received
+	fn read_trace(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+	fn write_command(&mut self, cmd: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		(**self).write_command(cmd, timeout)
+	}
+
+	fn write_data(&mut self, data: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		(**self).write_data(data, timeout)
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		(**self).read_data(buf, timeout)
+	}
+
+	fn read_trace(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		(**self).read_trace(buf, timeout)
+	}
+}
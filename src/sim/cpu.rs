@@ -0,0 +1,478 @@
+//! Thumb instruction set of the simulated core
+//! Only the ARMv6-M instructions are decoded, which is enough for the flash
+//! loaders and algorithms downloaded by the host. Exceptions are not simulated:
+//! an instruction that cannot be fetched, decoded or executed is a fault.
+
+use super::target::Target;
+
+
+/// Outcome of the execution of an instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Exec {
+	/// The instruction was executed
+	Next,
+	/// A BKPT instruction, the PC is left on it
+	Breakpoint,
+	/// The instruction could not be fetched, decoded or executed
+	Fault,
+}
+
+/// Condition flags of the xPSR
+const N: u32 = 1 << 31;
+const Z: u32 = 1 << 30;
+const C: u32 = 1 << 29;
+const V: u32 = 1 << 28;
+
+
+/// Execute the instruction at the PC of `target`
+pub(super) fn execute(target: &mut Target) -> Exec {
+	let pc = target.regs[15];
+
+	let op = match target.read(pc, 2) {
+		Ok(op) => op,
+		Err(_) => return Exec::Fault,
+	};
+
+	let mut cpu = Core { target, pc, next: pc.wrapping_add(2) };
+
+	match cpu.decode(op) {
+		Some(Exec::Next) => {
+			cpu.target.regs[15] = cpu.next;
+			Exec::Next
+		},
+		Some(e) => e,
+		None => Exec::Fault,
+	}
+}
+
+
+/// Core executing a single instruction
+struct Core<'a> {
+	target: &'a mut Target,
+	/// Address of the instruction
+	pc: u32,
+	/// Address of the next instruction, changed by the branches
+	next: u32,
+}
+
+impl<'a> Core<'a> {
+	/// Decode and execute the 16 bit instruction `op`
+	/// Returns `None` on a fault
+	fn decode(&mut self, op: u32) -> Option<Exec> {
+		let rd = (op & 7) as usize;
+		let rn = (op >> 3 & 7) as usize;
+		let rm = (op >> 6 & 7) as usize;
+		let imm5 = op >> 6 & 0x1F;
+		let imm8 = op & 0xFF;
+		let rdn = (op >> 8 & 7) as usize;
+
+		match op >> 11 {
+			// LSLS, LSRS, ASRS by an immediate
+			0b00000 ..= 0b00010 => {
+				let (result, carry) = shift(op >> 11, self.reg(rn), match (op >> 11, imm5) {
+					(0, n) => n,
+					(_, 0) => 32,
+					(_, n) => n,
+				}, self.flag(C));
+				self.set(rd, result);
+				self.flags(result, carry, self.flag(V));
+			},
+
+			// ADDS, SUBS with a register or a 3 bit immediate
+			0b00011 => {
+				let operand = match op & (1 << 10) {
+					0 => self.reg(rm),
+					_ => rm as u32,
+				};
+				let result = match op & (1 << 9) {
+					0 => self.add(self.reg(rn), operand, false),
+					_ => self.add(self.reg(rn), !operand, true),
+				};
+				self.set(rd, result);
+			},
+
+			// MOVS, CMP, ADDS, SUBS with an 8 bit immediate
+			0b00100 ..= 0b00111 => match op >> 11 & 3 {
+				0 => {
+					self.set(rdn, imm8);
+					self.flags(imm8, self.flag(C), self.flag(V));
+				},
+				1 => { self.add(self.reg(rdn), !imm8, true); },
+				2 => {
+					let result = self.add(self.reg(rdn), imm8, false);
+					self.set(rdn, result);
+				},
+				_ => {
+					let result = self.add(self.reg(rdn), !imm8, true);
+					self.set(rdn, result);
+				},
+			},
+
+			0b01000 => match op & (1 << 10) {
+				0 => self.data_processing(op >> 6 & 0xF, rn, rd),
+				_ => return self.special(op),
+			},
+
+			// LDR literal
+			0b01001 => {
+				let value = self.load((self.reg(15) & !3).wrapping_add(imm8 * 4), 4)?;
+				self.set(rdn, value);
+			},
+
+			// Loads and stores with a register offset
+			0b01010 | 0b01011 => {
+				let address = self.reg(rn).wrapping_add(self.reg(rm));
+
+				match op >> 9 & 7 {
+					0 => self.store(address, self.reg(rd), 4)?,
+					1 => self.store(address, self.reg(rd), 2)?,
+					2 => self.store(address, self.reg(rd), 1)?,
+					3 => {
+						let value = self.load(address, 1)? as u8 as i8 as u32;
+						self.set(rd, value);
+					},
+					4 => { let value = self.load(address, 4)?; self.set(rd, value); },
+					5 => { let value = self.load(address, 2)?; self.set(rd, value); },
+					6 => { let value = self.load(address, 1)?; self.set(rd, value); },
+					_ => {
+						let value = self.load(address, 2)? as u16 as i16 as u32;
+						self.set(rd, value);
+					},
+				}
+			},
+
+			// Loads and stores with an immediate offset
+			0b01100 ..= 0b10001 => {
+				let width = match op >> 11 {
+					0b01100 | 0b01101 => 4,
+					0b01110 | 0b01111 => 1,
+					_ => 2,
+				};
+				let address = self.reg(rn).wrapping_add(imm5 * width as u32);
+
+				match op & (1 << 11) {
+					0 => self.store(address, self.reg(rd), width)?,
+					_ => {
+						let value = self.load(address, width)?;
+						self.set(rd, value);
+					},
+				}
+			},
+
+			// STR and LDR relative to the SP
+			0b10010 | 0b10011 => {
+				let address = self.reg(13).wrapping_add(imm8 * 4);
+
+				match op & (1 << 11) {
+					0 => self.store(address, self.reg(rdn), 4)?,
+					_ => {
+						let value = self.load(address, 4)?;
+						self.set(rdn, value);
+					},
+				}
+			},
+
+			// ADR and ADD relative to the SP
+			0b10100 => self.set(rdn, (self.reg(15) & !3).wrapping_add(imm8 * 4)),
+			0b10101 => self.set(rdn, self.reg(13).wrapping_add(imm8 * 4)),
+
+			0b10110 | 0b10111 => return self.miscellaneous(op),
+
+			// STM and LDM
+			0b11000 => {
+				let mut address = self.reg(rdn);
+				for r in (0..8).filter(|r| imm8 & (1 << r) != 0) {
+					self.store(address, self.reg(r), 4)?;
+					address = address.wrapping_add(4);
+				}
+				self.set(rdn, address);
+			},
+			0b11001 => {
+				let mut address = self.reg(rdn);
+				for r in (0..8).filter(|r| imm8 & (1 << r) != 0) {
+					let value = self.load(address, 4)?;
+					self.set(r, value);
+					address = address.wrapping_add(4);
+				}
+				// No write back when the base register is loaded
+				if imm8 & (1 << rdn) == 0 {
+					self.set(rdn, address);
+				}
+			},
+
+			// Conditional branch, UDF and SVC are faults
+			0b11010 | 0b11011 => match op >> 8 & 0xF {
+				0xE | 0xF => return None,
+				cond => if self.condition(cond) {
+					self.next = self.reg(15).wrapping_add((imm8 as u8 as i8 as i32 as u32) << 1);
+				},
+			},
+
+			// Unconditional branch
+			0b11100 => self.next = self.reg(15).wrapping_add(sign_extend(op & 0x7FF, 11) << 1),
+
+			// BL, the only 32 bit instruction
+			0b11110 => {
+				let low = self.load(self.pc.wrapping_add(2), 2)?;
+				if low & 0xD000 != 0xD000 {
+					return None;
+				}
+
+				let s = op >> 10 & 1;
+				let i1 = !(low >> 13 ^ s) & 1;
+				let i2 = !(low >> 11 ^ s) & 1;
+				let offset = s << 24 | i1 << 23 | i2 << 22 | (op & 0x3FF) << 12 | (low & 0x7FF) << 1;
+
+				let ret = self.pc.wrapping_add(4);
+				self.set(14, ret | 1);
+				self.next = ret.wrapping_add(sign_extend(offset, 25));
+			},
+
+			_ => return None,
+		}
+
+		Some(Exec::Next)
+	}
+
+	/// Data processing instructions on the low registers
+	fn data_processing(&mut self, opcode: u32, rm: usize, rdn: usize) {
+		let (a, b) = (self.reg(rdn), self.reg(rm));
+		let (carry, overflow) = (self.flag(C), self.flag(V));
+
+		let result = match opcode {
+			0x0 => a & b,
+			0x1 => a ^ b,
+			// Shifts by a register
+			0x2 | 0x3 | 0x4 | 0x7 => {
+				let kind = match opcode { 0x2 => 0, 0x3 => 1, 0x4 => 2, _ => 3 };
+				let (result, carry) = shift(kind, a, b & 0xFF, carry);
+				self.set(rdn, result);
+				self.flags(result, carry, overflow);
+				return;
+			},
+			0x5 => self.add(a, b, carry),
+			0x6 => self.add(a, !b, carry),
+			// TST
+			0x8 => {
+				self.flags(a & b, carry, overflow);
+				return;
+			},
+			// RSBS #0
+			0x9 => {
+				let result = self.add(!b, 0, true);
+				self.set(rdn, result);
+				return;
+			},
+			// CMP and CMN
+			0xA => { self.add(a, !b, true); return; },
+			0xB => { self.add(a, b, false); return; },
+			0xC => a | b,
+			0xD => a.wrapping_mul(b),
+			0xE => a & !b,
+			_ => !b,
+		};
+
+		self.set(rdn, result);
+
+		// The additions already set all the flags
+		if opcode != 0x5 && opcode != 0x6 {
+			self.flags(result, carry, overflow);
+		}
+	}
+
+	/// ADD, CMP and MOV with the high registers, BX and BLX
+	fn special(&mut self, op: u32) -> Option<Exec> {
+		let rm = (op >> 3 & 0xF) as usize;
+		let rdn = ((op >> 4 & 8) | (op & 7)) as usize;
+
+		match op >> 8 & 3 {
+			0 => {
+				let result = self.reg(rdn).wrapping_add(self.reg(rm));
+				self.write(rdn, result);
+			},
+			1 => { self.add(self.reg(rdn), !self.reg(rm), true); },
+			2 => {
+				let value = self.reg(rm);
+				self.write(rdn, value);
+			},
+			_ => {
+				let target = self.reg(rm);
+
+				// Switching to the ARM state is a fault on a Cortex-M
+				if target & 1 == 0 {
+					return None;
+				}
+				if op & (1 << 7) != 0 {
+					self.set(14, self.pc.wrapping_add(2) | 1);
+				}
+				self.next = target & !1;
+			},
+		}
+
+		Some(Exec::Next)
+	}
+
+	/// SP adjustments, extensions, PUSH, POP, BKPT and the hints
+	fn miscellaneous(&mut self, op: u32) -> Option<Exec> {
+		let rd = (op & 7) as usize;
+		let rm = (op >> 3 & 7) as usize;
+
+		if op & 0xFF00 == 0xB000 {
+			let offset = (op & 0x7F) * 4;
+			let sp = match op & (1 << 7) {
+				0 => self.reg(13).wrapping_add(offset),
+				_ => self.reg(13).wrapping_sub(offset),
+			};
+			self.set(13, sp);
+		} else if op & 0xFF00 == 0xB200 {
+			let value = self.reg(rm);
+			self.set(rd, match op >> 6 & 3 {
+				0 => value as u16 as i16 as u32,
+				1 => value as u8 as i8 as u32,
+				2 => value & 0xFFFF,
+				_ => value & 0xFF,
+			});
+		} else if op & 0xFE00 == 0xB400 {
+			let list = (op & 0xFF) | (op & 0x100) << 6;
+			let mut address = self.reg(13).wrapping_sub(4 * list.count_ones());
+			self.set(13, address);
+
+			for r in (0..15).filter(|r| list & (1 << r) != 0) {
+				self.store(address, self.reg(r), 4)?;
+				address = address.wrapping_add(4);
+			}
+		} else if op & 0xFE00 == 0xBC00 {
+			let list = (op & 0xFF) | (op & 0x100) << 7;
+			let mut address = self.reg(13);
+
+			for r in (0..16).filter(|r| list & (1 << r) != 0) {
+				let value = self.load(address, 4)?;
+				self.write(r, value);
+				address = address.wrapping_add(4);
+			}
+			self.set(13, address);
+		} else if op & 0xFF00 == 0xBE00 {
+			return Some(Exec::Breakpoint);
+		} else if op & 0xFF00 == 0xBF00 || op & 0xFFEF == 0xB662 {
+			// NOP, WFI, ... and CPSIE, CPSID: interrupts are not simulated
+		} else {
+			return None;
+		}
+
+		Some(Exec::Next)
+	}
+
+	/// Value of register `n` seen by the instruction, the PC reads 4 bytes ahead
+	fn reg(&self, n: usize) -> u32 {
+		match n {
+			15 => self.pc.wrapping_add(4),
+			_ => self.target.regs[n],
+		}
+	}
+
+	/// Set register `n`, other than the PC
+	/// The SP is the MSP, the PSP is never used
+	fn set(&mut self, n: usize, value: u32) {
+		self.target.regs[n] = value;
+		if n == 13 {
+			self.target.regs[17] = value;
+		}
+	}
+
+	/// Set register `n`, a write to the PC is a branch
+	fn write(&mut self, n: usize, value: u32) {
+		match n {
+			15 => self.next = value & !1,
+			_ => self.set(n, value),
+		}
+	}
+
+	fn flag(&self, flag: u32) -> bool {
+		self.target.regs[16] & flag != 0
+	}
+
+	/// Set the N and Z flags from `result`, C and V from `carry` and `overflow`
+	fn flags(&mut self, result: u32, carry: bool, overflow: bool) {
+		let mut xpsr = self.target.regs[16] & !(N | Z | C | V);
+
+		if result & (1 << 31) != 0 { xpsr |= N; }
+		if result == 0 { xpsr |= Z; }
+		if carry { xpsr |= C; }
+		if overflow { xpsr |= V; }
+
+		self.target.regs[16] = xpsr;
+	}
+
+	/// Add with carry, setting all the flags
+	fn add(&mut self, a: u32, b: u32, carry: bool) -> u32 {
+		let sum = a as u64 + b as u64 + carry as u64;
+		let result = sum as u32;
+
+		self.flags(result, sum >> 32 != 0, ((a ^ result) & (b ^ result)) >> 31 != 0);
+
+		result
+	}
+
+	/// Check the condition `cond` of a branch
+	fn condition(&self, cond: u32) -> bool {
+		let (n, z, c, v) = (self.flag(N), self.flag(Z), self.flag(C), self.flag(V));
+
+		let result = match cond >> 1 {
+			0 => z,
+			1 => c,
+			2 => n,
+			3 => v,
+			4 => c && !z,
+			5 => n == v,
+			_ => !z && n == v,
+		};
+
+		match cond & 1 {
+			0 => result,
+			_ => !result,
+		}
+	}
+
+	fn load(&mut self, address: u32, width: usize) -> Option<u32> {
+		self.target.read(address, width).ok()
+	}
+
+	fn store(&mut self, address: u32, value: u32, width: usize) -> Option<()> {
+		self.target.write(address, value, width).ok()
+	}
+}
+
+
+/// Shift `value` by `amount` bits with the shift `kind` (LSL, LSR, ASR or ROR)
+/// Returns the result and the carry out, `carry` if the amount is 0
+fn shift(kind: u32, value: u32, amount: u32, carry: bool) -> (u32, bool) {
+	if amount == 0 {
+		return (value, carry);
+	}
+
+	match kind {
+		0 => match amount {
+			1 ..= 31 => (value << amount, value >> (32 - amount) & 1 != 0),
+			32 => (0, value & 1 != 0),
+			_ => (0, false),
+		},
+		1 => match amount {
+			1 ..= 31 => (value >> amount, value >> (amount - 1) & 1 != 0),
+			32 => (0, value >> 31 != 0),
+			_ => (0, false),
+		},
+		2 => match amount {
+			1 ..= 31 => (((value as i32) >> amount) as u32, value >> (amount - 1) & 1 != 0),
+			_ => (((value as i32) >> 31) as u32, value >> 31 != 0),
+		},
+		_ => {
+			let result = value.rotate_right(amount % 32);
+			(result, result >> 31 != 0)
+		},
+	}
+}
+
+/// Sign extend the `bits` low bits of `value`
+fn sign_extend(value: u32, bits: u32) -> u32 {
+	(((value << (32 - bits)) as i32) >> (32 - bits)) as u32
+}
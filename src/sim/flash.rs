@@ -0,0 +1,1522 @@
+//! Simulated STM32 flash controllers
+
+use std::ops::Range;
+
+use crate::link::constants::flash::{ cr, f1, l0, l4, misc::{ KEY1, KEY2, OPTKEY1, OPTKEY2 } };
+
+
+/// Flash memory and its controller, as seen from the bus
+pub trait FlashController {
+	/// Address range of the controller registers
+	fn registers(&self) -> Range<u32>;
+
+	/// Address range of the flash memory
+	fn memory(&self) -> Range<u32>;
+
+	/// Address range of the option bytes programmed through the controller, if any
+	/// They are accessed with `read`, `program` and `load` like the flash memory.
+	fn options(&self) -> Range<u32> {
+		0..0
+	}
+
+	/// Read the register at `offset` from the start of the register block
+	fn read_reg(&mut self, offset: u32) -> u32;
+
+	/// Write the register at `offset` from the start of the register block
+	fn write_reg(&mut self, offset: u32, value: u32);
+
+	/// Read a byte of the flash memory
+	fn read(&self, address: u32) -> u8;
+
+	/// Check if the flash memory is read protected from the debug port (RDP level 1 or 2)
+	fn read_protected(&self) -> bool {
+		false
+	}
+
+	/// Bus write of `width` bytes into the flash memory
+	/// Programming errors are reported in the status register, not on the bus
+	fn program(&mut self, address: u32, value: u32, width: usize);
+
+	/// Load `data` into the flash memory without going through the controller
+	fn load(&mut self, address: u32, data: &[u8]);
+
+	/// Reset the controller registers (system reset)
+	fn reset(&mut self);
+}
+
+
+/// Status register bits of the F2/F4/F7 controller
+pub mod sr {
+	pub const EOP    : u32 = 1 <<  0;
+	pub const OPERR  : u32 = 1 <<  1;
+	pub const WRPERR : u32 = 1 <<  4;
+	pub const PGAERR : u32 = 1 <<  5;
+	pub const PGPERR : u32 = 1 <<  6;
+	pub const PGSERR : u32 = 1 <<  7;
+}
+
+/// Reset value of the F4 option control register
+const OPTCR_RESET: u32 = 0x0FFF_AAED;
+/// Option register lock bit
+const OPTLOCK: u32 = 1 << 0;
+/// Option register start bit
+const OPTSTRT: u32 = 1 << 1;
+/// nWRP selects the PCROP sectors
+const SPRMOD: u32 = 1 << 31;
+
+/// Flash controller of the STM32F2/F4 devices
+pub struct FlashF4 {
+	base: u32,
+	data: Vec<u8>,
+	/// Offset and size of every sector
+	sectors: Vec<(u32, u32)>,
+
+	/// Number of correct keys written to KEYR
+	keys: usize,
+	/// Number of correct keys written to OPTKEYR
+	optkeys: usize,
+
+	acr: u32,
+	sr: u32,
+	cr: u32,
+	optcr: u32,
+	/// Option bytes programmed with OPTSTRT, loaded into OPTCR at reset
+	options: u32,
+}
+
+impl FlashF4 {
+	/// Flash controller of a 1 MB STM32F40x/41x
+	pub fn new() -> Self {
+		let mut sectors = Vec::new();
+		let mut offset = 0;
+
+		for size in [16, 16, 16, 16, 64, 128, 128, 128, 128, 128, 128, 128].iter() {
+			sectors.push((offset, size * 1024));
+			offset += size * 1024;
+		}
+
+		Self {
+			base: 0x4002_3C00,
+			data: vec![0xFF; offset as usize],
+			sectors,
+
+			keys: 0,
+			optkeys: 0,
+
+			acr: 0,
+			sr: 0,
+			cr: cr::LOCK,
+			optcr: OPTCR_RESET,
+			options: OPTCR_RESET & !OPTLOCK,
+		}
+	}
+
+	/// Current value of the option control register
+	pub fn optcr(&self) -> u32 {
+		self.optcr
+	}
+
+	/// Program OPTCR into the option bytes
+	/// Level 2 cannot be left, leaving level 1 mass erases the flash.
+	fn program_options(&mut self) {
+		let (old, new) = ((self.options >> 8) as u8, (self.optcr >> 8) as u8);
+
+		if old == 0xCC {
+			self.optcr = self.options;
+			return;
+		}
+
+		if old != 0xAA && new == 0xAA {
+			self.data.iter_mut().for_each(|b| *b = 0xFF);
+		}
+
+		self.options = self.optcr & !OPTLOCK;
+		self.sr |= sr::EOP;
+	}
+
+	/// Check if the sector `n` is protected by nWRP
+	/// Cleared bits protect the sectors, set bits select PCROP with SPRMOD set.
+	fn protected(&self, n: usize) -> bool {
+		let bit = (self.optcr >> 16) & (1 << n) != 0;
+
+		match self.optcr & SPRMOD {
+			0 => !bit,
+			_ => bit,
+		}
+	}
+
+	/// Erase the sector `n`
+	fn erase_sector(&mut self, n: usize) {
+		let (offset, size) = match self.sectors.get(n) {
+			Some(s) => *s,
+			None => {
+				self.sr |= sr::PGSERR;
+				return;
+			},
+		};
+
+		if self.protected(n) {
+			self.sr |= sr::WRPERR;
+			return;
+		}
+
+		self.data[offset as usize..(offset + size) as usize].iter_mut().for_each(|b| *b = 0xFF);
+	}
+
+	/// Start the operation selected in the control register
+	fn start(&mut self) {
+		match self.cr & (cr::SER | cr::MER) {
+			cr::MER => {
+				if (0..self.sectors.len()).any(|n| self.protected(n)) {
+					self.sr |= sr::WRPERR;
+					return;
+				}
+				self.data.iter_mut().for_each(|b| *b = 0xFF);
+			},
+			cr::SER => self.erase_sector(((self.cr >> 3) & 0xF) as usize),
+			_ => self.sr |= sr::PGSERR,
+		}
+
+		self.sr |= sr::EOP;
+	}
+}
+
+impl Default for FlashF4 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FlashController for FlashF4 {
+	fn registers(&self) -> Range<u32> {
+		self.base..self.base + 0x400
+	}
+
+	fn memory(&self) -> Range<u32> {
+		0x0800_0000..0x0800_0000 + self.data.len() as u32
+	}
+
+	fn read_protected(&self) -> bool {
+		(self.options >> 8) as u8 != 0xAA
+	}
+
+	fn read_reg(&mut self, offset: u32) -> u32 {
+		match offset {
+			0x00 => self.acr,
+			0x0C => self.sr,
+			0x10 => self.cr,
+			0x14 => self.optcr,
+			_ => 0,
+		}
+	}
+
+	fn write_reg(&mut self, offset: u32, value: u32) {
+		match offset {
+			0x00 => self.acr = value,
+
+			// KEYR
+			0x04 => match (self.keys, value) {
+				(0, KEY1) => self.keys = 1,
+				(1, KEY2) => {
+					self.keys = 0;
+					self.cr &= !cr::LOCK;
+				},
+				// A wrong sequence locks the controller until the next reset
+				_ => self.keys = 2,
+			},
+
+			// OPTKEYR
+			0x08 => match (self.optkeys, value) {
+				(0, OPTKEY1) => self.optkeys = 1,
+				(1, OPTKEY2) => {
+					self.optkeys = 0;
+					self.optcr &= !OPTLOCK;
+				},
+				_ => self.optkeys = 2,
+			},
+
+			// SR, the error flags are cleared by writing 1
+			0x0C => self.sr &= !(value & (sr::EOP | sr::OPERR | sr::WRPERR | sr::PGAERR | sr::PGPERR | sr::PGSERR)),
+
+			// CR
+			0x10 => {
+				if self.cr & cr::LOCK != 0 {
+					return;
+				}
+
+				self.cr = value & !cr::STRT;
+
+				if value & cr::STRT != 0 {
+					self.start();
+				}
+			},
+
+			// OPTCR
+			0x14 => {
+				if self.optcr & OPTLOCK != 0 {
+					return;
+				}
+
+				self.optcr = value & !OPTSTRT;
+
+				if value & OPTSTRT != 0 {
+					self.program_options();
+				}
+			},
+
+			_ => (),
+		}
+	}
+
+	fn read(&self, address: u32) -> u8 {
+		self.data[(address - self.memory().start) as usize]
+	}
+
+	fn program(&mut self, address: u32, value: u32, width: usize) {
+		if self.cr & cr::LOCK != 0 || self.cr & cr::PG == 0 {
+			self.sr |= sr::PGSERR;
+			return;
+		}
+
+		// No bus access has the width of PSIZE x64
+		let psize = match (self.cr >> 8) & 3 {
+			0 => 1,
+			1 => 2,
+			2 => 4,
+			_ => 8,
+		};
+
+		if psize != width {
+			self.sr |= sr::PGPERR;
+			return;
+		}
+
+		if !(address as usize).is_multiple_of(width) {
+			self.sr |= sr::PGAERR;
+			return;
+		}
+
+		let offset = address - self.memory().start;
+
+		match self.sectors.iter().position(|&(o, s)| offset >= o && offset < o + s) {
+			Some(n) if self.protected(n) => {
+				self.sr |= sr::WRPERR;
+				return;
+			},
+			_ => (),
+		}
+
+		// Programming can only clear bits
+		for i in 0..width {
+			self.data[offset as usize + i] &= (value >> (8 * i)) as u8;
+		}
+	}
+
+	fn load(&mut self, address: u32, data: &[u8]) {
+		let offset = (address - self.memory().start) as usize;
+		self.data[offset..offset + data.len()].copy_from_slice(data);
+	}
+
+	fn reset(&mut self) {
+		self.keys = 0;
+		self.optkeys = 0;
+		self.sr = 0;
+		self.cr = cr::LOCK;
+		self.optcr = self.options | OPTLOCK;
+	}
+}
+
+
+/// Registers of a bank of the F0/F1/F3 controller
+#[derive(Debug, Clone, Default)]
+struct BankF1 {
+	/// Number of correct keys written to KEYR
+	keys: usize,
+	sr: u32,
+	cr: u32,
+	ar: u32,
+}
+
+/// Flash controller of the STM32F0/F1/F3 devices
+/// XL-density devices have a second bank above 512 kB with its own registers.
+pub struct FlashF1 {
+	data: Vec<u8>,
+	page: u32,
+	/// Size of the first bank, the whole flash on single bank devices
+	bank1: u32,
+
+	banks: [BankF1; 2],
+	/// Number of correct keys written to OPTKEYR
+	optkeys: usize,
+
+	acr: u32,
+	obr: u32,
+	/// Write protection, each cleared bit protects `wrp_pages` pages
+	wrpr: u32,
+	wrp_pages: u32,
+
+	/// Option bytes from RDP to WRP3, each followed by its complement
+	options: [u8; 16],
+}
+
+/// Option bytes of an unprotected F1
+const F1_OPTIONS: [u8; 16] = [0xA5, 0x5A, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+
+impl FlashF1 {
+	/// Flash controller of a 128 kB medium-density STM32F103 with 1 kB pages
+	pub fn new() -> Self {
+		Self::with_size(128 * 1024, 1024, 4)
+	}
+
+	/// Flash controller of a 1 MB XL-density STM32F103 with 2 kB pages and two banks
+	pub fn xl() -> Self {
+		let mut new = Self::with_size(1024 * 1024, 2048, 2);
+		new.bank1 = 512 * 1024;
+		new
+	}
+
+	fn with_size(size: u32, page: u32, wrp_pages: u32) -> Self {
+		let locked = BankF1 { cr: f1::cr::LOCK, ..Default::default() };
+
+		Self {
+			data: vec![0xFF; size as usize],
+			page,
+			bank1: size,
+
+			banks: [locked.clone(), locked],
+			optkeys: 0,
+
+			acr: 0x30,
+			obr: 0x03FF_FFFC,
+			wrpr: 0xFFFF_FFFF,
+			wrp_pages,
+
+			options: F1_OPTIONS,
+		}
+	}
+
+	/// Protect the pages of the cleared bits of `wrpr`, in the option bytes too
+	pub fn set_wrpr(&mut self, wrpr: u32) {
+		for (i, b) in wrpr.to_le_bytes().iter().enumerate() {
+			self.options[8 + 2 * i] = *b;
+			self.options[9 + 2 * i] = !*b;
+		}
+
+		self.wrpr = wrpr;
+	}
+
+	/// Option bytes from RDP to WRP3, each followed by its complement
+	pub fn option_bytes(&self) -> [u8; 16] {
+		self.options
+	}
+
+	/// Load OBR and WRPR from the option bytes
+	fn load_options(&mut self) {
+		let o = &self.options;
+		let rdprt = match o[0] {
+			0xA5 => 0,
+			_ => f1::obr::RDPRT,
+		};
+
+		self.obr = rdprt | (o[2] as u32) << 2 | (o[4] as u32) << 10 | (o[6] as u32) << 18;
+		self.wrpr = u32::from_le_bytes([o[8], o[10], o[12], o[14]]);
+	}
+
+	/// Bus write into the option bytes at `offset`, with OPTPG set
+	/// The complement is computed from the low byte of the half-word.
+	fn program_option(&mut self, offset: usize, value: u32, width: usize) {
+		let cr = self.banks[0].cr;
+
+		if cr & f1::cr::OPTPG == 0 {
+			return;
+		}
+
+		if cr & f1::cr::OPTWRE == 0 {
+			self.banks[0].sr |= f1::sr::WRPRTERR;
+			return;
+		}
+
+		if width != 2 || self.options[offset] != 0xFF || self.options[offset + 1] != 0xFF {
+			self.banks[0].sr |= f1::sr::PGERR;
+			return;
+		}
+
+		self.options[offset] = value as u8;
+		self.options[offset + 1] = !value as u8;
+		self.banks[0].sr |= f1::sr::EOP;
+	}
+
+	/// Bank of the flash memory at `offset`
+	fn bank_of(&self, offset: u32) -> usize {
+		if offset < self.bank1 { 0 } else { 1 }
+	}
+
+	/// Check if the page at `offset` is write protected
+	fn protected(&self, offset: u32) -> bool {
+		let bit = std::cmp::min(offset / self.page / self.wrp_pages, 31);
+		self.wrpr & (1 << bit) == 0
+	}
+
+	/// Start the operation selected in the control register of the bank
+	fn start(&mut self, b: usize) {
+		let cr = self.banks[b].cr;
+
+		if cr & f1::cr::PER != 0 {
+			let offset = self.banks[b].ar.wrapping_sub(0x0800_0000) & !(self.page - 1);
+
+			if offset as usize >= self.data.len() || self.bank_of(offset) != b {
+				return;
+			}
+
+			if self.protected(offset) {
+				self.banks[b].sr |= f1::sr::WRPRTERR;
+				return;
+			}
+
+			self.data[offset as usize..(offset + self.page) as usize].iter_mut().for_each(|b| *b = 0xFF);
+		} else if cr & f1::cr::MER != 0 {
+			let range = match b {
+				0 => 0..self.bank1 as usize,
+				_ => self.bank1 as usize..self.data.len(),
+			};
+
+			if range.clone().step_by(self.page as usize).any(|o| self.protected(o as u32)) {
+				self.banks[b].sr |= f1::sr::WRPRTERR;
+				return;
+			}
+
+			self.data[range].iter_mut().for_each(|b| *b = 0xFF);
+		} else if cr & f1::cr::OPTER != 0 && b == 0 {
+			if cr & f1::cr::OPTWRE == 0 {
+				self.banks[b].sr |= f1::sr::WRPRTERR;
+				return;
+			}
+
+			// Removing the read protection erases the flash
+			if self.obr & f1::obr::RDPRT != 0 {
+				self.data.iter_mut().for_each(|b| *b = 0xFF);
+			}
+
+			self.options = [0xFF; 16];
+		} else {
+			return;
+		}
+
+		self.banks[b].sr |= f1::sr::EOP;
+	}
+}
+
+impl Default for FlashF1 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl FlashController for FlashF1 {
+	fn registers(&self) -> Range<u32> {
+		f1::register::BASE..f1::register::BASE + 0x400
+	}
+
+	fn memory(&self) -> Range<u32> {
+		0x0800_0000..0x0800_0000 + self.data.len() as u32
+	}
+
+	fn options(&self) -> Range<u32> {
+		f1::option::RDP..f1::option::RDP + 16
+	}
+
+	fn read_protected(&self) -> bool {
+		self.obr & f1::obr::RDPRT != 0
+	}
+
+	fn read_reg(&mut self, offset: u32) -> u32 {
+		let dual = self.bank1 as usize != self.data.len();
+
+		match offset {
+			0x00 => self.acr,
+			0x0C => self.banks[0].sr,
+			0x10 => self.banks[0].cr,
+			0x1C => self.obr,
+			0x20 => self.wrpr,
+			0x4C if dual => self.banks[1].sr,
+			0x50 if dual => self.banks[1].cr,
+			_ => 0,
+		}
+	}
+
+	fn write_reg(&mut self, offset: u32, value: u32) {
+		let dual = self.bank1 as usize != self.data.len();
+
+		let (b, reg) = match offset {
+			0x00 => {
+				self.acr = value;
+				return;
+			},
+			// OPTKEYR, once the controller is unlocked
+			0x08 => {
+				match (self.optkeys, value) {
+					(0, KEY1) => self.optkeys = 1,
+					(1, KEY2) if self.banks[0].cr & f1::cr::LOCK == 0 => {
+						self.optkeys = 0;
+						self.banks[0].cr |= f1::cr::OPTWRE;
+					},
+					_ => self.optkeys = 2,
+				}
+				return;
+			},
+			0x04 | 0x0C | 0x10 | 0x14 => (0, offset),
+			0x44 | 0x4C | 0x50 | 0x54 if dual => (1, offset - 0x40),
+			_ => return,
+		};
+
+		let bank = &mut self.banks[b];
+
+		match reg {
+			// KEYR
+			0x04 => match (bank.keys, value) {
+				(0, KEY1) => bank.keys = 1,
+				(1, KEY2) => {
+					bank.keys = 0;
+					bank.cr &= !f1::cr::LOCK;
+				},
+				// A wrong sequence locks the controller until the next reset
+				_ => bank.keys = 2,
+			},
+
+			// SR, the flags are cleared by writing 1
+			0x0C => bank.sr &= !(value & (f1::sr::EOP | f1::sr::ERRORS)),
+
+			// CR
+			0x10 => {
+				if bank.cr & f1::cr::LOCK != 0 {
+					return;
+				}
+
+				// OPTWRE is only cleared by writing 0
+				bank.cr = (value & !(f1::cr::STRT | f1::cr::OPTWRE)) | (bank.cr & value & f1::cr::OPTWRE);
+
+				if value & f1::cr::STRT != 0 {
+					self.start(b);
+				}
+			},
+
+			// AR
+			_ => bank.ar = value,
+		}
+	}
+
+	fn read(&self, address: u32) -> u8 {
+		if self.options().contains(&address) {
+			return self.options[(address - self.options().start) as usize];
+		}
+
+		self.data[(address - self.memory().start) as usize]
+	}
+
+	fn program(&mut self, address: u32, value: u32, width: usize) {
+		if self.options().contains(&address) {
+			self.program_option((address - self.options().start) as usize, value, width);
+			return;
+		}
+
+		let offset = address - self.memory().start;
+		let b = self.bank_of(offset);
+
+		if self.banks[b].cr & f1::cr::PG == 0 {
+			return;
+		}
+
+		// Only half-words can be programmed
+		if width != 2 {
+			self.banks[b].sr |= f1::sr::PGERR;
+			return;
+		}
+
+		if self.protected(offset) {
+			self.banks[b].sr |= f1::sr::WRPRTERR;
+			return;
+		}
+
+		let o = offset as usize;
+		let old = self.data[o] as u32 | (self.data[o + 1] as u32) << 8;
+		let value = value & 0xFFFF;
+
+		// A half-word that is not erased can only be cleared
+		if old != 0xFFFF && value != 0 {
+			self.banks[b].sr |= f1::sr::PGERR;
+			return;
+		}
+
+		self.data[o] = value as u8;
+		self.data[o + 1] = (value >> 8) as u8;
+		self.banks[b].sr |= f1::sr::EOP;
+	}
+
+	fn load(&mut self, address: u32, data: &[u8]) {
+		if self.options().contains(&address) {
+			let offset = (address - self.options().start) as usize;
+			self.options[offset..offset + data.len()].copy_from_slice(data);
+			return;
+		}
+
+		let offset = (address - self.memory().start) as usize;
+		self.data[offset..offset + data.len()].copy_from_slice(data);
+	}
+
+	fn reset(&mut self) {
+		for bank in self.banks.iter_mut() {
+			bank.keys = 0;
+			bank.sr = 0;
+			bank.cr = f1::cr::LOCK;
+		}
+
+		self.optkeys = 0;
+		self.load_options();
+	}
+}
+
+
+/// Non-volatile memory interface of the STM32L0/L1 devices
+/// The program memory is erased to 0. The data EEPROM is mapped after it at
+/// `l0::EEPROM_BASE`, the memory range of the controller includes the gap.
+pub struct FlashL0 {
+	base: u32,
+	data: Vec<u8>,
+	eeprom: Vec<u8>,
+	page: u32,
+
+	/// Number of correct keys written to PEKEYR
+	pekeys: usize,
+	/// Number of correct keys written to PRGKEYR
+	prgkeys: usize,
+	/// Number of correct keys written to OPTKEYR
+	optkeys: usize,
+
+	acr: u32,
+	pecr: u32,
+	sr: u32,
+	optr: u32,
+	/// Write protection, each set bit protects 4 kB
+	wrprot: [u32; 4],
+
+	/// Option bytes from RDP, each half-word followed by its complement
+	/// Loaded into OPTR and the WRPROT registers with OBL_LAUNCH.
+	options: Vec<u8>,
+
+	/// Words of the half-page being programmed
+	half: Vec<(u32, u32)>,
+}
+
+/// Size of the simulated option bytes, up to WRPROT2 of L0 devices
+/// The factory information block follows them. WRPROT2 to WRPROT4 of L1
+/// devices are further away and not simulated.
+const L0_OPTIONS: u32 = 0x20;
+
+impl FlashL0 {
+	/// NVM of a STM32L053 with 64 kB of flash in 128 B pages and 2 kB of data EEPROM
+	pub fn l053() -> Self {
+		Self::new(l0::L0_BASE, 64 * 1024, 128, 2 * 1024)
+	}
+
+	/// NVM of a STM32L152 medium-density with 128 kB of flash in 256 B pages and 4 kB of data EEPROM
+	pub fn l152() -> Self {
+		Self::new(l0::L1_BASE, 128 * 1024, 256, 4 * 1024)
+	}
+
+	/// NVM with the registers at `base` and the given memory sizes
+	pub fn new(base: u32, size: u32, page: u32, eeprom: u32) -> Self {
+		let mut new = Self {
+			base,
+			data: vec![0; size as usize],
+			eeprom: vec![0; eeprom as usize],
+			page,
+
+			pekeys: 0,
+			prgkeys: 0,
+			optkeys: 0,
+
+			acr: 0,
+			pecr: l0::pecr::PELOCK | l0::pecr::PRGLOCK | l0::pecr::OPTLOCK,
+			sr: 0,
+			// nBOOT1 only exists on L0
+			optr: match base {
+				l0::L0_BASE => 0x8070_00AA,
+				_ => 0x0070_00AA,
+			},
+			wrprot: [0; 4],
+
+			options: vec![0; L0_OPTIONS as usize],
+
+			half: Vec::new(),
+		};
+
+		new.store_options();
+		new
+	}
+
+	/// Protect the 4 kB sectors of the set bits of `wrprot`, in the option bytes too
+	pub fn set_wrprot(&mut self, wrprot: u32) {
+		self.wrprot[0] = wrprot;
+		self.store_options();
+	}
+
+	/// Option bytes from RDP, each half-word followed by its complement
+	pub fn option_bytes(&self) -> Vec<u8> {
+		self.options.clone()
+	}
+
+	/// Size of the program memory
+	pub fn size(&self) -> u32 {
+		self.data.len() as u32
+	}
+
+	/// Current value of PECR
+	pub fn pecr(&self) -> u32 {
+		self.pecr
+	}
+
+	/// Check if the program memory at `offset` is write protected
+	/// With WPRMOD set, the cleared bits protect the sectors against reads and writes.
+	fn protected(&self, offset: u32) -> bool {
+		let sector = std::cmp::min(offset / 4096, 127);
+		let bit = self.wrprot[(sector / 32) as usize] & (1 << (sector % 32)) != 0;
+
+		bit != (self.optr & l0::optr::WPRMOD != 0)
+	}
+
+	/// Offsets from RDP of the option bytes of the WRPROT registers that are simulated
+	fn wrprot_options(&self) -> Vec<u32> {
+		use self::l0::option::{ RDP, WRPROT1, L0_WRPROT2, L1_WRPROT2 };
+
+		let wrprot2 = match self.base {
+			l0::L0_BASE => L0_WRPROT2,
+			_ => L1_WRPROT2,
+		};
+
+		[WRPROT1, wrprot2, wrprot2 + 8, wrprot2 + 16].iter()
+			.map(|a| a - RDP)
+			.take_while(|o| o + 8 <= L0_OPTIONS)
+			.collect()
+	}
+
+	/// Write OPTR and the WRPROT registers into the option bytes
+	fn store_options(&mut self) {
+		let mut halves = vec![(0, self.optr as u16), (4, (self.optr >> 16) as u16)];
+		for (offset, w) in self.wrprot_options().iter().zip(self.wrprot.iter()) {
+			halves.push((*offset, *w as u16));
+			halves.push((offset + 4, (*w >> 16) as u16));
+		}
+
+		for (offset, half) in halves {
+			let word = half as u32 | (!half as u32) << 16;
+			self.options[offset as usize..offset as usize + 4].copy_from_slice(&word.to_le_bytes());
+		}
+	}
+
+	/// Half-word of the option bytes at `offset`, `None` if its complement does not match
+	fn option_half(&self, offset: u32) -> Option<u16> {
+		let o = offset as usize;
+		let (half, complement) = (u16::from_le_bytes([self.options[o], self.options[o + 1]]), u16::from_le_bytes([self.options[o + 2], self.options[o + 3]]));
+
+		match half == !complement {
+			true => Some(half),
+			false => None,
+		}
+	}
+
+	/// Load OPTR and the WRPROT registers from the option bytes (OBL_LAUNCH)
+	/// A half-word without its complement sets OPTVERR and is not loaded.
+	fn load_options(&mut self) {
+		let mut halves = vec![0, 4];
+		for offset in self.wrprot_options().iter() {
+			halves.push(*offset);
+			halves.push(offset + 4);
+		}
+
+		let mut values = Vec::new();
+		for offset in halves {
+			match self.option_half(offset) {
+				Some(h) => values.push(Some(h as u32)),
+				None => {
+					self.sr |= l0::sr::OPTVERR;
+					values.push(None);
+				},
+			}
+		}
+
+		let merge = |reg: u32, low: Option<u32>, high: Option<u32>| {
+			let reg = low.map(|l| (reg & !0xFFFF) | l).unwrap_or(reg);
+			high.map(|h| (reg & 0xFFFF) | h << 16).unwrap_or(reg)
+		};
+
+		self.optr = merge(self.optr, values[0], values[1]);
+		for (i, pair) in values[2..].chunks(2).enumerate() {
+			self.wrprot[i] = merge(self.wrprot[i], pair[0], pair[1]);
+		}
+	}
+
+	/// Bus write into the option bytes at `offset`, with OPTLOCK cleared
+	/// Writing RDP level 0 over level 1 erases the program memory and the data EEPROM.
+	fn program_option(&mut self, offset: u32, value: u32, width: usize) {
+		let level = self.optr & l0::optr::RDP;
+
+		if self.pecr & l0::pecr::OPTLOCK != 0 || level == 0xCC {
+			self.sr |= l0::sr::WRPERR;
+			return;
+		}
+
+		if width != 4 || !offset.is_multiple_of(4) {
+			self.sr |= l0::sr::SIZERR;
+			return;
+		}
+
+		if offset == 0 && level != 0xAA && value & l0::optr::RDP == 0xAA {
+			self.data.iter_mut().for_each(|b| *b = 0);
+			self.eeprom.iter_mut().for_each(|b| *b = 0);
+		}
+
+		self.options[offset as usize..offset as usize + 4].copy_from_slice(&value.to_le_bytes());
+		self.sr |= l0::sr::EOP;
+	}
+
+	/// Bus write into the program memory at `offset`
+	fn program_flash(&mut self, offset: u32, value: u32, width: usize) {
+		use self::l0::pecr::{ PRGLOCK, PROG, ERASE, FPRG };
+
+		if self.pecr & PRGLOCK != 0 || self.protected(offset) {
+			self.sr |= l0::sr::WRPERR;
+			return;
+		}
+
+		// Only words can be written to the program memory
+		if width != 4 {
+			self.sr |= l0::sr::SIZERR;
+			return;
+		}
+
+		match self.pecr & (PROG | ERASE | FPRG) {
+			// Page erase
+			p if p == PROG | ERASE => {
+				let start = (offset & !(self.page - 1)) as usize;
+				self.data[start..start + self.page as usize].iter_mut().for_each(|b| *b = 0);
+			},
+
+			// Half-page programming, once all the words are written
+			p if p == PROG | FPRG => {
+				let half = self.page / 2;
+
+				if let Some(&(first, _)) = self.half.first() {
+					if offset / half != first / half {
+						self.half.clear();
+						self.sr |= l0::sr::PGAERR;
+						return;
+					}
+				}
+
+				self.half.push((offset, value));
+
+				if self.half.len() < (half / 4) as usize {
+					return;
+				}
+
+				let words = std::mem::take(&mut self.half);
+
+				if words.iter().any(|&(o, _)| self.word(o) != 0) {
+					self.sr |= l0::sr::NOTZEROERR;
+					return;
+				}
+
+				for (o, v) in words {
+					self.data[o as usize..o as usize + 4].copy_from_slice(&v.to_le_bytes());
+				}
+			},
+
+			// Single word
+			0 => {
+				if self.word(offset) != 0 && value != 0 {
+					self.sr |= l0::sr::NOTZEROERR;
+					return;
+				}
+
+				self.data[offset as usize..offset as usize + 4].copy_from_slice(&value.to_le_bytes());
+			},
+
+			_ => {
+				self.sr |= l0::sr::PGAERR;
+				return;
+			},
+		}
+
+		self.sr |= l0::sr::EOP;
+	}
+
+	/// Bus write into the data EEPROM at `offset`, erased first as needed
+	fn program_eeprom(&mut self, offset: u32, value: u32, width: usize) {
+		use self::l0::pecr::{ PELOCK, DATA, ERASE };
+
+		if self.pecr & PELOCK != 0 {
+			self.sr |= l0::sr::WRPERR;
+			return;
+		}
+
+		let value = match self.pecr & (DATA | ERASE) == DATA | ERASE {
+			true => 0,
+			false => value,
+		};
+
+		for i in 0..width {
+			self.eeprom[offset as usize + i] = (value >> (8 * i)) as u8;
+		}
+
+		self.sr |= l0::sr::EOP;
+	}
+
+	/// Word of the program memory at `offset`
+	fn word(&self, offset: u32) -> u32 {
+		let o = offset as usize;
+		u32::from_le_bytes([self.data[o], self.data[o + 1], self.data[o + 2], self.data[o + 3]])
+	}
+}
+
+impl FlashController for FlashL0 {
+	fn registers(&self) -> Range<u32> {
+		self.base..self.base + 0x400
+	}
+
+	fn memory(&self) -> Range<u32> {
+		0x0800_0000..l0::EEPROM_BASE + self.eeprom.len() as u32
+	}
+
+	fn options(&self) -> Range<u32> {
+		l0::option::RDP..l0::option::RDP + L0_OPTIONS
+	}
+
+	fn read_protected(&self) -> bool {
+		self.optr & l0::optr::RDP != 0xAA
+	}
+
+	fn read_reg(&mut self, offset: u32) -> u32 {
+		use self::l0::register::{ ACR, PECR, SR, OPTR, WRPROT1, WRPROT2 };
+
+		match offset {
+			ACR => self.acr,
+			PECR => self.pecr,
+			SR => self.sr,
+			OPTR => self.optr,
+			WRPROT1 => self.wrprot[0],
+			o if (WRPROT2..WRPROT2 + 12).contains(&o) => self.wrprot[1 + ((o - WRPROT2) / 4) as usize],
+			_ => 0,
+		}
+	}
+
+	fn write_reg(&mut self, offset: u32, value: u32) {
+		use self::l0::pecr::{ PELOCK, PRGLOCK, OPTLOCK, OBL_LAUNCH };
+		use self::l0::misc::{ PEKEY1, PEKEY2, PRGKEY1, PRGKEY2, OPTKEY1, OPTKEY2 };
+
+		match offset {
+			l0::register::ACR => self.acr = value,
+
+			l0::register::PEKEYR => match (self.pekeys, value) {
+				(0, PEKEY1) => self.pekeys = 1,
+				(1, PEKEY2) => {
+					self.pekeys = 0;
+					self.pecr &= !PELOCK;
+				},
+				// A wrong sequence locks PECR until the next reset
+				_ => self.pekeys = 2,
+			},
+
+			l0::register::PRGKEYR if self.pecr & PELOCK == 0 => match (self.prgkeys, value) {
+				(0, PRGKEY1) => self.prgkeys = 1,
+				(1, PRGKEY2) => {
+					self.prgkeys = 0;
+					self.pecr &= !PRGLOCK;
+				},
+				_ => self.prgkeys = 2,
+			},
+
+			l0::register::OPTKEYR if self.pecr & PELOCK == 0 => match (self.optkeys, value) {
+				(0, OPTKEY1) => self.optkeys = 1,
+				(1, OPTKEY2) => {
+					self.optkeys = 0;
+					self.pecr &= !OPTLOCK;
+				},
+				_ => self.optkeys = 2,
+			},
+
+			// Load the option bytes, which resets the controller
+			l0::register::PECR if self.pecr & PELOCK == 0 && value & OBL_LAUNCH != 0 => {
+				self.load_options();
+				self.reset();
+			},
+
+			l0::register::PECR if self.pecr & PELOCK == 0 => {
+				// The lock bits can only be set, PELOCK sets all of them
+				let locks = match value & PELOCK {
+					0 => (self.pecr | value) & (PRGLOCK | OPTLOCK),
+					_ => PELOCK | PRGLOCK | OPTLOCK,
+				};
+
+				self.pecr = locks | (value & !(PELOCK | PRGLOCK | OPTLOCK));
+				self.half.clear();
+			},
+
+			// The flags are cleared by writing 1
+			l0::register::SR => self.sr &= !(value & (l0::sr::EOP | l0::sr::ERRORS)),
+
+			_ => (),
+		}
+	}
+
+	fn read(&self, address: u32) -> u8 {
+		let offset = address.wrapping_sub(0x0800_0000);
+
+		if self.options().contains(&address) {
+			self.options[(address - l0::option::RDP) as usize]
+		} else if address >= l0::EEPROM_BASE {
+			self.eeprom[(address - l0::EEPROM_BASE) as usize]
+		} else if (offset as usize) < self.data.len() {
+			self.data[offset as usize]
+		} else {
+			0
+		}
+	}
+
+	fn program(&mut self, address: u32, value: u32, width: usize) {
+		let offset = address.wrapping_sub(0x0800_0000);
+
+		if self.options().contains(&address) {
+			self.program_option(address - l0::option::RDP, value, width);
+		} else if address >= l0::EEPROM_BASE {
+			self.program_eeprom(address - l0::EEPROM_BASE, value, width);
+		} else if (offset as usize) < self.data.len() {
+			self.program_flash(offset, value, width);
+		}
+	}
+
+	fn load(&mut self, address: u32, data: &[u8]) {
+		if self.options().contains(&address) {
+			let offset = (address - l0::option::RDP) as usize;
+			self.options[offset..offset + data.len()].copy_from_slice(data);
+		} else if address >= l0::EEPROM_BASE {
+			let offset = (address - l0::EEPROM_BASE) as usize;
+			self.eeprom[offset..offset + data.len()].copy_from_slice(data);
+		} else {
+			let offset = (address - 0x0800_0000) as usize;
+			self.data[offset..offset + data.len()].copy_from_slice(data);
+		}
+	}
+
+	fn reset(&mut self) {
+		self.pekeys = 0;
+		self.prgkeys = 0;
+		self.optkeys = 0;
+		self.pecr = l0::pecr::PELOCK | l0::pecr::PRGLOCK | l0::pecr::OPTLOCK;
+		self.sr = 0;
+		self.half.clear();
+	}
+}
+
+
+/// Reset value of the WRP area registers, with the start page after the end page
+const WRP_DISABLED: u32 = 0xFF00_FFFF;
+
+/// Flash controller of the STM32L4/G0/WB devices
+/// Dual bank capable controllers split the flash in two banks with 1 MB or
+/// when the DUALBANK option bit is set. On WB the pages from SFSA are secure.
+pub struct FlashL4 {
+	base: u32,
+	data: Vec<u8>,
+	page: u32,
+	row: u32,
+	/// The flash can be split in two banks
+	dual: bool,
+
+	/// Number of correct keys written to KEYR
+	keys: usize,
+	/// Number of correct keys written to OPTKEYR
+	optkeys: usize,
+
+	acr: u32,
+	sr: u32,
+	cr: u32,
+	optr: u32,
+	/// Option bytes programmed with OPTSTRT, loaded into OPTR with OBL_LAUNCH
+	options: u32,
+	/// WRP1AR, WRP1BR, WRP2AR and WRP2BR
+	wrp: [u32; 4],
+	/// Write protection areas programmed with OPTSTRT
+	wrp_options: [u32; 4],
+	/// PCROP1SR, PCROP1ER, PCROP2SR and PCROP2ER
+	pcrop: [u32; 4],
+	sfr: u32,
+
+	/// Words written since the start of the double word or row being programmed
+	pending: Vec<(u32, u32)>,
+}
+
+impl FlashL4 {
+	/// Flash controller of a 1 MB STM32L476 with 2 kB pages in two banks
+	pub fn l476() -> Self {
+		Self::l47x(1024 * 1024)
+	}
+
+	/// Flash controller of a STM32L47x with `size` bytes of flash in 2 kB pages
+	/// The devices with less than 1 MB are single bank until DUALBANK is set.
+	pub fn l47x(size: u32) -> Self {
+		let mut new = Self::new(l4::L4_BASE, size, 2048, l4::ROW);
+		new.dual = true;
+		new.set_optr(0xFFCF_F8AA);
+		new
+	}
+
+	/// Flash controller of a 128 kB STM32G071 with 2 kB pages
+	pub fn g071() -> Self {
+		let mut new = Self::new(l4::L4_BASE, 128 * 1024, 2048, l4::ROW);
+		new.set_optr(0xDFFF_E1AA);
+		new
+	}
+
+	/// Flash controller of a 1 MB STM32WB55 with 4 kB pages and the wireless
+	/// stack of CPU2 from 0x080CB000
+	pub fn wb55() -> Self {
+		let mut new = Self::new(l4::WB_BASE, 1024 * 1024, 4096, l4::WB_ROW);
+		new.set_optr(0x3DFF_E1AA);
+		new.sfr = 0xCB;
+		new
+	}
+
+	/// Single bank controller with the registers at `base`
+	pub fn new(base: u32, size: u32, page: u32, row: u32) -> Self {
+		Self {
+			base,
+			data: vec![0xFF; size as usize],
+			page,
+			row,
+			dual: false,
+
+			keys: 0,
+			optkeys: 0,
+
+			acr: 0x600,
+			sr: 0,
+			cr: l4::cr::LOCK | l4::cr::OPTLOCK,
+			optr: 0,
+			options: 0,
+			wrp: [WRP_DISABLED; 4],
+			wrp_options: [WRP_DISABLED; 4],
+			pcrop: [0xFFFF_FFFF, 0, 0xFFFF_FFFF, 0],
+			sfr: l4::sfr::FSD | l4::sfr::SFSA,
+
+			pending: Vec::new(),
+		}
+	}
+
+	/// Set the user option bytes, including the bank mode
+	pub fn set_optr(&mut self, optr: u32) {
+		self.optr = optr;
+		self.options = optr;
+	}
+
+	/// Option bytes programmed into the flash, loaded at the next OBL_LAUNCH
+	pub fn option_bytes(&self) -> u32 {
+		self.options
+	}
+
+	/// Set the PCROP area of the first bank, from and to the double words at these offsets
+	pub fn set_pcrop(&mut self, start: u32, end: u32) {
+		self.pcrop[0] = start / 8;
+		self.pcrop[1] = end / 8;
+	}
+
+	/// Set the secure flash start address register of WB devices
+	pub fn set_sfr(&mut self, sfr: u32) {
+		self.sfr = sfr;
+	}
+
+	/// Offset of the second bank, `None` in single bank mode
+	fn bank2(&self) -> Option<u32> {
+		match self.dual && (self.data.len() >= 1024 * 1024 || self.optr & l4::optr::DUALBANK != 0) {
+			true => Some(self.data.len() as u32 / 2),
+			false => None,
+		}
+	}
+
+	/// Check if the flash at `offset` is in a WRP area of its bank or in the PCROP area
+	fn protected(&self, offset: u32) -> bool {
+		let (bank, page) = match self.bank2() {
+			Some(bank2) if offset >= bank2 => (1, (offset - bank2) / self.page),
+			_ => (0, offset / self.page),
+		};
+
+		let wrp = self.wrp[2 * bank..2 * bank + 2].iter()
+			.any(|r| (r & 0xFF) <= page && page <= (r >> 16) & 0xFF);
+
+		let (start, end) = (self.pcrop[0] & 0xFFFF, self.pcrop[1] & 0xFFFF);
+		let pcrop = bank == 0 && start <= offset / 8 && offset / 8 <= end;
+
+		wrp || pcrop
+	}
+
+	/// Check if the flash at `offset` belongs to CPU2
+	fn secure(&self, offset: u32) -> bool {
+		self.sfr & l4::sfr::FSD == 0 && offset >= (self.sfr & l4::sfr::SFSA) * self.page
+	}
+
+	/// Erase the pages of `range` that are not secure
+	fn erase(&mut self, range: Range<u32>) {
+		let end = std::cmp::min(range.end, self.data.len() as u32);
+
+		for offset in range.start..end {
+			if !self.secure(offset) {
+				self.data[offset as usize] = 0xFF;
+			}
+		}
+	}
+
+	/// Start the operation selected in the control register
+	fn start(&mut self) {
+		use self::l4::cr::{ PER, MER1, MER2, BKER, PNB_SHIFT };
+
+		if self.cr & PER != 0 {
+			let bank = match self.cr & BKER {
+				0 => 0,
+				_ => self.bank2().unwrap_or(self.data.len() as u32),
+			};
+			let offset = bank + ((self.cr >> PNB_SHIFT) & 0xFF) * self.page;
+
+			if offset as usize >= self.data.len() {
+				self.sr |= l4::sr::PGSERR;
+				return;
+			}
+
+			if self.secure(offset) || self.protected(offset) {
+				self.sr |= l4::sr::WRPERR;
+				return;
+			}
+
+			self.erase(offset..offset + self.page);
+		} else if self.cr & (MER1 | MER2) != 0 {
+			let half = self.bank2().unwrap_or(self.data.len() as u32);
+
+			// No bank with a protected page is erased
+			let protected = |range: Range<u32>| range.step_by(self.page as usize).any(|o| self.protected(o));
+
+			if (self.cr & MER1 != 0 && protected(0..half)) || (self.cr & MER2 != 0 && protected(half..self.data.len() as u32)) {
+				self.sr |= l4::sr::WRPERR;
+				return;
+			}
+
+			if self.cr & MER1 != 0 {
+				self.erase(0..half);
+			}
+			if self.cr & MER2 != 0 {
+				self.erase(half..self.data.len() as u32);
+			}
+		} else {
+			self.sr |= l4::sr::PGSERR;
+			return;
+		}
+
+		self.sr |= l4::sr::EOP;
+	}
+
+	/// Program OPTR into the option bytes
+	/// Level 2 cannot be left, leaving level 1 mass erases the flash.
+	fn program_options(&mut self) {
+		let (old, new) = (self.options as u8, self.optr as u8);
+
+		if old == 0xCC {
+			self.optr = self.options;
+			self.wrp = self.wrp_options;
+			return;
+		}
+
+		if old != 0xAA && new == 0xAA {
+			self.erase(0..self.data.len() as u32);
+		}
+
+		self.options = self.optr;
+		self.wrp_options = self.wrp;
+		self.sr |= l4::sr::EOP;
+	}
+
+	/// Program the pending words once there are `n` of them
+	fn commit(&mut self, n: usize) {
+		if self.pending.len() < n {
+			return;
+		}
+
+		let words = std::mem::take(&mut self.pending);
+
+		if words.iter().any(|&(o, _)| self.data[o as usize..o as usize + 4].iter().any(|b| *b != 0xFF)) {
+			self.sr |= l4::sr::PROGERR;
+			return;
+		}
+
+		for (o, v) in words {
+			self.data[o as usize..o as usize + 4].copy_from_slice(&v.to_le_bytes());
+		}
+
+		self.sr |= l4::sr::EOP;
+	}
+}
+
+impl FlashController for FlashL4 {
+	fn registers(&self) -> Range<u32> {
+		self.base..self.base + 0x400
+	}
+
+	fn memory(&self) -> Range<u32> {
+		0x0800_0000..0x0800_0000 + self.data.len() as u32
+	}
+
+	fn read_protected(&self) -> bool {
+		self.options as u8 != 0xAA
+	}
+
+	fn read_reg(&mut self, offset: u32) -> u32 {
+		match offset {
+			l4::register::ACR => self.acr,
+			l4::register::SR => self.sr,
+			l4::register::CR => self.cr,
+			l4::register::OPTR => self.optr,
+			l4::register::PCROP1SR => self.pcrop[0],
+			l4::register::PCROP1ER => self.pcrop[1],
+			l4::register::PCROP2SR if self.dual => self.pcrop[2],
+			l4::register::PCROP2ER if self.dual => self.pcrop[3],
+			l4::register::WRP1AR => self.wrp[0],
+			l4::register::WRP1BR => self.wrp[1],
+			l4::register::WRP2AR if self.dual => self.wrp[2],
+			l4::register::WRP2BR if self.dual => self.wrp[3],
+			l4::register::SFR if self.base == l4::WB_BASE => self.sfr,
+			_ => 0,
+		}
+	}
+
+	fn write_reg(&mut self, offset: u32, value: u32) {
+		match offset {
+			l4::register::ACR => self.acr = value,
+
+			l4::register::KEYR => match (self.keys, value) {
+				(0, KEY1) => self.keys = 1,
+				(1, KEY2) => {
+					self.keys = 0;
+					self.cr &= !l4::cr::LOCK;
+				},
+				// A wrong sequence locks the controller until the next reset
+				_ => self.keys = 2,
+			},
+
+			// OPTKEYR, once the controller is unlocked
+			l4::register::OPTKEYR => match (self.optkeys, value) {
+				(0, OPTKEY1) => self.optkeys = 1,
+				(1, OPTKEY2) if self.cr & l4::cr::LOCK == 0 => {
+					self.optkeys = 0;
+					self.cr &= !l4::cr::OPTLOCK;
+				},
+				_ => self.optkeys = 2,
+			},
+
+			// The flags are cleared by writing 1
+			l4::register::SR => self.sr &= !(value & (l4::sr::EOP | l4::sr::ERRORS)),
+
+			l4::register::CR => {
+				use self::l4::cr::{ LOCK, OPTLOCK, STRT, OPTSTRT, OBL_LAUNCH };
+
+				if self.cr & LOCK != 0 {
+					return;
+				}
+
+				// Load the option bytes, which resets the controller
+				if value & OBL_LAUNCH != 0 {
+					self.optr = self.options;
+					self.wrp = self.wrp_options;
+					self.reset();
+					return;
+				}
+
+				// OPTLOCK can only be set
+				self.cr = (value & !(STRT | OPTSTRT)) | (self.cr & OPTLOCK);
+				self.pending.clear();
+
+				if value & OPTSTRT != 0 {
+					match self.cr & OPTLOCK {
+						0 => self.program_options(),
+						_ => self.sr |= l4::sr::PGSERR,
+					}
+				}
+
+				if value & STRT != 0 {
+					self.start();
+				}
+			},
+
+			l4::register::OPTR if self.cr & l4::cr::OPTLOCK == 0 => self.optr = value,
+			l4::register::WRP1AR if self.cr & l4::cr::OPTLOCK == 0 => self.wrp[0] = value,
+			l4::register::WRP1BR if self.cr & l4::cr::OPTLOCK == 0 => self.wrp[1] = value,
+			l4::register::WRP2AR if self.cr & l4::cr::OPTLOCK == 0 && self.dual => self.wrp[2] = value,
+			l4::register::WRP2BR if self.cr & l4::cr::OPTLOCK == 0 && self.dual => self.wrp[3] = value,
+
+			_ => (),
+		}
+	}
+
+	fn read(&self, address: u32) -> u8 {
+		self.data[(address - self.memory().start) as usize]
+	}
+
+	fn program(&mut self, address: u32, value: u32, width: usize) {
+		use self::l4::cr::{ PG, FSTPG };
+
+		let offset = address - self.memory().start;
+
+		if self.cr & (PG | FSTPG) == 0 {
+			self.sr |= l4::sr::PGSERR;
+			return;
+		}
+
+		if width != 4 {
+			self.sr |= l4::sr::SIZERR;
+			return;
+		}
+
+		if self.secure(offset) || self.protected(offset) {
+			self.sr |= l4::sr::WRPERR;
+			return;
+		}
+
+		let (block, error) = match self.cr & FSTPG {
+			0 => (8, l4::sr::PGAERR),
+			_ => (self.row, l4::sr::MISERR),
+		};
+
+		// The words come in order from the start of the double word or row
+		let expected = match self.pending.last() {
+			Some(&(o, _)) => o + 4,
+			None => offset & !(block - 1),
+		};
+
+		if offset != expected {
+			self.pending.clear();
+			self.sr |= error;
+			return;
+		}
+
+		self.pending.push((offset, value));
+		self.commit(block as usize / 4);
+	}
+
+	fn load(&mut self, address: u32, data: &[u8]) {
+		let offset = (address - self.memory().start) as usize;
+		self.data[offset..offset + data.len()].copy_from_slice(data);
+	}
+
+	fn reset(&mut self) {
+		self.keys = 0;
+		self.optkeys = 0;
+		self.sr = 0;
+		self.cr = l4::cr::LOCK | l4::cr::OPTLOCK;
+		self.pending.clear();
+	}
+}
@@ -0,0 +1,23 @@
+//! Simulated STLink probe and STM32 target
+//! `SimProbe` implements `Transport` and answers the STLink protocol from an
+//! in-process `Target`, so `Link` can be used without any hardware:
+//!
+//! ```no_run
+//! use rustylink::{ Link, DebugMode };
+//! use rustylink::sim::{ SimProbe, Target };
+//!
+//! let probe = SimProbe::new(Target::stm32f407());
+//! let mut link = Link::open(probe, SimProbe::model(), DebugMode::SWD).unwrap();
+//!
+//! link.halt().unwrap();
+//! assert!(link.transport().target().halted());
+//! ```
+
+mod probe;
+mod target;
+mod flash;
+mod cpu;
+
+pub use self::probe::SimProbe;
+pub use self::target::{ Target, Region, BusFault };
+pub use self::flash::{ FlashController, FlashF4, FlashF1, FlashL0, FlashL4 };
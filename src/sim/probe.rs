@@ -0,0 +1,383 @@
+//! Simulated STLink V2-1 probe
+
+use std::time::Duration;
+
+use crate::link::constants::commands::{ GET_VERSION, GET_CURRENT_MODE, GET_TARGET_VOLTAGE, dfu, swd, debug::{ self, apiv2 } };
+use crate::link::constants::modes;
+use crate::link::error::LinkError;
+use crate::link::transport::Transport;
+use crate::link::util::{ buf_read_u32, buf_read_u16, buf_write_u32, buf_write_u16 };
+use crate::usb::model::{ self, ProbeModel };
+
+use super::target::Target;
+
+
+/// Product ID of the simulated probe
+const PID: u16 = 0x374B;
+/// Default firmware version of the simulated probe (V2 J37 M26)
+const JTAG: u16 = 37;
+const MSD: u16 = 26;
+/// SW-DP IDCODE of the target
+const DP_IDCODE: u32 = 0x2BA0_1477;
+
+/// Memory write waiting for its data stage
+#[derive(Debug, Copy, Clone)]
+struct PendingWrite {
+	address: u32,
+	len: usize,
+	width: usize,
+}
+
+/// STLink V2-1 probe connected to a simulated target
+/// Implements `Transport` by answering the STLink protocol in process.
+pub struct SimProbe {
+	target: Target,
+
+	/// JTAG firmware version
+	jtag: u16,
+
+	/// Current STLink mode
+	mode: u8,
+	/// Answer to the last command, returned by the next `read_data`
+	response: Vec<u8>,
+	/// Write command waiting for its data
+	pending: Option<PendingWrite>,
+
+	/// Status of the last memory transfer
+	rw_status: u8,
+	/// Address of the last memory fault
+	fault_address: u32,
+
+	/// Target voltage
+	voltage: f32,
+	/// Number of debug register accesses still to answer with WAIT
+	waits: usize,
+
+	/// Every command received, in order
+	pub commands: Vec<Vec<u8>>,
+}
+
+impl SimProbe {
+	/// New probe connected to `target`
+	pub fn new(target: Target) -> Self {
+		Self::with_firmware(target, JTAG)
+	}
+
+	/// New probe with the JTAG firmware version `jtag` connected to `target`
+	/// Older firmwares lack some commands, such as the 16 bit memory accesses
+	pub fn with_firmware(target: Target, jtag: u16) -> Self {
+		Self {
+			target,
+
+			jtag,
+
+			mode: modes::DFU,
+			response: Vec::new(),
+			pending: None,
+
+			rw_status: debug::ERR_OK,
+			fault_address: 0,
+
+			voltage: 3.3,
+			waits: 0,
+
+			commands: Vec::new(),
+		}
+	}
+
+	/// Model of the simulated probe
+	pub fn model() -> &'static ProbeModel {
+		model::find(model::VID, PID).expect("The simulated probe model is missing from the model table")
+	}
+
+	/// Simulated target
+	pub fn target(&self) -> &Target {
+		&self.target
+	}
+
+	/// Mutable access to the simulated target
+	pub fn target_mut(&mut self) -> &mut Target {
+		&mut self.target
+	}
+
+	/// Set the target voltage measured by the probe
+	pub fn set_voltage(&mut self, volts: f32) {
+		self.voltage = volts;
+	}
+
+	/// Answer the next `n` debug register accesses with an AP WAIT, as a busy AP
+	/// The accesses answered with WAIT are not executed
+	pub fn answer_wait(&mut self, n: usize) {
+		self.waits = n;
+	}
+
+	/// Answer with a status byte padded to `len` bytes
+	fn status(&mut self, status: u8, len: usize) {
+		self.response = vec![0; len];
+		self.response[0] = status;
+	}
+
+	/// Decode and execute a command
+	fn execute(&mut self, cmd: &[u8]) {
+		self.response.clear();
+
+		match cmd[0] {
+			GET_VERSION => {
+				self.response = vec![0; 6];
+				buf_write_u16(&mut self.response, 0, (2 << 12) | (self.jtag << 6) | MSD, false);
+				buf_write_u16(&mut self.response, 2, model::VID, true);
+				buf_write_u16(&mut self.response, 4, PID, true);
+			},
+
+			GET_CURRENT_MODE => self.response = vec![self.mode, 0],
+
+			GET_TARGET_VOLTAGE => {
+				// Reference and VAPP readings, the voltage is 2.4 * VAPP / reference
+				self.response = vec![0; 8];
+				buf_write_u32(&mut self.response, 0, 1000, true);
+				buf_write_u32(&mut self.response, 4, (self.voltage * 1000.0 / 2.4).round() as u32, true);
+			},
+
+			dfu::COMMAND => match cmd.get(1) {
+				Some(&dfu::EXIT) => self.mode = modes::MASS,
+				_ => warn!("Simulated probe. Unknown DFU command {:02X?}", cmd),
+			},
+
+			debug::DEBUG_COMMAND if cmd.len() > 1 => self.debug(cmd),
+
+			_ => warn!("Simulated probe. Unknown command {:02X?}", cmd),
+		}
+	}
+
+	/// Execute a debug command
+	fn debug(&mut self, cmd: &[u8]) {
+		let arg32 = |offset: usize| if cmd.len() >= offset + 4 { buf_read_u32(cmd, offset, true) } else { 0 };
+
+		match cmd[1] {
+			debug::EXIT => self.mode = modes::MASS,
+
+			apiv2::ENTER => {
+				self.mode = modes::DEBUG;
+				self.status(debug::ERR_OK, 2);
+			},
+
+			apiv2::READ_IDCODES => {
+				self.status(debug::ERR_OK, 12);
+				buf_write_u32(&mut self.response, 4, DP_IDCODE, true);
+			},
+
+			debug::GETSTATUS => {
+				let state = if self.target.halted() { 0x81 } else { 0x80 };
+				self.response = vec![state, 0];
+			},
+
+			apiv2::RESETSYS => {
+				self.target.reset();
+				self.status(debug::ERR_OK, 2);
+			},
+
+			apiv2::DRIVE_NRST => {
+				if cmd.get(2) == Some(&apiv2::DRIVE_NRST_PULSE) {
+					self.target.reset();
+				}
+				self.status(debug::ERR_OK, 2);
+			},
+
+			apiv2::SWD_SET_FREQ | apiv2::JTAG_SET_FREQ => self.status(debug::ERR_OK, 2),
+
+			apiv2::READREG => {
+				let n = cmd.get(2).cloned().unwrap_or(0) as usize;
+				match self.target.regs.get(n) {
+					Some(&value) => {
+						self.status(debug::ERR_OK, 8);
+						buf_write_u32(&mut self.response, 4, value, true);
+					},
+					None => self.status(swd::AP_ERROR, 8),
+				}
+			},
+
+			apiv2::WRITEREG => {
+				let n = cmd.get(2).cloned().unwrap_or(0) as usize;
+				let value = arg32(3);
+				match n < self.target.regs.len() {
+					true => {
+						self.target.regs[n] = value;
+						self.status(debug::ERR_OK, 2);
+					},
+					false => self.status(swd::AP_ERROR, 2),
+				}
+			},
+
+			apiv2::READALLREGS => {
+				self.status(debug::ERR_OK, 88);
+				for (i, r) in self.target.regs.clone().iter().enumerate() {
+					buf_write_u32(&mut self.response, 4 + i * 4, *r, true);
+				}
+			},
+
+			apiv2::READDEBUGREG | apiv2::WRITEDEBUGREG if self.waits > 0 => {
+				self.waits -= 1;
+				self.status(swd::AP_WAIT, if cmd[1] == apiv2::READDEBUGREG { 8 } else { 2 });
+			},
+
+			apiv2::READDEBUGREG => match self.target.read(arg32(2), 4) {
+				Ok(value) => {
+					self.status(debug::ERR_OK, 8);
+					buf_write_u32(&mut self.response, 4, value, true);
+				},
+				Err(_) => self.status(swd::AP_FAULT, 8),
+			},
+
+			apiv2::WRITEDEBUGREG => match self.target.write(arg32(2), arg32(6), 4) {
+				Ok(_) => self.status(debug::ERR_OK, 2),
+				Err(_) => self.status(swd::AP_FAULT, 2),
+			},
+
+			apiv2::GETLASTRWSTATUS => {
+				self.status(self.rw_status, 2);
+				self.rw_status = debug::ERR_OK;
+			},
+
+			apiv2::GETLASTRWSTATUS2 => {
+				self.status(self.rw_status, 12);
+				buf_write_u32(&mut self.response, 4, self.fault_address, true);
+				self.rw_status = debug::ERR_OK;
+			},
+
+			debug::READMEM_8BIT  => self.read_mem(cmd, 1),
+			apiv2::READMEM_16BIT if self.jtag >= 26 => self.read_mem(cmd, 2),
+			debug::READMEM_32BIT => self.read_mem(cmd, 4),
+
+			debug::WRITEMEM_8BIT  => self.write_mem(cmd, 1),
+			apiv2::WRITEMEM_16BIT if self.jtag >= 26 => self.write_mem(cmd, 2),
+			debug::WRITEMEM_32BIT => self.write_mem(cmd, 4),
+
+			_ => warn!("Simulated probe. Unknown debug command {:02X?}", cmd),
+		}
+	}
+
+	/// Decode the address and length of a memory command
+	fn mem_args(cmd: &[u8]) -> (u32, usize) {
+		match cmd.len() {
+			8..=31 => (buf_read_u32(cmd, 2, true), buf_read_u16(cmd, 6, true) as usize),
+			_ => (0, 0),
+		}
+	}
+
+	/// Check that a memory transfer is valid for the probe
+	fn check_transfer(&mut self, address: u32, len: usize, width: usize) -> bool {
+		let max = match width {
+			1 => Self::model().max_rw8,
+			_ => 4096,
+		};
+
+		if len > max || !(address as usize).is_multiple_of(width) || !len.is_multiple_of(width) {
+			self.rw_status = swd::AP_ERROR;
+			self.fault_address = address;
+			return false;
+		}
+
+		true
+	}
+
+	/// Execute a memory read
+	fn read_mem(&mut self, cmd: &[u8], width: usize) {
+		let (address, len) = Self::mem_args(cmd);
+
+		// A single byte read is answered with two bytes
+		self.response = vec![0; if len == 1 { 2 } else { len }];
+
+		if !self.check_transfer(address, len, width) {
+			return;
+		}
+
+		for offset in (0..len).step_by(width) {
+			match self.target.read(address + offset as u32, width) {
+				Ok(value) => for i in 0..width {
+					self.response[offset + i] = (value >> (8 * i)) as u8;
+				},
+				Err(f) => {
+					self.rw_status = swd::AP_FAULT;
+					self.fault_address = f.address;
+					return;
+				},
+			}
+		}
+	}
+
+	/// Prepare a memory write, executed when the data arrives
+	fn write_mem(&mut self, cmd: &[u8], width: usize) {
+		let (address, len) = Self::mem_args(cmd);
+
+		self.pending = Some(PendingWrite { address, len, width });
+	}
+
+	/// Execute a memory write with its data
+	fn write_data_stage(&mut self, write: PendingWrite, data: &[u8]) {
+		if !self.check_transfer(write.address, data.len(), write.width) {
+			return;
+		}
+
+		if data.len() != write.len {
+			warn!("Simulated probe. Write of {} bytes announced, {} received", write.len, data.len());
+		}
+
+		for offset in (0..data.len()).step_by(write.width) {
+			let value = data[offset..offset + write.width].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32);
+
+			match self.target.write(write.address + offset as u32, value, write.width) {
+				Ok(_) => (),
+				Err(f) => {
+					self.rw_status = swd::AP_FAULT;
+					self.fault_address = f.address;
+					return;
+				},
+			}
+		}
+	}
+}
+
+impl Transport for SimProbe {
+	fn write_command(&mut self, cmd: &[u8], _: Duration) -> Result<usize, LinkError> {
+		self.commands.push(cmd.to_vec());
+
+		if cmd.is_empty() {
+			return Ok(0);
+		}
+
+		self.pending = None;
+		self.execute(cmd);
+
+		Ok(cmd.len())
+	}
+
+	fn write_data(&mut self, data: &[u8], _: Duration) -> Result<usize, LinkError> {
+		match self.pending.take() {
+			Some(write) => {
+				self.write_data_stage(write, data);
+				Ok(data.len())
+			},
+			None => {
+				warn!("Simulated probe. Received {} bytes of data without a write command", data.len());
+				Err(LinkError::Usb(rusb::Error::Pipe))
+			},
+		}
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		// A real probe does not answer when there is nothing to send
+		if self.response.is_empty() {
+			return Err(LinkError::Usb(rusb::Error::Timeout));
+		}
+
+		let n = std::cmp::min(buf.len(), self.response.len());
+		buf[..n].copy_from_slice(&self.response[..n]);
+		self.response.clear();
+
+		Ok(n)
+	}
+
+	fn read_trace(&mut self, _: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		Ok(0)
+	}
+}
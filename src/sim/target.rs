@@ -0,0 +1,443 @@
+//! Simulated Cortex-M target
+
+use crate::link::constants::registers::{ dcb::{ DHCSREG, DCRSREG, DCRDREG, DEMCREG, dhcsr, demcr }, nvic::{ register::AIRCR, aircr } };
+use crate::link::constants::address::STM32::{ CPUID, DBGMCU_IDCODE, DBGMCU_IDCODE_M0 };
+
+use super::flash::{ FlashController, FlashF4, FlashF1, FlashL0, FlashL4 };
+use super::cpu::{ self, Exec };
+
+
+/// Maximum number of instructions executed when the core resumes
+const STEP_LIMIT: usize = 1 << 22;
+
+
+/// A bus access to an address that is not mapped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusFault {
+	pub address: u32,
+}
+
+/// Block of plain memory
+#[derive(Debug, Clone)]
+pub struct Region {
+	pub base: u32,
+	pub data: Vec<u8>,
+}
+
+impl Region {
+	/// New region of `size` bytes filled with `fill`
+	pub fn new(base: u32, size: usize, fill: u8) -> Self {
+		Self { base, data: vec![fill; size] }
+	}
+
+	fn contains(&self, address: u32, width: usize) -> bool {
+		address >= self.base && (address - self.base) as usize + width <= self.data.len()
+	}
+}
+
+
+/// Simulated STM32 target: a Cortex-M core with its debug registers,
+/// plain memory regions and a flash controller
+pub struct Target {
+	/// Plain memory regions (SRAM, system memory, ...)
+	pub regions: Vec<Region>,
+	flash: Box<dyn FlashController + Send>,
+
+	/// Core registers in the STLink order: r0-r15, xPSR, MSP, PSP, RW, RW2
+	pub regs: [u32; 21],
+
+	/// DHCSR control bits (C_DEBUGEN, C_HALT, C_STEP, C_MASKINTS)
+	dhcsr: u32,
+	/// DHCSR S_RESET_ST flag, cleared on read
+	reset_st: bool,
+	halted: bool,
+
+	demcr: u32,
+	dcrdr: u32,
+
+	cpuid: u32,
+	idcode: u32,
+
+	/// Number of system resets since the creation of the target
+	pub resets: usize,
+}
+
+impl Target {
+	/// New target with only the system control space mapped
+	pub fn new(cpuid: u32, idcode: u32, flash: Box<dyn FlashController + Send>) -> Self {
+		Self {
+			// Registers of the system control space without a simulated behaviour
+			regions: vec![Region::new(0xE000_E000, 0x1000, 0)],
+			flash,
+
+			regs: [0; 21],
+
+			dhcsr: 0,
+			reset_st: false,
+			halted: false,
+
+			demcr: 0,
+			dcrdr: 0,
+
+			cpuid,
+			idcode,
+
+			resets: 0,
+		}
+	}
+
+	/// STM32F407 with 1 MB of flash and 192 kB of SRAM
+	pub fn stm32f407() -> Self {
+		let mut new = Self::new(0x410F_C241, 0x1007_6413, Box::new(FlashF4::new()));
+
+		new.regions.push(Region::new(0x2000_0000, 0x30000, 0));
+		new.regions.push(Region::new(0x1FFF_0000, 0x7800, 0));
+
+		// OTP, unique ID and flash size register
+		let mut otp = Region::new(0x1FFF_7800, 0x230, 0xFF);
+		otp.data[0x222] = 0x00;
+		otp.data[0x223] = 0x04;
+		new.regions.push(otp);
+
+		new
+	}
+
+	/// STM32F103 medium-density with 128 kB of flash in 1 kB pages and 20 kB of SRAM
+	pub fn stm32f103() -> Self {
+		Self::stm32f1(0x2000_6410, FlashF1::new(), 0x5000)
+	}
+
+	/// STM32F103 XL-density with 1 MB of flash in two banks and 96 kB of SRAM
+	pub fn stm32f103xg() -> Self {
+		Self::stm32f1(0x1000_6430, FlashF1::xl(), 0x18000)
+	}
+
+	/// STM32F1 with the chip ID code `idcode`, the given flash controller and `sram` bytes of SRAM
+	pub fn stm32f1(idcode: u32, flash: FlashF1, sram: usize) -> Self {
+		let kb = (flash.memory().end - flash.memory().start) / 1024;
+		let mut new = Self::new(0x411F_C231, idcode, Box::new(flash));
+
+		new.regions.push(Region::new(0x2000_0000, sram, 0));
+
+		// System memory, ending with the flash size register and the unique ID
+		let mut system = Region::new(0x1FFF_E000, 0x1800, 0);
+		system.data[0x17E0] = kb as u8;
+		system.data[0x17E1] = (kb >> 8) as u8;
+		new.regions.push(system);
+
+		new
+	}
+
+	/// STM32L053 with 64 kB of flash, 2 kB of data EEPROM and 8 kB of SRAM
+	pub fn stm32l053() -> Self {
+		Self::stm32l0(0x410C_C601, 0x1000_6417, FlashL0::l053(), 0x2000, 0x7C)
+	}
+
+	/// STM32L152 medium-density with 128 kB of flash, 4 kB of data EEPROM and 16 kB of SRAM
+	pub fn stm32l152() -> Self {
+		Self::stm32l0(0x412F_C230, 0x1018_6416, FlashL0::l152(), 0x4000, 0x4C)
+	}
+
+	/// STM32L0/L1 with the given core, chip ID code, controller and `sram` bytes of SRAM
+	/// `size_reg` is the offset of the flash size register in the factory information block
+	pub fn stm32l0(cpuid: u32, idcode: u32, flash: FlashL0, sram: usize, size_reg: usize) -> Self {
+		let kb = flash.size() / 1024;
+		let mut new = Self::new(cpuid, idcode, Box::new(flash));
+
+		new.regions.push(Region::new(0x2000_0000, sram, 0));
+		new.regions.push(Region::new(0x1FF0_0000, 0x1000, 0));
+
+		// Factory information with the flash size register
+		let mut factory = Region::new(0x1FF8_0000, 0x100, 0);
+		factory.data[size_reg] = kb as u8;
+		factory.data[size_reg + 1] = (kb >> 8) as u8;
+		new.regions.push(factory);
+
+		new
+	}
+
+	/// STM32L476 with 1 MB of flash in two banks and 96 kB of SRAM
+	pub fn stm32l476() -> Self {
+		Self::stm32l4(0x410F_C241, 0x1007_6415, FlashL4::l476(), 0x18000)
+	}
+
+	/// STM32G071 with 128 kB of flash and 36 kB of SRAM
+	pub fn stm32g071() -> Self {
+		Self::stm32l4(0x410C_C601, 0x1000_6460, FlashL4::g071(), 0x9000)
+	}
+
+	/// STM32WB55 with 1 MB of flash, the top of it used by CPU2, and 256 kB of SRAM
+	pub fn stm32wb55() -> Self {
+		Self::stm32l4(0x410F_C241, 0x2001_6495, FlashL4::wb55(), 0x40000)
+	}
+
+	/// STM32L4/G0/WB with the given core, chip ID code, controller and `sram` bytes of SRAM
+	pub fn stm32l4(cpuid: u32, idcode: u32, flash: FlashL4, sram: usize) -> Self {
+		let kb = (flash.memory().end - flash.memory().start) / 1024;
+		let mut new = Self::new(cpuid, idcode, Box::new(flash));
+
+		new.regions.push(Region::new(0x2000_0000, sram, 0));
+
+		// System memory, OTP and the flash size register
+		let mut system = Region::new(0x1FFF_0000, 0x7800, 0);
+		system.data[0x75E0] = kb as u8;
+		system.data[0x75E1] = (kb >> 8) as u8;
+		new.regions.push(system);
+
+		new
+	}
+
+	/// Check if the core is halted
+	pub fn halted(&self) -> bool {
+		self.halted
+	}
+
+	/// Program counter
+	pub fn pc(&self) -> u32 {
+		self.regs[15]
+	}
+
+	/// Access to the flash controller
+	pub fn flash(&mut self) -> &mut (dyn FlashController + Send) {
+		&mut *self.flash
+	}
+
+	/// Load `data` at `address` without side effects
+	/// Flash memory is written directly, without going through the controller
+	pub fn load(&mut self, address: u32, data: &[u8]) {
+		if self.flash_contains(address) {
+			self.flash.load(address, data);
+			return;
+		}
+
+		match self.regions.iter_mut().find(|r| r.contains(address, data.len())) {
+			Some(r) => {
+				let offset = (address - r.base) as usize;
+				r.data[offset..offset + data.len()].copy_from_slice(data);
+			},
+			None => panic!("Simulated target. Cannot load {} bytes at 0x{:08X}", data.len(), address),
+		}
+	}
+
+	/// Read `n` bytes at `address` without side effects
+	pub fn peek(&self, address: u32, n: usize) -> Vec<u8> {
+		(0..n as u32).map(|i| {
+			let a = address + i;
+			if self.flash_contains(a) {
+				self.flash.read(a)
+			} else {
+				match self.regions.iter().find(|r| r.contains(a, 1)) {
+					Some(r) => r.data[(a - r.base) as usize],
+					None => panic!("Simulated target. Cannot peek at 0x{:08X}", a),
+				}
+			}
+		}).collect()
+	}
+
+	/// System reset
+	/// The core fetches its stack pointer and entry point from the vector table
+	/// at the start of the flash and halts if the reset vector catch is enabled
+	pub fn reset(&mut self) {
+		let start = self.flash.memory().start;
+		let vector = self.peek(start, 8);
+		let word = |i: usize| (vector[i] as u32) | (vector[i+1] as u32) << 8 | (vector[i+2] as u32) << 16 | (vector[i+3] as u32) << 24;
+
+		self.regs = [0; 21];
+		self.regs[13] = word(0);
+		self.regs[17] = word(0);
+		self.regs[14] = 0xFFFF_FFFF;
+		self.regs[15] = word(4) & !1;
+		self.regs[16] = 0x0100_0000;
+
+		self.flash.reset();
+
+		let debug = self.dhcsr & dhcsr::C_DEBUGEN != 0;
+		if debug && self.demcr & demcr::VC_CORERESET != 0 {
+			self.dhcsr |= dhcsr::C_HALT;
+		}
+		self.halted = debug && self.dhcsr & dhcsr::C_HALT != 0;
+
+		self.reset_st = true;
+		self.resets += 1;
+	}
+
+	/// Bus read of `width` bytes
+	pub fn read(&mut self, address: u32, width: usize) -> Result<u32, BusFault> {
+		let fault = BusFault { address };
+
+		if !(address as usize).is_multiple_of(width) {
+			return Err(fault);
+		}
+
+		// Core debug and system control registers
+		match address & !3 {
+			DHCSREG => {
+				let mut value = self.dhcsr | dhcsr::S_REGRDY;
+				if self.halted { value |= dhcsr::S_HALT; }
+				if self.reset_st { value |= dhcsr::S_RESET_ST; }
+				self.reset_st = false;
+				return Ok(sub(value, address, width));
+			},
+			DCRDREG => return Ok(sub(self.dcrdr, address, width)),
+			DEMCREG => return Ok(sub(self.demcr, address, width)),
+			CPUID => return Ok(sub(self.cpuid, address, width)),
+			DBGMCU_IDCODE => return Ok(sub(self.idcode, address, width)),
+			// The DBGMCU of the Cortex-M0/M0+ is on the peripheral bus
+			DBGMCU_IDCODE_M0 if self.m0() => return Ok(sub(self.idcode, address, width)),
+			_ => (),
+		}
+
+		if self.flash.registers().contains(&address) {
+			let base = self.flash.registers().start;
+			let value = self.flash.read_reg((address & !3) - base);
+			return Ok(sub(value, address, width));
+		}
+
+		// The debug port cannot read a read protected flash
+		if self.flash.memory().contains(&address) && self.flash.read_protected() {
+			return Err(fault);
+		}
+
+		if self.flash_contains(address) {
+			let bytes = (0..width as u32).map(|i| self.flash.read(address + i)).collect::<Vec<_>>();
+			return Ok(bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32));
+		}
+
+		match self.regions.iter().find(|r| r.contains(address, width)) {
+			Some(r) => {
+				let offset = (address - r.base) as usize;
+				Ok(r.data[offset..offset + width].iter().rev().fold(0, |acc, &b| (acc << 8) | b as u32))
+			},
+			None => Err(fault),
+		}
+	}
+
+	/// Bus write of the `width` low bytes of `value`
+	pub fn write(&mut self, address: u32, value: u32, width: usize) -> Result<(), BusFault> {
+		let fault = BusFault { address };
+
+		if !(address as usize).is_multiple_of(width) {
+			return Err(fault);
+		}
+
+		match address {
+			DHCSREG if width == 4 => {
+				self.write_dhcsr(value);
+				return Ok(());
+			},
+			DCRSREG if width == 4 => {
+				let sel = (value & 0x7F) as usize;
+				if sel < self.regs.len() {
+					match value & (1 << 16) {
+						0 => self.dcrdr = self.regs[sel],
+						_ => self.regs[sel] = self.dcrdr,
+					}
+				}
+				return Ok(());
+			},
+			DCRDREG if width == 4 => {
+				self.dcrdr = value;
+				return Ok(());
+			},
+			DEMCREG if width == 4 => {
+				self.demcr = value;
+				return Ok(());
+			},
+			AIRCR if width == 4 => {
+				if value & 0xFFFF_0000 == aircr::VECTKEY && value & aircr::SYSRESETREQ != 0 {
+					self.reset();
+				}
+				return Ok(());
+			},
+			_ => (),
+		}
+
+		if self.flash.registers().contains(&address) {
+			let base = self.flash.registers().start;
+			self.flash.write_reg(address - base, value);
+			return Ok(());
+		}
+
+		if self.flash_contains(address) {
+			self.flash.program(address, value, width);
+			return Ok(());
+		}
+
+		match self.regions.iter_mut().find(|r| r.contains(address, width)) {
+			Some(r) => {
+				let offset = (address - r.base) as usize;
+				for i in 0..width {
+					r.data[offset + i] = (value >> (8 * i)) as u8;
+				}
+				Ok(())
+			},
+			None => Err(fault),
+		}
+	}
+
+	/// Check if the flash memory or the option bytes of the controller hold `address`
+	fn flash_contains(&self, address: u32) -> bool {
+		self.flash.memory().contains(&address) || self.flash.options().contains(&address)
+	}
+
+	/// Check if the core is a Cortex-M0 or M0+
+	fn m0(&self) -> bool {
+		matches!((self.cpuid >> 4) & 0xFFF, 0xC20 | 0xC60)
+	}
+
+	/// Write the debug halting control and status register
+	fn write_dhcsr(&mut self, value: u32) {
+		// Writes without the key are ignored
+		if value & 0xFFFF_0000 != dhcsr::DBGKEY {
+			return;
+		}
+
+		self.dhcsr = value & (dhcsr::C_DEBUGEN | dhcsr::C_HALT | dhcsr::C_STEP | dhcsr::C_MASKINTS);
+
+		match (self.dhcsr & dhcsr::C_DEBUGEN, self.dhcsr & dhcsr::C_HALT, self.dhcsr & dhcsr::C_STEP) {
+			(0, _, _) | (_, 0, 0) => if self.halted {
+				self.resume();
+			},
+			(_, 0, _) if self.halted => self.step(),
+			(_, 0, _) => self.halted = false,
+			_ => self.halted = true,
+		}
+	}
+
+	/// Execute instructions from the PC until a breakpoint halts the core
+	/// Nothing more is simulated after a fault or `STEP_LIMIT` instructions, the
+	/// core stays running as if locked up or in an endless loop.
+	fn resume(&mut self) {
+		self.halted = false;
+
+		for _ in 0..STEP_LIMIT {
+			match cpu::execute(self) {
+				Exec::Next => (),
+				// Without halting debug, a breakpoint is a fault
+				Exec::Breakpoint if self.dhcsr & dhcsr::C_DEBUGEN != 0 => {
+					self.dhcsr |= dhcsr::C_HALT;
+					self.halted = true;
+					return;
+				},
+				_ => return,
+			}
+		}
+	}
+
+	/// Execute one instruction
+	/// The PC is only advanced over an instruction that cannot be executed
+	fn step(&mut self) {
+		if cpu::execute(self) == Exec::Fault {
+			self.regs[15] = self.regs[15].wrapping_add(2);
+		}
+	}
+}
+
+/// Extract the `width` bytes at `address` of the register `value`
+fn sub(value: u32, address: u32, width: usize) -> u32 {
+	let shift = 8 * (address & 3);
+	match width {
+		4 => value,
+		w => (value >> shift) & ((1u32 << (8 * w)) - 1),
+	}
+}
@@ -1,5 +1,12 @@
 //! USB utilities
 
+use rusb::{ Context, Device, DeviceDescriptor, DeviceHandle, UsbContext };
+
+use crate::link::enums::ProbeSelector;
+
+pub mod model;
+pub mod transport;
+
 pub const STLINK_USB_VID_ST               : u32 = 0x0483;
 pub const STLINK_USB_PID_STLINK           : u32 = 0x3744;
 pub const STLINK_USB_PID_STLINK_32L       : u32 = 0x3748;
@@ -19,49 +26,95 @@ pub struct Endpoint {
 }
 
 
-/// Analize the USB ports for an On-board STLink
-pub fn search<'a>(ctx: &'a libusb::Context) -> Option<(libusb::Device<'a>, libusb::DeviceDescriptor, libusb::DeviceHandle<'a>)> {
-
-
+/// List the STLink devices connected to the USB ports
+pub fn devices(ctx: &Context) -> Vec<(Device<Context>, DeviceDescriptor)> {
 	let devices = match ctx.devices() {
 		Ok(d) => d,
 		Err(e) => {
 			error!("USB Error: Could not generate USB devices list.\nError: {}", e);
-			return None;
+			return Vec::new();
 		},
 	};
 
-	for device in devices.iter() {
-		let desc = match device.device_descriptor() {
-			Ok(d) => d,
+	devices.iter()
+		.filter_map(|device| match device.device_descriptor() {
+			Ok(desc) => Some((device, desc)),
 			Err(e) => {
 				error!("USB Error: Could not get device descriptor.\nError: {}", e);
-				continue;
+				None
 			},
-		};
+		})
+		.filter(|(_, desc)| match model::find(desc.vendor_id(), desc.product_id()) {
+			Some(_) => true,
+			None if desc.vendor_id() == model::VID => {
+				warn!("USB Warning: Found a ST device that is not a known STLink (PID 0x{:04X})", desc.product_id());
+				false
+			},
+			None => false,
+		})
+		.collect()
+}
+
+/// Read the serial number string of an opened device
+pub fn serial(handle: &DeviceHandle<Context>, desc: &DeviceDescriptor) -> Option<String> {
+	let timeout = std::time::Duration::from_millis(100);
+
+	let language = match handle.read_languages(timeout) {
+		Ok(l) => *l.first()?,
+		Err(e) => {
+			warn!("USB Warning: Could not read the string descriptor languages.\nError: {}", e);
+			return None;
+		},
+	};
+
+	match handle.read_serial_number_string(language, desc, timeout) {
+		Ok(s) => Some(s),
+		Err(e) => {
+			warn!("USB Warning: Could not read the serial number.\nError: {}", e);
+			None
+		},
+	}
+}
 
-		match desc.vendor_id() {
-			0x0483 => (),
-			_ => continue,
+/// Analize the USB ports for an On-board STLink
+/// Returns the device chosen by `selector`
+pub fn search(ctx: &Context, selector: &ProbeSelector) -> Option<(Device<Context>, DeviceDescriptor, DeviceHandle<Context>)> {
+	for (i, (device, desc)) in devices(ctx).into_iter().enumerate() {
+		match *selector {
+			ProbeSelector::Index(n) if n != i => continue,
+			ProbeSelector::Path { bus, address } if bus != device.bus_number() || address != device.address() => continue,
+			ProbeSelector::Port { bus, ref ports } if bus != device.bus_number() || device.port_numbers().ok().as_ref() != Some(ports) => continue,
+			_ => (),
 		}
 
-		match desc.product_id() {
-			0x3744 | 0x3748 | 0x374a | 0x374b => match device.open() {
-				Ok(handle) => {
-					info!("Found STLink Device");
-					return Some((device, desc, handle));
-				},
-				Err(e) => {
-					error!("USB Error: Could not open device.\nError: {}", e);
-					continue;
-				},
+		match model::find(desc.vendor_id(), desc.product_id()) {
+			Some(m) if m.has(model::caps::LOADER) => {
+				warn!("USB Warning: {} is in its USB loader and cannot be used for debugging", m.name);
+				continue;
 			},
-			_ => warn!("USB Warning: Found a ST device that is not an on board STLink"),
+			_ => (),
+		}
+
+		match device.open() {
+			Ok(handle) => {
+				if let ProbeSelector::Serial(ref wanted) = *selector {
+					match serial(&handle, &desc) {
+						Some(ref s) if s == wanted => (),
+						_ => continue,
+					}
+				}
 
+				info!("Found STLink Device");
+				return Some((device, desc, handle));
+			},
+			Err(e) => {
+				error!("USB Error: Could not open device.\nError: {}", e);
+				continue;
+			},
 		}
 	}
 
-	warn!(target: "critical", "Could not find STLINK device.");
+	warn!(target: "critical", "Could not find STLINK device matching {:?}.", selector);
 
 	None
-}
\ No newline at end of file
+}
@@ -0,0 +1,106 @@
+//! Table of the known STLink probe models
+//! Every USB product ID recognised by `rustylink` is described here. The
+//! enumeration and the connection code only use this table.
+
+use crate::link::constants::misc::{ ENDPOINT::{ ENDPOINT_IN, ENDPOINT_OUT }, V1, V2, V2_1, V3, V3_2, V3E, V3S };
+
+/// ST Microelectronics USB vendor ID
+pub const VID: u16 = 0x0483;
+
+/// Capabilities of the probe hardware
+/// The features that depend on the firmware version are in `constants::flags`
+pub mod caps {
+	/// Can debug STM8 devices through SWIM
+	pub const SWIM   : u32 = 1 << 0;
+	/// Has a trace (SWO) endpoint
+	pub const TRACE  : u32 = 1 << 1;
+	/// Exposes a mass storage device for drag and drop programming
+	pub const MSD    : u32 = 1 << 2;
+	/// Has a bridge interface (SPI, I2C, CAN, GPIO)
+	pub const BRIDGE : u32 = 1 << 3;
+	/// The probe is in its USB bootloader and cannot debug
+	pub const LOADER : u32 = 1 << 4;
+}
+
+/// Description of a STLink probe model
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProbeModel {
+	/// USB product ID
+	pub pid: u16,
+	/// Commercial name
+	pub name: &'static str,
+	/// Major STLink version
+	pub stlink: usize,
+	/// Command and data OUT endpoint
+	pub tx: u8,
+	/// Data IN endpoint
+	pub rx: u8,
+	/// Trace IN endpoint
+	pub trace: Option<u8>,
+	/// Maximum number of bytes of a single 8 bit memory transfer
+	pub max_rw8: usize,
+	/// Hardware capabilities, see `caps`
+	pub caps: u32,
+}
+
+impl ProbeModel {
+	/// Check if the model has all the capabilities in `caps`
+	pub fn has(&self, caps: u32) -> bool {
+		self.caps & caps == caps
+	}
+}
+
+/// All the known probe models
+pub const MODELS: [ProbeModel; 11] = [
+	ProbeModel { pid: V1::PID, name: "STLink V1", stlink: 1,
+		tx: 2 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: None,
+		max_rw8: V1::MAX_RW, caps: caps::SWIM | caps::MSD },
+
+	ProbeModel { pid: V2::PID, name: "STLink V2", stlink: 2,
+		tx: 2 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(3 | ENDPOINT_IN),
+		max_rw8: V2::MAX_RW, caps: caps::SWIM | caps::TRACE },
+
+	ProbeModel { pid: 0x374A, name: "STLink V2 (32L audio)", stlink: 2,
+		tx: 2 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(3 | ENDPOINT_IN),
+		max_rw8: V2::MAX_RW, caps: caps::TRACE },
+
+	ProbeModel { pid: V2_1::STLINK_V2_1_PID, name: "STLink V2-1", stlink: 2,
+		tx: V2_1::STLINK_V2_1_TX_EP, rx: 1 | ENDPOINT_IN, trace: Some(V2_1::STLINK_V2_1_TRACE_EP),
+		max_rw8: V2_1::MAX_RW, caps: caps::TRACE | caps::MSD },
+
+	ProbeModel { pid: V2_1::STLINK_V2_1_NO_MSD_PID, name: "STLink V2-1 (no MSD)", stlink: 2,
+		tx: V2_1::STLINK_V2_1_TX_EP, rx: 1 | ENDPOINT_IN, trace: Some(V2_1::STLINK_V2_1_TRACE_EP),
+		max_rw8: V2_1::MAX_RW, caps: caps::TRACE },
+
+	ProbeModel { pid: V3::USBLOADER_PID, name: "STLink V3 (USB loader)", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::LOADER },
+
+	ProbeModel { pid: V3E::PID, name: "STLink V3E", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::TRACE | caps::MSD },
+
+	ProbeModel { pid: V3S::PID, name: "STLink V3S", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::SWIM | caps::TRACE | caps::MSD | caps::BRIDGE },
+
+	ProbeModel { pid: V3_2::PID, name: "STLink V3 (2 VCP)", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::SWIM | caps::TRACE | caps::MSD | caps::BRIDGE },
+
+	ProbeModel { pid: 0x3754, name: "STLink V3 (no MSD)", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::TRACE | caps::BRIDGE },
+
+	ProbeModel { pid: 0x3757, name: "STLink V3PWR", stlink: 3,
+		tx: 1 | ENDPOINT_OUT, rx: 1 | ENDPOINT_IN, trace: Some(2 | ENDPOINT_IN),
+		max_rw8: V3::MAX_RW, caps: caps::TRACE | caps::MSD | caps::BRIDGE },
+];
+
+/// Look up the model of a probe by its USB IDs
+pub fn find(vid: u16, pid: u16) -> Option<&'static ProbeModel> {
+	match vid {
+		VID => MODELS.iter().find(|m| m.pid == pid),
+		_ => None,
+	}
+}
@@ -0,0 +1,99 @@
+//! libusb backend of the STLink transport
+
+use std::time::Duration;
+
+use rusb::{ Context, Device, DeviceHandle };
+
+use crate::link::error::LinkError;
+use crate::link::transport::Transport;
+use super::Endpoint;
+
+use super::model::ProbeModel;
+
+
+/// Transport over a libusb device handle
+/// The handle keeps the USB context alive, so the transport can outlive it and be sent to other threads
+pub struct UsbTransport {
+	handle: DeviceHandle<Context>,
+
+	tx: u8,
+	rx: u8,
+	trace: Option<u8>,
+}
+
+impl UsbTransport {
+	/// Claim the debug interface of an opened probe
+	/// The endpoints are taken from the probe `model`, see `debug_interface`
+	pub fn open(device: &Device<Context>, handle: DeviceHandle<Context>, model: &ProbeModel) -> Result<Self, LinkError> {
+		let cfg = device.config_descriptor(0)?;
+
+		let mut eps = Vec::new();
+
+		for interface in cfg.interfaces() {
+			for idesc in interface.descriptors() {
+				for endpoint in idesc.endpoint_descriptors() {
+					eps.push(Endpoint {
+						config: cfg.number(),
+						iface: idesc.interface_number(),
+						setting: idesc.setting_number(),
+						address: endpoint.address(),
+					});
+				}
+			}
+		}
+
+		let (iface, setting) = match debug_interface(&eps, model) {
+			Some(i) => i,
+			None => {
+				error!("{} has no interface with the endpoints 0x{:02X} and 0x{:02X} in its {} endpoints", model.name, model.tx, model.rx, eps.len());
+				return Err(LinkError::Descriptor { tx: model.tx, rx: model.rx });
+			},
+		};
+
+		if let Err(e) = handle.claim_interface(iface) {
+			warn!("Could not claim interface {}: {}", iface, e);
+		}
+		if let Err(e) = handle.set_alternate_setting(iface, setting) {
+			warn!("Could not set alternate setting {}: {}", setting, e);
+		}
+
+		Ok(Self {
+			handle,
+			tx: model.tx,
+			rx: model.rx,
+			trace: model.trace,
+		})
+	}
+}
+
+/// Interface and alternate setting with the command and data endpoints of `model`
+/// Composite probes (V2-1, V3) also expose mass storage, virtual COM port and bridge interfaces.
+pub fn debug_interface(endpoints: &[Endpoint], model: &ProbeModel) -> Option<(u8, u8)> {
+	let has = |iface: u8, setting: u8, address: u8| endpoints.iter()
+		.any(|e| e.iface == iface && e.setting == setting && e.address == address);
+
+	endpoints.iter()
+		.map(|e| (e.iface, e.setting))
+		.find(|&(iface, setting)| has(iface, setting, model.tx) && has(iface, setting, model.rx))
+}
+
+impl Transport for UsbTransport {
+	fn write_command(&mut self, cmd: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		Ok(self.handle.write_bulk(self.tx, cmd, timeout)?)
+	}
+
+	fn write_data(&mut self, data: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		Ok(self.handle.write_bulk(self.tx, data, timeout)?)
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		Ok(self.handle.read_bulk(self.rx, buf, timeout)?)
+	}
+
+	fn read_trace(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		match self.trace {
+			Some(ep) => Ok(self.handle.read_bulk(ep, buf, timeout)?),
+			None => Err(LinkError::Unsupported("trace endpoint")),
+		}
+	}
+}
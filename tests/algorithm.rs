@@ -0,0 +1,365 @@
+//! Flash programming with CMSIS flash algorithms
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, FlashAlgorithm, FlashDevice, SRamInfo };
+use rustylink::sim::{ SimProbe, Target, FlashF1 };
+use common::{ open, pattern };
+
+
+const FLASH: u32 = 0x0800_0000;
+const SRAM: u32 = 0x2000_0000;
+
+const F1_CR: u32 = 0x4002_2010;
+const LOCK: u32 = 1 << 7;
+
+/// Flash algorithm of the 128 kB STM32F103, with the fnc of `Init` kept in its data
+const CODE: [u16; 74] = [
+	// Init
+	0x4649, // mov  r1, r9
+	0x600A, // str  r2, [r1]
+	0x491F, // ldr  r1, =KEYR
+	0x4A20, // ldr  r2, =KEY1
+	0x600A, // str  r2, [r1]
+	0x4A20, // ldr  r2, =KEY2
+	0x600A, // str  r2, [r1]
+	0x2000, // movs r0, #0
+	0x4770, // bx   lr
+	// UnInit
+	0x4649, // mov  r1, r9
+	0x6809, // ldr  r1, [r1]
+	0x4288, // cmp  r0, r1
+	0xD104, // bne  mismatch
+	0x491D, // ldr  r1, =BASE
+	0x2280, // movs r2, #LOCK
+	0x610A, // str  r2, [r1, #CR]
+	0x2000, // movs r0, #0
+	0x4770, // bx   lr
+	// mismatch
+	0x2001, // movs r0, #1
+	0x4770, // bx   lr
+	// EraseSector
+	0x4919, // ldr  r1, =BASE
+	0x2202, // movs r2, #PER
+	0x610A, // str  r2, [r1, #CR]
+	0x6148, // str  r0, [r1, #AR]
+	0x2242, // movs r2, #PER|STRT
+	0x610A, // str  r2, [r1, #CR]
+	0xE004, // b    wait
+	// EraseChip
+	0x4916, // ldr  r1, =BASE
+	0x2204, // movs r2, #MER
+	0x610A, // str  r2, [r1, #CR]
+	0x2244, // movs r2, #MER|STRT
+	0x610A, // str  r2, [r1, #CR]
+	// wait
+	0x68CA, // ldr  r2, [r1, #SR]
+	0x2301, // movs r3, #BSY
+	0x421A, // tst  r2, r3
+	0xD1FB, // bne  wait
+	0x2300, // movs r3, #0
+	0x610B, // str  r3, [r1, #CR]
+	0x60CA, // str  r2, [r1, #SR]
+	0x2014, // movs r0, #PGERR|WRPRTERR
+	0x4010, // ands r0, r2
+	0x4770, // bx   lr
+	// ProgramPage
+	0xB530, // push {r4, r5, lr}
+	0x4B0E, // ldr  r3, =BASE
+	0x2401, // movs r4, #PG
+	0x611C, // str  r4, [r3, #CR]
+	// loop
+	0x8814, // ldrh r4, [r2]
+	0x8004, // strh r4, [r0]
+	// busy
+	0x68DC, // ldr  r4, [r3, #SR]
+	0x2501, // movs r5, #BSY
+	0x422C, // tst  r4, r5
+	0xD1FB, // bne  busy
+	0x2514, // movs r5, #PGERR|WRPRTERR
+	0x422C, // tst  r4, r5
+	0xD105, // bne  error
+	0x3002, // adds r0, #2
+	0x3202, // adds r2, #2
+	0x3902, // subs r1, #2
+	0xD8F2, // bhi  loop
+	0x2000, // movs r0, #0
+	0xE001, // b    done
+	// error
+	0x60DC, // str  r4, [r3, #SR]
+	0x2001, // movs r0, #1
+	// done
+	0x2400, // movs r4, #0
+	0x611C, // str  r4, [r3, #CR]
+	0xBD30, // pop  {r4, r5, pc}
+	// Literals: KEYR, KEY1, KEY2, BASE
+	0x2004, 0x4002,
+	0x0123, 0x4567,
+	0x89AB, 0xCDEF,
+	0x2000, 0x4002,
+];
+
+/// Offsets of the functions in `CODE`
+const FUNCTIONS: [(&str, u32); 5] = [
+	("Init", 0x00),
+	("UnInit", 0x12),
+	("EraseSector", 0x28),
+	("EraseChip", 0x36),
+	("ProgramPage", 0x54),
+];
+
+
+/// Section of the ELF file
+struct Section<'a> {
+	kind: u32,
+	flags: u32,
+	address: u32,
+	contents: &'a [u8],
+	link: u32,
+	entsize: u32,
+}
+
+/// `FlashDevice` descriptor of `size` bytes of flash with the sector runs `sectors`
+fn descriptor(size: u32, page: u32, sectors: &[(u32, u32)]) -> Vec<u8> {
+	let mut d = vec![0; 160];
+	d[0..2].copy_from_slice(&0x0101u16.to_le_bytes());
+	d[2..2 + 13].copy_from_slice(b"STM32F10x Med");
+	d[130..132].copy_from_slice(&1u16.to_le_bytes());
+	d[132..136].copy_from_slice(&FLASH.to_le_bytes());
+	d[136..140].copy_from_slice(&size.to_le_bytes());
+	d[140..144].copy_from_slice(&page.to_le_bytes());
+	d[148] = 0xFF;
+	d[152..156].copy_from_slice(&100u32.to_le_bytes());
+	d[156..160].copy_from_slice(&500u32.to_le_bytes());
+
+	for &(size, offset) in sectors.iter().chain(&[(!0, !0)]) {
+		d.extend_from_slice(&size.to_le_bytes());
+		d.extend_from_slice(&offset.to_le_bytes());
+	}
+
+	d
+}
+
+/// ELF file of the algorithm, with the given functions
+fn flm(functions: &[(&str, u32)]) -> Vec<u8> {
+	let code = CODE.iter().flat_map(|h| h.to_le_bytes().to_vec()).collect::<Vec<_>>();
+	let data = vec![0; 4];
+	let device = descriptor(0x2_0000, 0x400, &[(0x400, 0)]);
+
+	let code_addr = 0;
+	let data_addr = code.len() as u32;
+	let device_addr = data_addr + data.len() as u32;
+
+	// Symbols: null, the functions then the descriptor
+	let mut strtab = vec![0u8];
+	let mut symtab = vec![0u8; 16];
+	let mut symbol = |name: &str, value: u32, info: u8, shndx: u16| {
+		symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+		symtab.extend_from_slice(&value.to_le_bytes());
+		symtab.extend_from_slice(&0u32.to_le_bytes());
+		symtab.extend_from_slice(&[info, 0]);
+		symtab.extend_from_slice(&shndx.to_le_bytes());
+		strtab.extend_from_slice(name.as_bytes());
+		strtab.push(0);
+	};
+	for &(name, offset) in functions.iter() {
+		symbol(name, (code_addr + offset) | 1, 0x12, 1);
+	}
+	symbol("FlashDevice", device_addr, 0x11, 3);
+
+	let names = ["", "PrgCode", "PrgData", "DevDscr", ".symtab", ".strtab", ".shstrtab"];
+	let mut shstrtab = Vec::new();
+	let mut name_offsets = Vec::new();
+	for name in names.iter() {
+		name_offsets.push(shstrtab.len() as u32);
+		shstrtab.extend_from_slice(name.as_bytes());
+		shstrtab.push(0);
+	}
+
+	let sections = [
+		Section { kind: 0, flags: 0, address: 0, contents: &[], link: 0, entsize: 0 },
+		Section { kind: 1, flags: 0x6, address: code_addr, contents: &code, link: 0, entsize: 0 },
+		Section { kind: 1, flags: 0x3, address: data_addr, contents: &data, link: 0, entsize: 0 },
+		Section { kind: 1, flags: 0x2, address: device_addr, contents: &device, link: 0, entsize: 0 },
+		Section { kind: 2, flags: 0, address: 0, contents: &symtab, link: 5, entsize: 16 },
+		Section { kind: 3, flags: 0, address: 0, contents: &strtab, link: 0, entsize: 0 },
+		Section { kind: 3, flags: 0, address: 0, contents: &shstrtab, link: 0, entsize: 0 },
+	];
+
+	let mut file = vec![0u8; 52];
+	let mut headers = Vec::new();
+
+	for (i, section) in sections.iter().enumerate() {
+		let offset = file.len() as u32;
+		file.extend_from_slice(section.contents);
+		file.resize(file.len().div_ceil(4) * 4, 0);
+
+		// The symbols are all global
+		let info = if section.kind == 2 { 1 } else { 0 };
+		let header = [name_offsets[i], section.kind, section.flags, section.address, offset, section.contents.len() as u32, section.link, info, 4, section.entsize];
+		for word in header.iter() {
+			headers.extend_from_slice(&word.to_le_bytes());
+		}
+	}
+
+	let shoff = file.len() as u32;
+	file.extend_from_slice(&headers);
+
+	// ELF32 little endian relocatable file for ARM
+	file[0..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1, 1]);
+	file[16..18].copy_from_slice(&1u16.to_le_bytes());
+	file[18..20].copy_from_slice(&40u16.to_le_bytes());
+	file[20..24].copy_from_slice(&1u32.to_le_bytes());
+	file[32..36].copy_from_slice(&shoff.to_le_bytes());
+	file[40..42].copy_from_slice(&52u16.to_le_bytes());
+	file[46..48].copy_from_slice(&40u16.to_le_bytes());
+	file[48..50].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+	file[50..52].copy_from_slice(&(sections.len() as u16 - 1).to_le_bytes());
+
+	file
+}
+
+fn algorithm() -> FlashAlgorithm {
+	FlashAlgorithm::parse(&flm(&FUNCTIONS)).unwrap()
+}
+
+fn locked(link: &mut Link<SimProbe>) -> bool {
+	link.read_debug_reg(F1_CR).unwrap() & LOCK != 0
+}
+
+/// Program `data` at `address` and check the flash and the progress reports
+fn program(link: &mut Link<SimProbe>, address: u32, data: &[u8]) {
+	let mut last = (0, 0);
+	link.program_flash(address, data, &mut |done, total| last = (done, total)).unwrap();
+
+	assert_eq!(last, (data.len(), data.len()));
+	assert_eq!(link.transport().target().peek(address, data.len()), data);
+
+	// The core stops on the breakpoint the functions return to
+	let target = link.transport().target();
+	assert!(target.halted());
+	assert_eq!(target.pc(), SRAM);
+}
+
+
+#[test]
+fn algorithm_parse() {
+	let algorithm = algorithm();
+
+	assert_eq!(algorithm.device.name, "STM32F10x Med");
+	assert_eq!(algorithm.device.size, 0x2_0000);
+	assert_eq!(algorithm.device.erased, 0xFF);
+	assert_eq!(algorithm.device.to_string(), "STM32F10x Med: 128 kB at 0x08000000 in pages of 1024 bytes");
+
+	// The descriptor is not part of the image, the data follows the code
+	assert_eq!(algorithm.image.len(), CODE.len() * 2 + 4);
+	assert_eq!(algorithm.data, CODE.len() as u32 * 2);
+
+	assert_eq!(algorithm.init, Some(0x00));
+	assert_eq!(algorithm.uninit, Some(0x12));
+	assert_eq!(algorithm.erase_chip, Some(0x36));
+	assert_eq!(algorithm.erase_sector, 0x28);
+	assert_eq!(algorithm.program_page, 0x54);
+}
+
+#[test]
+fn algorithm_without_program_page() {
+	match FlashAlgorithm::parse(&flm(&FUNCTIONS[..4])) {
+		Err(LinkError::Algorithm(_)) => (),
+		r => panic!("Expected an invalid algorithm, got {:?}", r),
+	}
+
+	match FlashAlgorithm::parse(&[0x7F, b'E', b'L', b'F']) {
+		Err(LinkError::Algorithm(_)) => (),
+		r => panic!("Expected an invalid algorithm, got {:?}", r),
+	}
+}
+
+#[test]
+fn device_sector_runs() {
+	let device = FlashDevice::parse(&descriptor(0x4_0000, 0x100, &[(0x4000, 0), (0x1_0000, 0x1_0000)])).unwrap();
+	let sectors = device.flash_sectors();
+
+	assert_eq!(sectors.len(), 7);
+	assert_eq!(sectors.iter().map(|s| s.index).collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+	assert_eq!((sectors[3].address, sectors[3].size), (FLASH + 0xC000, 0x4000));
+	assert_eq!((sectors[4].address, sectors[4].size), (FLASH + 0x1_0000, 0x1_0000));
+	assert_eq!(sectors[6].address, FLASH + 0x3_0000);
+}
+
+#[test]
+fn algorithm_program_and_erase() {
+	let mut link = open(Target::stm32f103());
+	link.set_flash_algorithm(Some(algorithm()));
+
+	assert_eq!(link.flash_sectors().unwrap().len(), 128);
+
+	// Unaligned, across three pages
+	let data = pattern(0x901);
+	assert_eq!(link.erase_range(FLASH + 0x401, data.len()).unwrap(), vec![1, 2, 3]);
+	program(&mut link, FLASH + 0x401, &data);
+
+	assert_eq!(link.transport().target().peek(FLASH + 0x400, 1), vec![0xFF]);
+	assert!(locked(&mut link));
+
+	link.erase_sectors(&[2]).unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x800, 0x400), vec![0xFF; 0x400]);
+	assert_eq!(link.transport().target().peek(FLASH + 0x401, 0x3FF), data[..0x3FF].to_vec());
+
+	link.mass_erase().unwrap();
+	assert_eq!(link.transport().target().peek(FLASH, 0x2_0000), vec![0xFF; 0x2_0000]);
+	assert!(locked(&mut link));
+}
+
+#[test]
+fn algorithm_mass_erase_by_sector() {
+	let mut link = open(Target::stm32f103());
+	link.set_flash_algorithm(Some(FlashAlgorithm::parse(&flm(&[FUNCTIONS[0], FUNCTIONS[1], FUNCTIONS[2], FUNCTIONS[4]])).unwrap()));
+
+	program(&mut link, FLASH + 0x1_FC00, &pattern(0x400));
+
+	link.mass_erase().unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x1_FC00, 0x400), vec![0xFF; 0x400]);
+}
+
+#[test]
+fn algorithm_unknown_chip() {
+	let mut link = open(Target::stm32f1(0x1000_0999, FlashF1::new(), 0x5000));
+	assert!(link.program_flash(FLASH, &pattern(0x100), &mut |_, _| ()).is_err());
+
+	link.set_flash_algorithm(Some(algorithm()));
+
+	match link.program_flash(FLASH, &pattern(0x100), &mut |_, _| ()) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected an unknown SRAM, got {:?}", r),
+	}
+
+	link.set_algorithm_ram(Some(SRamInfo { base: SRAM, size: 0x5000 }));
+	program(&mut link, FLASH + 0x1_0000, &pattern(0x1000));
+
+	// Too small for the code and the page buffers
+	link.set_algorithm_ram(Some(SRamInfo { base: SRAM, size: 0x400 }));
+	match link.program_flash(FLASH + 0x1_1000, &pattern(0x100), &mut |_, _| ()) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected a small SRAM, got {:?}", r),
+	}
+}
+
+#[test]
+fn algorithm_program_error() {
+	let mut link = open(Target::stm32f103());
+	link.set_flash_algorithm(Some(algorithm()));
+
+	program(&mut link, FLASH + 0x800, &pattern(0x10));
+
+	match link.program_flash(FLASH + 0x800, &[0x55; 0x10], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::Algorithm("ProgramPage"))) => (),
+		r => panic!("Expected a failed ProgramPage, got {:?}", r),
+	}
+
+	// UnInit still locked the controller
+	assert!(locked(&mut link));
+	assert_eq!(link.transport().target().peek(FLASH + 0x800, 0x10), pattern(0x10));
+}
@@ -0,0 +1,125 @@
+//! Fill, compare and search operations on the target memory
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ LinkError, Mismatch, AccessPolicy };
+use rustylink::sim::Target;
+use common::open;
+
+
+const SRAM: u32 = 0x2000_0000;
+
+
+#[test]
+fn fill_with_a_byte() {
+	let mut link = open(Target::stm32f407());
+	link.transport_mut().target_mut().load(SRAM, &[0x11; 0x20]);
+
+	link.fill_memory(SRAM + 3, 0x5003, &[0xA5]).unwrap();
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(SRAM, 3), vec![0x11; 3]);
+	assert_eq!(target.peek(SRAM + 3, 0x5003), vec![0xA5; 0x5003]);
+	assert_eq!(target.peek(SRAM + 0x5006, 1), vec![0x00]);
+}
+
+#[test]
+fn fill_with_a_pattern() {
+	let mut link = open(Target::stm32f407());
+
+	link.fill_memory(SRAM + 1, 10, &[1, 2, 3, 4]).unwrap();
+
+	assert_eq!(link.transport().target().peek(SRAM, 12), vec![0, 1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 0]);
+
+	match link.fill_memory(SRAM, 4, &[]) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+}
+
+#[test]
+fn bulk_range_past_the_end() {
+	let mut link = open(Target::stm32f407());
+
+	match link.fill_memory(0xFFFF_F000, 0x2000, &[0xAA]) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+
+	match link.compare_memory(0xFFFF_F000, &[0; 0x2000]) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+
+	match link.find_pattern(std::ops::Range { start: SRAM + 0x100, end: SRAM }, &[0xAA], None) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+
+	// A range running past the SRAM is refused before the first chunk is read
+	link.set_access_policy(AccessPolicy::Refuse);
+	let start = link.transport().commands.len();
+
+	match link.find_pattern(SRAM..SRAM + 0x40000, &[0xAA], None) {
+		Err(LinkError::Unmapped { .. }) => (),
+		r => panic!("Expected an unmapped address, got {:?}", r),
+	}
+
+	assert_eq!(link.transport().commands.len(), start);
+}
+
+#[test]
+fn compare_reports_runs() {
+	let mut link = open(Target::stm32f407());
+	let expected: Vec<u8> = (0..0x6000).map(|i| i as u8).collect();
+	link.transport_mut().target_mut().load(SRAM, &expected);
+
+	assert_eq!(link.compare_memory(SRAM, &expected).unwrap(), vec![]);
+
+	// A run across a chunk boundary and a single byte
+	link.transport_mut().target_mut().load(SRAM + 0x3FFE, &[0xEE; 4]);
+	link.transport_mut().target_mut().load(SRAM + 0x5000, &[0x42]);
+
+	assert_eq!(link.compare_memory(SRAM, &expected).unwrap(), vec![
+		Mismatch { address: SRAM + 0x3FFE, expected: vec![0xFE, 0xFF, 0x00, 0x01], actual: vec![0xEE; 4] },
+		Mismatch { address: SRAM + 0x5000, expected: vec![0x00], actual: vec![0x42] },
+	]);
+}
+
+#[test]
+fn find_exact_pattern() {
+	let mut link = open(Target::stm32f407());
+	let magic = [0xEF, 0xBE, 0xAD, 0xDE];
+
+	link.transport_mut().target_mut().load(SRAM + 0x10, &magic);
+	// Across a chunk boundary
+	link.transport_mut().target_mut().load(SRAM + 0x3FFE, &magic);
+	link.transport_mut().target_mut().load(SRAM + 0x7001, &magic);
+
+	assert_eq!(link.find_pattern(SRAM..SRAM + 0x8000, &magic, None).unwrap(), vec![SRAM + 0x10, SRAM + 0x3FFE, SRAM + 0x7001]);
+
+	// Matches must be inside the range
+	assert_eq!(link.find_pattern(SRAM + 0x11..SRAM + 0x7004, &magic, None).unwrap(), vec![SRAM + 0x3FFE]);
+}
+
+#[test]
+fn find_masked_pattern() {
+	let mut link = open(Target::stm32f407());
+
+	link.transport_mut().target_mut().load(SRAM + 0x20, &[0x12, 0x34, 0x56]);
+	link.transport_mut().target_mut().load(SRAM + 0x40, &[0x12, 0xFF, 0x57]);
+
+	let found = link.find_pattern(SRAM..SRAM + 0x100, &[0x12, 0x00, 0x56], Some(&[0xFF, 0x00, 0xFE])).unwrap();
+	assert_eq!(found, vec![SRAM + 0x20, SRAM + 0x40]);
+
+	// Overlapping matches
+	link.transport_mut().target_mut().load(SRAM + 0x80, &[0xAA; 4]);
+	assert_eq!(link.find_pattern(SRAM + 0x80..SRAM + 0x84, &[0xAA, 0xAA], None).unwrap(), vec![SRAM + 0x80, SRAM + 0x81, SRAM + 0x82]);
+
+	match link.find_pattern(SRAM..SRAM + 0x100, &[0x12, 0x34], Some(&[0xFF])) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+}
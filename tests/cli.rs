@@ -0,0 +1,51 @@
+//! Arguments of the command line tool, run on a replayed session
+
+extern crate rustylink;
+
+use std::path::PathBuf;
+use std::process::{ Command, Output };
+
+use rustylink::{ Link, DebugMode, Recorder };
+use rustylink::sim::{ SimProbe, Target };
+
+
+const SRAM: u32 = 0x2000_0000;
+
+
+/// Record a session writing `data` to the SRAM into a file named after the test
+fn session(name: &str, data: &[u8]) -> PathBuf {
+	let transport = Recorder::new(SimProbe::new(Target::stm32f407()), SimProbe::model(), Vec::new()).unwrap();
+	let mut link = Link::open(transport, SimProbe::model(), DebugMode::SWD).unwrap();
+
+	link.write_memory(SRAM, data).unwrap();
+
+	let path = std::env::temp_dir().join(format!("rustylink-cli-{}-{}.session", name, std::process::id()));
+	std::fs::write(&path, link.into_transport().into_inner().1).unwrap();
+
+	path
+}
+
+fn rustylink(args: &[&str]) -> Output {
+	Command::new(env!("CARGO_BIN_EXE_rustylink")).args(args).output().unwrap()
+}
+
+
+#[test]
+fn write_hex_bytes() {
+	let path = session("write", &[0x01, 0x02, 0xAB]);
+	let output = rustylink(&["--replay", path.to_str().unwrap(), "write", "0x20000000", "0x01_02", "ab"]);
+	std::fs::remove_file(&path).unwrap();
+
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+#[test]
+fn non_ascii_hex_bytes() {
+	let path = session("non-ascii", &[]);
+	let output = rustylink(&["--replay", path.to_str().unwrap(), "write", "0x20000000", "aéb"]);
+	std::fs::remove_file(&path).unwrap();
+
+	// An error of the tool, not a panic
+	assert_eq!(output.status.code(), Some(1));
+	assert!(String::from_utf8_lossy(&output.stdout).contains("'aéb' is not a valid hex byte string"));
+}
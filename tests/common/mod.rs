@@ -0,0 +1,18 @@
+//! Helpers shared by the integration tests
+
+// Each test crate uses its own subset of the helpers
+#![allow(dead_code)]
+
+use rustylink::{ Link, DebugMode };
+use rustylink::sim::{ SimProbe, Target };
+
+
+/// Connect to the simulated `target` over SWD
+pub fn open(target: Target) -> Link<SimProbe> {
+	Link::open(SimProbe::new(target), SimProbe::model(), DebugMode::SWD).expect("Could not open the simulated probe")
+}
+
+/// Test data, which does not repeat every 256 bytes
+pub fn pattern(n: usize) -> Vec<u8> {
+	(0..n).map(|i| (i * 13 + i / 256 + 7) as u8).collect()
+}
@@ -0,0 +1,208 @@
+//! Streaming memory dumps
+
+extern crate rustylink;
+
+mod common;
+
+use std::time::Duration;
+
+use rustylink::{ Link, LinkError, Transport, DebugMode, Dump, DumpFormat };
+use rustylink::rusb;
+use rustylink::sim::{ SimProbe, Target };
+use common::{ open, pattern };
+
+
+const SRAM: u32 = 0x2000_0000;
+
+
+/// Probe that loses the answer of one read after `countdown` reads
+struct Flaky {
+	probe: SimProbe,
+	countdown: Option<usize>,
+}
+
+impl Transport for Flaky {
+	fn write_command(&mut self, cmd: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		self.probe.write_command(cmd, timeout)
+	}
+
+	fn write_data(&mut self, data: &[u8], timeout: Duration) -> Result<usize, LinkError> {
+		self.probe.write_data(data, timeout)
+	}
+
+	fn read_data(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		let result = self.probe.read_data(buf, timeout);
+
+		match self.countdown {
+			Some(0) => {
+				self.countdown = None;
+				Err(LinkError::Usb(rusb::Error::Timeout))
+			},
+			Some(n) => {
+				self.countdown = Some(n - 1);
+				result
+			},
+			None => result,
+		}
+	}
+
+	fn read_trace(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, LinkError> {
+		self.probe.read_trace(buf, timeout)
+	}
+}
+
+
+#[test]
+fn raw_dump_in_chunks() {
+	let mut link = open(Target::stm32f407());
+	let data = pattern(0x3000);
+	link.transport_mut().target_mut().load(SRAM + 0x123, &data);
+
+	let mut updates = Vec::new();
+	let mut dump = Dump::new(SRAM + 0x123, data.len(), DumpFormat::Raw, Vec::new()).with_chunk(0x1000);
+
+	link.dump(&mut dump, &mut |done: usize, total: usize| updates.push((done, total))).unwrap();
+
+	assert!(dump.done());
+	assert_eq!(dump.into_inner(), data);
+
+	// The first chunk ends at a chunk boundary
+	assert_eq!(updates, vec![(0, 0x3000), (0xEDD, 0x3000), (0x1EDD, 0x3000), (0x2EDD, 0x3000), (0x3000, 0x3000)]);
+}
+
+#[test]
+fn intel_hex_dump() {
+	let mut link = open(Target::stm32f407());
+	link.transport_mut().target_mut().load(0x2000_FFF8, &pattern(24));
+
+	let mut dump = Dump::new(0x2000_FFF8, 24, DumpFormat::IntelHex, Vec::new());
+	link.dump(&mut dump, &mut |_, _| ()).unwrap();
+
+	let text = String::from_utf8(dump.into_inner()).unwrap();
+	let lines: Vec<&str> = text.lines().collect();
+
+	// Records are split at the 64 kB boundary
+	assert_eq!(lines, vec![
+		":020000042000DA",
+		":08FFF8000714212E3B4855625D",
+		":020000042001D9",
+		":100000006F7C8996A3B0BDCAD7E4F1FE0B182532E8",
+		":00000001FF",
+	]);
+
+	// Every record sums to zero
+	for line in lines {
+		let bytes: Vec<u8> = (1..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap()).collect();
+		assert_eq!(bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b)), 0, "{}", line);
+		assert_eq!(bytes[0] as usize, bytes.len() - 5, "{}", line);
+	}
+}
+
+#[test]
+fn elf_core_dump() {
+	let mut link = open(Target::stm32f407());
+	let data = pattern(0x400);
+	link.transport_mut().target_mut().load(SRAM, &data);
+
+	let mut dump = Dump::new(SRAM, data.len(), DumpFormat::ElfCore, Vec::new());
+	link.dump(&mut dump, &mut |_, _| ()).unwrap();
+
+	let file = dump.into_inner();
+	let u16_at = |o: usize| u16::from_le_bytes([file[o], file[o + 1]]);
+	let u32_at = |o: usize| u32::from_le_bytes([file[o], file[o + 1], file[o + 2], file[o + 3]]);
+
+	assert_eq!(&file[0..6], &[0x7F, b'E', b'L', b'F', 1, 1]);
+	// Core file for ARM
+	assert_eq!(u16_at(16), 4);
+	assert_eq!(u16_at(18), 40);
+	// A single PT_LOAD at the dumped address
+	assert_eq!(u32_at(28), 52);
+	assert_eq!(u16_at(44), 1);
+	assert_eq!(u32_at(52), 1);
+	assert_eq!(u32_at(60), SRAM);
+	assert_eq!(u32_at(68), 0x400);
+
+	let offset = u32_at(56) as usize;
+	assert_eq!(&file[offset..], &data[..]);
+}
+
+#[test]
+fn resume_after_a_transient_error() {
+	let mut probe = SimProbe::new(Target::stm32f407());
+	let data = pattern(0x4000);
+	probe.target_mut().load(SRAM, &data);
+
+	let mut link = Link::open(Flaky { probe, countdown: None }, SimProbe::model(), DebugMode::SWD).unwrap();
+
+	// Each chunk is a read and its status, lose the status of the third chunk
+	link.transport_mut().countdown = Some(5);
+
+	let mut dump = Dump::new(SRAM, data.len(), DumpFormat::Raw, Vec::new()).with_chunk(0x1000);
+
+	let error = link.dump(&mut dump, &mut |_, _| ()).unwrap_err();
+	assert!(error.is_transient(), "{:?}", error);
+	assert_eq!(dump.offset(), 0x2000);
+	assert_eq!(dump.output(), &data[..0x2000]);
+
+	link.dump(&mut dump, &mut |_, _| ()).unwrap();
+	assert_eq!(dump.into_inner(), data);
+}
+
+#[test]
+fn resume_into_a_new_dump() {
+	let mut link = open(Target::stm32f407());
+	let data = pattern(0x800);
+	link.transport_mut().target_mut().load(SRAM, &data);
+
+	let mut output = data[..0x300].to_vec();
+
+	let mut dump = Dump::resume(SRAM, data.len(), DumpFormat::Raw, &mut output, 0x300);
+	let mut first = None;
+	link.dump(&mut dump, &mut |done: usize, _| if first.is_none() { first = Some(done) }).unwrap();
+
+	assert_eq!(first, Some(0x300));
+	assert_eq!(output, data);
+}
+
+#[test]
+fn format_from_extension() {
+	assert_eq!(DumpFormat::from_path("flash.hex"), DumpFormat::IntelHex);
+	assert_eq!(DumpFormat::from_path("ram.ELF"), DumpFormat::ElfCore);
+	assert_eq!(DumpFormat::from_path("flash.bin"), DumpFormat::Raw);
+	assert_eq!(DumpFormat::from_path("flash"), DumpFormat::Raw);
+}
+
+#[test]
+fn refused_range_writes_nothing() {
+	let mut link = open(Target::stm32f407());
+	link.set_access_policy(rustylink::AccessPolicy::Refuse);
+
+	for format in [DumpFormat::Raw, DumpFormat::ElfCore] {
+		let mut dump = Dump::new(0x3000_0000, 0x100, format, Vec::new());
+
+		match link.dump(&mut dump, &mut |_, _| ()) {
+			Err(LinkError::Unmapped { .. }) => (),
+			r => panic!("Expected the {:?} dump to be refused, got {:?}", format, r),
+		}
+
+		assert_eq!(dump.offset(), 0);
+		assert!(dump.into_inner().is_empty());
+	}
+}
+
+#[test]
+fn elf_headers_are_written_once() {
+	let mut probe = SimProbe::new(Target::stm32f407());
+	probe.target_mut().load(SRAM, &pattern(0x100));
+
+	let mut link = Link::open(Flaky { probe, countdown: None }, SimProbe::model(), DebugMode::SWD).unwrap();
+	link.transport_mut().countdown = Some(0);
+
+	let mut dump = Dump::new(SRAM, 0x100, DumpFormat::ElfCore, Vec::new());
+
+	assert!(link.dump(&mut dump, &mut |_, _| ()).is_err());
+	assert_eq!(dump.output().len(), DumpFormat::ElfCore.header_size());
+
+	link.dump(&mut dump, &mut |_, _| ()).unwrap();
+	assert_eq!(&dump.output()[DumpFormat::ElfCore.header_size()..], &pattern(0x100)[..]);
+}
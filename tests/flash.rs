@@ -0,0 +1,183 @@
+//! Flash programming of the STM32F2/F4/F7 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, FlashSector, Parallelism };
+use rustylink::sim::{ SimProbe, Target, Region, FlashF4 };
+use common::{ open, pattern };
+
+
+const FLASH: u32 = 0x0800_0000;
+
+const FLASH_SR: u32 = 0x4002_3C0C;
+const FLASH_CR: u32 = 0x4002_3C10;
+const FLASH_OPTKEYR: u32 = 0x4002_3C08;
+const FLASH_OPTCR: u32 = 0x4002_3C14;
+
+const LOCK: u32 = 1 << 31;
+
+
+/// Chip with the given ID and a flash of `kb` kB reported at `size_reg`
+fn open_chip(chip: u32, size_reg: u32, kb: u16) -> Link<SimProbe> {
+	let mut target = Target::new(0x410F_C241, 0x1000_0000 | chip, Box::new(FlashF4::new()));
+	target.regions.push(Region::new(0x2000_0000, 0x30000, 0));
+
+	let mut info = Region::new(size_reg & !0xFF, 0x100, 0xFF);
+	info.data[(size_reg & 0xFF) as usize..(size_reg & 0xFF) as usize + 2].copy_from_slice(&kb.to_le_bytes());
+	target.regions.push(info);
+
+	open(target)
+}
+
+fn kb(sectors: &[FlashSector]) -> Vec<u32> {
+	sectors.iter().map(|s| s.size / 1024).collect()
+}
+
+
+#[test]
+fn f407_sector_layout() {
+	let mut link = open(Target::stm32f407());
+	let sectors = link.flash_sectors().unwrap();
+
+	assert_eq!(kb(&sectors), vec![16, 16, 16, 16, 64, 128, 128, 128, 128, 128, 128, 128]);
+	assert_eq!(sectors[4], FlashSector { index: 4, address: 0x0801_0000, size: 0x1_0000 });
+	assert_eq!(sectors[11].address, 0x080E_0000);
+}
+
+#[test]
+fn dual_bank_layouts() {
+	// F42x/43x with 2 MB
+	let sectors = open_chip(0x419, 0x1FFF_7A22, 2048).flash_sectors().unwrap();
+	assert_eq!(sectors.len(), 24);
+	assert_eq!(sectors[12], FlashSector { index: 12, address: 0x0810_0000, size: 16 * 1024 });
+	assert_eq!(sectors[23].index, 23);
+
+	// F76x/77x, dual bank with nDBANK cleared
+	let sectors = open_chip(0x451, 0x1FF0_F442, 2048).flash_sectors().unwrap();
+	assert_eq!(kb(&sectors[..12]), vec![16, 16, 16, 16, 64, 128, 128, 128, 128, 128, 128, 128]);
+	assert_eq!(sectors[12].address, 0x0810_0000);
+
+	// F74x/75x
+	let sectors = open_chip(0x449, 0x1FF0_F442, 1024).flash_sectors().unwrap();
+	assert_eq!(kb(&sectors), vec![32, 32, 32, 32, 128, 256, 256, 256]);
+}
+
+#[test]
+fn erase_sectors() {
+	let mut link = open(Target::stm32f407());
+	link.transport_mut().target_mut().load(FLASH, &[0; 0x20000]);
+
+	link.erase_sectors(&[1, 4]).unwrap();
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x4000), vec![0; 0x4000]);
+	assert_eq!(target.peek(FLASH + 0x4000, 0x4000), vec![0xFF; 0x4000]);
+	assert_eq!(target.peek(FLASH + 0x8000, 0x8000), vec![0; 0x8000]);
+	assert_eq!(target.peek(FLASH + 0x1_0000, 0x1_0000), vec![0xFF; 0x1_0000]);
+
+	// The flash is locked again
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+
+	match link.erase_sectors(&[12]) {
+		Err(LinkError::Flash(FlashError::Sector(12))) => (),
+		r => panic!("Expected a missing sector, got {:?}", r),
+	}
+}
+
+#[test]
+fn erase_range_and_mass_erase() {
+	let mut link = open(Target::stm32f407());
+	link.transport_mut().target_mut().load(FLASH, &[0; 0x2_0000]);
+
+	assert_eq!(link.erase_range(FLASH + 0x3FFF, 2).unwrap(), vec![0, 1]);
+	assert_eq!(link.transport().target().peek(FLASH + 0x8000, 4), vec![0; 4]);
+
+	link.mass_erase().unwrap();
+	assert_eq!(link.transport().target().peek(FLASH, 0x2_0000), vec![0xFF; 0x2_0000]);
+}
+
+#[test]
+fn program_and_verify() {
+	let mut link = open(Target::stm32f407());
+	let data = pattern(0x1803);
+
+	let mut last = (0, 0);
+	link.program_flash(FLASH + 0x4001, &data, &mut |done: usize, total: usize| last = (done, total)).unwrap();
+
+	assert_eq!(last, (data.len(), data.len()));
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH + 0x4000, 1), vec![0xFF]);
+	assert_eq!(target.peek(FLASH + 0x4001, data.len()), data);
+	assert_eq!(target.peek(FLASH + 0x4001 + data.len() as u32, 1), vec![0xFF]);
+
+	assert_eq!(link.read_debug_reg(FLASH_SR).unwrap(), 0);
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+}
+
+#[test]
+fn program_with_forced_parallelism() {
+	for &(psize, address) in [(Parallelism::X8, FLASH + 0x8001), (Parallelism::X16, FLASH + 0x8102), (Parallelism::X64, FLASH + 0x8200)].iter() {
+		let mut link = open(Target::stm32f407());
+		link.set_flash_parallelism(Some(psize));
+
+		let data = pattern(37);
+		link.program_flash(address, &data, &mut |_, _| ()).unwrap();
+		assert_eq!(link.transport().target().peek(address, data.len()), data, "{:?}", psize);
+	}
+}
+
+#[test]
+fn program_over_data_fails_verification() {
+	let mut link = open(Target::stm32f407());
+	link.transport_mut().target_mut().load(FLASH + 0x100, &[0x0F; 4]);
+
+	match link.program_flash(FLASH + 0x100, &[0xF0; 8], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::Verify(a))) if a == FLASH + 0x100 => (),
+		r => panic!("Expected a verification error, got {:?}", r),
+	}
+}
+
+#[test]
+fn write_protected_sector() {
+	let mut link = open(Target::stm32f407());
+
+	// Clear nWRP of sector 2
+	link.write_debug_reg(FLASH_OPTKEYR, 0x0819_2A3B).unwrap();
+	link.write_debug_reg(FLASH_OPTKEYR, 0x4C5D_6E7F).unwrap();
+	link.write_debug_reg(FLASH_OPTCR, 0x0FFB_AAEC).unwrap();
+
+	match link.erase_sectors(&[2]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(2))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	match link.program_flash(FLASH + 0x8000, &[0; 4], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(2))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	// Errors are cleared and the flash locked again
+	assert_eq!(link.read_debug_reg(FLASH_SR).unwrap(), 0);
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+
+	// Other sectors can still be programmed
+	link.program_flash(FLASH, &[0x12, 0x34, 0x56, 0x78], &mut |_, _| ()).unwrap();
+}
+
+#[test]
+fn program_outside_the_flash() {
+	let mut link = open(Target::stm32f407());
+
+	match link.program_flash(0x2000_0000, &[0; 4], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::Address(0x2000_0000))) => (),
+		r => panic!("Expected an address error, got {:?}", r),
+	}
+
+	match link.program_flash(0x080F_FFFC, &[0; 8], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::Address(0x0810_0000))) => (),
+		r => panic!("Expected an address error, got {:?}", r),
+	}
+}
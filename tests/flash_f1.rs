@@ -0,0 +1,173 @@
+//! Flash programming of the STM32F0/F1/F3 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, DebugMode, FlashSector, OptionBytes };
+use rustylink::sim::{ SimProbe, Target, FlashF1 };
+use common::{ open, pattern };
+
+
+const FLASH: u32 = 0x0800_0000;
+
+const FLASH_SR: u32 = 0x4002_200C;
+const FLASH_CR: u32 = 0x4002_2010;
+const FLASH_CR2: u32 = 0x4002_2050;
+
+const LOCK: u32 = 1 << 7;
+/// PGERR and WRPRTERR
+const ERRORS: u32 = (1 << 2) | (1 << 4);
+
+
+#[test]
+fn page_layout() {
+	let mut link = open(Target::stm32f103());
+
+	assert_eq!(link.memory().flash.size, 128);
+
+	let pages = link.flash_sectors().unwrap();
+	assert_eq!(pages.len(), 128);
+	assert_eq!(pages[5], FlashSector { index: 5, address: FLASH + 0x1400, size: 0x400 });
+
+	let pages = open(Target::stm32f103xg()).flash_sectors().unwrap();
+	assert_eq!(pages.len(), 512);
+	assert_eq!(pages[256], FlashSector { index: 256, address: FLASH + 0x8_0000, size: 0x800 });
+}
+
+#[test]
+fn open_leaves_flash_locked() {
+	let mut link = open(Target::stm32f103());
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+
+	link.unlock_flash().unwrap();
+	assert_eq!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+}
+
+#[test]
+fn erase_pages() {
+	let mut link = open(Target::stm32f103());
+	link.transport_mut().target_mut().load(FLASH, &[0; 0x1000]);
+
+	assert_eq!(link.erase_range(FLASH + 0x7FF, 2).unwrap(), vec![1, 2]);
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x400), vec![0; 0x400]);
+	assert_eq!(target.peek(FLASH + 0x400, 0x800), vec![0xFF; 0x800]);
+	assert_eq!(target.peek(FLASH + 0xC00, 0x400), vec![0; 0x400]);
+
+	// The flash is locked again
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+
+	match link.erase_sectors(&[128]) {
+		Err(LinkError::Flash(FlashError::Sector(128))) => (),
+		r => panic!("Expected a missing page, got {:?}", r),
+	}
+}
+
+#[test]
+fn program_half_words() {
+	let mut link = open(Target::stm32f103());
+	let data = pattern(0x1001);
+
+	let mut last = (0, 0);
+	link.program_flash(FLASH + 0x801, &data, &mut |done: usize, total: usize| last = (done, total)).unwrap();
+	assert_eq!(last, (data.len(), data.len()));
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH + 0x800, 1), vec![0xFF]);
+	assert_eq!(target.peek(FLASH + 0x801, data.len()), data);
+	assert_eq!(target.peek(FLASH + 0x1802, 1), vec![0xFF]);
+
+	assert_eq!(link.read_debug_reg(FLASH_SR).unwrap() & ERRORS, 0);
+}
+
+#[test]
+fn program_not_erased() {
+	let mut link = open(Target::stm32f103());
+	link.transport_mut().target_mut().load(FLASH + 0x100, &[0x34, 0x12]);
+
+	match link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::NotErased)) => (),
+		r => panic!("Expected a programming error, got {:?}", r),
+	}
+
+	assert_eq!(link.read_debug_reg(FLASH_SR).unwrap() & ERRORS, 0);
+
+	// Erase, then program
+	link.erase_range(FLASH + 0x100, 2).unwrap();
+	link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()).unwrap();
+}
+
+#[test]
+fn write_protected_pages() {
+	let mut flash = FlashF1::new();
+	// Pages 4 to 7
+	flash.set_wrpr(!0b10);
+
+	let mut link = open(Target::stm32f1(0x2000_6410, flash, 0x5000));
+
+	match link.erase_sectors(&[5]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(5))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	// The first protected page is named
+	match link.mass_erase() {
+		Err(LinkError::Flash(FlashError::SectorProtected(4))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	link.erase_sectors(&[8]).unwrap();
+}
+
+#[test]
+fn xl_banks() {
+	let mut link = open(Target::stm32f103xg());
+	link.transport_mut().target_mut().load(FLASH + 0x7_F000, &[0; 0x2000]);
+
+	// The second bank is unlocked with its own registers
+	link.unlock_flash().unwrap();
+	assert_eq!(link.read_debug_reg(FLASH_CR2).unwrap() & LOCK, 0);
+
+	// Pages on both sides of the bank boundary
+	assert_eq!(link.erase_range(FLASH + 0x7_FFFE, 4).unwrap(), vec![255, 256]);
+	assert_eq!(link.transport().target().peek(FLASH + 0x7_F800, 0x1000), vec![0xFF; 0x1000]);
+	assert_eq!(link.transport().target().peek(FLASH + 0x7_F000, 0x800), vec![0; 0x800]);
+
+	// Programming across the boundary
+	let data = pattern(0x100);
+	link.program_flash(FLASH + 0x7_FF80, &data, &mut |_, _| ()).unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x7_FF80, 0x100), data);
+
+	// Both banks are locked again
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & LOCK, 0);
+	assert_ne!(link.read_debug_reg(FLASH_CR2).unwrap() & LOCK, 0);
+
+	link.transport_mut().target_mut().load(FLASH + 0xF_0000, &[0; 4]);
+	link.mass_erase().unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x7_FF80, 0x100), vec![0xFF; 0x100]);
+	assert_eq!(link.transport().target().peek(FLASH + 0xF_0000, 4), vec![0xFF; 4]);
+}
+
+#[test]
+fn probe_without_16bit_accesses() {
+	// STLink V2 J25, before the 16 bit memory commands
+	let mut link = Link::open(SimProbe::with_firmware(Target::stm32f103(), 25), SimProbe::model(), DebugMode::SWD).unwrap();
+	assert!(link.write_mem16(FLASH, &[0; 2]).is_err());
+
+	// The half-words are written by the flash loader
+	let data = pattern(0x803);
+	link.program_flash(FLASH + 0x401, &data, &mut |_, _| ()).unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x401, data.len()), data);
+	assert!(link.transport().target().halted());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::F1(o) => o,
+		o => panic!("Expected F1 option bytes, got {:?}", o),
+	};
+
+	options.data = [0x12, 0x34];
+	link.write_option_bytes(&OptionBytes::F1(options)).unwrap();
+	assert_eq!(link.option_bytes().unwrap(), OptionBytes::F1(options));
+}
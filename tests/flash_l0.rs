@@ -0,0 +1,168 @@
+//! Flash programming and data EEPROM of the STM32L0/L1 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ LinkError, FlashError, FlashSector, RegionKind };
+use rustylink::sim::{ Target, FlashL0 };
+use common::{ open, pattern };
+
+
+const FLASH: u32 = 0x0800_0000;
+const EEPROM: u32 = 0x0808_0000;
+
+const L0_PECR: u32 = 0x4002_2004;
+const L0_SR: u32 = 0x4002_2018;
+const L1_PECR: u32 = 0x4002_3C04;
+
+const PELOCK: u32 = 1 << 0;
+const PRGLOCK: u32 = 1 << 1;
+/// WRPERR, PGAERR, SIZERR and NOTZEROERR
+const ERRORS: u32 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 16);
+
+
+#[test]
+fn identify() {
+	// The IDCODE of the Cortex-M0+ is read from the peripheral bus
+	let mut link = open(Target::stm32l053());
+
+	assert_eq!(link.get_chip_info().unwrap(), 0x417);
+	assert_eq!(link.memory().flash.size, 64);
+	assert_eq!((link.memory().eeprom.base, link.memory().eeprom.size), (EEPROM, 0x800));
+	assert_eq!(link.memory_map().kind(EEPROM + 0x7FF), RegionKind::Eeprom);
+
+	let pages = link.flash_sectors().unwrap();
+	assert_eq!(pages.len(), 512);
+	assert_eq!(pages[3], FlashSector { index: 3, address: FLASH + 0x180, size: 0x80 });
+
+	let mut link = open(Target::stm32l152());
+	assert_eq!(link.memory().eeprom.size, 0x1000);
+	assert_eq!(link.flash_sectors().unwrap()[1], FlashSector { index: 1, address: FLASH + 0x100, size: 0x100 });
+}
+
+#[test]
+fn open_leaves_flash_locked() {
+	let mut link = open(Target::stm32l053());
+	assert_eq!(link.read_debug_reg(L0_PECR).unwrap() & (PELOCK | PRGLOCK), PELOCK | PRGLOCK);
+
+	let mut link = open(Target::stm32l152());
+	assert_eq!(link.read_debug_reg(L1_PECR).unwrap() & (PELOCK | PRGLOCK), PELOCK | PRGLOCK);
+
+	link.unlock_flash().unwrap();
+	assert_eq!(link.read_debug_reg(L1_PECR).unwrap() & (PELOCK | PRGLOCK), 0);
+}
+
+#[test]
+fn erase_pages() {
+	let mut link = open(Target::stm32l053());
+	link.transport_mut().target_mut().load(FLASH, &[0xA5; 0x200]);
+
+	assert_eq!(link.erase_range(FLASH + 0xFF, 2).unwrap(), vec![1, 2]);
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x80), vec![0xA5; 0x80]);
+	assert_eq!(target.peek(FLASH + 0x80, 0x100), vec![0; 0x100]);
+	assert_eq!(target.peek(FLASH + 0x180, 0x80), vec![0xA5; 0x80]);
+
+	// The flash is locked again
+	assert_eq!(link.read_debug_reg(L0_PECR).unwrap() & (PELOCK | PRGLOCK), PELOCK | PRGLOCK);
+}
+
+#[test]
+fn program_half_pages() {
+	for (target, pecr) in [(Target::stm32l053(), L0_PECR), (Target::stm32l152(), L1_PECR)] {
+		let mut link = open(target);
+		let data = pattern(0x203);
+
+		let mut last = (0, 0);
+		link.program_flash(FLASH + 0x11, &data, &mut |done: usize, total: usize| last = (done, total)).unwrap();
+		assert_eq!(last, (data.len(), data.len()));
+
+		let target = link.transport().target();
+		assert_eq!(target.peek(FLASH + 0x10, 1), vec![0]);
+		assert_eq!(target.peek(FLASH + 0x11, data.len()), data);
+		assert_eq!(target.peek(FLASH + 0x214, 4), vec![0; 4]);
+
+		assert_eq!(link.read_debug_reg(pecr + 0x14).unwrap() & ERRORS, 0);
+	}
+}
+
+#[test]
+fn program_not_erased() {
+	let mut link = open(Target::stm32l053());
+	link.transport_mut().target_mut().load(FLASH + 0x100, &[0x34, 0x12, 0, 0]);
+
+	match link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::NotErased)) => (),
+		r => panic!("Expected a programming error, got {:?}", r),
+	}
+
+	assert_eq!(link.read_debug_reg(L0_SR).unwrap() & ERRORS, 0);
+
+	// Erase, then program
+	link.erase_range(FLASH + 0x100, 2).unwrap();
+	link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()).unwrap();
+}
+
+#[test]
+fn write_protected_pages() {
+	let mut flash = FlashL0::l053();
+	// Second 4 kB sector
+	flash.set_wrprot(0b10);
+
+	let mut link = open(Target::stm32l0(0x410C_C601, 0x1000_6417, flash, 0x2000, 0x7C));
+
+	match link.erase_range(FLASH + 0x1000, 4) {
+		Err(LinkError::Flash(FlashError::SectorProtected(32))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	match link.program_flash(FLASH + 0x1FFC, &pattern(8), &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(63))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+
+	link.erase_range(FLASH + 0x2000, 4).unwrap();
+}
+
+#[test]
+fn mass_erase() {
+	let mut link = open(Target::stm32l152());
+	link.transport_mut().target_mut().load(FLASH, &[0xFF; 0x100]);
+	link.transport_mut().target_mut().load(FLASH + 0x1_FF00, &[0xFF; 0x100]);
+
+	link.mass_erase().unwrap();
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x100), vec![0; 0x100]);
+	assert_eq!(target.peek(FLASH + 0x1_FF00, 0x100), vec![0; 0x100]);
+}
+
+#[test]
+fn data_eeprom() {
+	let mut link = open(Target::stm32l053());
+	link.transport_mut().target_mut().load(EEPROM, &[0xEE; 0x20]);
+
+	// Bytes at both ends, words in between
+	let data = pattern(11);
+	link.write_eeprom(EEPROM + 3, &data).unwrap();
+
+	assert_eq!(link.read_eeprom(EEPROM + 3, data.len()).unwrap(), data);
+	assert_eq!(link.transport().target().peek(EEPROM, 3), vec![0xEE; 3]);
+	assert_eq!(link.transport().target().peek(EEPROM + 14, 2), vec![0xEE; 2]);
+
+	// PECR is locked again
+	assert_ne!(link.read_debug_reg(L0_PECR).unwrap() & PELOCK, 0);
+
+	match link.write_eeprom(EEPROM + 0x7FE, &[1, 2, 3]) {
+		Err(LinkError::Flash(FlashError::Address(0x0808_0800))) => (),
+		r => panic!("Expected an address error, got {:?}", r),
+	}
+
+	// Chips without data EEPROM
+	match open(Target::stm32f407()).read_eeprom(EEPROM, 4) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected an unsupported operation, got {:?}", r),
+	}
+}
@@ -0,0 +1,171 @@
+//! Flash programming of the STM32L4/G0/WB devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, FlashSector };
+use rustylink::sim::{ SimProbe, Target, FlashL4 };
+use common::{ open, pattern };
+
+
+const FLASH: u32 = 0x0800_0000;
+
+const L4_SR: u32 = 0x4002_2010;
+const L4_CR: u32 = 0x4002_2014;
+const WB_CR: u32 = 0x5800_4014;
+
+const LOCK: u32 = 1 << 31;
+/// PROGERR, WRPERR, PGAERR, SIZERR, PGSERR and MISERR
+const ERRORS: u32 = 0x1F8;
+
+const WRITEMEM_32BIT: u8 = 0x08;
+
+/// Start of the CPU2 area of the simulated WB55
+const SECURE: u32 = 0x080C_B000;
+
+
+/// Sizes of the 32 bit memory writes sent since the command `from`
+fn writes(link: &Link<SimProbe>, from: usize) -> Vec<usize> {
+	link.transport().commands[from..].iter()
+		.filter(|c| c.len() >= 8 && c[0] == 0xF2 && c[1] == WRITEMEM_32BIT)
+		.map(|c| c[6] as usize | (c[7] as usize) << 8)
+		.collect()
+}
+
+
+#[test]
+fn page_layout() {
+	let mut link = open(Target::stm32l476());
+	let pages = link.flash_sectors().unwrap();
+	assert_eq!(pages.len(), 512);
+	assert_eq!(pages[256], FlashSector { index: 256, address: FLASH + 0x8_0000, size: 0x800 });
+
+	// Identified through the DBGMCU of the Cortex-M0+
+	let mut link = open(Target::stm32g071());
+	assert_eq!(link.chip().unwrap().id, 0x460);
+	assert_eq!(link.flash_sectors().unwrap().len(), 64);
+
+	// The pages of CPU2 are left out
+	let mut link = open(Target::stm32wb55());
+	let pages = link.flash_sectors().unwrap();
+	assert_eq!(pages.len(), 0xCB);
+	assert_eq!(pages.last().unwrap().address + pages.last().unwrap().size, SECURE);
+}
+
+#[test]
+fn open_leaves_flash_locked() {
+	let mut link = open(Target::stm32l476());
+	assert_ne!(link.read_debug_reg(L4_CR).unwrap() & LOCK, 0);
+
+	let mut link = open(Target::stm32wb55());
+	assert_ne!(link.read_debug_reg(WB_CR).unwrap() & LOCK, 0);
+
+	link.unlock_flash().unwrap();
+	assert_eq!(link.read_debug_reg(WB_CR).unwrap() & LOCK, 0);
+}
+
+#[test]
+fn erase_pages_of_both_banks() {
+	let mut link = open(Target::stm32l476());
+	link.transport_mut().target_mut().load(FLASH, &[0; 0x800]);
+	link.transport_mut().target_mut().load(FLASH + 0x7_F000, &[0; 0x2000]);
+
+	assert_eq!(link.erase_range(FLASH + 0x7_FFFE, 4).unwrap(), vec![255, 256]);
+
+	// The first page of the second bank is selected with BKER, not page 0
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x800), vec![0; 0x800]);
+	assert_eq!(target.peek(FLASH + 0x7_F000, 0x800), vec![0; 0x800]);
+	assert_eq!(target.peek(FLASH + 0x7_F800, 0x1000), vec![0xFF; 0x1000]);
+	assert_eq!(target.peek(FLASH + 0x8_0800, 0x800), vec![0; 0x800]);
+
+	// The flash is locked again
+	assert_ne!(link.read_debug_reg(L4_CR).unwrap() & LOCK, 0);
+
+	// A 512 kB device without DUALBANK has a single bank of 256 pages
+	let mut link = open(Target::stm32l4(0x410F_C241, 0x1007_6415, FlashL4::l47x(512 * 1024), 0x18000));
+	link.transport_mut().target_mut().load(FLASH + 0x7_F800, &[0; 0x800]);
+	link.erase_sectors(&[255]).unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x7_F800, 0x800), vec![0xFF; 0x800]);
+}
+
+#[test]
+fn program_double_words() {
+	let mut link = open(Target::stm32g071());
+	let data = pattern(0x405);
+
+	let mut last = (0, 0);
+	link.program_flash(FLASH + 0x103, &data, &mut |done: usize, total: usize| last = (done, total)).unwrap();
+	assert_eq!(last, (data.len(), data.len()));
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH + 0x100, 3), vec![0xFF; 3]);
+	assert_eq!(target.peek(FLASH + 0x103, data.len()), data);
+	assert_eq!(target.peek(FLASH + 0x508, 8), vec![0xFF; 8]);
+
+	assert_eq!(link.read_debug_reg(L4_SR).unwrap() & ERRORS, 0);
+}
+
+#[test]
+fn program_not_erased() {
+	let mut link = open(Target::stm32l476());
+	link.transport_mut().target_mut().load(FLASH + 0x104, &[0x34, 0x12]);
+
+	match link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::NotErased)) => (),
+		r => panic!("Expected a programming error, got {:?}", r),
+	}
+
+	assert_eq!(link.read_debug_reg(L4_SR).unwrap() & ERRORS, 0);
+
+	link.erase_range(FLASH + 0x100, 2).unwrap();
+	link.program_flash(FLASH + 0x100, &[0x78, 0x56], &mut |_, _| ()).unwrap();
+}
+
+#[test]
+fn fast_programming() {
+	let mut link = open(Target::stm32l476());
+	let data = pattern(0x300);
+
+	link.mass_erase().unwrap();
+	link.set_fast_programming(true);
+
+	let start = link.transport().commands.len();
+	link.program_flash(FLASH + 0x8_0080, &data, &mut |_, _| ()).unwrap();
+
+	// Double words up to the first row, two rows, then double words again
+	assert_eq!(writes(&link, start), vec![0x80, 0x100, 0x100, 0x80]);
+	assert_eq!(link.transport().target().peek(FLASH + 0x8_0080, data.len()), data);
+}
+
+#[test]
+fn secure_area_of_cpu2() {
+	let mut link = open(Target::stm32wb55());
+	link.transport_mut().target_mut().load(FLASH, &[0; 0x1000]);
+	link.transport_mut().target_mut().load(SECURE - 4, &[0xAB; 8]);
+
+	match link.erase_sectors(&[0xCB]) {
+		Err(LinkError::Flash(FlashError::Sector(0xCB))) => (),
+		r => panic!("Expected a missing page, got {:?}", r),
+	}
+
+	match link.program_flash(SECURE - 4, &[0; 8], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::Address(SECURE))) => (),
+		r => panic!("Expected an address error, got {:?}", r),
+	}
+
+	// The wireless stack survives a mass erase
+	link.mass_erase().unwrap();
+
+	let target = link.transport().target();
+	assert_eq!(target.peek(FLASH, 0x1000), vec![0xFF; 0x1000]);
+	assert_eq!(target.peek(SECURE - 4, 8), vec![0xFF, 0xFF, 0xFF, 0xFF, 0xAB, 0xAB, 0xAB, 0xAB]);
+
+	// Without a secure area the whole flash is available
+	let mut flash = FlashL4::wb55();
+	flash.set_sfr(1 << 8);
+
+	let mut link = open(Target::stm32l4(0x410F_C241, 0x2001_6495, flash, 0x40000));
+	assert_eq!(link.flash_sectors().unwrap().len(), 256);
+}
@@ -0,0 +1,27 @@
+//! Ownership of the `Link`
+
+extern crate rustylink;
+
+use rustylink::{ Link, DebugMode, UsbTransport };
+use rustylink::sim::{ SimProbe, Target };
+
+
+fn assert_send_static<T: Send + 'static>() {}
+
+
+#[test]
+fn usb_link_is_send_and_static() {
+	assert_send_static::<Link<UsbTransport>>();
+}
+
+#[test]
+fn link_moves_to_a_worker_thread() {
+	let link = Link::open(SimProbe::new(Target::stm32f407()), SimProbe::model(), DebugMode::SWD).unwrap();
+
+	let worker = std::thread::spawn(move || {
+		let mut link = link;
+		link.core_id().unwrap()
+	});
+
+	assert_eq!(worker.join().unwrap(), 0x2BA0_1477);
+}
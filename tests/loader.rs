@@ -0,0 +1,113 @@
+//! Flash programming with the loader running in the target SRAM
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, Parallelism };
+use rustylink::sim::{ SimProbe, Target };
+use common::pattern;
+
+
+const FLASH: u32 = 0x0800_0000;
+const SRAM: u32 = 0x2000_0000;
+
+const F4_SR: u32 = 0x4002_3C0C;
+
+const WRITEMEM_32BIT: u8 = 0x08;
+const READDEBUGREG: u8 = 0x36;
+
+/// Offset of the breakpoint ending the stub
+const BKPT: u32 = 22;
+
+
+fn open(target: Target) -> Link<SimProbe> {
+	let mut link = common::open(target);
+	link.set_flash_loader(true);
+	link
+}
+
+/// Addresses of the commands `command` sent since the command `from`
+fn addresses(link: &Link<SimProbe>, from: usize, command: u8) -> Vec<u32> {
+	link.transport().commands[from..].iter()
+		.filter(|c| c.len() >= 6 && c[0] == 0xF2 && c[1] == command)
+		.map(|c| c[2] as u32 | (c[3] as u32) << 8 | (c[4] as u32) << 16 | (c[5] as u32) << 24)
+		.collect()
+}
+
+/// Program `data` at `address` and check the flash and the progress reports
+fn program(link: &mut Link<SimProbe>, address: u32, data: &[u8]) {
+	let mut last = (0, 0);
+	link.program_flash(address, data, &mut |done, total| last = (done, total)).unwrap();
+
+	assert_eq!(last, (data.len(), data.len()));
+	assert_eq!(link.transport().target().peek(address, data.len()), data);
+
+	// The core stops on the breakpoint of the stub
+	let target = link.transport().target();
+	assert!(target.halted());
+	assert_eq!(target.pc(), SRAM + BKPT);
+}
+
+
+#[test]
+fn loader_f4() {
+	let mut link = open(Target::stm32f407());
+	let data = pattern(0x9000);
+
+	let from = link.transport().commands.len();
+	program(&mut link, FLASH + 0x1000, &data);
+
+	// The data only goes to the SRAM buffers and the host does not poll the controller
+	assert!(addresses(&link, from, WRITEMEM_32BIT).iter().all(|a| *a >= SRAM && *a < SRAM + 0x8100));
+	assert!(addresses(&link, from, READDEBUGREG).iter().filter(|a| **a == F4_SR).count() <= 1);
+}
+
+#[test]
+fn loader_f4_bytes() {
+	let mut link = open(Target::stm32f407());
+	link.set_flash_parallelism(Some(Parallelism::X8));
+
+	program(&mut link, FLASH + 0x4003, &pattern(0x4001));
+	assert_eq!(link.transport().target().peek(FLASH + 0x4002, 1), vec![0xFF]);
+}
+
+#[test]
+fn loader_f1_banks() {
+	let mut link = open(Target::stm32f103xg());
+
+	// Across the boundary of the two banks, with an odd start
+	program(&mut link, FLASH + 0x7_F001, &pattern(0x2000));
+}
+
+#[test]
+fn loader_l0() {
+	// 8 kB of SRAM, the buffers are smaller
+	let mut link = open(Target::stm32l053());
+
+	program(&mut link, FLASH + 0x0802, &pattern(0x1402));
+	assert_eq!(link.transport().target().peek(FLASH + 0x0800, 2), vec![0; 2]);
+}
+
+#[test]
+fn loader_l4() {
+	let mut link = open(Target::stm32l476());
+	link.set_fast_programming(true);
+
+	program(&mut link, FLASH + 0x8_0000 - 0x804, &pattern(0x1005));
+}
+
+#[test]
+fn loader_stops_on_error() {
+	let mut link = open(Target::stm32f407());
+	link.protect_range(FLASH + 0x4000, 4).unwrap();
+
+	match link.program_flash(FLASH + 0x3000, &pattern(0x2000), &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(1))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	// The stub stopped on the first word of the protected sector
+	assert_eq!(link.read_reg(1).unwrap(), FLASH + 0x4000);
+	assert_eq!(link.transport().target().peek(FLASH + 0x3FFC, 4), pattern(0x1000)[0xFFC..].to_vec());
+}
@@ -0,0 +1,90 @@
+//! Memory map of the target
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ LinkError, MemoryMap, RegionKind, AccessPolicy };
+use rustylink::sim::Target;
+use common::open;
+
+
+#[test]
+fn classify_addresses() {
+	let link = open(Target::stm32f407());
+	let map = link.memory_map();
+
+	assert_eq!(map.kind(0x0800_0000), RegionKind::Flash);
+	assert_eq!(map.kind(0x080F_FFFF), RegionKind::Flash);
+	assert_eq!(map.kind(0x0000_0004), RegionKind::Flash);
+	assert_eq!(map.kind(0x2000_0000), RegionKind::Ram);
+	assert_eq!(map.kind(0x1FFF_0000), RegionKind::System);
+	assert_eq!(map.kind(0x1FFF_7A22), RegionKind::System);
+	assert_eq!(map.kind(0x4002_3C00), RegionKind::Peripheral);
+	assert_eq!(map.kind(0x6000_0000), RegionKind::External);
+	assert_eq!(map.kind(0xE000_ED00), RegionKind::System);
+	assert_eq!(map.kind(0xE004_2000), RegionKind::System);
+	assert_eq!(map.kind(0xFFFF_FFFF), RegionKind::System);
+
+	assert_eq!(map.kind(0x0810_0000), RegionKind::Unknown);
+	assert_eq!(map.kind(0x3000_0000), RegionKind::Unknown);
+
+	assert_eq!(map.find(0x0800_1000).unwrap().name, "Flash");
+}
+
+#[test]
+fn unmapped_ranges() {
+	let link = open(Target::stm32f407());
+	let map = link.memory_map();
+
+	let sram = map.find(0x2000_0000).unwrap();
+	let end = sram.end() as u32;
+
+	assert_eq!(map.unmapped(0x2000_0000, sram.size as usize), None);
+	assert_eq!(map.unmapped(end - 4, 8), Some(end));
+	assert_eq!(map.unmapped(0x4000_0000, 0x100), None);
+}
+
+#[test]
+fn generic_map_before_identification() {
+	let map = MemoryMap::cortex_m();
+
+	assert_eq!(map.kind(0x0800_0000), RegionKind::Unknown);
+	assert_eq!(map.kind(0x4000_0000), RegionKind::Peripheral);
+	assert_eq!(map.kind(0xE000_EDF0), RegionKind::System);
+}
+
+#[test]
+fn refuse_unmapped_access() {
+	let mut link = open(Target::stm32f407());
+	link.set_access_policy(AccessPolicy::Refuse);
+
+	let start = link.transport().commands.len();
+
+	match link.read_memory(0x3000_0000, 4) {
+		Err(LinkError::Unmapped { address: 0x3000_0000 }) => (),
+		r => panic!("Expected the access to be refused, got {:?}", r),
+	}
+
+	match link.write_u32(0x0810_0000, 0) {
+		Err(LinkError::Unmapped { address: 0x0810_0000 }) => (),
+		r => panic!("Expected the access to be refused, got {:?}", r),
+	}
+
+	// Nothing is sent to the probe
+	assert_eq!(link.transport().commands.len(), start);
+
+	// Mapped accesses are still allowed
+	assert_eq!(link.read_u32(0x2000_0000).unwrap(), 0);
+}
+
+#[test]
+fn warn_does_the_access() {
+	let mut link = open(Target::stm32f407());
+	assert_eq!(link.access_policy(), AccessPolicy::Warn);
+
+	match link.read_memory(0x3000_0000, 4) {
+		Err(LinkError::Status(_)) => (),
+		r => panic!("Expected the target to fault, got {:?}", r),
+	}
+}
@@ -0,0 +1,209 @@
+//! Memory access of any size and alignment
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, StatusError, DebugMode };
+use rustylink::sim::{ SimProbe, Target };
+use common::{ open, pattern };
+
+
+const SRAM: u32 = 0x2000_0000;
+
+const READMEM_32BIT: u8 = 0x07;
+const READMEM_8BIT: u8 = 0x0C;
+const READMEM_16BIT: u8 = 0x47;
+
+
+/// Width and size of the memory reads sent since the command `from`
+fn reads(link: &Link<SimProbe>, from: usize) -> Vec<(u8, usize)> {
+	link.transport().commands[from..].iter()
+		.filter(|c| c.len() >= 8 && c[0] == 0xF2 && [READMEM_8BIT, READMEM_16BIT, READMEM_32BIT].contains(&c[1]))
+		.map(|c| (c[1], c[6] as usize | (c[7] as usize) << 8))
+		.collect()
+}
+
+
+#[test]
+fn round_trip_any_alignment() {
+	let mut link = open(Target::stm32f407());
+
+	for offset in 0..8 {
+		for &n in [1, 2, 3, 5, 7, 64, 1027].iter() {
+			let address = SRAM + 0x1000 * offset + offset;
+			let data = pattern(n);
+
+			link.write_memory(address, &data).unwrap();
+			assert_eq!(link.read_memory(address, n).unwrap(), data, "{} bytes at 0x{:08X}", n, address);
+			assert_eq!(link.transport().target().peek(address, n), data);
+		}
+	}
+}
+
+#[test]
+fn head_body_tail_split() {
+	let mut link = open(Target::stm32f407());
+	let start = link.transport().commands.len();
+
+	link.read_memory(SRAM + 1, 12).unwrap();
+
+	// 8 bit, 16 bit, 32 bit body, 8 bit tail
+	assert_eq!(reads(&link, start), vec![(READMEM_8BIT, 1), (READMEM_16BIT, 2), (READMEM_32BIT, 8), (READMEM_8BIT, 1)]);
+}
+
+#[test]
+fn no_16bit_on_old_firmware() {
+	let mut link = Link::open(SimProbe::with_firmware(Target::stm32f407(), 24), SimProbe::model(), DebugMode::SWD).unwrap();
+	let start = link.transport().commands.len();
+
+	link.read_memory(SRAM + 1, 14).unwrap();
+
+	assert_eq!(reads(&link, start), vec![(READMEM_8BIT, 3), (READMEM_32BIT, 8), (READMEM_8BIT, 3)]);
+}
+
+#[test]
+fn bursts_do_not_cross_packet_boundaries() {
+	let mut link = open(Target::stm32f407());
+	let start = link.transport().commands.len();
+
+	let data = link.read_memory(SRAM + 0x800, 0x2000).unwrap();
+	assert_eq!(data.len(), 0x2000);
+
+	assert_eq!(reads(&link, start), vec![(READMEM_32BIT, 0x800), (READMEM_32BIT, 0x1000), (READMEM_32BIT, 0x800)]);
+}
+
+#[test]
+fn fault_in_the_middle_is_reported() {
+	let mut link = open(Target::stm32f407());
+
+	// The SRAM ends at 0x20030000
+	match link.read_memory(0x2002_FFF0, 0x20) {
+		Err(LinkError::Status(StatusError::ApFault)) => (),
+		r => panic!("Expected an AP fault, got {:?}", r.map(|d| d.len())),
+	}
+
+	match link.write_memory(0x2002_FFFE, &[0; 8]) {
+		Err(LinkError::Status(StatusError::ApFault)) => (),
+		r => panic!("Expected an AP fault, got {:?}", r),
+	}
+}
+
+#[test]
+fn word_access_alignment() {
+	let mut link = open(Target::stm32f407());
+
+	match link.read_mem32(SRAM + 2, 4) {
+		Err(LinkError::Alignment { width: 4, .. }) => (),
+		r => panic!("Expected an alignment error, got {:?}", r),
+	}
+
+	match link.write_mem32(SRAM, &[0; 6]) {
+		Err(LinkError::Alignment { width: 4, .. }) => (),
+		r => panic!("Expected an alignment error, got {:?}", r),
+	}
+}
+
+#[test]
+fn halfword_access() {
+	let mut link = open(Target::stm32f407());
+
+	link.write_mem16(SRAM + 2, &[0x34, 0x12, 0x78, 0x56]).unwrap();
+	assert_eq!(link.read_mem16(SRAM + 2, 4).unwrap(), vec![0x34, 0x12, 0x78, 0x56]);
+	assert_eq!(link.read_mem8(SRAM, 6).unwrap(), vec![0x00, 0x00, 0x34, 0x12, 0x78, 0x56]);
+
+	let start = link.transport().commands.len();
+	link.read_mem16(SRAM + 2, 2).unwrap();
+	assert_eq!(reads(&link, start), vec![(READMEM_16BIT, 2)]);
+}
+
+#[test]
+fn halfword_alignment() {
+	let mut link = open(Target::stm32f407());
+
+	match link.read_mem16(SRAM + 1, 2) {
+		Err(LinkError::Alignment { width: 2, .. }) => (),
+		r => panic!("Expected an alignment error, got {:?}", r),
+	}
+
+	match link.write_mem16(SRAM, &[0; 3]) {
+		Err(LinkError::Alignment { width: 2, .. }) => (),
+		r => panic!("Expected an alignment error, got {:?}", r),
+	}
+}
+
+#[test]
+fn halfword_unsupported_on_old_firmware() {
+	let mut link = Link::open(SimProbe::with_firmware(Target::stm32f407(), 24), SimProbe::model(), DebugMode::SWD).unwrap();
+	let start = link.transport().commands.len();
+
+	match link.read_mem16(SRAM, 2) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected the access to be unsupported, got {:?}", r),
+	}
+
+	match link.write_mem16(SRAM, &[0; 2]) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected the access to be unsupported, got {:?}", r),
+	}
+
+	// Nothing is sent to the probe
+	assert_eq!(link.transport().commands.len(), start);
+}
+
+#[test]
+fn typed_reads() {
+	let mut link = open(Target::stm32f407());
+
+	link.transport_mut().target_mut().load(SRAM, &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99]);
+
+	assert_eq!(link.read_u8(SRAM + 3).unwrap(), 0x44);
+	assert_eq!(link.read_u16(SRAM + 2).unwrap(), 0x4433);
+	assert_eq!(link.read_u32(SRAM).unwrap(), 0x4433_2211);
+	assert_eq!(link.read_u32(SRAM + 1).unwrap(), 0x5544_3322);
+	assert_eq!(link.read_u64(SRAM).unwrap(), 0x8877_6655_4433_2211);
+	assert_eq!(link.read_u32_slice(SRAM, 2).unwrap(), vec![0x4433_2211, 0x8877_6655]);
+}
+
+#[test]
+fn range_past_the_end() {
+	let mut link = open(Target::stm32f407());
+	let start = link.transport().commands.len();
+
+	match link.read_u64(0xFFFF_FFFC) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+
+	match link.write_memory(0xFFFF_FFF0, &[0; 0x20]) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an invalid argument, got {:?}", r),
+	}
+
+	// Nothing is sent to the probe
+	assert_eq!(link.transport().commands.len(), start);
+}
+
+#[test]
+fn typed_writes() {
+	let mut link = open(Target::stm32f407());
+
+	link.write_u32(SRAM, 0xDEAD_BEEF).unwrap();
+	assert_eq!(link.transport().target().peek(SRAM, 4), vec![0xEF, 0xBE, 0xAD, 0xDE]);
+
+	link.write_u32_slice(SRAM + 4, &[0x0403_0201, 0x0807_0605]).unwrap();
+	assert_eq!(link.transport().target().peek(SRAM + 4, 8), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+
+	assert_eq!(link.modify_u32(SRAM, 0x0000_FF00, 0x1234_5678).unwrap(), 0xDEAD_56EF);
+	assert_eq!(link.read_u32(SRAM).unwrap(), 0xDEAD_56EF);
+}
+
+#[test]
+fn aligned_word_is_a_single_access() {
+	let mut link = open(Target::stm32f407());
+	let start = link.transport().commands.len();
+
+	link.read_u32(SRAM + 8).unwrap();
+
+	assert_eq!(reads(&link, start), vec![(READMEM_32BIT, 4)]);
+}
@@ -0,0 +1,218 @@
+//! Option bytes of the F4, F1, L0/L1 and L4/G0 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ LinkError, FlashError, OptionBytes, RdpLevel };
+use rustylink::sim::{ Target, FlashL4 };
+use common::open;
+
+
+const FLASH: u32 = 0x0800_0000;
+
+const F4_OPTCR: u32 = 0x4002_3C14;
+const L4_OPTR: u32 = 0x4002_2020;
+const L0_OPTR: u32 = 0x4002_201C;
+
+const OPTLOCK: u32 = 1 << 0;
+/// DUALBANK of L47x/L48x
+const DUALBANK: u32 = 1 << 21;
+
+
+#[test]
+fn decode_f4() {
+	let mut link = open(Target::stm32f407());
+
+	let options = match link.option_bytes().unwrap() {
+		OptionBytes::F4(o) => o,
+		o => panic!("Expected F4 option bytes, got {:?}", o),
+	};
+
+	assert_eq!(options.rdp, RdpLevel::Level0);
+	assert_eq!(options.bor, 0);
+	assert!(options.wdg_sw && options.nrst_stop && options.nrst_stdby);
+	assert_eq!(options.nwrp, 0xFFFF_FFFF);
+	assert_eq!((options.dual_bank, options.bfb2, options.boot_add), (None, None, None));
+
+	let text = format!("{}", OptionBytes::F4(options));
+	assert!(text.contains("RDP          level 0"));
+	assert!(text.contains("nWRP         none"));
+}
+
+#[test]
+fn program_f4() {
+	let mut link = open(Target::stm32f407());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::F4(o) => o,
+		o => panic!("Expected F4 option bytes, got {:?}", o),
+	};
+
+	options.bor = 2;
+	options.nrst_stdby = false;
+	options.nwrp &= !(1 << 3);
+
+	link.write_option_bytes(&OptionBytes::F4(options)).unwrap();
+
+	// BOR_LEV 01, nRST_STDBY cleared and sector 3 protected, locked again
+	assert_eq!(link.read_debug_reg(F4_OPTCR).unwrap(), 0x0FF7_AA65 | OPTLOCK);
+
+	// The option bytes survive a reset
+	link.usb_reset().unwrap();
+	assert_eq!(link.option_bytes().unwrap(), OptionBytes::F4(options));
+
+	match link.erase_sectors(&[3]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(3))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+}
+
+#[test]
+fn program_f1() {
+	let mut link = open(Target::stm32f103());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::F1(o) => o,
+		o => panic!("Expected F1 option bytes, got {:?}", o),
+	};
+
+	assert_eq!(options.rdp, RdpLevel::Level0);
+	assert_eq!(options.data, [0xFF, 0xFF]);
+
+	options.data = [0x12, 0x34];
+	options.wdg_sw = false;
+	// Pages 4 to 7
+	options.nwrp = !0b10;
+
+	link.write_option_bytes(&OptionBytes::F1(options)).unwrap();
+
+	// Each byte is followed by its complement
+	assert_eq!(link.transport().target().peek(0x1FFF_F800, 10), vec![0xA5, 0x5A, 0xFE, 0x01, 0x12, 0xED, 0x34, 0xCB, 0xFD, 0x02]);
+
+	// The option bytes are loaded by a reset, which halts the core
+	assert!(link.transport().target().halted());
+	assert_eq!(link.option_bytes().unwrap(), OptionBytes::F1(options));
+
+	match link.erase_range(FLASH + 0x1400, 4) {
+		Err(LinkError::Flash(FlashError::SectorProtected(5))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+}
+
+#[test]
+fn program_l4() {
+	let mut link = open(Target::stm32l4(0x410F_C241, 0x1007_6415, FlashL4::l47x(512 * 1024), 0x18000));
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::L4(o) => o,
+		o => panic!("Expected L4 option bytes, got {:?}", o),
+	};
+
+	assert_eq!(options.bor, Some(0));
+	assert_eq!(options.dual_bank, Some(false));
+	assert_eq!(link.flash_sectors().unwrap().len(), 256);
+
+	options.dual_bank = Some(true);
+	options.bor = Some(3);
+
+	link.write_option_bytes(&OptionBytes::L4(options)).unwrap();
+
+	assert_eq!(link.read_debug_reg(L4_OPTR).unwrap(), 0xFFCF_FBAA | DUALBANK);
+	assert_eq!(link.option_bytes().unwrap(), OptionBytes::L4(options));
+
+	// The second bank starts at 256 kB
+	link.transport_mut().target_mut().load(FLASH + 0x4_0000, &[0; 0x800]);
+	link.erase_range(FLASH + 0x4_0000, 4).unwrap();
+	assert_eq!(link.transport().target().peek(FLASH + 0x4_0000, 0x800), vec![0xFF; 0x800]);
+}
+
+#[test]
+fn program_l0() {
+	let mut link = open(Target::stm32l053());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::L0(o) => o,
+		o => panic!("Expected L0 option bytes, got {:?}", o),
+	};
+
+	assert_eq!(options.rdp, RdpLevel::Level0);
+	assert_eq!(options.bor, 0);
+	assert!(options.wdg_sw && options.nrst_stop && options.nrst_stdby);
+	assert_eq!(options.wrprot, [0; 4]);
+
+	options.bor = 2;
+	options.nrst_stop = false;
+	// Sectors 1 and 2
+	options.wrprot[0] = 0b110;
+
+	link.write_option_bytes(&OptionBytes::L0(options)).unwrap();
+
+	// Each half-word is followed by its complement
+	assert_eq!(link.transport().target().peek(0x1FF8_0000, 16), vec![
+		0xAA, 0x00, 0x55, 0xFF, 0x59, 0x80, 0xA6, 0x7F,
+		0x06, 0x00, 0xF9, 0xFF, 0x00, 0x00, 0xFF, 0xFF,
+	]);
+
+	// BOR_LEV 1001 and nRST_STOP cleared, loaded with OBL_LAUNCH
+	assert_eq!(link.read_debug_reg(L0_OPTR).unwrap(), 0x8059_00AA);
+	assert_eq!(link.option_bytes().unwrap(), OptionBytes::L0(options));
+
+	let text = format!("{}", OptionBytes::L0(options));
+	assert!(text.contains("BOR          level 2"));
+	assert!(text.contains("WRPROT       1 2 protected"));
+
+	// Sector 1 starts at page 32
+	match link.erase_range(FLASH + 0x1000, 4) {
+		Err(LinkError::Flash(FlashError::SectorProtected(32))) => (),
+		r => panic!("Expected a write protection error, got {:?}", r),
+	}
+}
+
+#[test]
+fn g0_reset_bits() {
+	let mut link = open(Target::stm32g071());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::L4(o) => o,
+		o => panic!("Expected L4 option bytes, got {:?}", o),
+	};
+
+	assert_eq!(options.bor, None);
+	assert_eq!(options.dual_bank, None);
+	assert!(options.nrst_shdw);
+
+	// nRST_SHDW is bit 15 on G0
+	options.nrst_shdw = false;
+	link.write_option_bytes(&OptionBytes::L4(options)).unwrap();
+
+	assert_eq!(link.read_debug_reg(L4_OPTR).unwrap(), 0xDFFF_61AA);
+}
+
+#[test]
+fn refused_changes() {
+	let mut link = open(Target::stm32f407());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::F4(o) => o,
+		o => panic!("Expected F4 option bytes, got {:?}", o),
+	};
+
+	// The RDP level has its own functions
+	options.rdp = RdpLevel::Level1;
+
+	match link.write_option_bytes(&OptionBytes::F4(options)) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an argument error, got {:?}", r),
+	}
+
+	// Option bytes of another controller
+	let f1 = open(Target::stm32f103()).option_bytes().unwrap();
+
+	match link.write_option_bytes(&f1) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an argument error, got {:?}", r),
+	}
+
+	assert_eq!(link.read_debug_reg(F4_OPTCR).unwrap(), 0x0FFF_AAED);
+}
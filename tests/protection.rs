@@ -0,0 +1,211 @@
+//! Write protection and PCROP of the F4, F1, L0 and L4 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, FlashError, OptionBytes, Protection };
+use rustylink::sim::{ SimProbe, Target, FlashL0, FlashL4 };
+use common::open;
+
+
+const FLASH: u32 = 0x0800_0000;
+
+const F4_OPTCR: u32 = 0x4002_3C14;
+const L4_WRP1AR: u32 = 0x4002_202C;
+const L4_WRP1BR: u32 = 0x4002_2030;
+const L4_WRP2AR: u32 = 0x4002_204C;
+const L0_WRPROT1: u32 = 0x4002_2020;
+
+
+/// Numbers and protection of the protected sectors
+fn protected(link: &mut Link<SimProbe>) -> Vec<(usize, Protection)> {
+	link.protected_sectors().unwrap().iter().map(|p| (p.sector.index, p.protection)).collect()
+}
+
+
+#[test]
+fn protect_f4_sectors() {
+	let mut link = open(Target::stm32f407());
+	assert_eq!(protected(&mut link), vec![]);
+
+	assert_eq!(link.protect_range(FLASH + 0x4000, 0x8000).unwrap(), vec![1, 2]);
+	assert_eq!(protected(&mut link), vec![(1, Protection::Write), (2, Protection::Write)]);
+	assert_eq!(link.read_debug_reg(F4_OPTCR).unwrap() >> 16 & 0xFFF, 0xFF9);
+
+	match link.erase_range(FLASH, 0x10000) {
+		Err(LinkError::Flash(FlashError::SectorProtected(1))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	assert_eq!(link.unprotect_range(FLASH + 0x8000, 1).unwrap(), vec![2]);
+	assert_eq!(protected(&mut link), vec![(1, Protection::Write)]);
+
+	link.program_flash(FLASH + 0x8000, &[0x12, 0x34, 0x56, 0x78], &mut |_, _| ()).unwrap();
+}
+
+#[test]
+fn pcrop_f4_sectors() {
+	let mut link = open(Target::stm32f407());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::F4(o) => o,
+		o => panic!("Expected F4 option bytes, got {:?}", o),
+	};
+
+	// With SPRMOD the set bits select the PCROP sectors
+	options.pcrop = true;
+	options.nwrp = 0xFFFF_F000 | 1 << 5;
+	link.write_option_bytes(&OptionBytes::F4(options)).unwrap();
+
+	assert_eq!(protected(&mut link), vec![(5, Protection::Pcrop)]);
+	assert!(format!("{}", link.option_bytes().unwrap()).contains("PCROP        5 protected"));
+
+	match link.program_flash(FLASH + 0x2_0000, &[0; 4], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(5))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	match link.protect_range(FLASH, 4) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected an unsupported operation, got {:?}", r),
+	}
+}
+
+#[test]
+fn protect_f1_groups() {
+	let mut link = open(Target::stm32f103());
+
+	// Each bit protects 4 kB, four pages of 1 kB
+	assert_eq!(link.protect_range(FLASH + 0x1400, 4).unwrap(), vec![5]);
+	assert_eq!(protected(&mut link).iter().map(|p| p.0).collect::<Vec<_>>(), vec![4, 5, 6, 7]);
+	assert_eq!(link.transport().target().peek(0x1FFF_F808, 2), vec![0xFD, 0x02]);
+
+	match link.erase_sectors(&[6]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(6))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	assert_eq!(link.unprotect_range(FLASH + 0x1000, 0x1000).unwrap(), vec![4, 5, 6, 7]);
+	assert_eq!(protected(&mut link), vec![]);
+	link.erase_sectors(&[6]).unwrap();
+}
+
+#[test]
+fn list_l0_sectors() {
+	let mut flash = FlashL0::l053();
+	// Second 4 kB sector
+	flash.set_wrprot(0b10);
+
+	let mut link = open(Target::stm32l0(0x410C_C601, 0x1000_6417, flash, 0x2000, 0x7C));
+
+	let pages = protected(&mut link);
+	assert_eq!(pages.len(), 32);
+	assert_eq!((pages[0].0, pages[31].0), (32, 63));
+
+	// The whole sector is unprotected
+	assert_eq!(link.unprotect_range(FLASH + 0x1000, 4).unwrap(), vec![32]);
+	assert_eq!(protected(&mut link), vec![]);
+	link.erase_sectors(&[40]).unwrap();
+}
+
+#[test]
+fn protect_l0_sectors() {
+	let mut link = open(Target::stm32l053());
+
+	// The last page of the first sector and the first page of the second one
+	assert_eq!(link.protect_range(FLASH + 0xF80, 0x100).unwrap(), vec![31, 32]);
+	assert_eq!(link.read_debug_reg(L0_WRPROT1).unwrap(), 0b11);
+	assert_eq!(link.transport().target().peek(0x1FF8_0008, 4), vec![0x03, 0x00, 0xFC, 0xFF]);
+	assert_eq!(protected(&mut link).len(), 64);
+
+	match link.erase_sectors(&[0]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(0))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	assert_eq!(link.unprotect_range(FLASH, 4).unwrap(), vec![0]);
+	assert_eq!(link.read_debug_reg(L0_WRPROT1).unwrap(), 0b10);
+	link.erase_sectors(&[0]).unwrap();
+}
+
+#[test]
+fn pcrop_l0_sectors() {
+	let mut link = open(Target::stm32l053());
+
+	let mut options = match link.option_bytes().unwrap() {
+		OptionBytes::L0(o) => o,
+		o => panic!("Expected L0 option bytes, got {:?}", o),
+	};
+
+	// With WPRMOD the cleared bits select the PCROP sectors
+	options.pcrop = true;
+	options.wrprot[0] = !(1 << 2);
+	link.write_option_bytes(&OptionBytes::L0(options)).unwrap();
+
+	let pages = protected(&mut link);
+	assert_eq!(pages.len(), 32);
+	assert_eq!((pages[0], pages[31].0), ((64, Protection::Pcrop), 95));
+	assert!(format!("{}", link.option_bytes().unwrap()).contains("PCROP        2 protected"));
+
+	match link.program_flash(FLASH + 0x2000, &[0; 4], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(64))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	match link.protect_range(FLASH, 4) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected an unsupported operation, got {:?}", r),
+	}
+}
+
+#[test]
+fn l4_areas() {
+	let mut link = open(Target::stm32l476());
+
+	assert_eq!(link.protect_range(FLASH + 0x1000, 0x1000).unwrap(), vec![2, 3]);
+	// Grows the area of pages 2 and 3
+	link.protect_range(FLASH + 0x2000, 4).unwrap();
+	// First page of the second bank
+	link.protect_range(FLASH + 0x8_0000, 4).unwrap();
+
+	assert_eq!(link.read_debug_reg(L4_WRP1AR).unwrap(), 0xFF04_FF02);
+	assert_eq!(link.read_debug_reg(L4_WRP2AR).unwrap(), 0xFF00_FF00);
+	assert_eq!(protected(&mut link).iter().map(|p| p.0).collect::<Vec<_>>(), vec![2, 3, 4, 256]);
+
+	match link.erase_sectors(&[4]) {
+		Err(LinkError::Flash(FlashError::SectorProtected(4))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	// Taking out page 3 splits the area in two
+	link.unprotect_range(FLASH + 0x1800, 4).unwrap();
+	assert_eq!(link.read_debug_reg(L4_WRP1BR).unwrap(), 0xFF04_FF04);
+	assert!(format!("{}", link.option_bytes().unwrap()).contains("WRP1A        pages 2 to 2"));
+
+	// Both areas of the first bank are used
+	match link.protect_range(FLASH + 0x8000, 4) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected an argument error, got {:?}", r),
+	}
+
+	// The areas survive a reset
+	link.usb_reset().unwrap();
+	assert_eq!(protected(&mut link).iter().map(|p| p.0).collect::<Vec<_>>(), vec![2, 4, 256]);
+}
+
+#[test]
+fn l4_pcrop() {
+	let mut flash = FlashL4::l476();
+	flash.set_pcrop(0x1000, 0x17F8);
+
+	let mut link = open(Target::stm32l4(0x410F_C241, 0x1007_6415, flash, 0x18000));
+	assert_eq!(protected(&mut link), vec![(2, Protection::Pcrop)]);
+
+	match link.program_flash(FLASH + 0x1400, &[0; 8], &mut |_, _| ()) {
+		Err(LinkError::Flash(FlashError::SectorProtected(2))) => (),
+		r => panic!("Expected a protected sector, got {:?}", r),
+	}
+
+	link.program_flash(FLASH + 0x1800, &[0; 8], &mut |_, _| ()).unwrap();
+}
@@ -0,0 +1,117 @@
+//! Read protection levels of the F4, F1, L0/L1 and L4 devices
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ LinkError, RdpLevel, rdp2_confirmation };
+use rustylink::sim::Target;
+use common::open;
+
+
+const FLASH: u32 = 0x0800_0000;
+const EEPROM: u32 = 0x0808_0000;
+
+const F4_OPTCR: u32 = 0x4002_3C14;
+
+
+/// Raise the protection of a programmed flash to level 1, then go back to level 0
+/// which erases the flash to `erased`
+fn raise_and_regress(target: Target, erased: u8) {
+	let mut link = open(target);
+	link.transport_mut().target_mut().load(FLASH, &[0x5A; 0x100]);
+
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level0);
+	assert_eq!(link.read_memory(FLASH, 4).unwrap(), vec![0x5A; 4]);
+
+	link.set_rdp_level(RdpLevel::Level1, None).unwrap();
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level1);
+
+	// The debug port cannot read the flash anymore
+	assert!(link.read_memory(FLASH, 4).is_err());
+
+	// Going back to level 0 erases the flash
+	link.set_rdp_level(RdpLevel::Level0, None).unwrap();
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level0);
+	assert_eq!(link.read_memory(FLASH, 0x100).unwrap(), vec![erased; 0x100]);
+}
+
+
+#[test]
+fn raise_and_regress_f4() {
+	raise_and_regress(Target::stm32f407(), 0xFF);
+}
+
+#[test]
+fn raise_and_regress_f1() {
+	raise_and_regress(Target::stm32f103(), 0xFF);
+}
+
+#[test]
+fn raise_and_regress_l0() {
+	raise_and_regress(Target::stm32l053(), 0x00);
+	raise_and_regress(Target::stm32l152(), 0x00);
+}
+
+#[test]
+fn raise_and_regress_l4() {
+	raise_and_regress(Target::stm32l476(), 0xFF);
+}
+
+#[test]
+fn level2_confirmation() {
+	let mut link = open(Target::stm32f407());
+
+	for token in [None, Some("yes"), Some(rdp2_confirmation(0x419).as_str())].iter() {
+		match link.set_rdp_level(RdpLevel::Level2, *token) {
+			Err(LinkError::Argument(_)) => (),
+			r => panic!("Expected a missing confirmation, got {:?}", r),
+		}
+	}
+
+	assert_eq!(link.read_debug_reg(F4_OPTCR).unwrap(), 0x0FFF_AAED);
+
+	link.set_rdp_level(RdpLevel::Level2, Some(&rdp2_confirmation(0x413))).unwrap();
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level2);
+
+	// Level 2 is permanent
+	for level in [RdpLevel::Level0, RdpLevel::Level1].iter() {
+		match link.set_rdp_level(*level, None) {
+			Err(LinkError::Argument(_)) => (),
+			r => panic!("Expected level 2 to be permanent, got {:?}", r),
+		}
+	}
+}
+
+#[test]
+fn no_level2_on_f1() {
+	let mut link = open(Target::stm32f103());
+
+	match link.set_rdp_level(RdpLevel::Level2, Some(&rdp2_confirmation(0x410))) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected an unsupported level, got {:?}", r),
+	}
+
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level0);
+}
+
+#[test]
+fn l0_levels() {
+	let mut link = open(Target::stm32l053());
+	link.transport_mut().target_mut().load(EEPROM, &[0x5A; 0x10]);
+
+	// Leaving level 1 erases the data EEPROM too
+	link.set_rdp_level(RdpLevel::Level1, None).unwrap();
+	assert!(link.read_eeprom(EEPROM, 4).is_err());
+
+	link.set_rdp_level(RdpLevel::Level0, None).unwrap();
+	assert_eq!(link.read_eeprom(EEPROM, 0x10).unwrap(), vec![0; 0x10]);
+
+	link.set_rdp_level(RdpLevel::Level2, Some(&rdp2_confirmation(0x417))).unwrap();
+	assert_eq!(link.rdp_level().unwrap(), RdpLevel::Level2);
+
+	match link.set_rdp_level(RdpLevel::Level0, None) {
+		Err(LinkError::Argument(_)) => (),
+		r => panic!("Expected level 2 to be permanent, got {:?}", r),
+	}
+}
@@ -0,0 +1,166 @@
+//! Recording of a session and its replay without the probe
+
+extern crate rustylink;
+
+use std::time::Duration;
+
+use rustylink::{ Link, LinkError, Transport, DebugMode, Recorder, Replay };
+use rustylink::sim::{ SimProbe, Target };
+
+
+const SRAM: u32 = 0x2000_0000;
+
+
+/// Record a session with the simulated probe and return the session file
+fn record<F: FnOnce(&mut Link<Recorder<SimProbe, Vec<u8>>>)>(session: F) -> Vec<u8> {
+	let transport = Recorder::new(SimProbe::new(Target::stm32f407()), SimProbe::model(), Vec::new()).unwrap();
+	let mut link = Link::open(transport, SimProbe::model(), DebugMode::SWD).unwrap();
+
+	session(&mut link);
+
+	let transport = link.into_transport();
+	transport.into_inner().1
+}
+
+/// Transport failing every transfer with the next of its errors
+struct Failing(Vec<LinkError>);
+
+impl Transport for Failing {
+	fn write_command(&mut self, _: &[u8], _: Duration) -> Result<usize, LinkError> {
+		Err(self.0.remove(0))
+	}
+
+	fn write_data(&mut self, _: &[u8], _: Duration) -> Result<usize, LinkError> {
+		Err(self.0.remove(0))
+	}
+
+	fn read_data(&mut self, _: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		Err(self.0.remove(0))
+	}
+
+	fn read_trace(&mut self, _: &mut [u8], _: Duration) -> Result<usize, LinkError> {
+		Err(self.0.remove(0))
+	}
+}
+
+
+#[test]
+fn session_file_format() {
+	let file = record(|_| ());
+	let text = String::from_utf8(file).unwrap();
+	let mut lines = text.lines();
+
+	assert_eq!(lines.next(), Some("# rustylink session"));
+	assert_eq!(lines.next(), Some("probe 0483:374B"));
+
+	// GET_VERSION and its answer
+	let cmd: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+	assert_eq!(&cmd[1..4], &["CMD", "1", "F1"]);
+	let answer: Vec<&str> = lines.next().unwrap().split_whitespace().collect();
+	assert_eq!(&answer[1..3], &["IN", "6"]);
+	assert_eq!(answer.len(), 9);
+}
+
+#[test]
+fn replay_reproduces_the_session() {
+	let data: Vec<u8> = (0..32).collect();
+
+	let file = record(|link| {
+		link.write_mem32(SRAM, &data).unwrap();
+		assert_eq!(link.read_mem32(SRAM, 32).unwrap(), data);
+		link.halt().unwrap();
+	});
+
+	let replay = Replay::from_reader(file.as_slice()).unwrap();
+	assert_eq!(replay.model().pid, 0x374B);
+
+	let mut link = Link::open(replay, SimProbe::model(), DebugMode::SWD).unwrap();
+	assert_eq!(link.probe_version().jtag, 37);
+
+	link.write_mem32(SRAM, &data).unwrap();
+	assert_eq!(link.read_mem32(SRAM, 32).unwrap(), data);
+	link.halt().unwrap();
+
+	assert_eq!(link.transport().remaining(), 0);
+}
+
+#[test]
+fn replay_reports_divergence() {
+	let file = record(|link| {
+		link.read_mem32(SRAM, 4).unwrap();
+	});
+
+	let replay = Replay::from_reader(file.as_slice()).unwrap();
+	let mut link = Link::open(replay, SimProbe::model(), DebugMode::SWD).unwrap();
+
+	match link.read_mem32(SRAM + 4, 4) {
+		Err(LinkError::Replay { .. }) => (),
+		r => panic!("Expected the replay to diverge, got {:?}", r),
+	}
+}
+
+#[test]
+fn replay_returns_recorded_errors() {
+	let file = record(|link| {
+		assert!(link.read_mem32(0x6000_0000, 4).is_err());
+	});
+
+	let replay = Replay::from_reader(file.as_slice()).unwrap();
+	let mut link = Link::open(replay, SimProbe::model(), DebugMode::SWD).unwrap();
+
+	match link.read_mem32(0x6000_0000, 4) {
+		Err(LinkError::Status(_)) => (),
+		r => panic!("Expected the recorded fault, got {:?}", r),
+	}
+}
+
+#[test]
+fn replay_returns_transport_errors() {
+	let timeout = Duration::from_millis(100);
+	let mut buf = [0; 8];
+
+	let mut recorder = Recorder::new(Failing(vec![
+		LinkError::Transfer { expected: 8, actual: 2 },
+		LinkError::Io(std::io::ErrorKind::TimedOut.into()),
+		LinkError::Unsupported("trace endpoint"),
+		LinkError::NoProbe,
+	]), SimProbe::model(), Vec::new()).unwrap();
+
+	assert!(recorder.read_data(&mut buf, timeout).is_err());
+	assert!(recorder.read_data(&mut buf, timeout).is_err());
+	assert!(recorder.read_trace(&mut buf, timeout).is_err());
+	assert!(recorder.write_command(&[0xF1], timeout).is_err());
+
+	let text = String::from_utf8(recorder.into_inner().1).unwrap();
+	let results: Vec<&str> = text.lines().skip(2).map(|l| l.split_whitespace().nth(2).unwrap()).collect();
+	assert_eq!(results, vec!["Transfer:8:2", "Io:TimedOut", "Unsupported", "NoProbe"]);
+
+	let mut replay = Replay::from_reader(text.as_bytes()).unwrap();
+
+	match replay.read_data(&mut buf, timeout) {
+		Err(LinkError::Transfer { expected: 8, actual: 2 }) => (),
+		r => panic!("Expected the recorded short transfer, got {:?}", r),
+	}
+
+	match replay.read_data(&mut buf, timeout) {
+		Err(LinkError::Io(ref e)) if e.kind() == std::io::ErrorKind::TimedOut => (),
+		r => panic!("Expected the recorded timeout, got {:?}", r),
+	}
+
+	match replay.read_trace(&mut buf, timeout) {
+		Err(LinkError::Unsupported(_)) => (),
+		r => panic!("Expected the recorded unsupported transfer, got {:?}", r),
+	}
+
+	match replay.write_command(&[0xF1], timeout) {
+		Err(LinkError::NoProbe) => (),
+		r => panic!("Expected the recorded missing probe, got {:?}", r),
+	}
+}
+
+#[test]
+fn invalid_session_file() {
+	assert!(Replay::from_reader("not a session".as_bytes()).is_err());
+	assert!(Replay::from_reader("# rustylink session\nprobe 0483:374B\n12 CMD 16 ZZ\n".as_bytes()).is_err());
+	assert!(Replay::from_reader("# rustylink session\nprobe 0483:374B\n12 IN Transfer:8\n".as_bytes()).is_err());
+}
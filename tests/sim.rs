@@ -0,0 +1,283 @@
+//! `Link` flows exercised against the simulated probe and target
+
+extern crate rustylink;
+
+mod common;
+
+use rustylink::{ Link, LinkError, StatusError, DebugMode };
+use rustylink::sim::{ SimProbe, Target };
+use common::open;
+
+
+const FLASH: u32 = 0x0800_0000;
+const SRAM: u32 = 0x2000_0000;
+
+const FLASH_KEYR: u32 = 0x4002_3C04;
+const FLASH_SR: u32 = 0x4002_3C0C;
+const FLASH_CR: u32 = 0x4002_3C10;
+
+const DHCSR: u32 = 0xE000_EDF0;
+
+const READDEBUGREG: u8 = 0x36;
+const GETLASTRWSTATUS2: u8 = 0x3E;
+
+/// Number of times a command is repeated after a WAIT answer
+const MAX_WAIT: usize = 8;
+
+
+fn open_with<F: FnOnce(&mut Target)>(setup: F) -> Link<SimProbe> {
+	let mut target = Target::stm32f407();
+	setup(&mut target);
+	open(target)
+}
+
+
+#[test]
+fn open_reads_version_and_chip() {
+	let mut link = open(Target::stm32f407());
+
+	let version = *link.probe_version();
+	assert_eq!(version.stlink, 2);
+	assert_eq!(version.jtag, 37);
+	assert_eq!(version.jtag_api, 2);
+
+	assert_eq!(link.current_mode().unwrap(), rustylink::STLinkMode::Debug(DebugMode::Unknown));
+	assert_eq!(link.core_id().unwrap(), 0x2BA0_1477);
+	assert_eq!(link.get_chip_info().unwrap(), 0x413);
+
+	let memory = link.memory().clone();
+	assert_eq!(memory.flash.base, FLASH);
+	assert_eq!(memory.flash.size, 1024);
+	assert_eq!(memory.ram[0].base, SRAM);
+}
+
+#[test]
+fn open_leaves_flash_locked() {
+	let mut link = open(Target::stm32f407());
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & (1 << 31), 0);
+
+	link.unlock_flash().unwrap();
+	assert_eq!(link.read_debug_reg(FLASH_CR).unwrap() & (1 << 31), 0);
+}
+
+#[test]
+fn target_voltage() {
+	let mut link = open(Target::stm32f407());
+
+	let v = link.voltage().unwrap();
+	assert!(v > 3.2 && v < 3.4, "Voltage {} out of range", v);
+}
+
+#[test]
+fn low_target_voltage() {
+	// An unwired VAPP pin reads about 0 V, the connection only warns
+	let mut probe = SimProbe::new(Target::stm32f407());
+	probe.set_voltage(0.0);
+	let mut link = Link::open(probe, SimProbe::model(), DebugMode::SWD).unwrap();
+	assert!(link.voltage().unwrap() < 0.1);
+
+	// Unless the voltage check is on
+	let mut probe = SimProbe::new(Target::stm32f407());
+	probe.set_voltage(0.0);
+	let mut link = Link::new(probe, SimProbe::model());
+	link.set_voltage_check(true);
+	link.version().unwrap();
+
+	match link.attach(DebugMode::SWD) {
+		Err(LinkError::Voltage(_)) => (),
+		r => panic!("Expected the voltage to be too low, got {:?}", r),
+	}
+}
+
+#[test]
+fn retry_after_wait() {
+	let mut link = open(Target::stm32f407());
+	link.write_debug_reg(SRAM, 0x1234_5678).unwrap();
+
+	let start = link.transport().commands.len();
+	link.transport_mut().answer_wait(3);
+
+	assert_eq!(link.read_debug_reg(SRAM).unwrap(), 0x1234_5678);
+
+	// The command is sent again after each WAIT
+	let sent: Vec<u8> = link.transport().commands[start..].iter().map(|c| c[1]).collect();
+	assert_eq!(sent, vec![READDEBUGREG; 4]);
+}
+
+#[test]
+fn give_up_after_wait() {
+	let mut link = open(Target::stm32f407());
+
+	let start = link.transport().commands.len();
+	link.transport_mut().answer_wait(MAX_WAIT + 1);
+
+	let time = std::time::Instant::now();
+
+	match link.read_debug_reg(SRAM) {
+		Err(LinkError::Status(StatusError::ApWait)) => (),
+		r => panic!("Expected the AP to stay busy, got {:?}", r),
+	}
+
+	// Delays of 1, 2, 4 ... 128 ms between the retries
+	assert!(time.elapsed() >= std::time::Duration::from_millis(255));
+
+	// The sticky flags are cleared after the last WAIT
+	let sent: Vec<u8> = link.transport().commands[start..].iter().map(|c| c[1]).collect();
+	let mut expected = vec![READDEBUGREG; MAX_WAIT + 1];
+	expected.push(GETLASTRWSTATUS2);
+	assert_eq!(sent, expected);
+}
+
+#[test]
+fn sram_read_write() {
+	let mut link = open(Target::stm32f407());
+
+	let data: Vec<u8> = (0..64).collect();
+	link.write_mem32(SRAM + 0x100, &data).unwrap();
+	assert_eq!(link.read_mem32(SRAM + 0x100, 64).unwrap(), data);
+
+	link.write_mem8(SRAM + 0x203, &[0xAA, 0xBB, 0xCC]).unwrap();
+	assert_eq!(link.read_mem8(SRAM + 0x202, 5).unwrap(), vec![0x00, 0xAA, 0xBB, 0xCC, 0x00]);
+	assert_eq!(link.read_mem8(SRAM + 0x204, 1).unwrap(), vec![0xBB]);
+
+	assert_eq!(link.transport().target().peek(SRAM + 0x100, 4), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn unmapped_read_reports_fault() {
+	let mut link = open(Target::stm32f407());
+
+	match link.read_mem32(0x6000_0000, 4) {
+		Err(LinkError::Status(StatusError::ApFault)) => (),
+		r => panic!("Expected an AP fault, got {:?}", r),
+	}
+
+	// The sticky error is cleared by reading the status
+	assert_eq!(link.read_mem32(SRAM, 4).unwrap(), vec![0; 4]);
+}
+
+#[test]
+fn halt_run_step() {
+	let mut link = open(Target::stm32f407());
+
+	link.halt().unwrap();
+	assert!(link.transport().target().halted());
+	assert_eq!(link.status().unwrap(), 0x81);
+	assert_ne!(link.read_debug_reg(DHCSR).unwrap() & (1 << 17), 0);
+
+	link.transport_mut().target_mut().regs[15] = 0x0800_0100;
+	link.step().unwrap();
+	assert!(link.transport().target().halted());
+	assert_eq!(link.read_reg(15).unwrap(), 0x0800_0102);
+
+	link.run().unwrap();
+	assert!(!link.transport().target().halted());
+	assert_eq!(link.status().unwrap(), 0x80);
+}
+
+#[test]
+fn run_until_breakpoint() {
+	let mut link = open(Target::stm32f407());
+	link.halt().unwrap();
+
+	// Sum of 1 to 10 with a subroutine, then a literal load
+	let code: [u16; 14] = [
+		0x2000, 0x210A,         // movs r0, #0 ; movs r1, #10
+		0xF000, 0xF804,         // loop: bl add
+		0x3901, 0xD1FB,         // subs r1, #1 ; bne loop
+		0x4A02, 0xBE00,         // ldr r2, =0xCAFEF00D ; bkpt
+		0xB500, 0x1840, 0xBD00, // add: push {lr} ; adds r0, r0, r1 ; pop {pc}
+		0x46C0,                 // nop
+		0xF00D, 0xCAFE,
+	];
+	let bytes = code.iter().flat_map(|h| h.to_le_bytes().to_vec()).collect::<Vec<_>>();
+	link.write_mem32(SRAM, &bytes).unwrap();
+
+	link.write_reg(13, SRAM + 0x1000).unwrap();
+	link.write_reg(15, SRAM).unwrap();
+	link.run().unwrap();
+
+	assert!(link.transport().target().halted());
+	assert_eq!(link.read_reg(15).unwrap(), SRAM + 0x0E);
+	assert_eq!(link.read_reg(0).unwrap(), 55);
+	assert_eq!(link.read_reg(2).unwrap(), 0xCAFE_F00D);
+	assert_eq!(link.read_reg(13).unwrap(), SRAM + 0x1000);
+}
+
+#[test]
+fn core_registers() {
+	let mut link = open(Target::stm32f407());
+
+	link.halt().unwrap();
+	for i in 0..16 {
+		link.transport_mut().target_mut().regs[i] = 0x1000 + i as u32;
+	}
+
+	let regs = format!("{}", link.read_core_regs().unwrap());
+	assert!(regs.contains("0x1000"));
+	assert!(regs.contains("0x100F"));
+	assert_eq!(link.read_reg(3).unwrap(), 0x1003);
+}
+
+#[test]
+fn reset_halt_stops_at_entry() {
+	let mut link = open_with(|t| {
+		// Vector table: initial SP and reset handler
+		t.load(FLASH, &[0x00, 0x00, 0x03, 0x20, 0x01, 0x02, 0x00, 0x08]);
+	});
+
+	link.run().unwrap();
+	link.reset_halt().unwrap();
+
+	let target = link.transport().target();
+	assert!(target.halted());
+	assert_eq!(target.pc(), 0x0800_0200);
+	assert_eq!(target.regs[13], 0x2003_0000);
+	assert_eq!(target.resets, 1);
+}
+
+#[test]
+fn flash_program_and_erase_through_registers() {
+	let mut link = open(Target::stm32f407());
+	link.unlock_flash().unwrap();
+
+	// PG with 32 bit parallelism
+	link.write_debug_reg(FLASH_CR, (2 << 8) | 1).unwrap();
+	link.write_mem32(FLASH, &[0x78, 0x56, 0x34, 0x12, 0xEF, 0xBE, 0xAD, 0xDE]).unwrap();
+	assert_eq!(link.read_debug_reg(FLASH_SR).unwrap(), 0);
+	assert_eq!(link.read_mem32(FLASH, 8).unwrap(), vec![0x78, 0x56, 0x34, 0x12, 0xEF, 0xBE, 0xAD, 0xDE]);
+
+	// Sector erase of sector 0, SNB left at 0
+	link.write_debug_reg(FLASH_CR, (2 << 8) | (1 << 1)).unwrap();
+	link.write_debug_reg(FLASH_CR, (2 << 8) | (1 << 1) | (1 << 16)).unwrap();
+	assert_eq!(link.read_mem32(FLASH, 8).unwrap(), vec![0xFF; 8]);
+}
+
+#[test]
+fn flash_parallelism_error() {
+	let mut link = open(Target::stm32f407());
+	link.unlock_flash().unwrap();
+
+	// PG with 32 bit parallelism, written with byte accesses
+	link.write_debug_reg(FLASH_CR, (2 << 8) | 1).unwrap();
+	link.write_mem8(FLASH, &[0x00]).unwrap();
+
+	// PGPERR
+	assert_ne!(link.read_debug_reg(FLASH_SR).unwrap() & (1 << 6), 0);
+	assert_eq!(link.read_mem8(FLASH, 1).unwrap(), vec![0xFF]);
+}
+
+#[test]
+fn flash_relock_and_wrong_key() {
+	let mut link = open(Target::stm32f407());
+
+	link.write_debug_reg(FLASH_CR, 1 << 31).unwrap();
+	assert_ne!(link.read_debug_reg(FLASH_CR).unwrap() & (1 << 31), 0);
+
+	// A wrong key keeps the flash locked
+	link.write_debug_reg(FLASH_KEYR, 0x1234_5678).unwrap();
+	match link.unlock_flash() {
+		Err(LinkError::FlashLocked) => (),
+		r => panic!("Expected the flash to stay locked, got {:?}", r),
+	}
+}
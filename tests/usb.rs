@@ -0,0 +1,39 @@
+//! Selection of the debug interface of the probes
+
+extern crate rustylink;
+
+use rustylink::usb::{ Endpoint, model };
+use rustylink::usb::transport::debug_interface;
+
+
+fn endpoint(iface: u8, address: u8) -> Endpoint {
+	Endpoint { config: 1, iface, setting: 0, address }
+}
+
+#[test]
+fn composite_probe() {
+	let v3 = model::find(model::VID, 0x374F).unwrap();
+
+	// Mass storage, then the debug interface, then the virtual COM port and the bridge
+	let endpoints = [
+		endpoint(0, 0x83), endpoint(0, 0x03),
+		endpoint(1, v3.rx), endpoint(1, v3.tx), endpoint(1, v3.trace.unwrap()),
+		endpoint(2, 0x84),
+		endpoint(3, 0x85), endpoint(3, 0x05),
+		endpoint(4, 0x86), endpoint(4, 0x06),
+	];
+
+	assert_eq!(debug_interface(&endpoints, v3), Some((1, 0)));
+}
+
+#[test]
+fn endpoints_on_separate_interfaces() {
+	let v21 = model::find(model::VID, 0x374B).unwrap();
+
+	// The command and data endpoints must be on the same interface
+	let endpoints = [endpoint(0, v21.rx), endpoint(1, v21.tx)];
+	assert_eq!(debug_interface(&endpoints, v21), None);
+
+	let endpoints = [endpoint(0, v21.rx), endpoint(0, v21.tx), endpoint(0, 0x83)];
+	assert_eq!(debug_interface(&endpoints, v21), Some((0, 0)));
+}